@@ -0,0 +1,125 @@
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use serenity::all::{
+    CommandInteraction, CommandOptionType, CreateCommand, CreateCommandOption,
+    CreateInteractionResponse, CreateInteractionResponseMessage,
+};
+use serenity::prelude::*;
+use serenity::Error;
+use tracing::error;
+
+use crate::database::Database;
+use crate::utils::helpers::is_bot_owner;
+use crate::RuntimeFlagsGlobal;
+
+pub async fn execute(
+    ctx: &Context,
+    command: &CommandInteraction,
+    database: Arc<Database>,
+) -> Result<(), Error> {
+    if !is_bot_owner(ctx, command.user.id).await {
+        return reply(ctx, command, "Only the bot owner can use this command.").await;
+    }
+
+    let flags = {
+        let data_read = ctx.data.read().await;
+        match data_read.get::<RuntimeFlagsGlobal>() {
+            Some(flags) => flags.clone(),
+            None => return reply(ctx, command, "Runtime flags are unavailable.").await,
+        }
+    };
+
+    let subcommand = match command.data.options.first() {
+        Some(opt) => opt.name.as_str(),
+        None => return Ok(()),
+    };
+
+    let (name, flag, value) = match subcommand {
+        "pause-posting" => ("posting_paused", &flags.posting_paused, true),
+        "resume-posting" => ("posting_paused", &flags.posting_paused, false),
+        "pause-logging" => ("logging_paused", &flags.logging_paused, true),
+        "resume-logging" => ("logging_paused", &flags.logging_paused, false),
+        "status" => {
+            let content = format!(
+                "**Runtime Flags**\n\
+                Posting: {}\n\
+                Logging: {}",
+                paused_label(flags.posting_paused.load(Ordering::Relaxed)),
+                paused_label(flags.logging_paused.load(Ordering::Relaxed)),
+            );
+            return reply(ctx, command, content).await;
+        }
+        _ => return Ok(()),
+    };
+
+    flag.store(value, Ordering::Relaxed);
+
+    let content = match database.set_runtime_flag(name, value).await {
+        Ok(_) => format!("`{}` is now {}.", name, value),
+        Err(e) => {
+            error!(flag = name, error = %e, "Failed to persist runtime flag");
+            format!(
+                "`{}` is now {}, but it could not be saved and will reset on restart.",
+                name, value
+            )
+        }
+    };
+
+    reply(ctx, command, content).await
+}
+
+fn paused_label(paused: bool) -> &'static str {
+    if paused {
+        "paused"
+    } else {
+        "running"
+    }
+}
+
+async fn reply(
+    ctx: &Context,
+    command: &CommandInteraction,
+    content: impl Into<String>,
+) -> Result<(), Error> {
+    command
+        .create_response(
+            &ctx.http,
+            CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .content(content)
+                    .ephemeral(true),
+            ),
+        )
+        .await
+}
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new("admin")
+        .description("Owner-only runtime controls.")
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::SubCommand,
+            "pause-posting",
+            "Stop autonomous posts and mention replies.",
+        ))
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::SubCommand,
+            "resume-posting",
+            "Resume autonomous posts and mention replies.",
+        ))
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::SubCommand,
+            "pause-logging",
+            "Stop storing new messages.",
+        ))
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::SubCommand,
+            "resume-logging",
+            "Resume storing new messages.",
+        ))
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::SubCommand,
+            "status",
+            "Show the current runtime flags.",
+        ))
+}
@@ -1,176 +1,1270 @@
+use std::collections::HashMap;
+use std::env;
 use std::sync::Arc;
-use std::{thread, time};
+use std::time::{Duration, Instant};
 
+use futures::StreamExt;
 use serenity::all::{
-    CommandInteraction, CommandOptionType, CreateCommand, CreateCommandOption, CreateMessage,
-    EditInteractionResponse, MessageId, MessagePagination,
+    ButtonStyle, ChannelId, ChannelType, CommandInteraction, CommandOptionType,
+    CreateAllowedMentions, CreateButton, CreateCommand, CreateCommandOption, CreateEmbed,
+    CreateInteractionResponse, CreateInteractionResponseMessage, CreateMessage,
+    EditInteractionResponse, GuildChannel, GuildId, Message, MessageId, MessagePagination,
+    Permissions, UserId,
 };
 use serenity::prelude::*;
 use serenity::Error;
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
 
-use crate::database::Database;
+use crate::database::{Database, GuildSettings, InsertOutcome, MessageRecord};
+use crate::tasks::heartbeat::record_stored_messages;
+use crate::tasks::shutdown_token;
+use crate::utils::dedupe::RecentMessages;
+use crate::utils::helpers::{
+    format_duration, is_bot_owner, is_storable, parse_date_snowflake, replied_to_message_id,
+    snowflake_days_ago, EMBED_DESCRIPTION_CHAR_LIMIT,
+};
+use crate::ActiveCollectionsGlobal;
+
+type ActiveCollections = <ActiveCollectionsGlobal as TypeMapKey>::Value;
+
+/// How many authors are listed by name in the final summary.
+const SUMMARY_TOP_AUTHORS: usize = 10;
+
+/// Tries at fetching one page before the collection gives up.
+const MAX_FETCH_ATTEMPTS: u32 = 5;
+
+/// Wait before the first retry, doubled for every one after it.
+const FIRST_RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// Default wait between pages, see `COLLECT_PAGE_DELAY_MS`.
+const DEFAULT_PAGE_DELAY: Duration = Duration::from_secs(2);
+
+/// Archived threads looked up per channel with `include_threads`.
+const MAX_ARCHIVED_THREADS: u64 = 100;
+
+/// `custom_id` of the button that cancels a running collection. It has no
+/// router prefix, the collection's own collector picks it up.
+const CANCEL_BUTTON_ID: &str = "collect_cancel";
+
+/// Which way a collection pages through a channel.
+#[derive(Debug, Clone, Copy)]
+enum Cursor {
+    /// Back through history, from the newest message or before this one.
+    Before(Option<u64>),
+    /// Forward from this message, for what was sent since the backfill.
+    After(u64),
+}
+
+/// Message ids a collection stays between, both ends exclusive.
+#[derive(Debug, Clone, Copy, Default)]
+struct Range {
+    after: Option<u64>,
+    before: Option<u64>,
+}
+
+impl Range {
+    /// Where a channel's collection starts. With a lower end it walks
+    /// forward from there, otherwise back from the upper end.
+    fn cursor(&self) -> Cursor {
+        match self.after {
+            Some(after) => Cursor::After(after),
+            None => Cursor::Before(self.before),
+        }
+    }
+
+    fn describe(&self) -> String {
+        let date = |id: u64| format!("<t:{}:f>", MessageId::new(id).created_at().unix_timestamp());
+
+        match (self.after, self.before) {
+            (None, None) => "Everything".to_string(),
+            (Some(after), None) => format!("After {}", date(after)),
+            (None, Some(before)) => format!("Before {}", date(before)),
+            (Some(after), Some(before)) => format!("{} - {}", date(after), date(before)),
+        }
+    }
+}
+
+/// What a collection run has seen so far, reported once it finishes.
+#[derive(Default)]
+struct CollectionSummary {
+    /// Messages stored per author.
+    authors: HashMap<u64, u64>,
+    stored: u64,
+    /// Messages stored by an earlier run.
+    duplicates: u64,
+    /// Bot messages, opted out authors, repeats and messages without text.
+    skipped: u64,
+    /// Messages the database failed to store.
+    failed: u64,
+    oldest_message_id: Option<u64>,
+    newest_message_id: Option<u64>,
+    /// Time spent collecting, waits between pages included.
+    elapsed: Duration,
+}
+
+impl CollectionSummary {
+    /// Counts a message that was sent to the database, `outcome` being
+    /// `None` when storing it failed.
+    fn record(&mut self, msg: &Message, outcome: Option<InsertOutcome>) {
+        self.track(msg);
+
+        match outcome {
+            Some(InsertOutcome::Inserted) => {
+                self.stored += 1;
+                *self.authors.entry(msg.author.id.get()).or_insert(0) += 1;
+            }
+            Some(InsertOutcome::Duplicate) => self.duplicates += 1,
+            None => self.failed += 1,
+        }
+    }
+
+    /// Counts a message that was left out on purpose.
+    fn record_skipped(&mut self, msg: &Message) {
+        self.track(msg);
+        self.skipped += 1;
+    }
+
+    fn track(&mut self, msg: &Message) {
+        let id = msg.id.get();
+        self.oldest_message_id = Some(self.oldest_message_id.map_or(id, |old| old.min(id)));
+        self.newest_message_id = Some(self.newest_message_id.map_or(id, |new| new.max(id)));
+    }
+
+    /// Every message fetched from Discord, whatever happened to it.
+    fn fetched(&self) -> u64 {
+        self.stored + self.duplicates + self.skipped + self.failed
+    }
+
+    /// Messages fetched per second.
+    fn rate(&self) -> f64 {
+        let seconds = self.elapsed.as_secs_f64();
+        if seconds > 0.0 {
+            self.fetched() as f64 / seconds
+        } else {
+            0.0
+        }
+    }
+
+    /// The counts, as they're shown while the collection runs.
+    fn progress_text(&self, remaining: Option<Duration>) -> String {
+        let mut text = format!(
+            "Fetched **{}** messages: **{}** new, **{}** already stored, **{}** skipped, **{}** failed\n{:.1} messages/s",
+            self.fetched(),
+            self.stored,
+            self.duplicates,
+            self.skipped,
+            self.failed,
+            self.rate()
+        );
+
+        if let Some(oldest) = self.oldest_message_id {
+            text.push_str(&format!(
+                ", back to <t:{}:d>",
+                MessageId::new(oldest).created_at().unix_timestamp()
+            ));
+        }
+        if let Some(remaining) = remaining {
+            text.push_str(&format!(
+                ", about {} left",
+                format_duration(remaining.as_secs() as i64)
+            ));
+        }
+
+        text
+    }
+
+    /// Adds the counts to a completion embed.
+    fn count_fields(&self, embed: CreateEmbed) -> CreateEmbed {
+        embed
+            .field("Fetched", self.fetched().to_string(), true)
+            .field("New", self.stored.to_string(), true)
+            .field("Already stored", self.duplicates.to_string(), true)
+            .field("Skipped", self.skipped.to_string(), true)
+            .field("Failed", self.failed.to_string(), true)
+            .field("Authors", self.authors.len().to_string(), true)
+            .field(
+                "Speed",
+                format!(
+                    "{:.1} messages/s over {}",
+                    self.rate(),
+                    format_duration(self.elapsed.as_secs() as i64)
+                ),
+                false,
+            )
+    }
+
+    /// Adds another channel's counts to these.
+    fn merge(&mut self, other: &CollectionSummary) {
+        for (author_id, count) in &other.authors {
+            *self.authors.entry(*author_id).or_insert(0) += count;
+        }
+        self.stored += other.stored;
+        self.duplicates += other.duplicates;
+        self.skipped += other.skipped;
+        self.failed += other.failed;
+        self.elapsed += other.elapsed;
+    }
+
+    /// Writes the progress so far to the run's `collection_runs` row.
+    async fn save(
+        &self,
+        database: &Database,
+        guild_id: GuildId,
+        run_id: Option<i64>,
+        finished_status: Option<&str>,
+    ) {
+        let Some(run_id) = run_id else {
+            return;
+        };
+
+        if let Err(e) = database
+            .update_collection_run(
+                guild_id.get(),
+                run_id,
+                self.stored,
+                self.skipped + self.duplicates + self.failed,
+                self.oldest_message_id,
+                finished_status,
+            )
+            .await
+        {
+            error!(error = %e, "Failed to update collection run");
+        }
+    }
+
+    fn to_embed(&self, range: &str) -> CreateEmbed {
+        let mut authors: Vec<(&u64, &u64)> = self.authors.iter().collect();
+        authors.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+
+        let mut description = String::new();
+        for (index, (author_id, count)) in authors.iter().take(SUMMARY_TOP_AUTHORS).enumerate() {
+            description.push_str(&format!(
+                "**{}**. <@{}>  -  {} messages\n",
+                index + 1,
+                author_id,
+                count
+            ));
+        }
+
+        if authors.len() > SUMMARY_TOP_AUTHORS {
+            description.push_str(&format!(
+                "+{} more authors\n",
+                authors.len() - SUMMARY_TOP_AUTHORS
+            ));
+        }
+
+        if description.is_empty() {
+            description = "No messages were stored.".to_string();
+        }
+
+        if description.chars().count() > EMBED_DESCRIPTION_CHAR_LIMIT {
+            description = description
+                .chars()
+                .take(EMBED_DESCRIPTION_CHAR_LIMIT)
+                .collect();
+        }
+
+        // Snowflakes carry their creation time, no need to keep timestamps
+        let date_range = match (self.oldest_message_id, self.newest_message_id) {
+            (Some(oldest), Some(newest)) => format!(
+                "<t:{}:d> - <t:{}:d>",
+                MessageId::new(oldest).created_at().unix_timestamp(),
+                MessageId::new(newest).created_at().unix_timestamp()
+            ),
+            _ => "-".to_string(),
+        };
+
+        let embed = CreateEmbed::new()
+            .title("Collection Complete!")
+            .description(description);
+
+        self.count_fields(embed)
+            .field("Range", range, false)
+            .field("Date range", date_range, false)
+            .color(0x5865F2)
+    }
+}
 
 pub async fn execute(
     ctx: &Context,
     command: &CommandInteraction,
     database: Arc<Database>,
 ) -> Result<(), Error> {
-    command.defer(&ctx.http).await?;
-
     let guild_id = match command.guild_id {
         Some(s) => s,
         _ => return Ok(()),
     };
 
-    let mut before_message_id = command
+    let can_manage_guild = command
+        .member
+        .as_ref()
+        .and_then(|member| member.permissions)
+        .is_some_and(|permissions| permissions.manage_guild());
+
+    if !can_manage_guild && !is_bot_owner(ctx, command.user.id).await {
+        return reply(
+            ctx,
+            command,
+            "You need the Manage Server permission to use this command.",
+        )
+        .await;
+    }
+
+    let active = ctx
         .data
-        .options
+        .read()
+        .await
+        .get::<ActiveCollectionsGlobal>()
+        .cloned()
+        .unwrap_or_default();
+
+    let (cancel_sender, cancel_receiver) = watch::channel(None);
+
+    let settings = match database.get_guild_settings(guild_id.get()).await {
+        Ok(settings) => settings,
+        Err(e) => {
+            error!(error = %e, "Failed to fetch guild settings");
+            GuildSettings::default()
+        }
+    };
+
+    let page_delay = env::var("COLLECT_PAGE_DELAY_MS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .map_or(DEFAULT_PAGE_DELAY, Duration::from_millis);
+
+    let options = &command.data.options;
+    let flag = |name: &str| {
+        options
+            .iter()
+            .find(|opt| opt.name == name)
+            .and_then(|opt| opt.value.as_bool())
+            .unwrap_or(false)
+    };
+
+    let message_id = |name: &str| {
+        options
+            .iter()
+            .find(|opt| opt.name == name)
+            .and_then(|opt| opt.value.as_i64())
+            .and_then(|n| u64::try_from(n).ok())
+            .filter(|&n| n > 0)
+    };
+
+    let mut range = Range {
+        after: message_id("after"),
+        before: message_id("before"),
+    };
+
+    for name in ["since", "until"] {
+        let Some(input) = options
+            .iter()
+            .find(|opt| opt.name == name)
+            .and_then(|opt| opt.value.as_str())
+        else {
+            continue;
+        };
+
+        let Some(id) = parse_date_snowflake(input) else {
+            return reply(
+                ctx,
+                command,
+                format!(
+                    "I couldn't read `{}`. Use a day like `2024-05-01`, or a number of days ago like `30d`.",
+                    name
+                ),
+            )
+            .await;
+        };
+
+        // The later of the lower ends and the earlier of the upper ends
+        if name == "since" {
+            range.after = Some(range.after.map_or(id, |after| after.max(id)));
+        } else {
+            range.before = Some(range.before.map_or(id, |before| before.min(id)));
+        }
+    }
+
+    // Nothing predates Discord's epoch, snowflake 0 bounds nothing
+    range.after = range.after.filter(|&after| after > 0);
+
+    let target = options
         .iter()
-        .find(|opt| opt.name == "before")
-        .and_then(|opt| opt.value.as_i64())
-        .and_then(|n| n.try_into().ok());
-
-    let channel_id = command.channel_id;
-    let limit = 100;
-    let mut loop_count = 0;
-    let mut total_messages_collected = 0;
-
-    println!(
-        "Starting message collection for channel {} in guild {}",
-        channel_id, guild_id
-    );
-
-    if let Err(e) = command
-        .edit_response(
+        .find(|opt| opt.name == "channel")
+        .and_then(|opt| opt.value.as_channel_id());
+
+    let problem = match range {
+        Range {
+            before: Some(0), ..
+        } => Some("There are no messages before that date."),
+        Range {
+            after: Some(after),
+            before: Some(before),
+        } if after >= before => Some("`before`/`until` has to be later than `after`/`since`."),
+        _ if flag("resume") && (range.after.is_some() || range.before.is_some()) => {
+            Some("`resume` continues from saved progress, it can't be combined with a range.")
+        }
+        _ if flag("all_channels") && target.is_some() => {
+            Some("Pick either `channel` or `all_channels`.")
+        }
+        _ => None,
+    };
+    if let Some(problem) = problem {
+        return reply(ctx, command, problem).await;
+    }
+
+    let collector = Collector {
+        ctx: ctx.clone(),
+        command: command.clone(),
+        database,
+        guild_id,
+        settings,
+        page_delay,
+        resume: flag("resume"),
+        range,
+        active,
+        cancelled: cancel_receiver,
+        shutdown: shutdown_token(ctx).await,
+    };
+
+    // A collection can run for many minutes, don't hold up the handler
+    if flag("all_channels") {
+        command.defer(&ctx.http).await?;
+
+        let watcher = tokio::spawn(watch_for_cancel(
+            ctx.clone(),
+            command.clone(),
+            cancel_sender,
+        ));
+        let include_threads = flag("include_threads");
+        tokio::spawn(async move {
+            collector.all_channels(include_threads).await;
+            watcher.abort();
+        });
+    } else {
+        let channel_id = target.unwrap_or(command.channel_id);
+        let Some(claim) = collector.claim(channel_id) else {
+            return reply(ctx, command, "This channel is already being collected.").await;
+        };
+
+        command.defer(&ctx.http).await?;
+
+        let watcher = tokio::spawn(watch_for_cancel(
+            ctx.clone(),
+            command.clone(),
+            cancel_sender,
+        ));
+        tokio::spawn(async move {
+            collector.single_channel(channel_id).await;
+            watcher.abort();
+            drop(claim);
+        });
+    }
+
+    Ok(())
+}
+
+/// Waits for the cancel button on the command's response, and tells the
+/// collection who pressed it.
+async fn watch_for_cancel(
+    ctx: Context,
+    command: CommandInteraction,
+    cancel: watch::Sender<Option<UserId>>,
+) {
+    let message = match command.get_response(&ctx.http).await {
+        Ok(message) => message,
+        Err(e) => {
+            error!(error = %e, "Failed to fetch collection message");
+            return;
+        }
+    };
+
+    let mut interactions = message
+        .await_component_interaction(&ctx.shard)
+        .custom_ids(vec![CANCEL_BUTTON_ID.to_string()])
+        .stream();
+
+    while let Some(interaction) = interactions.next().await {
+        let can_manage_guild = interaction
+            .member
+            .as_ref()
+            .and_then(|member| member.permissions)
+            .is_some_and(|permissions| permissions.manage_guild());
+
+        if interaction.user.id != command.user.id && !can_manage_guild {
+            let response = CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .content("Only whoever started this collection, or someone with the Manage Server permission, can cancel it.")
+                    .ephemeral(true),
+            );
+            if let Err(e) = interaction.create_response(&ctx.http, response).await {
+                error!(error = %e, "Failed to respond to cancel button");
+            }
+            continue;
+        }
+
+        // The collection edits the message once it has stopped
+        if let Err(e) = interaction
+            .create_response(&ctx.http, CreateInteractionResponse::Acknowledge)
+            .await
+        {
+            error!(error = %e, "Failed to respond to cancel button");
+        }
+
+        info!(
+            channel_id = command.channel_id.get(),
+            user_id = interaction.user.id.get(),
+            "Collection cancelled"
+        );
+        let _ = cancel.send(Some(interaction.user.id));
+        return;
+    }
+}
+
+fn cancel_button(disabled: bool) -> CreateButton {
+    CreateButton::new(CANCEL_BUTTON_ID)
+        .label("Cancel collection")
+        .style(ButtonStyle::Danger)
+        .disabled(disabled)
+}
+
+async fn reply(
+    ctx: &Context,
+    command: &CommandInteraction,
+    content: impl Into<String>,
+) -> Result<(), Error> {
+    command
+        .create_response(
             &ctx.http,
-            EditInteractionResponse::new().content(format!(
-                "Starting message collection for channel {} in guild {}",
-                channel_id, guild_id
-            )),
+            CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .content(content)
+                    .ephemeral(true),
+            ),
         )
         .await
-    {
-        eprintln!("Failed to update Discord progress: {}", e);
+}
+
+/// Marks a channel as being collected until it's dropped, however the
+/// collection ends.
+struct ChannelClaim {
+    active: ActiveCollections,
+    key: (u64, u64),
+}
+
+impl Drop for ChannelClaim {
+    fn drop(&mut self) {
+        self.active
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .remove(&self.key);
+    }
+}
+
+/// How collecting one channel went.
+#[derive(Clone, Copy, PartialEq)]
+enum ChannelResult {
+    Completed,
+    /// Gave up after Discord kept failing.
+    Stopped,
+    /// Someone pressed the cancel button.
+    Cancelled,
+    /// Skipped, another collection had it.
+    Busy,
+}
+
+/// One `/collect`, shared by every channel it goes through.
+struct Collector {
+    ctx: Context,
+    command: CommandInteraction,
+    database: Arc<Database>,
+    guild_id: GuildId,
+    settings: GuildSettings,
+    /// Wait between pages, to stay clear of rate limits.
+    page_delay: Duration,
+    /// Continue from each channel's saved progress.
+    resume: bool,
+    range: Range,
+    active: ActiveCollections,
+    /// Who cancelled the collection, once someone has.
+    cancelled: watch::Receiver<Option<UserId>>,
+    /// Stops the collection when the bot shuts down, like a cancel.
+    shutdown: CancellationToken,
+}
+
+impl Collector {
+    /// Claims the channel for this collection, `None` when another one is
+    /// already collecting it.
+    fn claim(&self, channel_id: ChannelId) -> Option<ChannelClaim> {
+        let key = (self.guild_id.get(), channel_id.get());
+        let claimed = self
+            .active
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .insert(key);
+
+        claimed.then(|| ChannelClaim {
+            active: self.active.clone(),
+            key,
+        })
     }
 
-    loop {
-        loop_count += 1;
-        println!(
-            "Loop {}: Fetching messages before ID: {:#?}",
-            loop_count, before_message_id
-        );
+    /// Collects one channel, then posts the summary where the command was
+    /// used.
+    async fn single_channel(&self, channel_id: ChannelId) {
+        let cursor = self.starting_cursor(channel_id).await;
+        let (summary, result) = self.channel(channel_id, cursor, None).await;
 
-        let pagination = before_message_id.map(|id| MessagePagination::Before(MessageId::new(id)));
+        match result {
+            ChannelResult::Completed => {
+                self.finish(format!(
+                    "Collection complete! {} new messages were stored.",
+                    summary.stored
+                ))
+                .await;
 
-        match ctx
-            .http
-            .get_messages(channel_id, pagination, Some(limit))
+                if let Err(e) = self
+                    .command
+                    .channel_id
+                    .send_message(
+                        &self.ctx.http,
+                        CreateMessage::new().embed(summary.to_embed(&self.describe_range())),
+                    )
+                    .await
+                {
+                    error!(error = %e, "Failed to send completion message");
+                }
+            }
+            ChannelResult::Cancelled => self.finish_cancelled(&summary).await,
+            _ => {
+                self.finish(format!(
+                    "Collection stopped, Discord kept failing to return messages. {} new messages were stored. Use `/collect resume:True` to continue.",
+                    summary.stored
+                ))
+                .await
+            }
+        }
+    }
+
+    /// Collects every channel the bot can read history in, one after
+    /// another, then posts per-channel totals.
+    async fn all_channels(&self, include_threads: bool) {
+        let channels = match self.readable_channels(include_threads).await {
+            Ok(channels) => channels,
+            Err(e) => {
+                error!(error = %e, "Failed to list channels");
+                self.finish("An error occurred while listing the server's channels.")
+                    .await;
+                return;
+            }
+        };
+
+        let mut totals = Vec::new();
+        let mut grand_total = CollectionSummary::default();
+
+        for (index, channel) in channels.iter().enumerate() {
+            let label = format!(
+                "channel {}/{}: #{}",
+                index + 1,
+                channels.len(),
+                channel.name
+            );
+            let Some(_claim) = self.claim(channel.id) else {
+                info!(
+                    channel_id = channel.id.get(),
+                    "Skipping channel, it's already being collected"
+                );
+                totals.push((channel.id, 0, ChannelResult::Busy));
+                continue;
+            };
+
+            let cursor = self.starting_cursor(channel.id).await;
+            let (summary, result) = self.channel(channel.id, cursor, Some(&label)).await;
+
+            grand_total.merge(&summary);
+            totals.push((channel.id, summary.stored, result));
+
+            if result == ChannelResult::Cancelled {
+                self.finish_cancelled(&grand_total).await;
+                return;
+            }
+        }
+
+        self.finish(format!(
+            "Collection complete! {} new messages were stored across {} channels.",
+            grand_total.stored,
+            totals.len()
+        ))
+        .await;
+
+        let mut description = String::new();
+        for (channel_id, stored, result) in &totals {
+            let status = match result {
+                ChannelResult::Completed => "",
+                ChannelResult::Stopped => " (stopped early)",
+                ChannelResult::Cancelled => " (cancelled)",
+                ChannelResult::Busy => " (already being collected)",
+            };
+            let entry = format!("<#{}>  -  {} new{}\n", channel_id, stored, status);
+
+            if description.chars().count() + entry.chars().count() > EMBED_DESCRIPTION_CHAR_LIMIT {
+                description.push_str("...");
+                break;
+            }
+            description.push_str(&entry);
+        }
+
+        if description.is_empty() {
+            description = "There were no channels I could read.".to_string();
+        }
+
+        let embed = CreateEmbed::new()
+            .title("Server Collection Complete!")
+            .description(description)
+            .field("Channels", totals.len().to_string(), true);
+        let embed = grand_total
+            .count_fields(embed)
+            .field("Range", self.describe_range(), false)
+            .color(0x5865F2);
+
+        if let Err(e) = self
+            .command
+            .channel_id
+            .send_message(&self.ctx.http, CreateMessage::new().embed(embed))
             .await
         {
-            Ok(messages) => {
-                println!("Fetched {} messages", messages.len());
+            error!(error = %e, "Failed to send completion message");
+        }
+    }
 
-                for msg in &messages {
-                    if msg.author.bot {
-                        continue;
-                    }
+    /// Text channels where the bot can read history, and optionally their
+    /// threads. Only the most recent archived threads are included.
+    async fn readable_channels(&self, include_threads: bool) -> Result<Vec<GuildChannel>, Error> {
+        let http = &self.ctx.http;
+        let bot_id = self.ctx.cache.current_user().id;
+        let member = self.guild_id.member(&self.ctx, bot_id).await?;
 
-                    let _ = database
-                        .insert_message(
-                            msg.id.get(),
-                            msg.author.id.get(),
-                            msg.channel_id.get(),
-                            guild_id.get(),
-                            &msg.content,
-                        )
-                        .await;
+        let mut channels: Vec<GuildChannel> = http
+            .get_channels(self.guild_id)
+            .await?
+            .into_iter()
+            .filter(|channel| matches!(channel.kind, ChannelType::Text | ChannelType::News))
+            .collect();
+
+        // Without a cached guild, unreadable channels fail and get skipped
+        if let Some(guild) = self.ctx.cache.guild(self.guild_id) {
+            channels.retain(|channel| {
+                let permissions = guild.user_permissions_in(channel, &member);
+                permissions.view_channel() && permissions.read_message_history()
+            });
+        }
+        channels.sort_by_key(|channel| channel.position);
+
+        if !include_threads {
+            return Ok(channels);
+        }
+
+        let mut threads = http.get_guild_active_threads(self.guild_id).await?.threads;
+        for channel in &channels {
+            match channel
+                .id
+                .get_archived_public_threads(http, None, Some(MAX_ARCHIVED_THREADS))
+                .await
+            {
+                Ok(archived) => threads.extend(archived.threads),
+                Err(e) => {
+                    warn!(channel_id = channel.id.get(), error = %e, "Failed to list threads")
                 }
+            }
+        }
 
-                total_messages_collected += messages.len();
-                println!(
-                    "Inserted {} messages into database. Total collected: {}",
-                    messages.len(),
-                    total_messages_collected
-                );
+        let parents: Vec<ChannelId> = channels.iter().map(|channel| channel.id).collect();
+        threads.retain(|thread| thread.parent_id.is_some_and(|id| parents.contains(&id)));
+        channels.extend(threads);
+
+        Ok(channels)
+    }
+
+    /// Where to start in a channel. Resuming continues an unfinished
+    /// backfill, or collects what's new once the backfill is done.
+    async fn starting_cursor(&self, channel_id: ChannelId) -> Cursor {
+        if !self.resume {
+            return self.range.cursor();
+        }
+
+        let progress = match self
+            .database
+            .get_collect_progress(self.guild_id.get(), channel_id.get())
+            .await
+        {
+            Ok(progress) => progress,
+            Err(e) => {
+                error!(error = %e, "Failed to fetch collect progress");
+                None
+            }
+        };
 
-                if loop_count % 5 == 0 {
-                    let progress_message = format!(
-                        "**Collection Progress**\n\
-                        Total messages collected: {}",
+        match progress {
+            Some(progress) if progress.status == "completed" => match progress.newest_message_id {
+                Some(newest) => Cursor::After(newest),
+                None => Cursor::Before(None),
+            },
+            Some(progress) => Cursor::Before(progress.last_message_id),
+            None => Cursor::Before(None),
+        }
+    }
+
+    fn describe_range(&self) -> String {
+        if self.resume {
+            return "Continued from saved progress".to_string();
+        }
+
+        self.range.describe()
+    }
+
+    /// Pages through a channel from `cursor`, storing every page and saving
+    /// where it got to, and edits the interaction's response with the
+    /// progress. Returns what was seen, and how it ended.
+    ///
+    /// A cancel only takes effect between pages, a page that's being stored
+    /// always finishes storing and saving its cursor first.
+    async fn channel(
+        &self,
+        channel_id: ChannelId,
+        mut cursor: Cursor,
+        label: Option<&str>,
+    ) -> (CollectionSummary, ChannelResult) {
+        let (ctx, database, guild_id) = (&self.ctx, &self.database, self.guild_id);
+        let limit = 100;
+        let mut loop_count = 0;
+        let started = Instant::now();
+        let start_cursor = cursor;
+        let mut summary = CollectionSummary::default();
+        let mut recent_messages = RecentMessages::default();
+
+        info!(
+            guild_id = guild_id.get(),
+            channel_id = channel_id.get(),
+            "Starting message collection"
+        );
+
+        let run_id = match database
+            .start_collection_run(guild_id.get(), channel_id.get())
+            .await
+        {
+            Ok(run_id) => Some(run_id),
+            Err(e) => {
+                error!(error = %e, "Failed to record collection run");
+                None
+            }
+        };
+
+        let heading = match label {
+            Some(label) => format!("**Collecting {}**", label),
+            None => "**Collection Progress**".to_string(),
+        };
+
+        self.progress(format!(
+            "{}\nStarting message collection for channel {} in guild {}",
+            heading, channel_id, guild_id
+        ))
+        .await;
+
+        loop {
+            loop_count += 1;
+            debug!(channel_id = channel_id.get(), loop_count, cursor = ?cursor, "Fetching messages");
+
+            let fetched = tokio::select! {
+                biased;
+                _ = self.wait_for_cancel() => None,
+                fetched = fetch_page(ctx, channel_id, cursor, limit) => Some(fetched),
+            };
+            summary.elapsed = started.elapsed();
+
+            let mut messages = match fetched {
+                None => {
+                    self.save_cancelled(channel_id, cursor, run_id, &summary)
+                        .await;
+                    return (summary, ChannelResult::Cancelled);
+                }
+                Some(Ok(messages)) => messages,
+                Some(Err(e)) => {
+                    error!(
+                        channel_id = channel_id.get(),
                         loop_count,
+                        error = %e,
+                        "Giving up on collection"
                     );
-
-                    if let Err(e) = command
-                        .edit_response(
-                            &ctx.http,
-                            EditInteractionResponse::new().content(progress_message),
-                        )
-                        .await
-                    {
-                        eprintln!("Failed to update Discord progress: {}", e);
+                    summary
+                        .save(database, guild_id, run_id, Some("failed"))
+                        .await;
+                    // Collecting new messages doesn't undo a finished backfill
+                    if let Cursor::Before(_) = cursor {
+                        self.save_progress(channel_id, None, None, Some("failed"))
+                            .await;
                     }
+                    return (summary, ChannelResult::Stopped);
                 }
+            };
 
-                before_message_id = Some(messages[messages.len() - 1].id.get());
+            debug!(
+                channel_id = channel_id.get(),
+                fetched = messages.len(),
+                "Fetched messages"
+            );
 
-                if messages.len() < limit as usize {
-                    println!("Reached end of messages. Collection complete!");
+            // Walking forward, the range ends partway through a page. The
+            // page comes back short then, which ends the collection
+            if let (Cursor::After(_), Some(before)) = (cursor, self.range.before) {
+                messages.retain(|msg| msg.id.get() < before);
+            }
 
-                    let final_message = format!(
-                        "**Collection Complete!**\n\
-                        Total messages collected: {}",
-                        total_messages_collected
-                    );
+            let mut page = Vec::new();
+            for msg in &messages {
+                if !is_storable(msg, &self.settings.command_prefixes)
+                    || database.is_opted_out(guild_id.get(), msg.author.id.get())
+                {
+                    summary.record_skipped(msg);
+                    continue;
+                }
 
-                    if let Err(e) = command
-                        .channel_id
-                        .send_message(&ctx.http, CreateMessage::new().content(final_message))
-                        .await
-                    {
-                        eprintln!("Failed to send completion message: {}", e);
-                    }
+                // Pages run newest to oldest, but a run of repeats is a
+                // run of repeats either way
+                if recent_messages.is_repeat(
+                    msg.channel_id.get(),
+                    msg.author.id.get(),
+                    &msg.content,
+                ) && self.settings.dedupe_consecutive
+                {
+                    summary.record_skipped(msg);
+                    continue;
+                }
+
+                page.push(msg);
+            }
+
+            let records: Vec<MessageRecord> = page
+                .iter()
+                .map(|msg| MessageRecord {
+                    message_id: msg.id.get(),
+                    author_id: msg.author.id.get(),
+                    channel_id: msg.channel_id.get(),
+                    content: &msg.content,
+                    replied_to_message_id: replied_to_message_id(msg),
+                })
+                .collect();
 
-                    break;
+            // One transaction per page instead of one per statement
+            let stored = match database
+                .insert_messages_batch(guild_id.get(), &records)
+                .await
+            {
+                Ok(stored) => stored,
+                Err(e) => {
+                    error!(error = %e, "Failed to store messages");
+                    vec![None; records.len()]
                 }
+            };
+
+            let stored_count = stored
+                .iter()
+                .filter(|outcome| **outcome == Some(InsertOutcome::Inserted))
+                .count() as u64;
+            if stored_count > 0 {
+                record_stored_messages(ctx, stored_count).await;
+            }
+            for (msg, stored) in page.into_iter().zip(stored) {
+                summary.record(msg, stored);
             }
-            Err(err) => loop {
-                let mut tries = 0;
-                tries += 1;
 
-                if tries > 5 {
-                    panic!(
-                        "Error fetching messages (loop {}, attempt {}): {}. Panicking!!",
-                        loop_count, tries, err
+            let page_ids = messages.iter().map(|msg| msg.id.get());
+            match cursor {
+                Cursor::Before(_) => {
+                    self.save_progress(channel_id, page_ids.clone().min(), page_ids.max(), None)
+                        .await
+                }
+                Cursor::After(_) => {
+                    self.save_progress(channel_id, None, page_ids.max(), None)
+                        .await
+                }
+            }
+
+            debug!(
+                channel_id = channel_id.get(),
+                stored = messages.len(),
+                total = summary.fetched(),
+                "Stored page"
+            );
+
+            if loop_count % 5 == 0 {
+                summary.save(database, guild_id, run_id, None).await;
+
+                let remaining = self.estimate_remaining(channel_id, start_cursor, &summary);
+                self.progress(format!("{}\n{}", heading, summary.progress_text(remaining)))
+                    .await;
+            }
+
+            match next_page_cursor(cursor, &messages, limit) {
+                Some(next) => cursor = next,
+                None => {
+                    info!(
+                        channel_id = channel_id.get(),
+                        total = summary.fetched(),
+                        "Collection complete"
                     );
+                    summary
+                        .save(database, guild_id, run_id, Some("completed"))
+                        .await;
+                    if let Cursor::Before(_) = cursor {
+                        self.save_progress(channel_id, None, None, Some("completed"))
+                            .await;
+                    }
+                    return (summary, ChannelResult::Completed);
                 }
+            }
+
+            // sleep between cycles
+            debug!(
+                channel_id = channel_id.get(),
+                loop_count,
+                delay_ms = self.page_delay.as_millis() as u64,
+                "Waiting before the next page"
+            );
+            tokio::select! {
+                biased;
+                _ = self.wait_for_cancel() => {
+                    self.save_cancelled(channel_id, cursor, run_id, &summary)
+                        .await;
+                    return (summary, ChannelResult::Cancelled);
+                }
+                _ = tokio::time::sleep(self.page_delay) => (),
+            }
+        }
+    }
+
+    /// Guesses how long a channel has left, from how much of its history
+    /// the time so far got through. Going back, the history ends at the
+    /// range's lower end or the channel's creation, going forward at the
+    /// range's upper end or now.
+    fn estimate_remaining(
+        &self,
+        channel_id: ChannelId,
+        start: Cursor,
+        summary: &CollectionSummary,
+    ) -> Option<Duration> {
+        let (from, reached, end) = match start {
+            Cursor::Before(_) => (
+                summary.newest_message_id?,
+                summary.oldest_message_id?,
+                self.range.after.unwrap_or(channel_id.get()),
+            ),
+            Cursor::After(after) => (
+                after,
+                summary.newest_message_id?,
+                self.range.before.unwrap_or_else(|| snowflake_days_ago(0)),
+            ),
+        };
+
+        // Snowflakes start with their creation time in milliseconds
+        let covered = (from >> 22).abs_diff(reached >> 22);
+        if covered == 0 {
+            return None;
+        }
+        let remaining = (reached >> 22).abs_diff(end >> 22);
+
+        Some(summary.elapsed.mul_f64(remaining as f64 / covered as f64))
+    }
+
+    /// Resolves once someone has pressed the cancel button or the bot is
+    /// shutting down. A gone button watcher only leaves the shutdown.
+    async fn wait_for_cancel(&self) {
+        let mut cancelled = self.cancelled.clone();
+        tokio::select! {
+            _ = self.shutdown.cancelled() => (),
+            Ok(()) = async { cancelled.wait_for(Option::is_some).await.map(|_| ()) } => (),
+            else => self.shutdown.cancelled().await,
+        }
+    }
+
+    /// Marks a cancelled channel's run and progress, the cursor of the last
+    /// stored page is already saved so a resume picks up from there.
+    async fn save_cancelled(
+        &self,
+        channel_id: ChannelId,
+        cursor: Cursor,
+        run_id: Option<i64>,
+        summary: &CollectionSummary,
+    ) {
+        summary
+            .save(&self.database, self.guild_id, run_id, Some("cancelled"))
+            .await;
+        if let Cursor::Before(_) = cursor {
+            self.save_progress(channel_id, None, None, Some("cancelled"))
+                .await;
+        }
+    }
+
+    async fn save_progress(
+        &self,
+        channel_id: ChannelId,
+        last_message_id: Option<u64>,
+        newest_message_id: Option<u64>,
+        status: Option<&str>,
+    ) {
+        if let Err(e) = self
+            .database
+            .save_collect_progress(
+                self.guild_id.get(),
+                channel_id.get(),
+                last_message_id,
+                newest_message_id,
+                status,
+            )
+            .await
+        {
+            error!(error = %e, "Failed to save collect progress");
+        }
+    }
+
+    /// Replaces the interaction's response with `content`, keeping the
+    /// cancel button under it.
+    async fn progress(&self, content: impl Into<String>) {
+        self.edit_response(content, false).await;
+    }
+
+    /// Replaces the interaction's response with `content`, and disables the
+    /// cancel button for good.
+    async fn finish(&self, content: impl Into<String>) {
+        self.edit_response(content, true).await;
+    }
+
+    async fn finish_cancelled(&self, summary: &CollectionSummary) {
+        let seen = summary.fetched();
+        let stopped = match *self.cancelled.borrow() {
+            Some(user_id) => format!("Cancelled by <@{}>", user_id),
+            None if self.shutdown.is_cancelled() => "Paused for a bot restart".to_string(),
+            None => "Cancelled".to_string(),
+        };
 
-                let retry_second = tries * 2;
-                eprintln!(
-                    "Error fetching messages (loop {}, attempt {}): {}. Retrying in {} seconds...",
-                    loop_count, tries, err, retry_second
+        self.finish(format!(
+            "{} after {} messages, {} of them new. Use `/collect resume:True` to continue.",
+            stopped, seen, summary.stored
+        ))
+        .await;
+    }
+
+    async fn edit_response(&self, content: impl Into<String>, finished: bool) {
+        let builder = EditInteractionResponse::new()
+            .content(content)
+            .button(cancel_button(finished))
+            .allowed_mentions(CreateAllowedMentions::new());
+
+        if let Err(e) = self.command.edit_response(&self.ctx.http, builder).await {
+            error!(error = %e, "Failed to update Discord progress");
+        }
+    }
+}
+
+/// Fetches up to `limit` messages from `cursor`, retrying with exponential
+/// backoff. The last error is returned once every attempt failed.
+async fn fetch_page(
+    ctx: &Context,
+    channel_id: ChannelId,
+    cursor: Cursor,
+    limit: u8,
+) -> Result<Vec<Message>, Error> {
+    let pagination = match cursor {
+        Cursor::Before(before) => before.map(|id| MessagePagination::Before(MessageId::new(id))),
+        Cursor::After(after) => Some(MessagePagination::After(MessageId::new(after))),
+    };
+
+    let mut attempt = 1;
+    loop {
+        match ctx
+            .http
+            .get_messages(channel_id, pagination, Some(limit))
+            .await
+        {
+            Ok(messages) => return Ok(messages),
+            Err(e) if attempt < MAX_FETCH_ATTEMPTS => {
+                let retry_in = FIRST_RETRY_DELAY * 2u32.pow(attempt - 1);
+                warn!(
+                    channel_id = channel_id.get(),
+                    attempt,
+                    retry_in_secs = retry_in.as_secs(),
+                    error = %e,
+                    "Failed to fetch messages, retrying"
                 );
 
-                thread::sleep(time::Duration::from_secs(retry_second));
-            },
+                tokio::time::sleep(retry_in).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
         }
+    }
+}
 
-        // sleep between cycles
-        println!(
-            "Loop {} complete. Sleeping for 2 seconds before next batch...",
-            loop_count
-        );
-        thread::sleep(time::Duration::from_secs(2));
+/// Where the page after `page` starts, `None` when `page` came back short
+/// and was the last one.
+fn next_page_cursor(cursor: Cursor, page: &[Message], limit: u8) -> Option<Cursor> {
+    if page.len() < limit as usize {
+        return None;
     }
 
-    Ok(())
+    let ids = page.iter().map(|msg| msg.id.get());
+    match cursor {
+        Cursor::Before(_) => ids.min().map(|id| Cursor::Before(Some(id))),
+        Cursor::After(_) => ids.max().map(Cursor::After),
+    }
 }
 
 pub fn register() -> CreateCommand {
     CreateCommand::new("collect")
         .description("Collects and records previous messages.")
+        .default_member_permissions(Permissions::MANAGE_GUILD)
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::Channel,
+                "channel",
+                "The channel to collect, instead of this one.",
+            )
+            .channel_types(vec![ChannelType::Text, ChannelType::News]),
+        )
         .add_option(CreateCommandOption::new(
             CommandOptionType::Integer,
             "before",
             "The ID of the message the bot will check before.",
         ))
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::Integer,
+            "after",
+            "The ID of the message the bot will collect after.",
+        ))
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::String,
+            "since",
+            "Only collect messages from this date on, like 2024-05-01 or 30d.",
+        ))
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::String,
+            "until",
+            "Only collect messages before this date, like 2024-06-01 or 7d.",
+        ))
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::Boolean,
+            "all_channels",
+            "Collect every channel of the server instead of this one.",
+        ))
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::Boolean,
+            "include_threads",
+            "With all_channels, also collect threads.",
+        ))
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::Boolean,
+            "resume",
+            "Continue where the last collection stopped, or collect what's new since.",
+        ))
 }
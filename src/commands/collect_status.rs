@@ -0,0 +1,185 @@
+use std::sync::Arc;
+
+use serenity::all::{
+    CommandDataOption, CommandDataOptionValue, CommandInteraction, CommandOptionType,
+    CreateCommand, CreateCommandOption, CreateEmbed, EditInteractionResponse, MessageId,
+};
+use serenity::prelude::*;
+use serenity::Error;
+use tracing::error;
+
+use crate::database::{CollectProgress, CollectionRun, Database};
+use crate::utils::helpers::format_duration;
+
+/// How many runs `/collect-status history` lists.
+const HISTORY_LIMIT: i64 = 10;
+
+const MAX_DESCRIPTION_LENGTH: usize = 4000;
+
+pub async fn execute(
+    ctx: &Context,
+    command: &CommandInteraction,
+    database: Arc<Database>,
+) -> Result<(), Error> {
+    command.defer(&ctx.http).await?;
+
+    let guild_id = match command.guild_id {
+        Some(s) => s,
+        _ => return Ok(()),
+    };
+
+    let subcommand = match command.data.options.first() {
+        Some(CommandDataOption {
+            name,
+            value: CommandDataOptionValue::SubCommand(_),
+            ..
+        }) => name.as_str(),
+        _ => return Ok(()),
+    };
+
+    match subcommand {
+        "history" => (),
+        "progress" => return progress(ctx, command, guild_id.get(), database).await,
+        _ => return Ok(()),
+    }
+
+    let runs = match database
+        .get_collection_runs(guild_id.get(), HISTORY_LIMIT)
+        .await
+    {
+        Ok(runs) => runs,
+        Err(e) => {
+            error!(error = %e, "Failed to fetch collection runs");
+            command
+                .edit_response(
+                    &ctx.http,
+                    EditInteractionResponse::new()
+                        .content("An error occurred while fetching the collection history."),
+                )
+                .await?;
+
+            return Ok(());
+        }
+    };
+
+    let mut description = runs.iter().map(format_run).collect::<Vec<_>>().join("\n");
+
+    if description.is_empty() {
+        description = "No collections have been run in this server yet.".to_string();
+    }
+
+    let embed = CreateEmbed::new()
+        .title("Collection History")
+        .description(description)
+        .color(0x5865F2);
+
+    command
+        .edit_response(&ctx.http, EditInteractionResponse::new().embed(embed))
+        .await?;
+    Ok(())
+}
+
+/// Lists where `/collect` got to in every channel it ran in.
+async fn progress(
+    ctx: &Context,
+    command: &CommandInteraction,
+    guild_id: u64,
+    database: Arc<Database>,
+) -> Result<(), Error> {
+    let progress = match database.get_guild_collect_progress(guild_id).await {
+        Ok(progress) => progress,
+        Err(e) => {
+            error!(error = %e, "Failed to fetch collect progress");
+            command
+                .edit_response(
+                    &ctx.http,
+                    EditInteractionResponse::new()
+                        .content("An error occurred while fetching the collection progress."),
+                )
+                .await?;
+
+            return Ok(());
+        }
+    };
+
+    let mut description = String::new();
+    for line in progress.iter().map(format_progress) {
+        if description.len() + line.len() > MAX_DESCRIPTION_LENGTH {
+            description.push_str("...");
+            break;
+        }
+        description.push_str(&line);
+        description.push('\n');
+    }
+
+    if description.is_empty() {
+        description = "No collections have been run in this server yet.".to_string();
+    }
+
+    let embed = CreateEmbed::new()
+        .title("Collection Progress")
+        .description(description.trim_end())
+        .color(0x5865F2);
+
+    command
+        .edit_response(&ctx.http, EditInteractionResponse::new().embed(embed))
+        .await?;
+    Ok(())
+}
+
+fn format_progress(progress: &CollectProgress) -> String {
+    let reached = match (progress.status.as_str(), progress.last_message_id) {
+        ("completed", _) => "history complete".to_string(),
+        (_, Some(last)) => format!(
+            "reached <t:{}:d>",
+            MessageId::new(last).created_at().unix_timestamp()
+        ),
+        (_, None) => "nothing collected yet".to_string(),
+    };
+
+    format!(
+        "<#{}>  -  **{}**, {}, updated <t:{}:R>",
+        progress.channel_id, progress.status, reached, progress.updated_at
+    )
+}
+
+fn format_run(run: &CollectionRun) -> String {
+    let duration = match run.finished_at {
+        Some(finished_at) => format_duration(finished_at - run.started_at),
+        None => "still running".to_string(),
+    };
+
+    let reached = match run.oldest_message_id {
+        Some(oldest) => format!(
+            ", back to <t:{}:d>",
+            MessageId::new(oldest).created_at().unix_timestamp()
+        ),
+        None => String::new(),
+    };
+
+    format!(
+        "<#{}> <t:{}:f>  -  **{}**, {}\n{} stored, {} skipped{}",
+        run.channel_id,
+        run.started_at,
+        run.status,
+        duration,
+        run.messages_stored,
+        run.messages_skipped,
+        reached
+    )
+}
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new("collect-status")
+        .description("See how message collection went.")
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::SubCommand,
+            "history",
+            "List the last collection runs in this server.",
+        ))
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::SubCommand,
+            "progress",
+            "Show where collection got to in each channel.",
+        ))
+}
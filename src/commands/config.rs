@@ -0,0 +1,585 @@
+use std::sync::Arc;
+
+use serenity::all::{
+    ChannelType, CommandDataOption, CommandDataOptionValue, CommandInteraction, CommandOptionType,
+    CreateCommand, CreateCommandOption, CreateInteractionResponse,
+    CreateInteractionResponseMessage, Permissions,
+};
+use serenity::prelude::*;
+use serenity::Error;
+use tracing::error;
+
+use crate::database::{Database, GuildSettings};
+use crate::utils::escape::escape_markdown;
+use crate::utils::helpers::{forget_guild_chains, format_duration};
+use crate::utils::stemmer::Stemmer;
+
+/// Highest `/config reply-chance`, in percent.
+const MAX_REPLY_CHANCE: f64 = 5.0;
+
+pub async fn execute(
+    ctx: &Context,
+    command: &CommandInteraction,
+    database: Arc<Database>,
+) -> Result<(), Error> {
+    let guild_id = match command.guild_id {
+        Some(s) => s,
+        _ => return Ok(()),
+    };
+
+    let can_manage_guild = command
+        .member
+        .as_ref()
+        .and_then(|member| member.permissions)
+        .is_some_and(|permissions| permissions.manage_guild());
+
+    if !can_manage_guild {
+        return reply(
+            ctx,
+            command,
+            "You need the Manage Server permission to use this command.",
+        )
+        .await;
+    }
+
+    let (subcommand, options) = match command.data.options.first() {
+        Some(CommandDataOption {
+            name,
+            value: CommandDataOptionValue::SubCommand(options),
+            ..
+        }) => (name.as_str(), options),
+        _ => return Ok(()),
+    };
+
+    let content = match subcommand {
+        "stemming" => {
+            let stemmer = options
+                .iter()
+                .find(|opt| opt.name == "language")
+                .and_then(|opt| opt.value.as_str())
+                .and_then(Stemmer::from_code);
+
+            match database.set_stem_words(guild_id.get(), stemmer).await {
+                Ok(_) => format!(
+                    "Word stemming is now **{}**. Run `/reindex` to apply it to existing word counts.",
+                    match stemmer {
+                        Some(Stemmer::English) => "English",
+                        Some(Stemmer::Turkish) => "Turkish",
+                        None => "off",
+                    }
+                ),
+                Err(e) => {
+                    error!(error = %e, "Failed to update guild settings");
+                    "An error occurred while saving the setting.".to_string()
+                }
+            }
+        }
+        "guess-recency" => {
+            let days = options
+                .iter()
+                .find(|opt| opt.name == "period")
+                .and_then(|opt| opt.value.as_i64())
+                .filter(|days| *days > 0)
+                .map(|days| days as u32);
+
+            match database.set_guess_recency_days(guild_id.get(), days).await {
+                Ok(_) => match days {
+                    Some(days) => format!(
+                        "`/guess` now uses messages from the last **{} days** by default.",
+                        days
+                    ),
+                    None => "`/guess` now uses messages from **all time** by default.".to_string(),
+                },
+                Err(e) => {
+                    error!(error = %e, "Failed to update guild settings");
+                    "An error occurred while saving the setting.".to_string()
+                }
+            }
+        }
+        "dedupe" => {
+            let enabled = options
+                .iter()
+                .find(|opt| opt.name == "enabled")
+                .and_then(|opt| opt.value.as_bool())
+                .unwrap_or(true);
+
+            match database
+                .set_dedupe_consecutive(guild_id.get(), enabled)
+                .await
+            {
+                Ok(_) => format!(
+                    "Repeated messages from the same member are now **{}**.",
+                    if enabled { "skipped" } else { "stored" }
+                ),
+                Err(e) => {
+                    error!(error = %e, "Failed to update guild settings");
+                    "An error occurred while saving the setting.".to_string()
+                }
+            }
+        }
+        "banned-words" => {
+            let action = options
+                .iter()
+                .find(|opt| opt.name == "action")
+                .and_then(|opt| opt.value.as_str())
+                .unwrap_or("list");
+            let word = options
+                .iter()
+                .find(|opt| opt.name == "word")
+                .and_then(|opt| opt.value.as_str())
+                .map(|word| word.trim().to_lowercase())
+                .filter(|word| !word.is_empty());
+
+            let result =
+                match (action, word) {
+                    ("add", Some(word)) => database
+                        .add_banned_word(guild_id.get(), &word)
+                        .await
+                        .map(|added| match added {
+                            true => format!("Banned ||{}||.", escape_markdown(&word)),
+                            false => "That word is already banned.".to_string(),
+                        }),
+                    ("remove", Some(word)) => database
+                        .remove_banned_word(guild_id.get(), &word)
+                        .await
+                        .map(|removed| match removed {
+                            true => format!("Unbanned ||{}||.", escape_markdown(&word)),
+                            false => "That word isn't on the list.".to_string(),
+                        }),
+                    ("add" | "remove", None) => Ok("Tell me which `word`.".to_string()),
+                    _ => database
+                        .get_guild_settings(guild_id.get())
+                        .await
+                        .map(|settings| match settings.banned_words.is_empty() {
+                            true => {
+                                "No extra words are banned, only the built-in list.".to_string()
+                            }
+                            false => format!(
+                                "Banned on top of the built-in list: {}",
+                                settings
+                                    .banned_words
+                                    .iter()
+                                    .map(|word| format!("||{}||", escape_markdown(word)))
+                                    .collect::<Vec<_>>()
+                                    .join(", ")
+                            ),
+                        }),
+                };
+
+            match result {
+                Ok(content) => content,
+                Err(e) => {
+                    error!(error = %e, "Failed to update banned words");
+                    "An error occurred while saving the setting.".to_string()
+                }
+            }
+        }
+        "filter-training" => {
+            let enabled = options
+                .iter()
+                .find(|opt| opt.name == "enabled")
+                .and_then(|opt| opt.value.as_bool())
+                .unwrap_or(false);
+
+            let result = match database
+                .set_filter_banned_training(guild_id.get(), enabled)
+                .await
+            {
+                Ok(_) => forget_guild_chains(ctx, &database, guild_id).await,
+                Err(e) => Err(e),
+            };
+
+            match result {
+                Ok(_) => format!(
+                    "Messages with banned words are now **{}** when learning. Chains are retrained with this the next time they're used.",
+                    if enabled { "skipped" } else { "used" }
+                ),
+                Err(e) => {
+                    error!(error = %e, "Failed to update guild settings");
+                    "An error occurred while saving the setting.".to_string()
+                }
+            }
+        }
+        "autopost" => {
+            let option = |name: &str| options.iter().find(|opt| opt.name == name);
+
+            let result = match database.get_guild_settings(guild_id.get()).await {
+                Ok(mut settings) => {
+                    let unchanged = options.is_empty();
+
+                    if let Some(enabled) = option("enabled").and_then(|opt| opt.value.as_bool()) {
+                        settings.autopost_enabled = enabled;
+                    }
+                    if let Some(minutes) = option("min_minutes").and_then(|opt| opt.value.as_i64())
+                    {
+                        settings.autopost_min_secs = minutes as u64 * 60;
+                    }
+                    if let Some(minutes) = option("max_minutes").and_then(|opt| opt.value.as_i64())
+                    {
+                        settings.autopost_max_secs = minutes as u64 * 60;
+                    }
+                    if let Some(channel_id) =
+                        option("channel").and_then(|opt| opt.value.as_channel_id())
+                    {
+                        settings.autopost_channel_id = Some(channel_id.get());
+                    }
+                    if option("any_channel").and_then(|opt| opt.value.as_bool()) == Some(true) {
+                        settings.autopost_channel_id = None;
+                    }
+
+                    if settings.autopost_min_secs > settings.autopost_max_secs {
+                        Ok("The shortest wait can't be longer than the longest one.".to_string())
+                    } else if unchanged {
+                        Ok(describe_autopost(&settings))
+                    } else {
+                        database
+                            .upsert_guild_settings(guild_id.get(), &settings)
+                            .await
+                            .map(|_| describe_autopost(&settings))
+                    }
+                }
+                Err(e) => Err(e),
+            };
+
+            match result {
+                Ok(content) => content,
+                Err(e) => {
+                    error!(error = %e, "Failed to update guild settings");
+                    "An error occurred while saving the setting.".to_string()
+                }
+            }
+        }
+        "name-trigger" => {
+            let result = match database.get_guild_settings(guild_id.get()).await {
+                Ok(mut settings) => {
+                    if let Some(enabled) = options
+                        .iter()
+                        .find(|opt| opt.name == "enabled")
+                        .and_then(|opt| opt.value.as_bool())
+                    {
+                        settings.name_trigger = enabled;
+                    }
+                    if let Some(aliases) = options
+                        .iter()
+                        .find(|opt| opt.name == "aliases")
+                        .and_then(|opt| opt.value.as_str())
+                    {
+                        settings.name_aliases = aliases
+                            .split(',')
+                            .map(|alias| alias.trim().to_lowercase())
+                            .filter(|alias| alias.chars().any(char::is_alphanumeric))
+                            .collect();
+                    }
+
+                    database
+                        .upsert_guild_settings(guild_id.get(), &settings)
+                        .await
+                        .map(|_| describe_name_trigger(&settings))
+                }
+                Err(e) => Err(e),
+            };
+
+            match result {
+                Ok(content) => content,
+                Err(e) => {
+                    error!(error = %e, "Failed to update guild settings");
+                    "An error occurred while saving the setting.".to_string()
+                }
+            }
+        }
+        "reply-chance" => {
+            let percent = options
+                .iter()
+                .find(|opt| opt.name == "percent")
+                .and_then(|opt| opt.value.as_f64())
+                .unwrap_or(0.0)
+                .clamp(0.0, MAX_REPLY_CHANCE);
+
+            let result = match database.get_guild_settings(guild_id.get()).await {
+                Ok(mut settings) => {
+                    settings.reply_chance = percent;
+                    database
+                        .upsert_guild_settings(guild_id.get(), &settings)
+                        .await
+                }
+                Err(e) => Err(e),
+            };
+
+            match result {
+                Ok(_) if percent > 0.0 => format!(
+                    "I now reply to **{}%** of messages unprompted, at most once every 10 minutes per channel.",
+                    percent
+                ),
+                Ok(_) => "I no longer reply unprompted.".to_string(),
+                Err(e) => {
+                    error!(error = %e, "Failed to update guild settings");
+                    "An error occurred while saving the setting.".to_string()
+                }
+            }
+        }
+        "command-prefixes" => {
+            let prefixes: Vec<String> = options
+                .iter()
+                .find(|opt| opt.name == "prefixes")
+                .and_then(|opt| opt.value.as_str())
+                .map(|prefixes| prefixes.split_whitespace().map(str::to_string).collect())
+                .unwrap_or_default();
+
+            let result = match database.get_guild_settings(guild_id.get()).await {
+                Ok(mut settings) => {
+                    settings.command_prefixes = prefixes.clone();
+                    database
+                        .upsert_guild_settings(guild_id.get(), &settings)
+                        .await
+                }
+                Err(e) => Err(e),
+            };
+
+            match result {
+                Ok(_) if prefixes.is_empty() => {
+                    "Messages are no longer skipped for their prefix.".to_string()
+                }
+                Ok(_) => format!(
+                    "Messages starting with {} are no longer stored.",
+                    prefixes
+                        .iter()
+                        .map(|prefix| format!("`{}`", prefix.replace('`', "")))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+                Err(e) => {
+                    error!(error = %e, "Failed to update guild settings");
+                    "An error occurred while saving the setting.".to_string()
+                }
+            }
+        }
+        _ => return Ok(()),
+    };
+
+    reply(ctx, command, content).await
+}
+
+fn describe_name_trigger(settings: &GuildSettings) -> String {
+    if !settings.name_trigger {
+        return "I only reply when mentioned.".to_string();
+    }
+
+    match settings.name_aliases.is_empty() {
+        true => "I reply when mentioned or when my name is said.".to_string(),
+        false => format!(
+            "I reply when mentioned or when my name is said, also as {}.",
+            settings
+                .name_aliases
+                .iter()
+                .map(|alias| format!("**{}**", escape_markdown(alias)))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+fn describe_autopost(settings: &GuildSettings) -> String {
+    if !settings.autopost_enabled {
+        return "Autoposting is **off**.".to_string();
+    }
+
+    format!(
+        "Autoposting is **on**, every **{}** to **{}** in {}.",
+        format_duration(settings.autopost_min_secs as i64),
+        format_duration(settings.autopost_max_secs as i64),
+        match settings.autopost_channel_id {
+            Some(channel_id) => format!("<#{}>", channel_id),
+            None => "the most active channels".to_string(),
+        }
+    )
+}
+
+async fn reply(
+    ctx: &Context,
+    command: &CommandInteraction,
+    content: impl Into<String>,
+) -> Result<(), Error> {
+    command
+        .create_response(
+            &ctx.http,
+            CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .content(content)
+                    .ephemeral(true),
+            ),
+        )
+        .await
+}
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new("config")
+        .description("Configure the bot for this server.")
+        .default_member_permissions(Permissions::MANAGE_GUILD)
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "stemming",
+                "Group word inflections (plays, playing, played) on the leaderboard.",
+            )
+            .add_sub_option(
+                CreateCommandOption::new(
+                    CommandOptionType::String,
+                    "language",
+                    "Which stemmer to use",
+                )
+                .required(true)
+                .add_string_choice("Off", "off")
+                .add_string_choice("English", "en")
+                .add_string_choice("Turkish", "tr"),
+            ),
+        )
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "guess-recency",
+                "Default period `/guess` picks messages from.",
+            )
+            .add_sub_option(
+                CreateCommandOption::new(CommandOptionType::Integer, "period", "Which period")
+                    .required(true)
+                    .add_int_choice("All time", 0)
+                    .add_int_choice("Last 90 days", 90)
+                    .add_int_choice("Last 30 days", 30)
+                    .add_int_choice("Last 7 days", 7),
+            ),
+        )
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "dedupe",
+                "Skip storing a message identical to the member's previous one in the channel.",
+            )
+            .add_sub_option(
+                CreateCommandOption::new(CommandOptionType::Boolean, "enabled", "Skip repeats")
+                    .required(true),
+            ),
+        )
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "banned-words",
+                "Words generated messages must never contain.",
+            )
+            .add_sub_option(
+                CreateCommandOption::new(CommandOptionType::String, "action", "What to do")
+                    .required(true)
+                    .add_string_choice("List", "list")
+                    .add_string_choice("Add", "add")
+                    .add_string_choice("Remove", "remove"),
+            )
+            .add_sub_option(CreateCommandOption::new(
+                CommandOptionType::String,
+                "word",
+                "The word to add or remove, end it with * to ban every word starting with it",
+            )),
+        )
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "filter-training",
+                "Don't learn from messages containing banned words.",
+            )
+            .add_sub_option(
+                CreateCommandOption::new(
+                    CommandOptionType::Boolean,
+                    "enabled",
+                    "Skip messages with banned words",
+                )
+                .required(true),
+            ),
+        )
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "autopost",
+                "When and where generated messages are posted on their own.",
+            )
+            .add_sub_option(CreateCommandOption::new(
+                CommandOptionType::Boolean,
+                "enabled",
+                "Post generated messages on a timer",
+            ))
+            .add_sub_option(
+                CreateCommandOption::new(
+                    CommandOptionType::Integer,
+                    "min_minutes",
+                    "Shortest wait between posts, in minutes",
+                )
+                .min_int_value(1)
+                .max_int_value(10080),
+            )
+            .add_sub_option(
+                CreateCommandOption::new(
+                    CommandOptionType::Integer,
+                    "max_minutes",
+                    "Longest wait between posts, in minutes",
+                )
+                .min_int_value(1)
+                .max_int_value(10080),
+            )
+            .add_sub_option(
+                CreateCommandOption::new(
+                    CommandOptionType::Channel,
+                    "channel",
+                    "Always post here instead of the most active channels",
+                )
+                .channel_types(vec![ChannelType::Text, ChannelType::News]),
+            )
+            .add_sub_option(CreateCommandOption::new(
+                CommandOptionType::Boolean,
+                "any_channel",
+                "Go back to posting in the most active channels",
+            )),
+        )
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "name-trigger",
+                "Reply when the bot's name is said, not only when it's mentioned.",
+            )
+            .add_sub_option(CreateCommandOption::new(
+                CommandOptionType::Boolean,
+                "enabled",
+                "Reply to the bot's name",
+            ))
+            .add_sub_option(CreateCommandOption::new(
+                CommandOptionType::String,
+                "aliases",
+                "Other names for the bot, separated by commas, or - to clear them",
+            )),
+        )
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "reply-chance",
+                "How often the bot joins conversations without being asked.",
+            )
+            .add_sub_option(
+                CreateCommandOption::new(
+                    CommandOptionType::Number,
+                    "percent",
+                    "Chance to reply to a message, 0 to turn it off",
+                )
+                .required(true)
+                .min_number_value(0.0)
+                .max_number_value(MAX_REPLY_CHANCE),
+            ),
+        )
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "command-prefixes",
+                "Don't store commands meant for other bots.",
+            )
+            .add_sub_option(CreateCommandOption::new(
+                CommandOptionType::String,
+                "prefixes",
+                "Prefixes separated by spaces, like `! ?`, leave out to clear them",
+            )),
+        )
+}
@@ -0,0 +1,249 @@
+use std::sync::Arc;
+
+use serenity::all::{
+    ButtonStyle, CommandDataOptionValue, CommandInteraction, CommandOptionType,
+    ComponentInteraction, CreateActionRow, CreateButton, CreateCommand, CreateCommandOption,
+    CreateInteractionResponse, CreateInteractionResponseMessage, EditInteractionResponse, GuildId,
+    Member, UserId,
+};
+use serenity::prelude::*;
+use serenity::Error;
+use tracing::{error, info};
+
+use crate::database::Database;
+use crate::utils::helpers::{is_bot_owner, purge_user_data};
+
+pub async fn execute(
+    ctx: &Context,
+    command: &CommandInteraction,
+    _database: Arc<Database>,
+) -> Result<(), Error> {
+    let mut target = command.user.id;
+    let mut global = false;
+
+    for option in &command.data.options {
+        match (option.name.as_str(), &option.value) {
+            ("user", CommandDataOptionValue::User(user_id)) => target = *user_id,
+            ("global", CommandDataOptionValue::Boolean(value)) => global = *value,
+            _ => (),
+        }
+    }
+
+    if target != command.user.id {
+        if !can_forget_others(ctx, command.user.id, command.member.as_deref()).await {
+            return reply(
+                ctx,
+                command,
+                "You need the Manage Server permission to forget someone else.",
+            )
+            .await;
+        }
+
+        // Admins of one server don't get a say over the others
+        if global && !is_bot_owner(ctx, command.user.id).await {
+            return reply(
+                ctx,
+                command,
+                "Only the bot owner can forget someone everywhere.",
+            )
+            .await;
+        }
+    }
+
+    if command.guild_id.is_none() && !global {
+        return reply(ctx, command, "Use this in a server, or set `global`.").await;
+    }
+
+    let whose = if target == command.user.id {
+        "everything you've said".to_string()
+    } else {
+        format!("everything <@{}> has said", target)
+    };
+    let where_ = if global {
+        "in every server I'm in"
+    } else {
+        "in this server"
+    };
+
+    let builder = CreateInteractionResponseMessage::new()
+        .content(format!(
+            "This deletes {} {}, and can't be undone.",
+            whose, where_
+        ))
+        .components(vec![confirm_buttons(command.user.id, target, global)])
+        .ephemeral(true);
+
+    command
+        .create_response(&ctx.http, CreateInteractionResponse::Message(builder))
+        .await
+}
+
+/// Delete and Cancel buttons for forgetting `target`. The invoker goes into
+/// the ids, only they get to answer.
+pub fn confirm_buttons(invoker: UserId, target: UserId, global: bool) -> CreateActionRow {
+    CreateActionRow::Buttons(vec![
+        CreateButton::new(format!(
+            "forgetme:confirm:{}:{}:{}",
+            invoker, target, global
+        ))
+        .label("Delete")
+        .style(ButtonStyle::Danger),
+        CreateButton::new(format!("forgetme:cancel:{}", invoker))
+            .label("Cancel")
+            .style(ButtonStyle::Secondary),
+    ])
+}
+
+/// Handles the `forgetme:confirm:<invoker>:<target>:<global>` and
+/// `forgetme:cancel:<invoker>` buttons.
+pub async fn handle_component(
+    ctx: &Context,
+    component: &ComponentInteraction,
+    database: Arc<Database>,
+) -> Result<(), Error> {
+    let mut args = component.data.custom_id.split(':').skip(1);
+    let action = args.next();
+    let invoker = args.next().and_then(|id| id.parse::<u64>().ok());
+
+    if invoker != Some(component.user.id.get()) {
+        // Leave the buttons alone for the person they're meant for
+        return component
+            .create_response(
+                &ctx.http,
+                CreateInteractionResponse::Message(
+                    CreateInteractionResponseMessage::new()
+                        .content("This isn't your confirmation.")
+                        .ephemeral(true),
+                ),
+            )
+            .await;
+    }
+
+    if action == Some("cancel") {
+        return update(ctx, component, "Nothing was deleted.").await;
+    }
+
+    let target = match args.next().and_then(|id| id.parse::<u64>().ok()) {
+        Some(id) if id > 0 => UserId::new(id),
+        _ => return Ok(()),
+    };
+    let global = args.next() == Some("true");
+
+    // Permissions may have changed since the command was used
+    if target != component.user.id
+        && !can_forget_others(ctx, component.user.id, component.member.as_ref()).await
+    {
+        return update(
+            ctx,
+            component,
+            "You need the Manage Server permission to forget someone else.",
+        )
+        .await;
+    }
+
+    component
+        .create_response(&ctx.http, CreateInteractionResponse::Acknowledge)
+        .await?;
+
+    let guild_ids: Vec<GuildId> = if global {
+        ctx.cache.guilds()
+    } else {
+        component.guild_id.into_iter().collect()
+    };
+
+    let mut deleted = 0;
+    let mut failed = false;
+    for guild_id in guild_ids {
+        match purge_user_data(ctx, &database, guild_id, target).await {
+            Ok(messages) => deleted += messages,
+            Err(e) => {
+                error!(
+                    user_id = %target,
+                    guild_id = %guild_id,
+                    error = %e,
+                    "Failed to purge user"
+                );
+                failed = true;
+            }
+        }
+    }
+
+    info!(user_id = %target, messages = deleted, "Forgot user");
+
+    let content = if failed {
+        format!(
+            "Deleted {} messages, but some couldn't be deleted. Try again later.",
+            deleted
+        )
+    } else {
+        format!("Deleted {} messages.", deleted)
+    };
+
+    component
+        .edit_response(
+            &ctx.http,
+            EditInteractionResponse::new()
+                .content(content)
+                .components(Vec::new()),
+        )
+        .await?;
+
+    Ok(())
+}
+
+async fn can_forget_others(ctx: &Context, user_id: UserId, member: Option<&Member>) -> bool {
+    let can_manage_guild = member
+        .and_then(|member| member.permissions)
+        .is_some_and(|permissions| permissions.manage_guild());
+
+    can_manage_guild || is_bot_owner(ctx, user_id).await
+}
+
+async fn reply(
+    ctx: &Context,
+    command: &CommandInteraction,
+    content: impl Into<String>,
+) -> Result<(), Error> {
+    command
+        .create_response(
+            &ctx.http,
+            CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .content(content)
+                    .ephemeral(true),
+            ),
+        )
+        .await
+}
+
+async fn update(
+    ctx: &Context,
+    component: &ComponentInteraction,
+    content: impl Into<String>,
+) -> Result<(), Error> {
+    component
+        .create_response(
+            &ctx.http,
+            CreateInteractionResponse::UpdateMessage(
+                CreateInteractionResponseMessage::new()
+                    .content(content)
+                    .components(Vec::new()),
+            ),
+        )
+        .await
+}
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new("forgetme")
+        .description("Delete the messages I've stored from you.")
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::Boolean,
+            "global",
+            "Delete them in every server I'm in, not just this one.",
+        ))
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::User,
+            "user",
+            "Someone else to forget, needs Manage Server.",
+        ))
+}
@@ -1,20 +1,60 @@
 use serenity::all::{
-    CommandInteraction, CommandOptionType, CreateCommand, CreateCommandOption,
-    EditInteractionResponse,
+    ButtonStyle, CommandInteraction, CommandOptionType, ComponentInteraction, CreateActionRow,
+    CreateAllowedMentions, CreateButton, CreateCommand, CreateCommandOption,
+    CreateInteractionResponse, CreateInteractionResponseMessage, CreateMessage,
+    EditInteractionResponse, MessageFlags, Timestamp,
 };
 use serenity::prelude::*;
 use serenity::Error;
 use std::sync::Arc;
 
 use crate::database::Database;
-use crate::utils::helpers::generate_markov_message;
+use crate::utils::helpers::{
+    generate_markov_message, GenerationOptions, MarkovOutcome, MAX_WORDS_LIMIT, MESSAGE_CHAR_LIMIT,
+};
+use crate::utils::markov_chain::DEFAULT_TEMPERATURE;
+
+/// How long after `/generate` the regenerate button keeps working.
+const REGENERATE_WINDOW_SECONDS: i64 = 10 * 60;
+
+/// How many times a single output can be regenerated.
+const MAX_REGENERATIONS: u32 = 10;
+
+/// Longest accepted seed word, so it always fits into a button's `custom_id`.
+const MAX_SEED_LENGTH: u16 = 60;
+
+/// Range accepted for the `temperature` option.
+const MIN_TEMPERATURE: f64 = 0.1;
+const MAX_TEMPERATURE: f64 = 2.0;
+
+/// Starts the "generated N times" line below regenerated output.
+const GENERATED_COUNT_PREFIX: &str = "\n-# Generated ";
+
+/// Room left in the message for the "generated N times" line.
+const GENERATED_CHAR_LIMIT: usize = MESSAGE_CHAR_LIMIT - 40;
 
 pub async fn execute(
     ctx: &Context,
     command: &CommandInteraction,
     database: Arc<Database>,
 ) -> Result<(), Error> {
-    command.defer(&ctx.http).await?;
+    let ephemeral = command
+        .data
+        .options
+        .iter()
+        .find(|opt| opt.name == "ephemeral")
+        .and_then(|opt| opt.value.as_bool())
+        .unwrap_or(false);
+
+    // Visibility is fixed by the first response, so it has to be decided here
+    command
+        .create_response(
+            &ctx.http,
+            CreateInteractionResponse::Defer(
+                CreateInteractionResponseMessage::new().ephemeral(ephemeral),
+            ),
+        )
+        .await?;
 
     let guild_id = match command.guild_id {
         Some(s) => s,
@@ -28,23 +68,302 @@ pub async fn execute(
         .find(|opt| opt.name == "word")
         .and_then(|opt| opt.value.as_str());
 
-    let builder =
-        match generate_markov_message(&ctx, guild_id, command.channel_id, word, database).await {
-            Some(markov_message) => EditInteractionResponse::new().content(markov_message),
-            None => EditInteractionResponse::new()
-                .content("Please wait until this channel has over 500 messages."),
-        };
+    let refresh = options
+        .iter()
+        .find(|opt| opt.name == "refresh")
+        .and_then(|opt| opt.value.as_bool())
+        .unwrap_or(false);
+
+    let temperature = options
+        .iter()
+        .find(|opt| opt.name == "temperature")
+        .and_then(|opt| opt.value.as_f64())
+        .map_or(DEFAULT_TEMPERATURE, clamp_temperature);
+
+    let word_count = |name: &str| {
+        options
+            .iter()
+            .find(|opt| opt.name == name)
+            .and_then(|opt| opt.value.as_i64())
+            .map(|count| count.clamp(1, MAX_WORDS_LIMIT as i64) as usize)
+    };
+    let (min_words, max_words) = (word_count("min_words"), word_count("max_words"));
+
+    if let (Some(min_words), Some(max_words)) = (min_words, max_words) {
+        if min_words > max_words {
+            command
+                .edit_response(
+                    &ctx.http,
+                    EditInteractionResponse::new()
+                        .content("`min_words` can't be more than `max_words`."),
+                )
+                .await?;
+            return Ok(());
+        }
+    }
+
+    let generation = GenerationOptions {
+        seed: word,
+        refresh,
+        temperature,
+        min_words,
+        max_words,
+    };
+
+    let builder = match generate_markov_message(
+        ctx,
+        guild_id,
+        command.channel_id,
+        database,
+        GENERATED_CHAR_LIMIT,
+        generation,
+    )
+    .await
+    {
+        MarkovOutcome::Generated(markov_message) => EditInteractionResponse::new()
+            .content(markov_message)
+            .allowed_mentions(CreateAllowedMentions::new())
+            .components(vec![buttons_row(&generation, 1, true, ephemeral)]),
+        outcome => EditInteractionResponse::new().content(outcome.into_content()),
+    };
 
     command.edit_response(&ctx.http, builder).await?;
     Ok(())
 }
 
+/// Handles the regenerate button, whose id is
+/// `generate:regenerate:<times generated>:<temperature>:<min words>:<max words>:<seed word>`
+/// with unset word counts left empty, and the post button of ephemeral
+/// output, `generate:post`.
+pub async fn handle_component(
+    ctx: &Context,
+    component: &ComponentInteraction,
+    database: Arc<Database>,
+) -> Result<(), Error> {
+    if component.data.custom_id == "generate:post" {
+        return post_publicly(ctx, component).await;
+    }
+
+    let ephemeral = component
+        .message
+        .flags
+        .is_some_and(|flags| flags.contains(MessageFlags::EPHEMERAL));
+
+    let mut args = component.data.custom_id.splitn(7, ':').skip(1);
+
+    let (
+        Some("regenerate"),
+        Some(times_generated),
+        Some(temperature),
+        Some(min_words),
+        Some(max_words),
+        Some(seed),
+    ) = (
+        args.next(),
+        args.next(),
+        args.next(),
+        args.next(),
+        args.next(),
+        args.next(),
+    )
+    else {
+        return Ok(());
+    };
+
+    let (Ok(times_generated), Ok(temperature)) =
+        (times_generated.parse::<u32>(), temperature.parse::<f64>())
+    else {
+        return Ok(());
+    };
+
+    let guild_id = match component.guild_id {
+        Some(s) => s,
+        _ => return Ok(()),
+    };
+
+    let generation = GenerationOptions {
+        seed: (!seed.is_empty()).then_some(seed),
+        temperature: clamp_temperature(temperature),
+        min_words: min_words.parse().ok(),
+        max_words: max_words.parse().ok(),
+        ..Default::default()
+    };
+
+    let age = Timestamp::now().unix_timestamp() - component.message.timestamp.unix_timestamp();
+    if age > REGENERATE_WINDOW_SECONDS || times_generated > MAX_REGENERATIONS {
+        return component
+            .create_response(
+                &ctx.http,
+                CreateInteractionResponse::UpdateMessage(
+                    CreateInteractionResponseMessage::new().components(vec![buttons_row(
+                        &generation,
+                        times_generated,
+                        false,
+                        ephemeral,
+                    )]),
+                ),
+            )
+            .await;
+    }
+
+    let response = match generate_markov_message(
+        ctx,
+        guild_id,
+        component.channel_id,
+        database,
+        GENERATED_CHAR_LIMIT,
+        generation,
+    )
+    .await
+    {
+        MarkovOutcome::Generated(markov_message) => {
+            let times_generated = times_generated + 1;
+            let row = buttons_row(
+                &generation,
+                times_generated,
+                times_generated <= MAX_REGENERATIONS,
+                ephemeral,
+            );
+
+            CreateInteractionResponse::UpdateMessage(
+                CreateInteractionResponseMessage::new()
+                    .content(format!(
+                        "{}{}{} times",
+                        markov_message, GENERATED_COUNT_PREFIX, times_generated
+                    ))
+                    .allowed_mentions(CreateAllowedMentions::new())
+                    .components(vec![row]),
+            )
+        }
+        outcome => CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content(outcome.into_content())
+                .ephemeral(true),
+        ),
+    };
+
+    component.create_response(&ctx.http, response).await
+}
+
+/// Sends an ephemeral output to the channel for everyone to see.
+async fn post_publicly(ctx: &Context, component: &ComponentInteraction) -> Result<(), Error> {
+    // Leave the "generated N times" line behind
+    let content = &component.message.content;
+    let content = content
+        .rsplit_once(GENERATED_COUNT_PREFIX)
+        .map_or(content.as_str(), |(message, _)| message);
+
+    component
+        .channel_id
+        .send_message(
+            &ctx.http,
+            CreateMessage::new()
+                .content(content)
+                .allowed_mentions(CreateAllowedMentions::new()),
+        )
+        .await?;
+
+    component
+        .create_response(
+            &ctx.http,
+            CreateInteractionResponse::UpdateMessage(
+                CreateInteractionResponseMessage::new()
+                    .content("Posted to the channel.")
+                    .components(Vec::new()),
+            ),
+        )
+        .await
+}
+
+/// Keeps a temperature from the command or a button in the supported range,
+/// rounded so it stays short in a `custom_id`.
+fn clamp_temperature(temperature: f64) -> f32 {
+    ((temperature.clamp(MIN_TEMPERATURE, MAX_TEMPERATURE) * 100.0).round() / 100.0) as f32
+}
+
+fn buttons_row(
+    generation: &GenerationOptions,
+    times_generated: u32,
+    can_regenerate: bool,
+    ephemeral: bool,
+) -> CreateActionRow {
+    let word_count =
+        |count: Option<usize>| count.map(|count| count.to_string()).unwrap_or_default();
+
+    let regenerate_id = match can_regenerate {
+        true => format!(
+            "generate:regenerate:{}:{}:{}:{}:{}",
+            times_generated,
+            generation.temperature,
+            word_count(generation.min_words),
+            word_count(generation.max_words),
+            generation.seed.unwrap_or_default()
+        ),
+        false => "generate:expired".to_string(),
+    };
+
+    let mut buttons = vec![CreateButton::new(regenerate_id)
+        .label("🔁 Regenerate")
+        .style(ButtonStyle::Secondary)
+        .disabled(!can_regenerate)];
+
+    if ephemeral {
+        buttons.push(
+            CreateButton::new("generate:post")
+                .label("Post publicly")
+                .style(ButtonStyle::Primary),
+        );
+    }
+
+    CreateActionRow::Buttons(buttons)
+}
+
 pub fn register() -> CreateCommand {
     CreateCommand::new("generate")
         .description("Generates a markov message.")
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::String,
+                "word",
+                "A word or phrase the sentence will start with",
+            )
+            .max_length(MAX_SEED_LENGTH),
+        )
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::Boolean,
+            "ephemeral",
+            "Only show the result to you, with a button to post it",
+        ))
         .add_option(CreateCommandOption::new(
-            CommandOptionType::String,
-            "word",
-            "What the sentence will start with",
+            CommandOptionType::Boolean,
+            "refresh",
+            "Retrain on the latest messages first",
         ))
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::Number,
+                "temperature",
+                "Lower is more predictable, higher is more chaotic (default 1.0)",
+            )
+            .min_number_value(MIN_TEMPERATURE)
+            .max_number_value(MAX_TEMPERATURE),
+        )
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::Integer,
+                "min_words",
+                "Fewest words the sentence should have",
+            )
+            .min_int_value(1)
+            .max_int_value(MAX_WORDS_LIMIT as u64),
+        )
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::Integer,
+                "max_words",
+                "Most words the sentence may have",
+            )
+            .min_int_value(1)
+            .max_int_value(MAX_WORDS_LIMIT as u64),
+        )
 }
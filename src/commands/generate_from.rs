@@ -0,0 +1,79 @@
+use serenity::all::{
+    CommandInteraction, CommandType, CreateAllowedMentions, CreateCommand,
+    CreateInteractionResponse, CreateInteractionResponseMessage, CreateMessage,
+    EditInteractionResponse, ResolvedTarget,
+};
+use serenity::prelude::*;
+use serenity::Error;
+use std::sync::Arc;
+
+use crate::database::Database;
+use crate::utils::helpers::{posting_paused, MarkovOutcome};
+use crate::utils::responder::reply_to;
+
+/// Context menu commands are invoked by name, so it's shown as-is.
+pub const NAME: &str = "Generate from this";
+
+pub async fn execute(
+    ctx: &Context,
+    command: &CommandInteraction,
+    database: Arc<Database>,
+) -> Result<(), Error> {
+    // The generation is posted to the channel, only failures stay private
+    command
+        .create_response(
+            &ctx.http,
+            CreateInteractionResponse::Defer(
+                CreateInteractionResponseMessage::new().ephemeral(true),
+            ),
+        )
+        .await?;
+
+    let guild_id = match command.guild_id {
+        Some(s) => s,
+        _ => return Ok(()),
+    };
+
+    let Some(ResolvedTarget::Message(target)) = command.data.target() else {
+        return Ok(());
+    };
+
+    if posting_paused(ctx).await {
+        command
+            .edit_response(
+                &ctx.http,
+                EditInteractionResponse::new().content("Posting is paused right now."),
+            )
+            .await?;
+        return Ok(());
+    }
+
+    let outcome = reply_to(ctx, &target.content, guild_id, command.channel_id, database).await;
+
+    let content = match outcome {
+        MarkovOutcome::Generated(markov_message) => {
+            command
+                .channel_id
+                .send_message(
+                    &ctx.http,
+                    CreateMessage::new()
+                        .content(markov_message)
+                        .reference_message(target)
+                        .allowed_mentions(CreateAllowedMentions::new()),
+                )
+                .await?;
+
+            "Posted to the channel.".to_string()
+        }
+        outcome => outcome.into_content(),
+    };
+
+    command
+        .edit_response(&ctx.http, EditInteractionResponse::new().content(content))
+        .await?;
+    Ok(())
+}
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new(NAME).kind(CommandType::Message)
+}
@@ -1,19 +1,288 @@
+use std::collections::HashMap;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use futures::StreamExt;
 use serenity::all::{
-    ButtonStyle, CommandInteraction, CreateButton, CreateCommand, CreateEmbed,
-    CreateInteractionResponse, CreateMessage, EditInteractionResponse, Message, User, UserId,
+    ButtonStyle, ChannelId, ChannelType, CommandInteraction, CommandOptionType,
+    ComponentInteraction, CreateAllowedMentions, CreateButton, CreateCommand, CreateCommandOption,
+    CreateEmbed, CreateEmbedFooter, CreateInteractionResponse, CreateInteractionResponseMessage,
+    CreateMessage, EditInteractionResponse, GetMessages, Message, MessageId, User, UserId,
 };
 use serenity::prelude::*;
 use serenity::Error;
+use tracing::{error, warn};
 
-use crate::database::Database;
+use crate::database::{Database, RandomMessageFilter, StoredMessage};
+use crate::utils::anonymize::anonymize;
+use crate::utils::content::truncate_at_word_boundary;
+use crate::utils::escape::{escape_codeblock, escape_inline_code, escape_markdown};
+use crate::utils::helpers::{format_duration, snowflake_days_ago, EMBED_DESCRIPTION_CHAR_LIMIT};
 use crate::utils::string_cmp::{gestalt_pattern_matching, levenshtein_similarity};
 
+/// Fewer eligible messages than this and a recency-limited game won't start.
+const MIN_RECENT_POOL: i64 = 10;
+
+/// Rounds a game lasts unless the `rounds` option says otherwise.
+const DEFAULT_ROUNDS: u32 = 5;
+const MAX_ROUNDS: u32 = 20;
+
+/// Messages a round tries before giving up on finding one whose author
+/// can still be looked up.
+const MAX_PICK_ATTEMPTS: u32 = 5;
+
+/// Hints a round can give, each showing more of the author's name.
+const MAX_HINTS: u32 = 2;
+
+/// A round nobody touches for this long ends the game.
+const GAME_STOP_TIMEOUT: Duration = Duration::from_secs(180);
+
+/// Characters of each message "Show context" posts.
+const CONTEXT_MESSAGE_LENGTH: usize = 300;
+
+/// How many players the end of game scoreboard lists.
+const SCOREBOARD_SIZE: usize = 10;
+
+/// Points a right guess is worth, from 100 for an instant answer down to
+/// 20 once a round has gone on as long as `GAME_STOP_TIMEOUT`. Every hint
+/// takes a quarter off.
+fn round_points(elapsed: Duration, hints: u32) -> u32 {
+    const MAX_POINTS: f64 = 100.0;
+    const MIN_POINTS: f64 = 20.0;
+
+    let progress = (elapsed.as_secs_f64() / GAME_STOP_TIMEOUT.as_secs_f64()).min(1.0);
+    let points = MAX_POINTS - (MAX_POINTS - MIN_POINTS) * progress;
+    let multiplier = (1.0 - 0.25 * hints as f64).max(0.0);
+
+    (points * multiplier).round() as u32
+}
+
+/// `name` with most of its letters hidden. The first hint shows its first
+/// and last letters, the second every other letter on top.
+fn name_hint(name: &str, hints: u32) -> String {
+    let chars: Vec<char> = name.chars().collect();
+    let last = chars.len().saturating_sub(1);
+
+    chars
+        .iter()
+        .enumerate()
+        .map(|(index, &c)| {
+            let shown = index == 0 || (index == last && last > 1) || (hints >= 2 && index % 2 == 0);
+            if shown {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// How forgiving the game is with misspelled names.
+#[derive(Debug, Clone, Copy, Default)]
+enum Difficulty {
+    Easy,
+    #[default]
+    Normal,
+    Hard,
+}
+
+impl Difficulty {
+    fn from_option(value: &str) -> Self {
+        match value {
+            "easy" => Self::Easy,
+            "hard" => Self::Hard,
+            _ => Self::Normal,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Self::Easy => "Easy",
+            Self::Normal => "Normal",
+            Self::Hard => "Hard",
+        }
+    }
+
+    /// Shortest message the game picks, in characters. Longer messages give
+    /// away more about who wrote them.
+    fn min_length(self) -> u64 {
+        match self {
+            Self::Easy => 60,
+            Self::Normal => 30,
+            Self::Hard => 10,
+        }
+    }
+
+    /// Similarity a guess needs to count as right, and to be told it was
+    /// close.
+    fn thresholds(self) -> (f32, f32) {
+        match self {
+            Self::Easy => (0.85, 0.65),
+            Self::Normal => (0.95, 0.8),
+            Self::Hard => (1.0, 0.9),
+        }
+    }
+}
+
+/// How a guess compares to a name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum GuessMatch {
+    NoMatch,
+    Close,
+    Exact,
+}
+
+/// Compares a guess to a name, ignoring case, spaces and punctuation, so
+/// `john doe` is `JohnDoe` and `jon doe` is close to it.
+fn match_guess(name: &str, guess: &str, difficulty: Difficulty) -> GuessMatch {
+    let normalize = |s: &str| -> String {
+        s.chars()
+            .filter(|c| c.is_alphanumeric())
+            .flat_map(char::to_lowercase)
+            .collect()
+    };
+    let (name, guess) = (normalize(name), normalize(guess));
+
+    if name.is_empty() || guess.is_empty() {
+        return GuessMatch::NoMatch;
+    }
+    if name == guess {
+        return GuessMatch::Exact;
+    }
+
+    let (exact, close) = difficulty.thresholds();
+    let similarity = levenshtein_similarity(&name, &guess);
+
+    if similarity >= exact {
+        GuessMatch::Exact
+    } else if similarity >= close || gestalt_pattern_matching(&name, &guess) >= close + 0.05 {
+        GuessMatch::Close
+    } else {
+        GuessMatch::NoMatch
+    }
+}
+
+/// How `guesser`'s guess compares to the author's username and display
+/// name. `None` when they're guessing their own message.
+fn judge_guess(
+    guesser: UserId,
+    author: &User,
+    guess: &str,
+    difficulty: Difficulty,
+) -> Option<GuessMatch> {
+    // Knowing who wrote your own message isn't much of a guess
+    if guesser == author.id {
+        return None;
+    }
+
+    let best = [author.name.as_str(), author.display_name()]
+        .iter()
+        .map(|name| match_guess(name, guess, difficulty))
+        .max()
+        .unwrap_or(GuessMatch::NoMatch);
+
+    Some(best)
+}
+
 pub fn register() -> CreateCommand {
-    CreateCommand::new("guess").description("Guess who a random message belongs to.")
+    CreateCommand::new("guess")
+        .description("Guess who a random message belongs to.")
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::Integer,
+                "recency",
+                "Only use messages from this period",
+            )
+            .add_int_choice("All time", 0)
+            .add_int_choice("Last 90 days", 90)
+            .add_int_choice("Last 30 days", 30)
+            .add_int_choice("Last 7 days", 7),
+        )
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::Boolean,
+            "hide_mentions",
+            "Also hide who the messages mention",
+        ))
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::String,
+                "difficulty",
+                "How close to the name a guess has to be",
+            )
+            .add_string_choice("Easy", "easy")
+            .add_string_choice("Normal", "normal")
+            .add_string_choice("Hard", "hard"),
+        )
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::Integer,
+                "rounds",
+                "How many messages to guess before the game ends",
+            )
+            .min_int_value(1)
+            .max_int_value(MAX_ROUNDS as u64),
+        )
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::Boolean,
+            "members_only",
+            "Only use messages from people still in the server (default: on)",
+        ))
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::Boolean,
+            "not_me",
+            "Leave out your own messages",
+        ))
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::Channel,
+                "channel",
+                "Only use messages from this channel",
+            )
+            .channel_types(vec![ChannelType::Text, ChannelType::News]),
+        )
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::Integer,
+                "max_age_days",
+                "Only use messages from the last this many days",
+            )
+            .min_int_value(1),
+        )
+}
+
+/// How a game is set up, from the command's options.
+#[derive(Debug, Clone, Copy)]
+struct GameOptions {
+    recency_days: Option<u32>,
+    hide_mentions: bool,
+    difficulty: Difficulty,
+    rounds: u32,
+    /// Skip messages from people who left the server.
+    members_only: bool,
+    /// Skip the invoker's own messages.
+    not_me: bool,
+    channel_id: Option<ChannelId>,
+}
+
+impl GameOptions {
+    /// Which messages a game started by `invoker` can pick from.
+    fn filter(&self, invoker: UserId) -> RandomMessageFilter {
+        RandomMessageFilter {
+            min_length: self.difficulty.min_length(),
+            excluded_ids: Vec::new(),
+            excluded_author_ids: if self.not_me {
+                vec![invoker.get()]
+            } else {
+                Vec::new()
+            },
+            channel_id: self.channel_id.map(|channel_id| channel_id.get()),
+            min_message_id: self
+                .recency_days
+                .map(|days| snowflake_days_ago(days as u64)),
+            scale_length_by_author: true,
+            ..Default::default()
+        }
+    }
 }
 
 pub async fn execute(
@@ -23,16 +292,104 @@ pub async fn execute(
 ) -> Result<(), Error> {
     command.defer(&ctx.http).await?;
 
-    let game_stop_seconds = 180;
+    let guild_id = match command.guild_id {
+        Some(s) => s,
+        _ => return Ok(()),
+    };
+
+    // An explicit option wins over the server's default, 0 meaning all time
+    let recency_days = match command
+        .data
+        .options
+        .iter()
+        .find(|opt| opt.name == "recency")
+        .and_then(|opt| opt.value.as_i64())
+    {
+        Some(days) => (days > 0).then_some(days as u32),
+        None => match database.get_guild_settings(guild_id.get()).await {
+            Ok(settings) => settings.guess_recency_days,
+            Err(e) => {
+                error!(error = %e, "Failed to fetch guild settings");
+                None
+            }
+        },
+    };
+
+    // `max_age_days` narrows `recency` further, never widens it
+    let recency_days = match command
+        .data
+        .options
+        .iter()
+        .find(|opt| opt.name == "max_age_days")
+        .and_then(|opt| opt.value.as_i64())
+        .and_then(|days| u32::try_from(days).ok())
+        .filter(|&days| days > 0)
+    {
+        Some(max_age) => Some(recency_days.map_or(max_age, |days| days.min(max_age))),
+        None => recency_days,
+    };
+
+    let channel_id = command
+        .data
+        .options
+        .iter()
+        .find(|opt| opt.name == "channel")
+        .and_then(|opt| opt.value.as_channel_id());
+
+    let hide_mentions = command
+        .data
+        .options
+        .iter()
+        .find(|opt| opt.name == "hide_mentions")
+        .and_then(|opt| opt.value.as_bool())
+        .unwrap_or(false);
+
+    let difficulty = command
+        .data
+        .options
+        .iter()
+        .find(|opt| opt.name == "difficulty")
+        .and_then(|opt| opt.value.as_str())
+        .map_or_else(Difficulty::default, Difficulty::from_option);
+
+    let rounds = command
+        .data
+        .options
+        .iter()
+        .find(|opt| opt.name == "rounds")
+        .and_then(|opt| opt.value.as_i64())
+        .map_or(DEFAULT_ROUNDS, |rounds| {
+            rounds.clamp(1, MAX_ROUNDS as i64) as u32
+        });
+
+    let members_only = command
+        .data
+        .options
+        .iter()
+        .find(|opt| opt.name == "members_only")
+        .and_then(|opt| opt.value.as_bool())
+        .unwrap_or(true);
+
+    let not_me = command
+        .data
+        .options
+        .iter()
+        .find(|opt| opt.name == "not_me")
+        .and_then(|opt| opt.value.as_bool())
+        .unwrap_or(false);
+
     let embed = CreateEmbed::new()
         .title("Message Guesser")
         .description(format!(
             "**How to play:**\n\
             • Bot picks a random message from this server\n\
             • Guess who wrote it using their nickname, username, or user ID\n\
-            • Game automatically ends after {} minutes of inactivity\n\n\
+            • The game lasts {} rounds, and ends after {} minutes of inactivity\n\n\
+            **Difficulty:** {}\n\n\
             Ready to test your memory?",
-            game_stop_seconds / 60
+            rounds,
+            GAME_STOP_TIMEOUT.as_secs() / 60,
+            difficulty.label()
         ))
         .color(0x5865F2);
 
@@ -86,7 +443,16 @@ pub async fn execute(
 
     match interaction.data.custom_id.as_str() {
         "start" => {
-            start_game(ctx, command, database).await?;
+            let options = GameOptions {
+                recency_days,
+                hide_mentions,
+                difficulty,
+                rounds,
+                members_only,
+                not_me,
+                channel_id,
+            };
+            start_game(ctx, command, database, options).await?;
         }
         "cancel" => {
             let embed = CreateEmbed::new()
@@ -114,6 +480,7 @@ async fn start_game(
     ctx: &Context,
     command: &CommandInteraction,
     database: Arc<Database>,
+    options: GameOptions,
 ) -> Result<(), Error> {
     let embed = CreateEmbed::new()
         .title("Message Guesser")
@@ -138,35 +505,194 @@ async fn start_game(
         )
         .await?;
 
-    let mut game = Game::new(ctx, command, database);
+    let mut game = Game::new(ctx, command, database, options);
     game.start_game().await?;
 
     Ok(())
 }
 
+/// Handles the `guess:context:<channel_id>:<message_id>` button, posting
+/// the messages sent right before a round's message.
+pub async fn handle_component(
+    ctx: &Context,
+    component: &ComponentInteraction,
+    _database: Arc<Database>,
+) -> Result<(), Error> {
+    let mut args = component.data.custom_id.split(':').skip(1);
+    let (Some("context"), Some(channel_id), Some(message_id)) = (
+        args.next(),
+        args.next().and_then(|id| id.parse::<u64>().ok()),
+        args.next().and_then(|id| id.parse::<u64>().ok()),
+    ) else {
+        return Ok(());
+    };
+    if channel_id == 0 || message_id == 0 {
+        return Ok(());
+    }
+    let (channel_id, message_id) = (ChannelId::new(channel_id), MessageId::new(message_id));
+
+    let content = match channel_id.message(&ctx.http, message_id).await {
+        Ok(original) => {
+            let mut messages = channel_id
+                .messages(&ctx.http, GetMessages::new().before(message_id).limit(2))
+                .await
+                .unwrap_or_default();
+            // Oldest first, ending with the round's message
+            messages.reverse();
+            messages.push(original);
+
+            messages
+                .iter()
+                .map(|msg| {
+                    let content = truncate_at_word_boundary(&msg.content, CONTEXT_MESSAGE_LENGTH);
+                    let content = if content.is_empty() {
+                        "*(no text)*".to_string()
+                    } else {
+                        content.replace('\n', "\n> ")
+                    };
+                    format!("**{}**\n> {}", escape_markdown(&msg.author.name), content)
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        }
+        Err(e) => {
+            warn!(message_id = %message_id, error = %e, "Failed to fetch guess message");
+            "That message no longer exists.".to_string()
+        }
+    };
+
+    component
+        .create_response(
+            &ctx.http,
+            CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .content(content)
+                    .allowed_mentions(CreateAllowedMentions::new()),
+            ),
+        )
+        .await
+}
+
+/// The round's prompt quoting `content`, cut to fit the embed.
+fn quote_description(content: &str, truncated: bool) -> String {
+    // Leave room for the text around the quoted content
+    let shown = truncate_at_word_boundary(content, EMBED_DESCRIPTION_CHAR_LIMIT - 200);
+
+    if truncated || shown != content {
+        format!(
+            "**Can you guess who wrote this message?**\n\n```\n{}\n```\n*This message was too long and has been cut short.*",
+            escape_codeblock(shown.trim_end_matches('…'))
+        )
+    } else {
+        format!(
+            "**Can you guess who wrote this message?**\n\n```\n{}\n```",
+            escape_codeblock(&shown)
+        )
+    }
+}
+
 struct Game<'a> {
     pub ctx: &'a Context,
     pub command: &'a CommandInteraction,
     pub database: Arc<Database>,
     pub game_ended: bool,
+    pub recency_days: Option<u32>,
+    /// Mask mentions of other users in the quoted messages too.
+    pub hide_mentions: bool,
+    difficulty: Difficulty,
+    members_only: bool,
+    /// Rounds the game lasts, and the one being played.
+    rounds: u32,
+    round: u32,
+    /// Hints given this round, kept so scoring can weigh a right guess
+    /// against them.
+    hints: u32,
+    /// Whether this round's author has been told off for guessing
+    /// themselves.
+    self_guess_warned: bool,
+    pub filter: RandomMessageFilter,
+    /// `(correct, attempts)` of everyone who guessed this game.
+    pub scores: HashMap<UserId, (u32, u32)>,
+    /// Points of everyone who guessed right this game.
+    pub points: HashMap<UserId, u32>,
+    /// When the current round's message was posted.
+    round_started: Instant,
+    /// Who won the last rounds, and how many in a row.
+    pub streak: Option<(UserId, u32)>,
+    /// Longest streak of each player this game, saved when it ends.
+    pub best_streaks: HashMap<UserId, u32>,
 }
 
 impl<'a> Game<'a> {
-    pub fn new(ctx: &'a Context, command: &'a CommandInteraction, database: Arc<Database>) -> Self {
+    fn new(
+        ctx: &'a Context,
+        command: &'a CommandInteraction,
+        database: Arc<Database>,
+        options: GameOptions,
+    ) -> Self {
         Self {
             ctx,
             command,
             database,
             game_ended: false,
+            recency_days: options.recency_days,
+            hide_mentions: options.hide_mentions,
+            difficulty: options.difficulty,
+            members_only: options.members_only,
+            rounds: options.rounds,
+            round: 0,
+            hints: 0,
+            self_guess_warned: false,
+            filter: options.filter(command.user.id),
+            scores: HashMap::new(),
+            points: HashMap::new(),
+            round_started: Instant::now(),
+            streak: None,
+            best_streaks: HashMap::new(),
         }
     }
 
     pub async fn start_game(&mut self) -> Result<(), Error> {
+        // Don't silently widen the range, tell the players instead
+        if let Some(days) = self.recency_days {
+            let candidates = match self
+                .database
+                .count_random_message_candidates(self.command.guild_id.unwrap().get(), &self.filter)
+                .await
+            {
+                Ok(count) => count,
+                Err(e) => {
+                    error!(error = %e, "Failed to count guess candidates");
+                    0
+                }
+            };
+
+            if candidates < MIN_RECENT_POOL {
+                self.end_game(format!(
+                    "**Game Ended**\n\nOnly {} messages from the last {} days can be used. \
+                    Try a wider `recency` or `max_age_days` range.",
+                    candidates, days
+                ))
+                .await?;
+                return Ok(());
+            }
+        }
+
         loop {
             if self.game_ended {
                 break;
             }
 
+            if self.round >= self.rounds {
+                self.end_game(format!(
+                    "**Game Over!**\n\nAll {} rounds have been played.",
+                    self.rounds
+                ))
+                .await?;
+                break;
+            }
+
+            self.round += 1;
             self.new_sentence().await?;
         }
 
@@ -174,29 +700,97 @@ impl<'a> Game<'a> {
     }
 
     pub async fn new_sentence(&mut self) -> Result<(), Error> {
-        let min_letters_amount = 30; // Minimum amount of characters in the content
+        let guild_id = self.command.guild_id.unwrap();
 
-        let (random_message, random_author) = match self
-            .get_random_message(&self.command.guild_id.unwrap().get(), &min_letters_amount)
-            .await
-        {
-            Some(s) => s,
-            None => {
-                self.end_game("**Game Ended**\n\nNo messages found that meet the requirements.")
-                    .await?;
+        // Authors that can't be looked up, or left with `members_only`, are
+        // left out for the rest of the game
+        let mut attempts = 0;
+        let (random_message, random_author, member) = loop {
+            attempts += 1;
+            if attempts > MAX_PICK_ATTEMPTS {
+                self.end_game(
+                    "**Game Ended**\n\nCouldn't find a message from someone I can still look up.",
+                )
+                .await?;
                 return Ok(());
             }
+
+            let random_message = match self.get_random_message(&guild_id.get()).await {
+                Some(s) => s,
+                None => {
+                    self.end_game(
+                        "**Game Ended**\n\nNo messages found that meet the requirements.",
+                    )
+                    .await?;
+                    return Ok(());
+                }
+            };
+            self.filter.excluded_ids.push(random_message.message_id);
+            let author_id = random_message.author_id;
+
+            let known_member = match self.database.is_member(guild_id.get(), author_id).await {
+                Ok(present) => present,
+                Err(e) => {
+                    error!(user_id = author_id, error = %e, "Failed to check membership");
+                    true
+                }
+            };
+            if self.members_only && !known_member {
+                self.filter.excluded_author_ids.push(author_id);
+                continue;
+            }
+
+            let random_author = match UserId::new(author_id).to_user(&self.ctx.http).await {
+                Ok(user) => user,
+                Err(e) => {
+                    warn!(user_id = author_id, error = %e, "Failed to fetch guess author");
+                    self.filter.excluded_author_ids.push(author_id);
+                    continue;
+                }
+            };
+
+            let member = guild_id.member(&self.ctx.http, random_author.id).await.ok();
+            if self.members_only && member.is_none() {
+                self.filter.excluded_author_ids.push(author_id);
+                continue;
+            }
+
+            break (random_message, random_author, member);
         };
-        let random_author = UserId::new(random_author).to_user(&self.ctx.http).await?;
 
-        let embed = self.create_embed_with_color(
-            format!(
-                "**Can you guess who wrote this message?**\n\n```\n{}\n```",
-                random_message
-            ),
-            0xFEE75C,
+        // A message signed with the author's name gives the answer away
+        let nick = member.and_then(|member| member.nick);
+        let names = [
+            Some(random_author.name.as_str()),
+            random_author.global_name.as_deref(),
+            nick.as_deref(),
+        ];
+        let anonymized = anonymize(
+            &random_message.content,
+            random_author.id.get(),
+            &names.into_iter().flatten().collect::<Vec<_>>(),
+            self.hide_mentions,
         );
 
+        let round = CreateEmbedFooter::new(format!(
+            "Round {}/{}  •  Faster answers score more",
+            self.round, self.rounds
+        ));
+        let embed = self
+            .create_embed_with_color(
+                quote_description(&anonymized, random_message.truncated),
+                0xFEE75C,
+            )
+            .footer(round.clone());
+
+        // The reveal shows the message as it was written
+        let revealed_embed = self
+            .create_embed_with_color(
+                quote_description(&random_message.content, random_message.truncated),
+                0xFEE75C,
+            )
+            .footer(round);
+
         let skip_buton = CreateButton::new("skip")
             .style(ButtonStyle::Primary)
             .label("Reveal Answer");
@@ -205,6 +799,15 @@ impl<'a> Game<'a> {
             .style(ButtonStyle::Danger)
             .label("End Game");
 
+        let hint_button = CreateButton::new("hint")
+            .style(ButtonStyle::Secondary)
+            .label("Hint");
+
+        self.hints = 0;
+        self.self_guess_warned = false;
+        let unhinted_embed = embed.clone();
+        let mut embed = embed;
+
         let mut message = self
             .command
             .channel_id
@@ -213,71 +816,96 @@ impl<'a> Game<'a> {
                 CreateMessage::new()
                     .embed(embed.clone())
                     .button(skip_buton.clone())
-                    .button(end_button.clone()),
+                    .button(end_button.clone())
+                    .button(hint_button.clone())
+                    .allowed_mentions(CreateAllowedMentions::new()),
             )
             .await?;
+        self.round_started = Instant::now();
 
         loop {
             let mut interaction_stream = message
                 .await_component_interaction(&self.ctx.shard)
                 .stream();
-            let mut message_stream = self.command.channel_id.await_reply(&self.ctx).stream();
+            let mut message_stream = self.command.channel_id.await_reply(self.ctx).stream();
 
             tokio::select! {
                 interaction = interaction_stream.next() => {
-                    match interaction {
-                        Some(interaction) => {
-                            match interaction.data.custom_id.as_str() {
-                                "skip" => {
-                                    message.edit(&self.ctx.http,
-                                        serenity::all::EditMessage::new()
-                                            .embed(embed.clone())
-                                            .button(skip_buton.clone().disabled(true))
-                                            .button(end_button.clone().disabled(true))
-                                    ).await?;
-
-                                    self.command
-                                        .channel_id
-                                        .send_message(&self.ctx.http, CreateMessage::new().content(format!(
-                                            "**Answer Revealed:** The message was written by `{}`", random_author.name
-                                        )))
-                                        .await?;
-
-                                    interaction
-                                        .create_response(&self.ctx.http, CreateInteractionResponse::Acknowledge)
-                                        .await?;
-                                    break;
-                                }
-                                "end" => {
-                                    message.edit(&self.ctx.http,
-                                        serenity::all::EditMessage::new()
-                                            .embed(embed.clone())
-                                            .button(skip_buton.clone().disabled(true))
-                                            .button(end_button.clone().disabled(true))
-                                    ).await?;
-
-                                    interaction
-                                        .create_response(&self.ctx.http, CreateInteractionResponse::Acknowledge)
-                                        .await?;
-                                    self.end_game("**Game Ended**\n\nThe game has been ended by user request.").await?;
-                                    return Ok(());
-                                }
-                                _ => {}
+                    if let Some(interaction) = interaction {
+                        match interaction.data.custom_id.as_str() {
+                            "skip" => {
+                                message.edit(&self.ctx.http,
+                                    serenity::all::EditMessage::new()
+                                        .embed(revealed_embed.clone())
+                                        .button(skip_buton.clone().disabled(true))
+                                        .button(end_button.clone().disabled(true))
+                                        .button(hint_button.clone().disabled(true))
+                                ).await?;
+
+                                self.command
+                                    .channel_id
+                                    .send_message(&self.ctx.http, self.answer_message(format!(
+                                        "**Answer Revealed:** The message was written by `{}`", escape_inline_code(&random_author.name)
+                                    ), &random_message))
+                                    .await?;
+
+                                interaction
+                                    .create_response(&self.ctx.http, CreateInteractionResponse::Acknowledge)
+                                    .await?;
+                                // Nobody won the round
+                                self.streak = None;
+                                break;
+                            }
+                            "end" => {
+                                message.edit(&self.ctx.http,
+                                    serenity::all::EditMessage::new()
+                                        .embed(embed.clone())
+                                        .button(skip_buton.clone().disabled(true))
+                                        .button(end_button.clone().disabled(true))
+                                        .button(hint_button.clone().disabled(true))
+                                ).await?;
+
+                                interaction
+                                    .create_response(&self.ctx.http, CreateInteractionResponse::Acknowledge)
+                                    .await?;
+                                self.end_game("**Game Ended**\n\nThe game has been ended by user request.").await?;
+                                return Ok(());
                             }
+                            "hint" if self.hints < MAX_HINTS => {
+                                self.hints += 1;
+                                embed = unhinted_embed.clone().field(
+                                    "Hint",
+                                    format!("`{}`", escape_inline_code(&name_hint(&random_author.name, self.hints))),
+                                    false,
+                                );
+
+                                message.edit(&self.ctx.http,
+                                    serenity::all::EditMessage::new()
+                                        .embed(embed.clone())
+                                        .button(skip_buton.clone())
+                                        .button(end_button.clone())
+                                        .button(hint_button.clone().disabled(self.hints >= MAX_HINTS))
+                                ).await?;
+
+                                interaction
+                                    .create_response(&self.ctx.http, CreateInteractionResponse::Acknowledge)
+                                    .await?;
+                            }
+                            _ => {}
                         }
-                        None => {}
                     }
                 }
 
                 message_collector = message_stream.next() => {
                     match message_collector {
                         Some(user_message) => {
-                            if self.check_msg_content(user_message, &random_author).await? {
+                            if self.check_msg_content(user_message, &random_author, &random_message).await? {
                                 message.edit(&self.ctx.http,
                                     serenity::all::EditMessage::new()
-                                        .embed(embed.clone())
+                                        .embed(revealed_embed.clone())
                                         .button(skip_buton.clone().disabled(true))
                                         .button(end_button.clone().disabled(true))
+                                        .button(hint_button.clone().disabled(true))
                                 ).await?;
                                 break;
                             }
@@ -288,6 +916,7 @@ impl<'a> Game<'a> {
                                         .embed(embed.clone())
                                         .button(skip_buton.clone().disabled(true))
                                         .button(end_button.clone().disabled(true))
+                                        .button(hint_button.clone().disabled(true))
                                 ).await?;
 
                             self.end_game("**Time's Up!**\n\nNo one guessed correctly within the time limit.")
@@ -296,6 +925,31 @@ impl<'a> Game<'a> {
                         }
                     }
                 }
+
+                // Restarts with every guess, so only a quiet round ends the game
+                _ = tokio::time::sleep(GAME_STOP_TIMEOUT) => {
+                    message.edit(&self.ctx.http,
+                        serenity::all::EditMessage::new()
+                            .embed(revealed_embed.clone())
+                            .button(skip_buton.clone().disabled(true))
+                            .button(end_button.clone().disabled(true))
+                            .button(hint_button.clone().disabled(true))
+                    ).await?;
+
+                    self.command
+                        .channel_id
+                        .send_message(&self.ctx.http, self.answer_message(format!(
+                            "**Answer Revealed:** The message was written by `{}`", escape_inline_code(&random_author.name)
+                        ), &random_message))
+                        .await?;
+
+                    self.end_game(format!(
+                        "**Time's Up!**\n\nNobody played for {} minutes.",
+                        GAME_STOP_TIMEOUT.as_secs() / 60
+                    ))
+                    .await?;
+                    return Ok(());
+                }
             }
         }
 
@@ -303,11 +957,34 @@ impl<'a> Game<'a> {
     }
 
     async fn end_game(&mut self, reason: impl Into<String>) -> Result<(), Error> {
-        let embed = self.create_embed_with_color(reason, 0xED4245);
+        let guild_id = self.command.guild_id.unwrap().get();
+        for (user_id, streak) in &self.best_streaks {
+            if let Err(e) = self
+                .database
+                .record_guess_streak(guild_id, user_id.get(), *streak)
+                .await
+            {
+                error!(user_id = user_id.get(), error = %e, "Failed to save guess streak");
+            }
+        }
+
+        let mut embed = self.create_embed_with_color(reason, 0xED4245).field(
+            "Difficulty",
+            self.difficulty.label(),
+            true,
+        );
+        if let Some(scoreboard) = self.scoreboard() {
+            embed = embed.field("Scoreboard", scoreboard, false);
+        }
 
         self.command
             .channel_id
-            .send_message(&self.ctx.http, CreateMessage::new().embed(embed))
+            .send_message(
+                &self.ctx.http,
+                CreateMessage::new()
+                    .embed(embed)
+                    .allowed_mentions(CreateAllowedMentions::new()),
+            )
             .await?;
 
         self.game_ended = true;
@@ -315,6 +992,96 @@ impl<'a> Game<'a> {
         Ok(())
     }
 
+    /// A message giving the round's answer, with where the quoted message
+    /// was posted and a button to see what was said before it.
+    fn answer_message(&self, content: String, random_message: &StoredMessage) -> CreateMessage {
+        let guild_id = self.command.guild_id.unwrap();
+        let context_button = CreateButton::new(format!(
+            "guess:context:{}:{}",
+            random_message.channel_id, random_message.message_id
+        ))
+        .style(ButtonStyle::Secondary)
+        .label("Show context");
+
+        CreateMessage::new()
+            .content(format!(
+                "{}, posted in <#{}>\nhttps://discord.com/channels/{}/{}/{}",
+                content,
+                random_message.channel_id,
+                guild_id,
+                random_message.channel_id,
+                random_message.message_id
+            ))
+            .button(context_button)
+            .allowed_mentions(CreateAllowedMentions::new())
+    }
+
+    /// Who got how many right this game, `None` if nobody guessed.
+    fn scoreboard(&self) -> Option<String> {
+        let points = |user_id: &UserId| self.points.get(user_id).copied().unwrap_or(0);
+
+        let mut scores: Vec<(&UserId, &(u32, u32))> = self.scores.iter().collect();
+        scores.sort_by(|a, b| {
+            points(b.0)
+                .cmp(&points(a.0))
+                .then(b.1 .0.cmp(&a.1 .0))
+                .then(a.1 .1.cmp(&b.1 .1))
+        });
+
+        let lines: Vec<String> = scores
+            .iter()
+            .take(SCOREBOARD_SIZE)
+            .enumerate()
+            .map(|(index, (user_id, (correct, attempts)))| {
+                let streak = self.best_streaks.get(user_id).copied().unwrap_or(0);
+                let streak = if streak > 1 {
+                    format!(", best streak {}", streak)
+                } else {
+                    String::new()
+                };
+                format!(
+                    "**{}**. <@{}>  -  {} pts, {}/{} correct{}",
+                    index + 1,
+                    user_id,
+                    points(user_id),
+                    correct,
+                    attempts,
+                    streak
+                )
+            })
+            .collect();
+
+        (!lines.is_empty()).then(|| lines.join("\n"))
+    }
+
+    /// Counts a guess towards the game's scoreboard and the guild's
+    /// all-time scores.
+    async fn record_guess(&mut self, user_id: UserId, correct: bool) {
+        let score = self.scores.entry(user_id).or_insert((0, 0));
+        score.1 += 1;
+
+        if correct {
+            score.0 += 1;
+
+            let streak = match self.streak {
+                Some((streaker, streak)) if streaker == user_id => streak + 1,
+                _ => 1,
+            };
+            self.streak = Some((user_id, streak));
+
+            let best = self.best_streaks.entry(user_id).or_insert(0);
+            *best = (*best).max(streak);
+        }
+
+        if let Err(e) = self
+            .database
+            .record_guess_result(self.command.guild_id.unwrap().get(), user_id.get(), correct)
+            .await
+        {
+            error!(user_id = user_id.get(), error = %e, "Failed to record guess");
+        }
+    }
+
     fn create_embed_with_color(&self, content: impl Into<String>, color: u32) -> CreateEmbed {
         CreateEmbed::new()
             .title("Message Guesser")
@@ -323,68 +1090,330 @@ impl<'a> Game<'a> {
     }
 
     async fn check_msg_content(
-        &self,
+        &mut self,
         user_message: Message,
         random_author: &User,
+        random_message: &StoredMessage,
     ) -> Result<bool, Error> {
-        let display_name = random_author.display_name();
-        let correct_guesses = vec![random_author.name.as_str(), &display_name];
+        if user_message.author.bot {
+            return Ok(false);
+        }
 
-        if correct_guesses.iter().any(|&correct_guess| {
-            self.matches(
-                &correct_guess.to_lowercase(),
-                &user_message.content.to_lowercase(),
-            )
-            .is_some()
-        }) {
+        let Some(result) = judge_guess(
+            user_message.author.id,
+            random_author,
+            &user_message.content,
+            self.difficulty,
+        ) else {
+            if !self.self_guess_warned {
+                self.self_guess_warned = true;
+                self.command
+                    .channel_id
+                    .send_message(
+                        &self.ctx.http,
+                        CreateMessage::new()
+                            .content(format!(
+                                "Nice try <@{}>, no guessing your own messages!",
+                                user_message.author.id.get()
+                            ))
+                            .allowed_mentions(CreateAllowedMentions::new()),
+                    )
+                    .await?;
+            }
+            return Ok(false);
+        };
+
+        if result == GuessMatch::Exact {
+            self.record_guess(user_message.author.id, true).await;
+
+            let elapsed = self.round_started.elapsed();
+            let points = round_points(elapsed, self.hints);
+            *self.points.entry(user_message.author.id).or_insert(0) += points;
+
+            let content = format!(
+                "**Correct!** <@{}> got it right{}! **+{} pts**, answered in {}. The message was written by `{}`",
+                user_message.author.id.get(),
+                match self.hints {
+                    0 => String::new(),
+                    1 => " with a hint".to_string(),
+                    hints => format!(" with {} hints", hints),
+                },
+                points,
+                format_duration(elapsed.as_secs() as i64),
+                escape_inline_code(&random_author.name)
+            );
+            self.command
+                .channel_id
+                .send_message(&self.ctx.http, self.answer_message(content, random_message))
+                .await?;
+
+            return Ok(true);
+        }
+
+        // wrong guess
+        self.record_guess(user_message.author.id, false).await;
+
+        if result == GuessMatch::Close {
             self.command
                 .channel_id
                 .send_message(
                     &self.ctx.http,
-                    CreateMessage::new().content(format!(
-                        "**Correct!** <@{}> got it right! The message was written by `{}`",
-                        user_message.author.id.get(),
-                        random_author.name
-                    )),
+                    CreateMessage::new()
+                        .content(format!(
+                            "**So close!** <@{}>, check the spelling and try again.",
+                            user_message.author.id.get()
+                        ))
+                        .allowed_mentions(CreateAllowedMentions::new()),
                 )
                 .await?;
+        }
 
-            return Ok(true);
+        Ok(false)
+    }
+
+    async fn get_random_message(&self, guild_id: &u64) -> Option<StoredMessage> {
+        // Prefer messages not yet shown this session, only allow repeats
+        // once the pool is exhausted.
+        let mut filter = self.filter.clone();
+
+        loop {
+            match self.database.get_random_message(*guild_id, &filter).await {
+                Ok(None) if !filter.excluded_ids.is_empty() => filter.excluded_ids.clear(),
+                Ok(result) => return result,
+                Err(e) => {
+                    error!(error = %e, "Failed to get random message");
+                    return None;
+                }
+            }
         }
+    }
+}
 
-        // wrong guess
-        return Ok(false);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIFFICULTIES: [Difficulty; 3] = [Difficulty::Easy, Difficulty::Normal, Difficulty::Hard];
+
+    #[test]
+    fn spacing_and_case_dont_matter() {
+        for difficulty in DIFFICULTIES {
+            assert_eq!(
+                match_guess("JohnDoe", "john doe", difficulty),
+                GuessMatch::Exact
+            );
+            assert_eq!(
+                match_guess("John.Doe", "JOHN_DOE", difficulty),
+                GuessMatch::Exact
+            );
+        }
     }
 
-    fn matches(&self, src: &str, content: &str) -> Option<bool> {
-        let difficulty = 1.0;
+    #[test]
+    fn one_letter_off_depends_on_difficulty() {
+        // 6/7 similar, past easy's 0.85 but short of normal's 0.95
+        assert_eq!(
+            match_guess("JohnDoe", "jon doe", Difficulty::Easy),
+            GuessMatch::Exact
+        );
+        assert_eq!(
+            match_guess("JohnDoe", "jon doe", Difficulty::Normal),
+            GuessMatch::Close
+        );
+        assert_eq!(
+            match_guess("JohnDoe", "jon doe", Difficulty::Hard),
+            GuessMatch::NoMatch
+        );
+    }
 
-        if src == content {
-            Some(true)
-        } else if levenshtein_similarity(src, content) > difficulty
-            || gestalt_pattern_matching(src, content) > difficulty + 0.1
-        {
-            Some(false)
-        } else {
-            None
+    #[test]
+    fn thresholds_are_inclusive() {
+        // One letter off in twenty is exactly 0.95
+        let name = "abcdefghijklmnopqrst";
+        let guess = "abcdefghijklmnopqrsx";
+
+        assert_eq!(
+            match_guess(name, guess, Difficulty::Easy),
+            GuessMatch::Exact
+        );
+        assert_eq!(
+            match_guess(name, guess, Difficulty::Normal),
+            GuessMatch::Exact
+        );
+        assert_eq!(
+            match_guess(name, guess, Difficulty::Hard),
+            GuessMatch::Close
+        );
+
+        // Two letters off in ten is exactly 0.8
+        let name = "abcdefghij";
+        let guess = "abcdefghxy";
+
+        assert_eq!(
+            match_guess(name, guess, Difficulty::Easy),
+            GuessMatch::Close
+        );
+        assert_eq!(
+            match_guess(name, guess, Difficulty::Normal),
+            GuessMatch::Close
+        );
+        assert_eq!(
+            match_guess(name, guess, Difficulty::Hard),
+            GuessMatch::NoMatch
+        );
+    }
+
+    #[test]
+    fn unrelated_or_empty_guesses_never_match() {
+        for difficulty in DIFFICULTIES {
+            assert_eq!(
+                match_guess("JohnDoe", "alice", difficulty),
+                GuessMatch::NoMatch
+            );
+            assert_eq!(
+                match_guess("JohnDoe", "!!!", difficulty),
+                GuessMatch::NoMatch
+            );
+            assert_eq!(match_guess("...", "...", difficulty), GuessMatch::NoMatch);
         }
     }
 
-    async fn get_random_message(
-        &self,
-        guild_id: &u64,
-        min_letters_amount: &u64,
-    ) -> Option<(String, u64)> {
-        match self
-            .database
-            .get_random_message(*guild_id, *min_letters_amount)
-            .await
-        {
-            Ok(result) => result,
-            Err(e) => {
-                eprintln!("Failed to get random message: {}", e);
+    #[test]
+    fn points_fall_with_time() {
+        assert_eq!(round_points(Duration::ZERO, 0), 100);
+        assert_eq!(round_points(GAME_STOP_TIMEOUT / 2, 0), 60);
+        assert_eq!(round_points(GAME_STOP_TIMEOUT, 0), 20);
+        // Never below the floor, however long the round took
+        assert_eq!(round_points(GAME_STOP_TIMEOUT * 10, 0), 20);
+    }
+
+    #[test]
+    fn hints_take_a_quarter_each() {
+        assert_eq!(round_points(Duration::ZERO, 1), 75);
+        assert_eq!(round_points(Duration::ZERO, 2), 50);
+        assert_eq!(round_points(GAME_STOP_TIMEOUT, 2), 10);
+        assert_eq!(round_points(Duration::ZERO, 4), 0);
+        assert_eq!(round_points(Duration::ZERO, 10), 0);
+    }
+
+    #[test]
+    fn first_hint_shows_the_ends() {
+        assert_eq!(name_hint("yoru", 1), "y__u");
+        assert_eq!(name_hint("yörükan", 1), "y_____n");
+        // Two letters would be the whole name
+        assert_eq!(name_hint("ab", 1), "a_");
+        assert_eq!(name_hint("a", 1), "a");
+        assert_eq!(name_hint("", 1), "");
+    }
+
+    #[test]
+    fn second_hint_shows_every_other_letter() {
+        assert_eq!(name_hint("yoru", 2), "y_ru");
+        assert_eq!(name_hint("yörükan", 2), "y_r_k_n");
+        assert_eq!(name_hint("ab", 2), "a_");
+    }
+
+    fn author(id: u64, name: &str, global_name: Option<&str>) -> User {
+        let mut user = User::default();
+        user.id = UserId::new(id);
+        user.name = name.to_string();
+        user.global_name = global_name.map(str::to_string);
+        user
+    }
+
+    #[test]
+    fn guesses_match_either_name() {
+        let author = author(1, "jdoe99", Some("John Doe"));
+
+        for guess in ["jdoe99", "john doe"] {
+            assert_eq!(
+                judge_guess(UserId::new(2), &author, guess, Difficulty::Normal),
+                Some(GuessMatch::Exact)
+            );
+        }
+        assert_eq!(
+            judge_guess(UserId::new(2), &author, "alice", Difficulty::Normal),
+            Some(GuessMatch::NoMatch)
+        );
+    }
+
+    #[test]
+    fn own_messages_cant_be_guessed() {
+        let author = author(1, "jdoe99", Some("John Doe"));
+
+        for guess in ["jdoe99", "john doe", "alice"] {
+            assert_eq!(
+                judge_guess(UserId::new(1), &author, guess, Difficulty::Easy),
                 None
-            }
+            );
+        }
+    }
+
+    fn game_options(not_me: bool) -> GameOptions {
+        GameOptions {
+            recency_days: None,
+            hide_mentions: false,
+            difficulty: Difficulty::Normal,
+            rounds: 1,
+            members_only: false,
+            not_me,
+            channel_id: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn not_me_leaves_out_the_invokers_messages() {
+        let database = Database::new("sqlite::memory:", 2000, None, 1)
+            .await
+            .unwrap();
+        for (message_id, author_id) in [(10, 1), (11, 2), (12, 1), (13, 2)] {
+            database
+                .insert_message(
+                    message_id,
+                    author_id,
+                    100,
+                    1,
+                    "long enough to be picked for a round",
+                    None,
+                )
+                .await
+                .unwrap();
+        }
+        let invoker = UserId::new(1);
+        // Nobody has enough messages for a length bucket here
+        let unscaled = |not_me| RandomMessageFilter {
+            scale_length_by_author: false,
+            ..game_options(not_me).filter(invoker)
+        };
+
+        assert_eq!(
+            game_options(true).filter(invoker).excluded_author_ids,
+            vec![1]
+        );
+        let filter = unscaled(true);
+        for _ in 0..20 {
+            let message = database
+                .get_random_message(1, &filter)
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(message.author_id, 2);
+        }
+
+        assert!(game_options(false)
+            .filter(invoker)
+            .excluded_author_ids
+            .is_empty());
+        let filter = unscaled(false);
+        let mut authors = std::collections::HashSet::new();
+        for _ in 0..100 {
+            let message = database
+                .get_random_message(1, &filter)
+                .await
+                .unwrap()
+                .unwrap();
+            authors.insert(message.author_id);
         }
+        assert_eq!(authors.len(), 2);
     }
 }
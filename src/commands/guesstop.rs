@@ -0,0 +1,86 @@
+use std::sync::Arc;
+
+use serenity::all::{
+    CommandInteraction, CreateAllowedMentions, CreateCommand, CreateEmbed, EditInteractionResponse,
+};
+use serenity::prelude::*;
+use serenity::Error;
+use tracing::error;
+
+use crate::database::{Database, GuessScore};
+
+/// How many players `/guesstop` lists.
+const TOP_LIMIT: u32 = 10;
+
+/// `/guesstop`, the server's best `/guess` players of all time.
+pub async fn execute(
+    ctx: &Context,
+    command: &CommandInteraction,
+    database: Arc<Database>,
+) -> Result<(), Error> {
+    command.defer(&ctx.http).await?;
+
+    let guild_id = match command.guild_id {
+        Some(s) => s,
+        _ => return Ok(()),
+    };
+
+    let scores = match database
+        .get_guess_leaderboard(guild_id.get(), TOP_LIMIT)
+        .await
+    {
+        Ok(scores) => scores,
+        Err(e) => {
+            error!(error = %e, "Failed to fetch guess scores");
+            command
+                .edit_response(
+                    &ctx.http,
+                    EditInteractionResponse::new()
+                        .content("An error occurred while fetching the guess scores."),
+                )
+                .await?;
+
+            return Ok(());
+        }
+    };
+
+    let mut description = scores
+        .iter()
+        .enumerate()
+        .map(|(index, score)| format_score(index + 1, score))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    if description.is_empty() {
+        description =
+            "Nobody has guessed right in this server yet. Start a game with `/guess`!".to_string();
+    }
+
+    let embed = CreateEmbed::new()
+        .title("Top Guessers")
+        .description(description)
+        .color(0x5865F2);
+
+    command
+        .edit_response(
+            &ctx.http,
+            EditInteractionResponse::new()
+                .embed(embed)
+                .allowed_mentions(CreateAllowedMentions::new()),
+        )
+        .await?;
+    Ok(())
+}
+
+fn format_score(rank: usize, score: &GuessScore) -> String {
+    let accuracy = score.correct as f64 / score.attempts.max(1) as f64 * 100.0;
+
+    format!(
+        "**{}**. <@{}>  -  {} correct out of {} guesses ({:.0}%), best streak {}, last played <t:{}:R>",
+        rank, score.user_id, score.correct, score.attempts, accuracy, score.best_streak, score.last_played
+    )
+}
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new("guesstop").description("See who's best at guessing in this server.")
+}
@@ -0,0 +1,77 @@
+use serenity::all::{
+    CommandInteraction, CommandOptionType, CreateAllowedMentions, CreateCommand,
+    CreateCommandOption, EditInteractionResponse,
+};
+use serenity::prelude::*;
+use serenity::Error;
+use std::sync::Arc;
+
+use crate::database::Database;
+use crate::utils::escape::escape_markdown;
+use crate::utils::helpers::{generate_author_message, MarkovOutcome, MESSAGE_CHAR_LIMIT};
+
+/// Room left in the message for the "imitating" line.
+const IMPERSONATION_CHAR_LIMIT: usize = MESSAGE_CHAR_LIMIT - 100;
+
+pub async fn execute(
+    ctx: &Context,
+    command: &CommandInteraction,
+    database: Arc<Database>,
+) -> Result<(), Error> {
+    command.defer(&ctx.http).await?;
+
+    let guild_id = match command.guild_id {
+        Some(s) => s,
+        _ => return Ok(()),
+    };
+
+    let Some(user_id) = command
+        .data
+        .options
+        .iter()
+        .find(|opt| opt.name == "user")
+        .and_then(|opt| opt.value.as_user_id())
+    else {
+        return Ok(());
+    };
+
+    let name = match command.data.resolved.users.get(&user_id) {
+        Some(user) => escape_markdown(user.display_name()),
+        None => format!("<@{}>", user_id),
+    };
+
+    let content =
+        match generate_author_message(ctx, guild_id, user_id, database, IMPERSONATION_CHAR_LIMIT)
+            .await
+        {
+            MarkovOutcome::Generated(markov_message) => {
+                format!("{}\n-# imitating <@{}>", markov_message, user_id)
+            }
+            MarkovOutcome::NotEnoughMessages { have, need } => format!(
+                "{} has **{}** messages I can learn from, **{}** more are needed.",
+                name,
+                have,
+                need.saturating_sub(have)
+            ),
+            outcome => outcome.into_content(),
+        };
+
+    command
+        .edit_response(
+            &ctx.http,
+            EditInteractionResponse::new()
+                .content(content)
+                .allowed_mentions(CreateAllowedMentions::new()),
+        )
+        .await?;
+    Ok(())
+}
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new("impersonate")
+        .description("Generates a markov message in the style of a member.")
+        .add_option(
+            CreateCommandOption::new(CommandOptionType::User, "user", "Who to imitate")
+                .required(true),
+        )
+}
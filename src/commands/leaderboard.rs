@@ -1,15 +1,41 @@
 use serenity::all::{
-    CommandInteraction, CommandOptionType, CreateCommand, CreateCommandOption, CreateEmbed,
-    EditInteractionResponse,
+    ButtonStyle, CommandInteraction, CommandOptionType, ComponentInteraction, CreateActionRow,
+    CreateButton, CreateCommand, CreateCommandOption, CreateEmbed, CreateInteractionResponse,
+    CreateInteractionResponseMessage, EditInteractionResponse, GuildId,
 };
 use serenity::prelude::*;
 use serenity::Error;
 use std::sync::Arc;
+use tracing::error;
 
-use crate::database::Database;
+use crate::database::{Database, LeaderboardFilter, LeaderboardSort};
+use crate::utils::escape::escape_inline_code;
+use crate::utils::helpers::snowflake_days_ago;
 
 const MAX_DESCRIPTION_LENGTH: usize = 4000;
 
+/// Messages a member needs before they show up on the verbosity leaderboard,
+/// so one long message doesn't win.
+const MIN_VERBOSITY_MESSAGES: i64 = 100;
+
+/// Length of the windows trending words are compared over.
+const TRENDING_WINDOW_DAYS: u64 = 7;
+
+/// Uses a word needs this week before it can trend.
+const MIN_TRENDING_COUNT: i64 = 5;
+
+/// Medals for the first three places of the message leaderboard.
+const MEDALS: [&str; 3] = ["🥇", "🥈", "🥉"];
+
+/// Members shown per page of a word breakdown.
+const BREAKDOWN_PAGE_SIZE: usize = 10;
+
+/// Width of the share bars in a word breakdown, in characters.
+const SHARE_BAR_WIDTH: usize = 10;
+
+/// Longest accepted `word`, so it always fits into a button's `custom_id`.
+const MAX_WORD_LENGTH: u16 = 80;
+
 pub async fn execute(
     ctx: &Context,
     command: &CommandInteraction,
@@ -24,6 +50,45 @@ pub async fn execute(
 
     let options = &command.data.options;
 
+    let mode = options
+        .iter()
+        .find(|opt| opt.name == "mode")
+        .and_then(|opt| opt.value.as_str())
+        .unwrap_or("words");
+
+    let selected_word = options
+        .iter()
+        .find(|opt| opt.name == "word")
+        .and_then(|opt| opt.value.as_str());
+
+    let group_by = options
+        .iter()
+        .find(|opt| opt.name == "group_by")
+        .and_then(|opt| opt.value.as_str())
+        .unwrap_or("word");
+
+    match (mode, selected_word) {
+        ("messages", _) => return messages_leaderboard(ctx, command, guild_id, database).await,
+        ("verbosity", _) => return verbosity_leaderboard(ctx, command, guild_id, database).await,
+        ("trending", _) => return trending_leaderboard(ctx, command, guild_id, database).await,
+        _ if group_by == "user" => {
+            return user_leaderboard(ctx, command, guild_id, selected_word, database).await
+        }
+        (_, Some(word)) => {
+            let builder = match word_breakdown(guild_id, word, 0, database).await {
+                Some((embed, row)) => EditInteractionResponse::new()
+                    .embed(embed)
+                    .components(row.into_iter().collect()),
+                None => EditInteractionResponse::new()
+                    .content("An error occurred while fetching the leaderboard."),
+            };
+
+            command.edit_response(&ctx.http, builder).await?;
+            return Ok(());
+        }
+        _ => (),
+    }
+
     let member_id = options
         .iter()
         .find(|opt| opt.name == "user")
@@ -35,12 +100,14 @@ pub async fn execute(
         .find(|opt| opt.name == "exclude_word")
         .and_then(|opt| opt.value.as_str());
 
-    let excludes_array: Option<Vec<String>> = excludes.map(|v| {
-        v.split(",")
-            .filter(|s| !s.is_empty())
-            .map(|s| s.to_lowercase())
-            .collect()
-    });
+    let excludes_array: Vec<String> = excludes
+        .map(|v| {
+            v.split(",")
+                .filter(|s| !s.is_empty())
+                .map(|s| s.to_lowercase())
+                .collect()
+        })
+        .unwrap_or_default();
 
     let min_word_length = options
         .iter()
@@ -48,27 +115,40 @@ pub async fn execute(
         .and_then(|opt| opt.value.as_i64())
         .unwrap_or(3);
 
-    let selected_word = options
+    let min_users = options
         .iter()
-        .find(|opt| opt.name == "word")
-        .and_then(|opt| opt.value.as_str());
+        .find(|opt| opt.name == "min_users")
+        .and_then(|opt| opt.value.as_i64())
+        .unwrap_or(1);
+
+    let sort = match options
+        .iter()
+        .find(|opt| opt.name == "sort")
+        .and_then(|opt| opt.value.as_str())
+    {
+        Some("least_used") => LeaderboardSort::LeastUsed,
+        Some("alphabetical") => LeaderboardSort::Alphabetical,
+        _ => LeaderboardSort::MostUsed,
+    };
 
     let limit = 50;
 
+    let filter = LeaderboardFilter {
+        target_user_id: member_id,
+        target_word: selected_word.map(|word| word.to_string()),
+        min_length: min_word_length,
+        excludes: excludes_array,
+        min_users,
+        sort,
+    };
+
     let leaderboard = match database
-        .get_leaderboard_data(
-            guild_id.get(),
-            member_id,
-            selected_word,
-            min_word_length,
-            excludes_array,
-            limit,
-        )
+        .get_leaderboard_data(guild_id.get(), &filter, limit, 0)
         .await
     {
         Ok(data) => data,
         Err(e) => {
-            eprintln!("Failed to fetch leaderboard data: {}", e);
+            error!(error = %e, "Failed to fetch leaderboard data");
             command
                 .edit_response(
                     &ctx.http,
@@ -87,7 +167,7 @@ pub async fn execute(
         let entry = format!(
             "**{}**. `{}`  -  {} uses by <@{}>\n",
             index + 1,
-            word,
+            escape_inline_code(word),
             count,
             author_id
         );
@@ -120,19 +200,496 @@ pub async fn execute(
     Ok(())
 }
 
+/// Ranks members by how many words they've used, or how often they've used
+/// `word`.
+async fn user_leaderboard(
+    ctx: &Context,
+    command: &CommandInteraction,
+    guild_id: GuildId,
+    word: Option<&str>,
+    database: Arc<Database>,
+) -> Result<(), Error> {
+    let options = &command.data.options;
+
+    let excludes: Vec<String> = options
+        .iter()
+        .find(|opt| opt.name == "exclude_word")
+        .and_then(|opt| opt.value.as_str())
+        .map(|v| {
+            v.split(",")
+                .filter(|s| !s.is_empty())
+                .map(|s| s.to_lowercase())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let min_word_length = options
+        .iter()
+        .find(|opt| opt.name == "min_word_length")
+        .and_then(|opt| opt.value.as_i64())
+        .unwrap_or(3);
+
+    let totals = match database
+        .get_user_totals(guild_id.get(), word, min_word_length, &excludes, 50)
+        .await
+    {
+        Ok(data) => data,
+        Err(e) => {
+            error!(error = %e, "Failed to fetch user totals");
+            command
+                .edit_response(
+                    &ctx.http,
+                    EditInteractionResponse::new()
+                        .content("An error occurred while fetching the leaderboard."),
+                )
+                .await?;
+
+            return Ok(());
+        }
+    };
+
+    let mut description = String::new();
+
+    for (index, (author_id, uses)) in totals.iter().enumerate() {
+        let entry = format!("**{}**. <@{}>  -  {} uses\n", index + 1, author_id, uses);
+
+        if description.len() + entry.len() > MAX_DESCRIPTION_LENGTH {
+            description.push_str("...");
+            break;
+        }
+        description.push_str(&entry);
+    }
+
+    if description.is_empty() {
+        description = "No data found matching your criteria.".to_string();
+    }
+
+    let title = match word {
+        Some(word) => format!("Who Says \"{}\" Most", word),
+        None => "Top Users".to_string(),
+    };
+
+    let embed = EditInteractionResponse::new().embed(
+        CreateEmbed::new()
+            .title(title)
+            .description(format!(
+                "**Server:** {}\n\n{}",
+                guild_id,
+                description.trim_end()
+            ))
+            .color(0x5865F2)
+            .footer(serenity::all::CreateEmbedFooter::new(format!(
+                "Showing top {} entries",
+                totals.len()
+            ))),
+    );
+
+    command.edit_response(&ctx.http, embed).await?;
+    Ok(())
+}
+
+/// Handles the word breakdown page buttons, whose id is
+/// `leaderboard:word:<page>:<word>`.
+pub async fn handle_component(
+    ctx: &Context,
+    component: &ComponentInteraction,
+    database: Arc<Database>,
+) -> Result<(), Error> {
+    let mut args = component.data.custom_id.splitn(4, ':').skip(1);
+
+    let (Some("word"), Some(page), Some(word)) = (args.next(), args.next(), args.next()) else {
+        return Ok(());
+    };
+
+    let (Ok(page), Some(guild_id)) = (page.parse::<usize>(), component.guild_id) else {
+        return Ok(());
+    };
+
+    let response = match word_breakdown(guild_id, word, page, database).await {
+        Some((embed, row)) => CreateInteractionResponse::UpdateMessage(
+            CreateInteractionResponseMessage::new()
+                .embed(embed)
+                .components(row.into_iter().collect()),
+        ),
+        None => CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content("An error occurred while fetching the leaderboard.")
+                .ephemeral(true),
+        ),
+    };
+
+    component.create_response(&ctx.http, response).await
+}
+
+/// One page of who uses `word` and how much of their vocabulary it is,
+/// with page buttons when there's more than one page.
+async fn word_breakdown(
+    guild_id: GuildId,
+    word: &str,
+    page: usize,
+    database: Arc<Database>,
+) -> Option<(CreateEmbed, Option<CreateActionRow>)> {
+    let rows = match database.get_word_breakdown(guild_id.get(), word).await {
+        Ok(rows) => rows,
+        Err(e) => {
+            error!(error = %e, "Failed to fetch word breakdown");
+            return None;
+        }
+    };
+
+    let total_uses: i64 = rows.iter().map(|(_, uses, _)| uses).sum();
+    let pages = rows.len().div_ceil(BREAKDOWN_PAGE_SIZE).max(1);
+    let page = page.min(pages - 1);
+
+    let mut description = format!(
+        "`{}` was used **{}** times by **{}** members.\n\n",
+        escape_inline_code(word),
+        total_uses,
+        rows.len()
+    );
+
+    for (index, (author_id, uses, author_total)) in rows
+        .iter()
+        .enumerate()
+        .skip(page * BREAKDOWN_PAGE_SIZE)
+        .take(BREAKDOWN_PAGE_SIZE)
+    {
+        let share_of_word = *uses as f64 / total_uses.max(1) as f64;
+        let share_of_author = *uses as f64 / (*author_total).max(1) as f64;
+
+        description.push_str(&format!(
+            "**{}**. <@{}>  -  {} uses\n`{}` {:.1}% of all uses, {:.2}% of their words\n",
+            index + 1,
+            author_id,
+            uses,
+            share_bar(share_of_word),
+            share_of_word * 100.0,
+            share_of_author * 100.0
+        ));
+    }
+
+    let embed = CreateEmbed::new()
+        .title("Word Breakdown")
+        .description(description.trim_end())
+        .color(0x5865F2)
+        .footer(serenity::all::CreateEmbedFooter::new(format!(
+            "Page {} of {}",
+            page + 1,
+            pages
+        )));
+
+    let row = (pages > 1).then(|| {
+        CreateActionRow::Buttons(vec![
+            CreateButton::new(format!(
+                "leaderboard:word:{}:{}",
+                page.saturating_sub(1),
+                word
+            ))
+            .label("◀")
+            .style(ButtonStyle::Secondary)
+            .disabled(page == 0),
+            CreateButton::new(format!("leaderboard:word:{}:{}", page + 1, word))
+                .label("▶")
+                .style(ButtonStyle::Secondary)
+                .disabled(page + 1 >= pages),
+        ])
+    });
+
+    Some((embed, row))
+}
+
+/// A fixed-width bar filled in proportion to `share`.
+fn share_bar(share: f64) -> String {
+    let filled = ((share * SHARE_BAR_WIDTH as f64).round() as usize).min(SHARE_BAR_WIDTH);
+    format!(
+        "{}{}",
+        "█".repeat(filled),
+        "░".repeat(SHARE_BAR_WIDTH - filled)
+    )
+}
+
+/// Ranks members by how many of their messages are stored.
+async fn messages_leaderboard(
+    ctx: &Context,
+    command: &CommandInteraction,
+    guild_id: GuildId,
+    database: Arc<Database>,
+) -> Result<(), Error> {
+    let days = command
+        .data
+        .options
+        .iter()
+        .find(|opt| opt.name == "days")
+        .and_then(|opt| opt.value.as_i64())
+        .map(|days| days as u64);
+
+    let (leaderboard, total) = match database
+        .get_top_posters(guild_id.get(), 50, days.map(snowflake_days_ago))
+        .await
+    {
+        Ok(data) => data,
+        Err(e) => {
+            error!(error = %e, "Failed to fetch message leaderboard");
+            command
+                .edit_response(
+                    &ctx.http,
+                    EditInteractionResponse::new()
+                        .content("An error occurred while fetching the leaderboard."),
+                )
+                .await?;
+
+            return Ok(());
+        }
+    };
+
+    let mut description = String::new();
+
+    for (index, (author_id, message_count)) in leaderboard.iter().enumerate() {
+        let rank = match MEDALS.get(index) {
+            Some(medal) => medal.to_string(),
+            None => format!("**{}**.", index + 1),
+        };
+        let entry = format!(
+            "{} <@{}>  -  {} messages ({:.1}%)\n",
+            rank,
+            author_id,
+            message_count,
+            *message_count as f64 / total.max(1) as f64 * 100.0
+        );
+
+        if description.len() + entry.len() > MAX_DESCRIPTION_LENGTH {
+            description.push_str("...");
+            break;
+        }
+        description.push_str(&entry);
+    }
+
+    if description.is_empty() {
+        description = "No messages have been stored yet.".to_string();
+    }
+
+    let period = match days {
+        Some(days) => format!("last {} days", days),
+        None => "all time".to_string(),
+    };
+
+    let embed = EditInteractionResponse::new().embed(
+        CreateEmbed::new()
+            .title("Message Leaderboard")
+            .description(format!(
+                "**Server:** {}\n\n{}",
+                guild_id,
+                description.trim_end()
+            ))
+            .color(0x5865F2)
+            .footer(serenity::all::CreateEmbedFooter::new(format!(
+                "Showing top {} entries out of {} messages, {}",
+                leaderboard.len(),
+                total,
+                period
+            ))),
+    );
+
+    command.edit_response(&ctx.http, embed).await?;
+    Ok(())
+}
+
+/// Ranks members by average message length.
+async fn verbosity_leaderboard(
+    ctx: &Context,
+    command: &CommandInteraction,
+    guild_id: GuildId,
+    database: Arc<Database>,
+) -> Result<(), Error> {
+    let leaderboard = match database
+        .get_verbosity_leaderboard(guild_id.get(), MIN_VERBOSITY_MESSAGES, 50)
+        .await
+    {
+        Ok(data) => data,
+        Err(e) => {
+            error!(error = %e, "Failed to fetch verbosity leaderboard");
+            command
+                .edit_response(
+                    &ctx.http,
+                    EditInteractionResponse::new()
+                        .content("An error occurred while fetching the leaderboard."),
+                )
+                .await?;
+
+            return Ok(());
+        }
+    };
+
+    let mut description = String::new();
+
+    for (index, (author_id, average_length, message_count, longest_length)) in
+        leaderboard.iter().enumerate()
+    {
+        let entry = format!(
+            "**{}**. <@{}>  -  {:.1} characters on average over {} messages, longest {}\n",
+            index + 1,
+            author_id,
+            average_length,
+            message_count,
+            longest_length
+        );
+
+        if description.len() + entry.len() > MAX_DESCRIPTION_LENGTH {
+            description.push_str("...");
+            break;
+        }
+        description.push_str(&entry);
+    }
+
+    if description.is_empty() {
+        description = format!(
+            "Nobody has sent at least {} messages yet.",
+            MIN_VERBOSITY_MESSAGES
+        );
+    }
+
+    let embed = EditInteractionResponse::new().embed(
+        CreateEmbed::new()
+            .title("Verbosity Leaderboard")
+            .description(format!(
+                "**Server:** {}\n\n{}",
+                guild_id,
+                description.trim_end()
+            ))
+            .color(0x5865F2)
+            .footer(serenity::all::CreateEmbedFooter::new(format!(
+                "Showing top {} entries, members need {} messages to qualify",
+                leaderboard.len(),
+                MIN_VERBOSITY_MESSAGES
+            ))),
+    );
+
+    command.edit_response(&ctx.http, embed).await?;
+    Ok(())
+}
+
+/// Ranks words by how much more they were used this week than last week.
+async fn trending_leaderboard(
+    ctx: &Context,
+    command: &CommandInteraction,
+    guild_id: GuildId,
+    database: Arc<Database>,
+) -> Result<(), Error> {
+    let min_word_length = command
+        .data
+        .options
+        .iter()
+        .find(|opt| opt.name == "min_word_length")
+        .and_then(|opt| opt.value.as_i64())
+        .unwrap_or(3);
+
+    let trending = match database
+        .get_trending_words(
+            guild_id.get(),
+            snowflake_days_ago(TRENDING_WINDOW_DAYS * 2),
+            snowflake_days_ago(TRENDING_WINDOW_DAYS),
+            min_word_length.max(0) as usize,
+            MIN_TRENDING_COUNT,
+        )
+        .await
+    {
+        Ok(data) => data,
+        Err(e) => {
+            error!(error = %e, "Failed to fetch trending words");
+            command
+                .edit_response(
+                    &ctx.http,
+                    EditInteractionResponse::new()
+                        .content("An error occurred while fetching the leaderboard."),
+                )
+                .await?;
+
+            return Ok(());
+        }
+    };
+
+    let mut description = String::new();
+    let mut shown = 0;
+
+    for (index, (word, current, previous)) in trending.iter().take(50).enumerate() {
+        let change = match previous {
+            0 => "🆕 new".to_string(),
+            previous => {
+                let percent = (current - previous) * 100 / previous;
+                match percent {
+                    p if p > 0 => format!("▲ {}%", p),
+                    p if p < 0 => format!("▼ {}%", -p),
+                    _ => "= 0%".to_string(),
+                }
+            }
+        };
+
+        let entry = format!(
+            "**{}**. `{}`  -  {} this week, {} last week  {}\n",
+            index + 1,
+            escape_inline_code(word),
+            current,
+            previous,
+            change
+        );
+
+        if description.len() + entry.len() > MAX_DESCRIPTION_LENGTH {
+            description.push_str("...");
+            break;
+        }
+        description.push_str(&entry);
+        shown += 1;
+    }
+
+    if description.is_empty() {
+        description = format!(
+            "No word was used at least {} times this week.",
+            MIN_TRENDING_COUNT
+        );
+    }
+
+    let embed = EditInteractionResponse::new().embed(
+        CreateEmbed::new()
+            .title("Trending Words")
+            .description(format!(
+                "**Server:** {}\n\n{}",
+                guild_id,
+                description.trim_end()
+            ))
+            .color(0x5865F2)
+            .footer(serenity::all::CreateEmbedFooter::new(format!(
+                "Showing top {} entries, last {} days against the {} before",
+                shown, TRENDING_WINDOW_DAYS, TRENDING_WINDOW_DAYS
+            ))),
+    );
+
+    command.edit_response(&ctx.http, embed).await?;
+    Ok(())
+}
+
 pub fn register() -> CreateCommand {
     CreateCommand::new("leaderboard")
         .description("Get the leaderboard of a server")
+        .add_option(
+            CreateCommandOption::new(CommandOptionType::String, "mode", "What to rank")
+                .add_string_choice("Word usage", "words")
+                .add_string_choice("Messages sent", "messages")
+                .add_string_choice("Average message length", "verbosity")
+                .add_string_choice("Trending this week", "trending"),
+        )
         .add_option(CreateCommandOption::new(
             serenity::all::CommandOptionType::User,
             "user",
             "Get a user's messages",
         ))
-        .add_option(CreateCommandOption::new(
-            CommandOptionType::String,
-            "word",
-            "Get the leaderboard of a word",
-        ))
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::String,
+                "word",
+                "Get the leaderboard of a word",
+            )
+            .max_length(MAX_WORD_LENGTH),
+        )
         .add_option(CreateCommandOption::new(
             CommandOptionType::String,
             "exclude_word",
@@ -143,4 +700,31 @@ pub fn register() -> CreateCommand {
             "min_word_length",
             "Minimum word length to fetch from database",
         ))
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::Integer,
+                "min_users",
+                "Only show words used by at least this many people",
+            )
+            .min_int_value(1),
+        )
+        .add_option(
+            CreateCommandOption::new(CommandOptionType::String, "sort", "How to order the words")
+                .add_string_choice("Most used", "most_used")
+                .add_string_choice("Least used", "least_used")
+                .add_string_choice("Alphabetical", "alphabetical"),
+        )
+        .add_option(
+            CreateCommandOption::new(CommandOptionType::String, "group_by", "What to list")
+                .add_string_choice("Words", "word")
+                .add_string_choice("Users", "user"),
+        )
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::Integer,
+                "days",
+                "Only count messages from the last this many days, for messages sent",
+            )
+            .min_int_value(1),
+        )
 }
@@ -0,0 +1,132 @@
+use serenity::all::{
+    ButtonStyle, ChannelId, CommandInteraction, ComponentInteraction, CreateActionRow,
+    CreateButton, CreateCommand, CreateEmbed, CreateInteractionResponse,
+    CreateInteractionResponseMessage, EditInteractionResponse, GuildId, Timestamp,
+};
+use serenity::prelude::*;
+use serenity::Error;
+use std::sync::Arc;
+
+use crate::database::Database;
+use crate::utils::chain_cache::ChainKey;
+use crate::utils::helpers::{chain_ttl, with_markov_chain, MarkovOutcome};
+use crate::MarkovChainGlobal;
+
+pub async fn execute(
+    ctx: &Context,
+    command: &CommandInteraction,
+    _database: Arc<Database>,
+) -> Result<(), Error> {
+    let guild_id = match command.guild_id {
+        Some(s) => s,
+        _ => return Ok(()),
+    };
+
+    let builder = match stats_embed(ctx, guild_id, command.channel_id).await {
+        Some(embed) => CreateInteractionResponseMessage::new().embed(embed),
+        None => CreateInteractionResponseMessage::new()
+            .content("No chain has been trained for this channel yet.")
+            .components(vec![CreateActionRow::Buttons(vec![CreateButton::new(
+                "markovstats:train",
+            )
+            .label("Train it now")
+            .style(ButtonStyle::Primary)])]),
+    };
+
+    command
+        .create_response(&ctx.http, CreateInteractionResponse::Message(builder))
+        .await
+}
+
+/// Handles the `markovstats:train` button, which trains the channel's chain
+/// and shows its stats in place of the button.
+pub async fn handle_component(
+    ctx: &Context,
+    component: &ComponentInteraction,
+    database: Arc<Database>,
+) -> Result<(), Error> {
+    let guild_id = match component.guild_id {
+        Some(s) => s,
+        _ => return Ok(()),
+    };
+
+    // Training a cold chain can take a while
+    component
+        .create_response(&ctx.http, CreateInteractionResponse::Acknowledge)
+        .await?;
+
+    let outcome = with_markov_chain(ctx, guild_id, component.channel_id, database, false, |_| {
+        String::new()
+    })
+    .await;
+
+    let builder = match (
+        outcome,
+        stats_embed(ctx, guild_id, component.channel_id).await,
+    ) {
+        (MarkovOutcome::Generated(_), Some(embed)) => {
+            EditInteractionResponse::new().content("").embed(embed)
+        }
+        // Evicted again already, or there was no cache to begin with
+        (MarkovOutcome::Generated(_), None) => {
+            EditInteractionResponse::new().content("The chain was trained but isn't cached.")
+        }
+        (outcome, _) => EditInteractionResponse::new().content(outcome.into_content()),
+    };
+
+    component
+        .edit_response(&ctx.http, builder.components(Vec::new()))
+        .await?;
+    Ok(())
+}
+
+/// Stats of the chain cached for the channel, or the guild-wide one it
+/// falls back to. `None` when neither is cached.
+async fn stats_embed(
+    ctx: &Context,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+) -> Option<CreateEmbed> {
+    let cache_lock = ctx.data.read().await.get::<MarkovChainGlobal>().cloned()?;
+    let cache = cache_lock.read().await;
+
+    let (title, cached) = match cache.get(&ChainKey::Channel(channel_id.get())) {
+        Some(cached) => ("Chain Stats for this Channel", cached),
+        None => (
+            "Chain Stats for this Server",
+            cache.get(&ChainKey::Guild(guild_id.get()))?,
+        ),
+    };
+
+    let stats = cached.chain.stats();
+    let trained_at =
+        Timestamp::now().unix_timestamp() - cached.trained_at.elapsed().as_secs() as i64;
+    let sentences = match stats.sentences {
+        0 => "-".to_string(),
+        sentences => sentences.to_string(),
+    };
+
+    Some(
+        CreateEmbed::new()
+            .title(title)
+            .field("Vocabulary", stats.vocabulary.to_string(), true)
+            .field("States", stats.states.to_string(), true)
+            .field("Transitions", stats.transitions.to_string(), true)
+            .field("Sentences", sentences, true)
+            .field("Order", stats.order.to_string(), true)
+            .field(
+                "Trained",
+                format!(
+                    "<t:{}:R>, retrained <t:{}:R>",
+                    trained_at,
+                    trained_at + chain_ttl().as_secs() as i64
+                ),
+                false,
+            )
+            .color(0x5865F2),
+    )
+}
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new("markovstats").description("Shows how big this channel's chain is.")
+}
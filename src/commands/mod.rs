@@ -1,18 +1,36 @@
+pub mod admin;
 pub mod collect;
+pub mod collect_status;
+pub mod config;
+pub mod forgetme;
 pub mod generate;
+pub mod generate_from;
 pub mod guess;
+pub mod guesstop;
+pub mod impersonate;
 pub mod leaderboard;
+pub mod markovstats;
+pub mod optout;
 pub mod ping;
+pub mod purge_guild;
+pub mod quote;
+pub mod reindex;
+pub mod search;
+pub mod stats;
+pub mod userstats;
+pub mod whostyles;
+pub mod word_stats;
 
-use serenity::all::{CommandInteraction, CreateCommand};
+use serenity::all::{CommandInteraction, ComponentInteraction, CreateCommand};
 use serenity::futures::future::BoxFuture;
 use serenity::prelude::*;
 use serenity::Error;
+use std::collections::HashMap;
 use std::sync::Arc;
 
 use crate::database::Database;
 
-type CommandFn = for<'a> fn(
+pub type CommandFn = for<'a> fn(
     &'a Context,            // Command context, `ctx`
     &'a CommandInteraction, // Command interaction, `command`
     Arc<Database>,          // Database connection
@@ -24,6 +42,23 @@ pub struct Command {
     pub exec: CommandFn,
 }
 
+type ComponentFn = for<'a> fn(
+    &'a Context,              // Command context, `ctx`
+    &'a ComponentInteraction, // Component interaction, `component`
+    Arc<Database>,            // Database connection
+) -> BoxFuture<'a, Result<(), Error>>;
+
+/// Handles buttons whose `custom_id` looks like `<prefix>:<args>`.
+///
+/// Everything a handler needs goes into `<args>`, so buttons keep working
+/// across restarts. Ids without a registered prefix are left to whichever
+/// collector created them.
+#[derive(Debug)]
+pub struct Component {
+    pub prefix: String,
+    pub exec: ComponentFn,
+}
+
 pub fn commands_vecs() -> Vec<Command> {
     vec![
         Command {
@@ -34,6 +69,10 @@ pub fn commands_vecs() -> Vec<Command> {
             name: "guess".into(),
             exec: |ctx, command, db| Box::pin(guess::execute(ctx, command, db)),
         },
+        Command {
+            name: "guesstop".into(),
+            exec: |ctx, command, db| Box::pin(guesstop::execute(ctx, command, db)),
+        },
         Command {
             name: "generate".into(),
             exec: |ctx, command, db| Box::pin(generate::execute(ctx, command, db)),
@@ -46,6 +85,111 @@ pub fn commands_vecs() -> Vec<Command> {
             name: "collect".into(),
             exec: |ctx, command, db| Box::pin(collect::execute(ctx, command, db)),
         },
+        Command {
+            name: "admin".into(),
+            exec: |ctx, command, db| Box::pin(admin::execute(ctx, command, db)),
+        },
+        Command {
+            name: "reindex".into(),
+            exec: |ctx, command, db| Box::pin(reindex::execute(ctx, command, db)),
+        },
+        Command {
+            name: "collect-status".into(),
+            exec: |ctx, command, db| Box::pin(collect_status::execute(ctx, command, db)),
+        },
+        Command {
+            name: "config".into(),
+            exec: |ctx, command, db| Box::pin(config::execute(ctx, command, db)),
+        },
+        Command {
+            name: generate_from::NAME.into(),
+            exec: |ctx, command, db| Box::pin(generate_from::execute(ctx, command, db)),
+        },
+        Command {
+            name: word_stats::NAME.into(),
+            exec: |ctx, command, db| Box::pin(word_stats::execute(ctx, command, db)),
+        },
+        Command {
+            name: "impersonate".into(),
+            exec: |ctx, command, db| Box::pin(impersonate::execute(ctx, command, db)),
+        },
+        Command {
+            name: "whostyles".into(),
+            exec: |ctx, command, db| Box::pin(whostyles::execute(ctx, command, db)),
+        },
+        Command {
+            name: whostyles::MESSAGE_COMMAND_NAME.into(),
+            exec: |ctx, command, db| Box::pin(whostyles::execute_message(ctx, command, db)),
+        },
+        Command {
+            name: "markovstats".into(),
+            exec: |ctx, command, db| Box::pin(markovstats::execute(ctx, command, db)),
+        },
+        Command {
+            name: "quote".into(),
+            exec: |ctx, command, db| Box::pin(quote::execute(ctx, command, db)),
+        },
+        Command {
+            name: "search".into(),
+            exec: |ctx, command, db| Box::pin(search::execute(ctx, command, db)),
+        },
+        Command {
+            name: "stats".into(),
+            exec: |ctx, command, db| Box::pin(stats::execute(ctx, command, db)),
+        },
+        Command {
+            name: "userstats".into(),
+            exec: |ctx, command, db| Box::pin(userstats::execute(ctx, command, db)),
+        },
+        Command {
+            name: "purge-guild".into(),
+            exec: |ctx, command, db| Box::pin(purge_guild::execute(ctx, command, db)),
+        },
+        Command {
+            name: "forgetme".into(),
+            exec: |ctx, command, db| Box::pin(forgetme::execute(ctx, command, db)),
+        },
+        Command {
+            name: "optout".into(),
+            exec: |ctx, command, db| Box::pin(optout::execute(ctx, command, db)),
+        },
+        Command {
+            name: optout::OPT_IN_NAME.into(),
+            exec: |ctx, command, db| Box::pin(optout::execute_optin(ctx, command, db)),
+        },
+    ]
+}
+
+/// Every command's handler by name, for looking up interactions.
+pub fn commands_map() -> HashMap<String, CommandFn> {
+    commands_vecs()
+        .into_iter()
+        .map(|command| (command.name, command.exec))
+        .collect()
+}
+
+pub fn components_vecs() -> Vec<Component> {
+    vec![
+        Component {
+            prefix: "generate".into(),
+            exec: |ctx, component, db| Box::pin(generate::handle_component(ctx, component, db)),
+        },
+        Component {
+            prefix: "leaderboard".into(),
+            exec: |ctx, component, db| Box::pin(leaderboard::handle_component(ctx, component, db)),
+        },
+        Component {
+            prefix: "markovstats".into(),
+            exec: |ctx, component, db| Box::pin(markovstats::handle_component(ctx, component, db)),
+        },
+        Component {
+            prefix: "guess".into(),
+            exec: |ctx, component, db| Box::pin(guess::handle_component(ctx, component, db)),
+        },
+        Component {
+            prefix: "forgetme".into(),
+            exec: |ctx, component, db| Box::pin(forgetme::handle_component(ctx, component, db)),
+        },
     ]
 }
 
@@ -55,6 +199,25 @@ pub fn register_vecs() -> Vec<CreateCommand> {
         generate::register(),
         leaderboard::register(),
         guess::register(),
+        guesstop::register(),
         collect::register(),
+        admin::register(),
+        reindex::register(),
+        config::register(),
+        collect_status::register(),
+        generate_from::register(),
+        word_stats::register(),
+        impersonate::register(),
+        whostyles::register(),
+        whostyles::register_message(),
+        markovstats::register(),
+        quote::register(),
+        search::register(),
+        stats::register(),
+        userstats::register(),
+        purge_guild::register(),
+        forgetme::register(),
+        optout::register(),
+        optout::register_optin(),
     ]
 }
@@ -0,0 +1,163 @@
+use std::sync::Arc;
+
+use serenity::all::{
+    CommandDataOptionValue, CommandInteraction, CommandOptionType, CreateActionRow, CreateCommand,
+    CreateCommandOption, CreateInteractionResponse, CreateInteractionResponseMessage,
+};
+use serenity::prelude::*;
+use serenity::Error;
+use tracing::error;
+
+use crate::commands::forgetme;
+use crate::database::Database;
+
+pub const OPT_IN_NAME: &str = "optin";
+
+/// `/optout`, stops storing the invoker's messages from now on, and offers
+/// to delete what's already stored.
+pub async fn execute(
+    ctx: &Context,
+    command: &CommandInteraction,
+    database: Arc<Database>,
+) -> Result<(), Error> {
+    let global = bool_option(command, "global");
+    let purge = bool_option(command, "purge");
+
+    let Some(scope) = scope(command, global) else {
+        return reply(ctx, command, "Use this in a server, or set `global`.", None).await;
+    };
+
+    if let Err(e) = database
+        .set_opted_out(scope, command.user.id.get(), true)
+        .await
+    {
+        error!(error = %e, "Failed to opt out user");
+        return reply(
+            ctx,
+            command,
+            "An error occurred while saving your choice.",
+            None,
+        )
+        .await;
+    }
+
+    let where_ = if global {
+        "in any server"
+    } else {
+        "in this server"
+    };
+
+    if purge {
+        let content = format!(
+            "I won't store your messages {} anymore. Delete the ones already stored too? This can't be undone.",
+            where_
+        );
+        let buttons = forgetme::confirm_buttons(command.user.id, command.user.id, global);
+        return reply(ctx, command, content, Some(buttons)).await;
+    }
+
+    let content = format!(
+        "I won't store your messages {} anymore, and the ones already stored won't be used. Use `/forgetme` to delete them.",
+        where_
+    );
+    reply(ctx, command, content, None).await
+}
+
+/// `/optin`, undoes an `/optout` with the same scope.
+pub async fn execute_optin(
+    ctx: &Context,
+    command: &CommandInteraction,
+    database: Arc<Database>,
+) -> Result<(), Error> {
+    let global = bool_option(command, "global");
+
+    let Some(scope) = scope(command, global) else {
+        return reply(ctx, command, "Use this in a server, or set `global`.", None).await;
+    };
+
+    if let Err(e) = database
+        .set_opted_out(scope, command.user.id.get(), false)
+        .await
+    {
+        error!(error = %e, "Failed to opt in user");
+        return reply(
+            ctx,
+            command,
+            "An error occurred while saving your choice.",
+            None,
+        )
+        .await;
+    }
+
+    // A server opt-in doesn't lift a global opt-out
+    let guild_id = command.guild_id.map_or(0, |guild_id| guild_id.get());
+    let content = if database.is_opted_out(guild_id, command.user.id.get()) {
+        "Done, but you're still opted out everywhere. Use `/optin global:True` to undo that."
+    } else {
+        "I'll store your messages again."
+    };
+
+    reply(ctx, command, content, None).await
+}
+
+fn bool_option(command: &CommandInteraction, name: &str) -> bool {
+    command
+        .data
+        .options
+        .iter()
+        .find(|option| option.name == name)
+        .is_some_and(|option| matches!(option.value, CommandDataOptionValue::Boolean(true)))
+}
+
+/// The guild the choice applies to, `Some(None)` for every guild. `None`
+/// outside of servers unless it's global.
+fn scope(command: &CommandInteraction, global: bool) -> Option<Option<u64>> {
+    if global {
+        return Some(None);
+    }
+
+    command.guild_id.map(|guild_id| Some(guild_id.get()))
+}
+
+async fn reply(
+    ctx: &Context,
+    command: &CommandInteraction,
+    content: impl Into<String>,
+    buttons: Option<CreateActionRow>,
+) -> Result<(), Error> {
+    let mut builder = CreateInteractionResponseMessage::new()
+        .content(content)
+        .ephemeral(true);
+    if let Some(buttons) = buttons {
+        builder = builder.components(vec![buttons]);
+    }
+
+    command
+        .create_response(&ctx.http, CreateInteractionResponse::Message(builder))
+        .await
+}
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new("optout")
+        .description("Stop me from storing your messages.")
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::Boolean,
+            "global",
+            "Opt out in every server I'm in, not just this one.",
+        ))
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::Boolean,
+            "purge",
+            "Also delete the messages already stored.",
+        ))
+}
+
+pub fn register_optin() -> CreateCommand {
+    CreateCommand::new(OPT_IN_NAME)
+        .description("Let me store your messages again.")
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::Boolean,
+            "global",
+            "Undo a global opt out.",
+        ))
+}
@@ -1,9 +1,12 @@
 use std::time::Instant;
 
-use serenity::all::{CommandInteraction, CreateCommand, EditInteractionResponse};
+use serenity::all::{CommandInteraction, CreateCommand, CreateEmbed, EditInteractionResponse};
 use serenity::prelude::*;
 use serenity::Error;
 
+use crate::utils::helpers::format_duration;
+use crate::{ShardManagerGlobal, StartedAtGlobal};
+
 pub async fn execute(ctx: &Context, command: &CommandInteraction) -> Result<(), Error> {
     command.defer(&ctx.http).await?;
     let timer_start = Instant::now();
@@ -14,7 +17,47 @@ pub async fn execute(ctx: &Context, command: &CommandInteraction) -> Result<(),
 
     let elapsed = (Instant::now() - timer_start).as_millis();
 
-    let builder = EditInteractionResponse::new().content(format!("{} ({:2}ms)", content, elapsed));
+    let (shard_manager, started_at) = {
+        let data_read = ctx.data.read().await;
+        (
+            data_read.get::<ShardManagerGlobal>().cloned(),
+            data_read.get::<StartedAtGlobal>().copied(),
+        )
+    };
+
+    // The runner only knows its latency once a heartbeat was acknowledged
+    let gateway_latency = match shard_manager {
+        Some(shard_manager) => shard_manager
+            .runners
+            .lock()
+            .await
+            .get(&ctx.shard_id)
+            .and_then(|runner| runner.latency),
+        None => None,
+    };
+    let gateway_latency = match gateway_latency {
+        Some(latency) => format!("{}ms", latency.as_millis()),
+        None => "Unknown".to_string(),
+    };
+
+    let uptime = match started_at {
+        Some(started_at) => format_duration(started_at.elapsed().as_secs() as i64),
+        None => "Unknown".to_string(),
+    };
+
+    let embed = CreateEmbed::new()
+        .title(content)
+        .field("REST latency", format!("{}ms", elapsed), true)
+        .field("Gateway latency", gateway_latency, true)
+        .field(
+            "Shard",
+            format!("{}/{}", ctx.shard_id.0, ctx.cache.shard_count()),
+            true,
+        )
+        .field("Guilds", ctx.cache.guild_count().to_string(), true)
+        .field("Uptime", uptime, true);
+
+    let builder = EditInteractionResponse::new().content("").embed(embed);
     command.edit_response(&ctx.http, builder).await?;
     Ok(())
 }
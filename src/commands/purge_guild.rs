@@ -0,0 +1,70 @@
+use std::sync::Arc;
+
+use serenity::all::{
+    CommandDataOptionValue, CommandInteraction, CommandOptionType, CreateCommand,
+    CreateCommandOption, EditInteractionResponse, GuildId,
+};
+use serenity::prelude::*;
+use serenity::Error;
+use tracing::{error, info};
+
+use crate::database::Database;
+use crate::utils::helpers::{is_bot_owner, purge_guild_data};
+
+pub async fn execute(
+    ctx: &Context,
+    command: &CommandInteraction,
+    database: Arc<Database>,
+) -> Result<(), Error> {
+    command.defer_ephemeral(&ctx.http).await?;
+
+    if !is_bot_owner(ctx, command.user.id).await {
+        return reply(ctx, command, "Only the bot owner can use this command.").await;
+    }
+
+    // Defaults to the guild it's used in
+    let guild_id = match command.data.options.first().map(|option| &option.value) {
+        Some(CommandDataOptionValue::String(value)) => match value.trim().parse::<u64>() {
+            Ok(id) if id > 0 => GuildId::new(id),
+            _ => return reply(ctx, command, "That isn't a valid guild ID.").await,
+        },
+        _ => match command.guild_id {
+            Some(guild_id) => guild_id,
+            None => return reply(ctx, command, "Pass the ID of the guild to purge.").await,
+        },
+    };
+
+    let content = match purge_guild_data(ctx, &database, guild_id).await {
+        Ok(rows) => {
+            info!(guild_id = %guild_id, rows, "Purged guild by hand");
+            format!("Purged guild {}, {} rows removed.", guild_id, rows)
+        }
+        Err(e) => {
+            error!(guild_id = %guild_id, error = %e, "Failed to purge guild");
+            "An error occurred while purging the guild.".to_string()
+        }
+    };
+
+    reply(ctx, command, content).await
+}
+
+async fn reply(
+    ctx: &Context,
+    command: &CommandInteraction,
+    content: impl Into<String>,
+) -> Result<(), Error> {
+    command
+        .edit_response(&ctx.http, EditInteractionResponse::new().content(content))
+        .await?;
+    Ok(())
+}
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new("purge-guild")
+        .description("Owner-only: delete everything stored for a guild.")
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::String,
+            "guild",
+            "ID of the guild to purge, defaults to this one.",
+        ))
+}
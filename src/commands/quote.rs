@@ -0,0 +1,214 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::StreamExt;
+use serenity::all::{
+    ButtonStyle, CommandInteraction, CommandOptionType, CreateButton, CreateCommand,
+    CreateCommandOption, CreateEmbed, CreateEmbedAuthor, CreateInteractionResponse,
+    CreateInteractionResponseMessage, EditInteractionResponse, GuildId, MessageId, UserId,
+};
+use serenity::prelude::*;
+use serenity::Error;
+use tracing::error;
+
+use crate::database::{Database, RandomMessageFilter, StoredMessage};
+use crate::utils::content::truncate_at_word_boundary;
+use crate::utils::helpers::EMBED_DESCRIPTION_CHAR_LIMIT;
+
+const REROLL_BUTTON_ID: &str = "quote_reroll";
+
+/// How long "Another one" keeps working after the last press.
+const REROLL_WINDOW: Duration = Duration::from_secs(3 * 60);
+
+pub async fn execute(
+    ctx: &Context,
+    command: &CommandInteraction,
+    database: Arc<Database>,
+) -> Result<(), Error> {
+    command.defer(&ctx.http).await?;
+
+    let guild_id = match command.guild_id {
+        Some(s) => s,
+        _ => return Ok(()),
+    };
+
+    let options = &command.data.options;
+    let filter = RandomMessageFilter {
+        min_length: 1,
+        author_id: options
+            .iter()
+            .find(|opt| opt.name == "user")
+            .and_then(|opt| opt.value.as_user_id())
+            .map(|user_id| user_id.get()),
+        contains: options
+            .iter()
+            .find(|opt| opt.name == "contains")
+            .and_then(|opt| opt.value.as_str())
+            .map(str::trim)
+            .filter(|text| !text.is_empty())
+            .map(str::to_string),
+        ..Default::default()
+    };
+
+    let message = match database.get_random_message(guild_id.get(), &filter).await {
+        Ok(Some(message)) => message,
+        Ok(None) => {
+            command
+                .edit_response(
+                    &ctx.http,
+                    EditInteractionResponse::new().content("No stored messages match that."),
+                )
+                .await?;
+            return Ok(());
+        }
+        Err(e) => {
+            error!(error = %e, "Failed to pick a quote");
+            command
+                .edit_response(
+                    &ctx.http,
+                    EditInteractionResponse::new()
+                        .content("An error occurred while picking a message."),
+                )
+                .await?;
+            return Ok(());
+        }
+    };
+
+    let embed = quote_embed(ctx, guild_id, &message).await;
+    command
+        .edit_response(
+            &ctx.http,
+            EditInteractionResponse::new()
+                .embed(embed)
+                .button(reroll_button(false)),
+        )
+        .await?;
+
+    // Re-rolling can go on for minutes, don't hold up the handler
+    tokio::spawn(watch_for_rerolls(
+        ctx.clone(),
+        command.clone(),
+        database,
+        filter,
+        message.message_id,
+    ));
+
+    Ok(())
+}
+
+/// Swaps in another matching message on every "Another one" press, until
+/// nobody pressed it for `REROLL_WINDOW`.
+async fn watch_for_rerolls(
+    ctx: Context,
+    command: CommandInteraction,
+    database: Arc<Database>,
+    mut filter: RandomMessageFilter,
+    shown_id: u64,
+) {
+    let Some(guild_id) = command.guild_id else {
+        return;
+    };
+
+    let response = match command.get_response(&ctx.http).await {
+        Ok(response) => response,
+        Err(e) => {
+            error!(error = %e, "Failed to fetch quote message");
+            return;
+        }
+    };
+
+    // Messages already shown aren't picked again
+    filter.excluded_ids.push(shown_id);
+
+    let mut interactions = response
+        .await_component_interaction(&ctx.shard)
+        .custom_ids(vec![REROLL_BUTTON_ID.to_string()])
+        .timeout(REROLL_WINDOW)
+        .stream();
+
+    while let Some(interaction) = interactions.next().await {
+        let builder = match database.get_random_message(guild_id.get(), &filter).await {
+            Ok(Some(message)) => {
+                filter.excluded_ids.push(message.message_id);
+                CreateInteractionResponse::UpdateMessage(
+                    CreateInteractionResponseMessage::new()
+                        .embed(quote_embed(&ctx, guild_id, &message).await),
+                )
+            }
+            Ok(None) => CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .content("That was the last matching message.")
+                    .ephemeral(true),
+            ),
+            Err(e) => {
+                error!(error = %e, "Failed to pick a quote");
+                CreateInteractionResponse::Message(
+                    CreateInteractionResponseMessage::new()
+                        .content("An error occurred while picking a message.")
+                        .ephemeral(true),
+                )
+            }
+        };
+
+        if let Err(e) = interaction.create_response(&ctx.http, builder).await {
+            error!(error = %e, "Failed to respond to quote button");
+        }
+    }
+
+    let builder = EditInteractionResponse::new().button(reroll_button(true));
+    if let Err(e) = command.edit_response(&ctx.http, builder).await {
+        error!(error = %e, "Failed to disable quote button");
+    }
+}
+
+async fn quote_embed(ctx: &Context, guild_id: GuildId, message: &StoredMessage) -> CreateEmbed {
+    // Someone who left may not be fetchable anymore
+    let author = match UserId::new(message.author_id).to_user(ctx).await {
+        Ok(user) => CreateEmbedAuthor::new(user.display_name()).icon_url(user.face()),
+        Err(_) => CreateEmbedAuthor::new(message.author_id.to_string()),
+    };
+
+    // Leave room for the jump link
+    let mut content =
+        truncate_at_word_boundary(&message.content, EMBED_DESCRIPTION_CHAR_LIMIT - 200);
+    if message.truncated && !content.ends_with('…') {
+        content.push('…');
+    }
+
+    let quoted = content
+        .lines()
+        .map(|line| format!("> {}", line))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    CreateEmbed::new()
+        .author(author)
+        .description(format!(
+            "{}\n\n[Jump to message](https://discord.com/channels/{}/{}/{}) in <#{}>",
+            quoted, guild_id, message.channel_id, message.message_id, message.channel_id
+        ))
+        .timestamp(MessageId::new(message.message_id).created_at())
+        .color(0x5865F2)
+}
+
+fn reroll_button(disabled: bool) -> CreateButton {
+    CreateButton::new(REROLL_BUTTON_ID)
+        .label("Another one")
+        .style(ButtonStyle::Secondary)
+        .disabled(disabled)
+}
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new("quote")
+        .description("Posts a random stored message.")
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::User,
+            "user",
+            "Only quote this member",
+        ))
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::String,
+            "contains",
+            "Only quote messages containing this",
+        ))
+}
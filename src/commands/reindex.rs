@@ -0,0 +1,141 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use serenity::all::{
+    CommandInteraction, CreateCommand, CreateEmbed, EditInteractionResponse, Permissions,
+};
+use serenity::prelude::*;
+use serenity::Error;
+use tokio::sync::watch;
+use tracing::error;
+
+use crate::database::Database;
+use crate::utils::helpers::is_bot_owner;
+
+pub async fn execute(
+    ctx: &Context,
+    command: &CommandInteraction,
+    database: Arc<Database>,
+) -> Result<(), Error> {
+    command.defer(&ctx.http).await?;
+
+    let guild_id = match command.guild_id {
+        Some(s) => s,
+        _ => return Ok(()),
+    };
+
+    let can_manage_guild = command
+        .member
+        .as_ref()
+        .and_then(|member| member.permissions)
+        .is_some_and(|permissions| permissions.manage_guild());
+
+    if !can_manage_guild && !is_bot_owner(ctx, command.user.id).await {
+        command
+            .edit_response(
+                &ctx.http,
+                EditInteractionResponse::new()
+                    .content("You need the Manage Server permission to use this command."),
+            )
+            .await?;
+        return Ok(());
+    }
+
+    let before = match database.count_derived_rows(guild_id.get()).await {
+        Ok(counts) => counts,
+        Err(e) => {
+            error!(error = %e, "Failed to count derived rows");
+            command
+                .edit_response(
+                    &ctx.http,
+                    EditInteractionResponse::new()
+                        .content("An error occurred while reading the database."),
+                )
+                .await?;
+            return Ok(());
+        }
+    };
+
+    command
+        .edit_response(
+            &ctx.http,
+            EditInteractionResponse::new().content("**Reindexing...**\n\nScanning messages."),
+        )
+        .await?;
+
+    let (progress_tx, progress_rx) = watch::channel(0);
+    let rebuild = database.rebuild_derived_tables(guild_id.get(), |scanned| {
+        let _ = progress_tx.send(scanned);
+    });
+    tokio::pin!(rebuild);
+
+    let result = loop {
+        tokio::select! {
+            result = &mut rebuild => break result,
+            _ = tokio::time::sleep(Duration::from_secs(5)) => {
+                let progress_message = format!(
+                    "**Reindexing...**\n\nMessages scanned: {}",
+                    *progress_rx.borrow()
+                );
+
+                if let Err(e) = command
+                    .edit_response(
+                        &ctx.http,
+                        EditInteractionResponse::new().content(progress_message),
+                    )
+                    .await
+                {
+                    error!(error = %e, "Failed to update Discord progress");
+                }
+            }
+        }
+    };
+
+    if let Err(e) = result {
+        error!(error = %e, "Failed to rebuild derived tables");
+        command
+            .edit_response(
+                &ctx.http,
+                EditInteractionResponse::new().content(
+                    "An error occurred while rebuilding. Run /reindex again to finish it.",
+                ),
+            )
+            .await?;
+        return Ok(());
+    }
+
+    let after = database
+        .count_derived_rows(guild_id.get())
+        .await
+        .unwrap_or_default();
+
+    let embed = CreateEmbed::new()
+        .title("Reindex Complete")
+        .description(format!("Messages scanned: {}", *progress_rx.borrow()))
+        .field(
+            "Word counts",
+            format!("{} → {} rows", before.0, after.0),
+            true,
+        )
+        .field(
+            "Channel stats",
+            format!("{} → {} rows", before.1, after.1),
+            true,
+        )
+        .color(0x57F287);
+
+    command
+        .edit_response(
+            &ctx.http,
+            EditInteractionResponse::new().content("").embed(embed),
+        )
+        .await?;
+
+    Ok(())
+}
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new("reindex")
+        .description("Rebuild word and channel statistics from stored messages.")
+        .default_member_permissions(Permissions::MANAGE_GUILD)
+}
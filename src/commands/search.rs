@@ -0,0 +1,95 @@
+use serenity::all::{
+    CommandInteraction, CommandOptionType, CreateCommand, CreateCommandOption, CreateEmbed,
+    EditInteractionResponse,
+};
+use serenity::prelude::*;
+use serenity::Error;
+use std::sync::Arc;
+use tracing::error;
+
+use crate::database::Database;
+use crate::utils::content::truncate_at_word_boundary;
+use crate::utils::escape::escape_markdown;
+
+/// How many matches are listed.
+const MAX_RESULTS: i64 = 10;
+
+/// Longest excerpt shown per match, the `LIKE` fallback returns whole
+/// messages.
+const MAX_EXCERPT_LENGTH: usize = 200;
+
+pub async fn execute(
+    ctx: &Context,
+    command: &CommandInteraction,
+    database: Arc<Database>,
+) -> Result<(), Error> {
+    command.defer(&ctx.http).await?;
+
+    let guild_id = match command.guild_id {
+        Some(s) => s,
+        _ => return Ok(()),
+    };
+
+    let query = command
+        .data
+        .options
+        .iter()
+        .find(|opt| opt.name == "query")
+        .and_then(|opt| opt.value.as_str())
+        .unwrap_or_default();
+
+    let results = match database
+        .search_messages(guild_id.get(), query, MAX_RESULTS)
+        .await
+    {
+        Ok(results) => results,
+        Err(e) => {
+            error!(error = %e, "Failed to search messages");
+            command
+                .edit_response(
+                    &ctx.http,
+                    EditInteractionResponse::new()
+                        .content("An error occurred while searching the messages."),
+                )
+                .await?;
+
+            return Ok(());
+        }
+    };
+
+    let mut description = String::new();
+    for (message_id, channel_id, author_id, snippet) in &results {
+        let excerpt = truncate_at_word_boundary(&snippet.replace('\n', " "), MAX_EXCERPT_LENGTH);
+        // The snippet's bold markers are the only markdown kept
+        let excerpt = escape_markdown(&excerpt.replace("**", "\u{0}")).replace('\u{0}', "**");
+
+        description.push_str(&format!(
+            "<@{}>: {} [Jump](https://discord.com/channels/{}/{}/{})\n",
+            author_id, excerpt, guild_id, channel_id, message_id
+        ));
+    }
+
+    if description.is_empty() {
+        description = "No stored messages contain all of those words.".to_string();
+    }
+
+    let embed = CreateEmbed::new()
+        .title(format!("Search results for \"{}\"", query))
+        .description(description.trim_end())
+        .color(0x5865F2);
+
+    command
+        .edit_response(&ctx.http, EditInteractionResponse::new().embed(embed))
+        .await?;
+    Ok(())
+}
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new("search")
+        .description("Finds stored messages containing every given word.")
+        .add_option(
+            CreateCommandOption::new(CommandOptionType::String, "query", "Words to look for")
+                .required(true)
+                .max_length(100),
+        )
+}
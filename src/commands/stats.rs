@@ -0,0 +1,121 @@
+use std::sync::Arc;
+
+use serenity::all::{
+    CommandInteraction, CreateCommand, CreateEmbed, EditInteractionResponse, Timestamp,
+};
+use serenity::prelude::*;
+use serenity::Error;
+use tracing::error;
+
+use crate::database::Database;
+use crate::utils::chain_cache::ChainKey;
+use crate::MarkovChainGlobal;
+
+/// How many channels the busiest-channels field lists.
+const TOP_CHANNELS: i64 = 3;
+
+const WEEK_SECS: i64 = 7 * 24 * 60 * 60;
+
+pub async fn execute(
+    ctx: &Context,
+    command: &CommandInteraction,
+    database: Arc<Database>,
+) -> Result<(), Error> {
+    command.defer(&ctx.http).await?;
+
+    let guild_id = match command.guild_id {
+        Some(s) => s,
+        _ => return Ok(()),
+    };
+
+    let week_ago = Timestamp::now().unix_timestamp() - WEEK_SECS;
+    let stats = tokio::try_join!(
+        database.get_guild_stats(guild_id.get()),
+        database.get_top_channels(guild_id.get(), TOP_CHANNELS),
+        database.get_message_count_since(guild_id.get(), week_ago),
+    );
+
+    let (stats, top_channels, this_week) = match stats {
+        Ok(stats) => stats,
+        Err(e) => {
+            error!(error = %e, "Failed to fetch guild stats");
+            command
+                .edit_response(
+                    &ctx.http,
+                    EditInteractionResponse::new()
+                        .content("An error occurred while fetching this server's stats."),
+                )
+                .await?;
+
+            return Ok(());
+        }
+    };
+
+    let channels = match top_channels.is_empty() {
+        true => "-".to_string(),
+        false => top_channels
+            .iter()
+            .map(|(channel_id, count)| format!("<#{}>: {} messages", channel_id, count))
+            .collect::<Vec<_>>()
+            .join("\n"),
+    };
+
+    let span = match (stats.oldest_message_at, stats.newest_message_at) {
+        (Some(oldest), Some(newest)) => format!("<t:{}:D> to <t:{}:D>", oldest, newest),
+        _ => "-".to_string(),
+    };
+
+    let chain_cached = match ctx.data.read().await.get::<MarkovChainGlobal>().cloned() {
+        Some(cache) => cache
+            .read()
+            .await
+            .get(&ChainKey::Channel(command.channel_id.get()))
+            .is_some(),
+        None => false,
+    };
+
+    let embed = CreateEmbed::new()
+        .title("Server Stats")
+        .field("Messages", stats.messages.to_string(), true)
+        .field("Messages this week", this_week.to_string(), true)
+        .field("Authors", stats.authors.to_string(), true)
+        .field("Distinct words", stats.words.to_string(), true)
+        .field("Stored text", format_bytes(stats.content_bytes), true)
+        .field(
+            "Chain for this channel",
+            match chain_cached {
+                true => "Cached",
+                false => "Not cached",
+            },
+            true,
+        )
+        .field("Messages from", span, false)
+        .field("Busiest channels", channels, false)
+        .color(0x5865F2);
+
+    command
+        .edit_response(&ctx.http, EditInteractionResponse::new().embed(embed))
+        .await?;
+    Ok(())
+}
+
+/// Formats a byte count like `1.5 MB`.
+fn format_bytes(bytes: i64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+
+    let mut size = bytes.max(0) as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    match unit {
+        0 => format!("{} B", bytes.max(0)),
+        _ => format!("{:.1} {}", size, UNITS[unit]),
+    }
+}
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new("stats").description("Shows what's stored for this server.")
+}
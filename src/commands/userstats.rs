@@ -0,0 +1,119 @@
+use serenity::all::{
+    CommandInteraction, CommandOptionType, CreateCommand, CreateCommandOption, CreateEmbed,
+    EditInteractionResponse,
+};
+use serenity::prelude::*;
+use serenity::Error;
+use std::sync::Arc;
+use tracing::error;
+
+use crate::database::Database;
+use crate::utils::escape::{escape_inline_code, escape_markdown};
+
+/// How many of the member's words are listed.
+const TOP_WORDS: i64 = 10;
+
+pub async fn execute(
+    ctx: &Context,
+    command: &CommandInteraction,
+    database: Arc<Database>,
+) -> Result<(), Error> {
+    command.defer(&ctx.http).await?;
+
+    let guild_id = match command.guild_id {
+        Some(s) => s,
+        _ => return Ok(()),
+    };
+
+    let user = command
+        .data
+        .options
+        .iter()
+        .find(|opt| opt.name == "user")
+        .and_then(|opt| opt.value.as_user_id())
+        .and_then(|user_id| command.data.resolved.users.get(&user_id))
+        .unwrap_or(&command.user);
+
+    let stats = tokio::try_join!(
+        database.get_user_message_stats(guild_id.get(), user.id.get()),
+        database.get_user_top_words(guild_id.get(), user.id.get(), TOP_WORDS),
+        database.get_user_top_channel(guild_id.get(), user.id.get()),
+    );
+
+    let (stats, words, top_channel) = match stats {
+        Ok(stats) => stats,
+        Err(e) => {
+            error!(error = %e, "Failed to fetch user stats");
+            command
+                .edit_response(
+                    &ctx.http,
+                    EditInteractionResponse::new()
+                        .content("An error occurred while fetching the stats."),
+                )
+                .await?;
+
+            return Ok(());
+        }
+    };
+
+    if stats.messages == 0 {
+        command
+            .edit_response(
+                &ctx.http,
+                EditInteractionResponse::new().content(format!(
+                    "No messages from {} are stored yet.",
+                    escape_markdown(user.display_name())
+                )),
+            )
+            .await?;
+        return Ok(());
+    }
+
+    let mut description = String::new();
+    for (index, (word, count)) in words.iter().enumerate() {
+        description.push_str(&format!(
+            "**{}**. `{}`  -  {} uses\n",
+            index + 1,
+            escape_inline_code(word),
+            count
+        ));
+    }
+
+    if description.is_empty() {
+        description = "No words recorded yet.".to_string();
+    }
+
+    let top_channel = match top_channel {
+        Some((channel_id, count)) => format!("<#{}> ({} messages)", channel_id, count),
+        None => "-".to_string(),
+    };
+
+    let embed = CreateEmbed::new()
+        .title(format!("Stats for {}", user.display_name()))
+        .thumbnail(user.face())
+        .description(description.trim_end())
+        .field("Messages", stats.messages.to_string(), true)
+        .field("Vocabulary", format!("{} words", stats.vocabulary), true)
+        .field(
+            "Average length",
+            format!("{:.1} characters", stats.average_length),
+            true,
+        )
+        .field("Most active in", top_channel, false)
+        .color(0x5865F2);
+
+    command
+        .edit_response(&ctx.http, EditInteractionResponse::new().embed(embed))
+        .await?;
+    Ok(())
+}
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new("userstats")
+        .description("Shows a member's stored messages and favourite words.")
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::User,
+            "user",
+            "Whose stats to show, yourself if left out",
+        ))
+}
@@ -0,0 +1,160 @@
+use serenity::all::{
+    CommandInteraction, CommandOptionType, CommandType, CreateCommand, CreateCommandOption,
+    CreateEmbed, CreateInteractionResponse, CreateInteractionResponseMessage,
+    EditInteractionResponse, ResolvedTarget, UserId,
+};
+use serenity::prelude::*;
+use serenity::Error;
+use std::sync::Arc;
+use tracing::error;
+
+use crate::database::Database;
+use crate::utils::style::{guess_author, MIN_STYLE_MESSAGES};
+
+/// Context menu commands are invoked by name, so it's shown as-is.
+pub const MESSAGE_COMMAND_NAME: &str = "Who writes like this?";
+
+/// Longest accepted `text`.
+const MAX_TEXT_LENGTH: u16 = 1000;
+
+pub async fn execute(
+    ctx: &Context,
+    command: &CommandInteraction,
+    database: Arc<Database>,
+) -> Result<(), Error> {
+    command.defer(&ctx.http).await?;
+
+    let guild_id = match command.guild_id {
+        Some(s) => s,
+        _ => return Ok(()),
+    };
+
+    let text = command
+        .data
+        .options
+        .iter()
+        .find(|opt| opt.name == "text")
+        .and_then(|opt| opt.value.as_str())
+        .unwrap_or_default();
+
+    let builder = match guess_author(ctx, guild_id, text, database).await {
+        Ok(candidates) if candidates.is_empty() => no_candidates(),
+        Ok(candidates) => EditInteractionResponse::new().embed(candidates_embed(&candidates, None)),
+        Err(e) => {
+            error!(error = %e, "Failed to guess author");
+            EditInteractionResponse::new().content("An error occurred while comparing styles.")
+        }
+    };
+
+    command.edit_response(&ctx.http, builder).await?;
+    Ok(())
+}
+
+/// The message context menu entry, which classifies the selected message and
+/// tells whether the guess was right without naming the author.
+pub async fn execute_message(
+    ctx: &Context,
+    command: &CommandInteraction,
+    database: Arc<Database>,
+) -> Result<(), Error> {
+    command
+        .create_response(
+            &ctx.http,
+            CreateInteractionResponse::Defer(
+                CreateInteractionResponseMessage::new().ephemeral(true),
+            ),
+        )
+        .await?;
+
+    let guild_id = match command.guild_id {
+        Some(s) => s,
+        _ => return Ok(()),
+    };
+
+    let Some(ResolvedTarget::Message(target)) = command.data.target() else {
+        return Ok(());
+    };
+
+    let builder = match guess_author(ctx, guild_id, &target.content, database).await {
+        Ok(candidates) if candidates.is_empty() => no_candidates(),
+        Ok(candidates) => EditInteractionResponse::new()
+            .embed(candidates_embed(&candidates, Some(target.author.id))),
+        Err(e) => {
+            error!(error = %e, "Failed to guess author");
+            EditInteractionResponse::new().content("An error occurred while comparing styles.")
+        }
+    };
+
+    command.edit_response(&ctx.http, builder).await?;
+    Ok(())
+}
+
+fn no_candidates() -> EditInteractionResponse {
+    EditInteractionResponse::new().content(format!(
+        "Not enough to go on, either the text has no usable words or nobody here has {} messages yet.",
+        MIN_STYLE_MESSAGES
+    ))
+}
+
+/// Lists the candidates. With the `actual_author` known, they're left out of
+/// the list and the footer tells how the guess went instead.
+fn candidates_embed(candidates: &[(UserId, f64)], actual_author: Option<UserId>) -> CreateEmbed {
+    let mut description = String::new();
+    let mut shown = 0;
+
+    for (user_id, confidence) in candidates {
+        if Some(*user_id) == actual_author {
+            continue;
+        }
+
+        shown += 1;
+        description.push_str(&format!(
+            "**{}**. <@{}>  -  {:.1}%\n",
+            shown,
+            user_id,
+            confidence * 100.0
+        ));
+    }
+
+    if description.is_empty() {
+        description = "Nobody else writes like this.".to_string();
+    }
+
+    let mut embed = CreateEmbed::new()
+        .title("Who Writes Like This?")
+        .description(description.trim_end())
+        .color(0x5865F2);
+
+    if let Some(actual_author) = actual_author {
+        let verdict = match candidates
+            .iter()
+            .position(|(user_id, _)| *user_id == actual_author)
+        {
+            Some(0) => "The model got it right, the author was its top guess.".to_string(),
+            Some(index) => format!("The model ranked the author #{}.", index + 1),
+            None => "The model didn't have the author among its top guesses.".to_string(),
+        };
+
+        embed = embed.footer(serenity::all::CreateEmbedFooter::new(verdict));
+    }
+
+    embed
+}
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new("whostyles")
+        .description("Guess who writes the most like the given text.")
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::String,
+                "text",
+                "The text to compare against everyone's style",
+            )
+            .max_length(MAX_TEXT_LENGTH)
+            .required(true),
+        )
+}
+
+pub fn register_message() -> CreateCommand {
+    CreateCommand::new(MESSAGE_COMMAND_NAME).kind(CommandType::Message)
+}
@@ -0,0 +1,125 @@
+use serenity::all::{
+    CommandInteraction, CommandType, CreateCommand, CreateEmbed, CreateInteractionResponse,
+    CreateInteractionResponseMessage, EditInteractionResponse, ResolvedTarget,
+};
+use serenity::prelude::*;
+use serenity::Error;
+use std::sync::Arc;
+use tracing::error;
+
+use crate::database::{Database, LeaderboardFilter};
+use crate::utils::escape::{escape_inline_code, escape_markdown};
+
+/// Context menu commands are invoked by name, so it's shown as-is.
+pub const NAME: &str = "Word stats";
+
+/// How many of the member's words are listed.
+const TOP_WORDS: usize = 10;
+
+pub async fn execute(
+    ctx: &Context,
+    command: &CommandInteraction,
+    database: Arc<Database>,
+) -> Result<(), Error> {
+    command
+        .create_response(
+            &ctx.http,
+            CreateInteractionResponse::Defer(
+                CreateInteractionResponseMessage::new().ephemeral(true),
+            ),
+        )
+        .await?;
+
+    let guild_id = match command.guild_id {
+        Some(s) => s,
+        _ => return Ok(()),
+    };
+
+    let Some(ResolvedTarget::User(user, _)) = command.data.target() else {
+        return Ok(());
+    };
+
+    if user.bot {
+        command
+            .edit_response(
+                &ctx.http,
+                EditInteractionResponse::new().content("Messages from bots aren't recorded."),
+            )
+            .await?;
+        return Ok(());
+    }
+
+    // Same words as `/leaderboard user:@them`
+    let filter = LeaderboardFilter {
+        target_user_id: Some(user.id.get()),
+        min_length: 3,
+        min_users: 1,
+        ..Default::default()
+    };
+
+    let stats = tokio::try_join!(
+        database.get_author_rank(guild_id.get(), user.id.get()),
+        database.get_leaderboard_data(guild_id.get(), &filter, TOP_WORDS as i64, 0),
+    );
+
+    let (rank, mut words) = match stats {
+        Ok(stats) => stats,
+        Err(e) => {
+            error!(error = %e, "Failed to fetch word stats");
+            command
+                .edit_response(
+                    &ctx.http,
+                    EditInteractionResponse::new()
+                        .content("An error occurred while fetching the stats."),
+                )
+                .await?;
+
+            return Ok(());
+        }
+    };
+
+    let Some((message_count, rank)) = rank else {
+        command
+            .edit_response(
+                &ctx.http,
+                EditInteractionResponse::new().content(format!(
+                    "No messages from {} have been recorded yet.",
+                    escape_markdown(user.display_name())
+                )),
+            )
+            .await?;
+        return Ok(());
+    };
+
+    words.sort_by(|a, b| b.2.cmp(&a.2).then_with(|| a.0.cmp(&b.0)));
+
+    let mut description = String::new();
+    for (index, (word, _, count)) in words.iter().take(TOP_WORDS).enumerate() {
+        description.push_str(&format!(
+            "**{}**. `{}`  -  {} uses\n",
+            index + 1,
+            escape_inline_code(word),
+            count
+        ));
+    }
+
+    if description.is_empty() {
+        description = "No words recorded yet.".to_string();
+    }
+
+    let embed = CreateEmbed::new()
+        .title(format!("Word Stats for {}", user.display_name()))
+        .description(description.trim_end())
+        .field("Messages", message_count.to_string(), true)
+        .field("Rank", format!("#{}", rank), true)
+        .color(0x5865F2);
+
+    command
+        .edit_response(&ctx.http, EditInteractionResponse::new().embed(embed))
+        .await?;
+    Ok(())
+}
+
+pub fn register() -> CreateCommand {
+    CreateCommand::new(NAME).kind(CommandType::User)
+}
@@ -1,19 +1,615 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
 
-use sqlx::{sqlite::SqlitePool, Row, SqlitePool as Pool};
+use futures::future::BoxFuture;
+use serenity::all::MessageId;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteConnection, SqliteJournalMode, SqlitePoolOptions};
+use sqlx::{Connection, QueryBuilder, Row, Sqlite, SqlitePool as Pool};
+use tracing::{info, warn};
+
+use crate::utils::content::strip_code_and_quotes;
+use crate::utils::helpers::DISCORD_EPOCH_MS;
+use crate::utils::stemmer::Stemmer;
+
+/// How many messages are read per query while rebuilding aggregates.
+const REBUILD_CHUNK_SIZE: i64 = 5000;
+
+/// How many rows go into a single multi-row `INSERT`.
+const INSERT_CHUNK_SIZE: usize = 200;
+
+/// `get_top_posters`' query. `idx_messages_guild_author` holds the message
+/// id too, so it never reads the messages themselves.
+const TOP_POSTERS_QUERY: &str = r#"
+    SELECT author_id, COUNT(*) AS messages, SUM(COUNT(*)) OVER ()
+    FROM messages
+    WHERE guild_id = ? AND message_id >= ?
+    GROUP BY author_id
+    ORDER BY messages DESC
+    LIMIT ?
+"#;
+
+/// `(author_id, stored messages)` for every author in a guild.
+type AuthorCounts = Arc<Vec<(u64, i64)>>;
+
+/// A step from one schema version to the next, run inside a transaction.
+type Migration = for<'c> fn(&'c mut SqliteConnection) -> BoxFuture<'c, Result<(), sqlx::Error>>;
+
+/// Every schema change, oldest first. Migration `n` brings a database to
+/// version `n + 1`, new ones are only ever appended.
+const MIGRATIONS: [Migration; 10] = [
+    |conn| Box::pin(Database::migrate_initial_schema(conn)),
+    |conn| Box::pin(Database::migrate_message_timestamps(conn)),
+    |conn| Box::pin(Database::migrate_opted_out_users(conn)),
+    |conn| Box::pin(Database::migrate_collect_progress(conn)),
+    |conn| Box::pin(Database::migrate_guess_scores(conn)),
+    |conn| Box::pin(Database::migrate_autopost_settings(conn)),
+    |conn| Box::pin(Database::migrate_name_trigger(conn)),
+    |conn| Box::pin(Database::migrate_reply_chance(conn)),
+    |conn| Box::pin(Database::migrate_command_prefixes(conn)),
+    |conn| Box::pin(Database::migrate_rebuild_progress(conn)),
+];
+
+/// `opted_out_users.guild_id` of opt-outs that hold in every guild.
+const GLOBAL_OPT_OUT: u64 = 0;
+
+/// Where the database is when `DATABASE_URL` isn't set.
+pub const DEFAULT_DATABASE_URL: &str = "sqlite:data.db";
+
+/// Default cap on connections per pool.
+pub const DEFAULT_MAX_CONNECTIONS: u32 = 10;
+
+/// How long a query waits for another connection's write lock.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Default shortest wait between autoposts in a guild, in seconds.
+pub const DEFAULT_AUTOPOST_MIN_SECS: u64 = 300;
+
+/// Default longest wait between autoposts in a guild, in seconds.
+pub const DEFAULT_AUTOPOST_MAX_SECS: u64 = 900;
+
+/// Default cap on stored message content, in characters.
+pub const DEFAULT_MAX_CONTENT_LENGTH: usize = 2000;
+
+/// Default cap on open per-guild databases.
+pub const DEFAULT_MAX_OPEN_GUILD_DATABASES: usize = 16;
+
+/// How many distinct words of a single message are counted.
+const MAX_WORDS_PER_MESSAGE: usize = 200;
+
+/// `(stored messages, minimum length)` pairs for the guess game, most
+/// prolific authors first.
+///
+/// Thirty characters of someone who posts all day is a fair guess, thirty
+/// characters of someone who posts once a month isn't. Authors with fewer
+/// messages than the last bucket are never picked.
+const GUESS_LENGTH_BUCKETS: [(i64, u64); 3] = [(5000, 30), (1000, 60), (100, 90)];
+
+/// How long per-author message counts are reused for `GUESS_LENGTH_BUCKETS`.
+const AUTHOR_COUNTS_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// The guess game's minimum length for an author with `message_count`
+/// stored messages, `None` if they can't be picked at all.
+pub fn min_length_for(message_count: i64) -> Option<u64> {
+    GUESS_LENGTH_BUCKETS
+        .iter()
+        .find(|(min_messages, _)| message_count >= *min_messages)
+        .map(|(_, min_length)| *min_length)
+}
+
+/// A JSON array of ids, bound as a single parameter and read back with
+/// `json_each`.
+fn json_id_list(ids: impl IntoIterator<Item = u64>) -> String {
+    let ids: Vec<String> = ids.into_iter().map(|id| id.to_string()).collect();
+    format!("[{}]", ids.join(","))
+}
+
+/// Splits message content into lowercase words and counts them, ignoring
+/// code and quoted lines.
+///
+/// This is the single source of truth for how `word_counts` is derived, used
+/// both when inserting messages and when rebuilding the aggregates.
+pub fn tally_words(content: &str) -> HashMap<String, i32> {
+    let prefix_list = [
+        "$", "&", "!", ".", "m.", ">", "<", "[", "]", "@", "#", "%", "^", "*", ",",
+    ];
+
+    let mut local_counts: HashMap<String, i32> = HashMap::new();
+
+    for word in strip_code_and_quotes(content).split_whitespace() {
+        let word_lower = word.to_lowercase();
+
+        if prefix_list.iter().any(|&p| p == word_lower) {
+            continue;
+        }
+
+        // Pasted walls of text shouldn't turn into thousands of upserts
+        if local_counts.len() >= MAX_WORDS_PER_MESSAGE && !local_counts.contains_key(&word_lower) {
+            continue;
+        }
+        *local_counts.entry(word_lower).or_insert(0) += 1;
+    }
+
+    local_counts
+}
+
+/// Filler words left out of trending words, they trend with activity alone.
+pub const STOPWORDS: [&str; 40] = [
+    "the", "and", "for", "that", "this", "with", "you", "are", "was", "but", "not", "have", "just",
+    "what", "like", "its", "it's", "i'm", "dont", "don't", "can", "all", "get", "out", "one",
+    "bir", "bu", "ve", "da", "de", "ne", "ben", "sen", "mi", "çok", "var", "yok", "ama", "için",
+    "gibi",
+];
+
+/// A stored message as picked for the guess game or `/quote`.
+#[derive(Debug, Clone)]
+pub struct StoredMessage {
+    pub message_id: u64,
+    pub author_id: u64,
+    pub channel_id: u64,
+    pub content: String,
+    /// Whether `content` was cut to the storage limit.
+    pub truncated: bool,
+}
+
+/// Which messages `get_random_message` may pick.
+#[derive(Debug, Clone, Default)]
+pub struct RandomMessageFilter {
+    /// Minimum content length, in characters.
+    pub min_length: u64,
+    /// Messages that must not be picked, e.g. ones already shown.
+    pub excluded_ids: Vec<u64>,
+    /// Authors whose messages must not be picked.
+    pub excluded_author_ids: Vec<u64>,
+    /// Only pick messages sent in this channel.
+    pub channel_id: Option<u64>,
+    /// Only pick messages from this author.
+    pub author_id: Option<u64>,
+    /// Only pick messages containing this, ignoring ASCII case.
+    pub contains: Option<String>,
+    /// Only pick messages with an id (and so a timestamp) at or after this.
+    pub min_message_id: Option<u64>,
+    /// Also apply the author's `GUESS_LENGTH_BUCKETS` minimum length.
+    pub scale_length_by_author: bool,
+}
+
+/// Which rows `get_leaderboard_data` returns.
+#[derive(Debug, Clone, Default)]
+pub struct LeaderboardFilter {
+    /// Only count this author's words.
+    pub target_user_id: Option<u64>,
+    /// Only count this word (or its stem, with stemming on).
+    pub target_word: Option<String>,
+    /// Minimum word length, in characters.
+    pub min_length: i64,
+    /// Words to leave out.
+    pub excludes: Vec<String>,
+    /// Only count words used by at least this many distinct authors.
+    pub min_users: i64,
+    pub sort: LeaderboardSort,
+}
+
+/// Order of `get_leaderboard_data` rows. Ties go alphabetically.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LeaderboardSort {
+    #[default]
+    MostUsed,
+    LeastUsed,
+    Alphabetical,
+}
+
+impl LeaderboardSort {
+    fn order_by(self) -> &'static str {
+        match self {
+            LeaderboardSort::MostUsed => "uses DESC, word ASC",
+            LeaderboardSort::LeastUsed => "uses ASC, word ASC",
+            LeaderboardSort::Alphabetical => "word ASC, uses DESC",
+        }
+    }
+}
+
+/// What happened to a message handed to `insert_message`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InsertOutcome {
+    Inserted,
+    /// It was stored before, nothing was counted again.
+    Duplicate,
+}
+
+/// A message to store with `insert_messages_batch`.
+pub struct MessageRecord<'a> {
+    pub message_id: u64,
+    pub author_id: u64,
+    pub channel_id: u64,
+    pub content: &'a str,
+    pub replied_to_message_id: Option<u64>,
+}
+
+/// Per-guild behaviour toggles, stored in `guild_settings`.
+#[derive(Debug, Clone)]
+pub struct GuildSettings {
+    /// Stemmer applied to counted words, `None` when stemming is off.
+    pub stem_words: Option<Stemmer>,
+    /// Default `/guess` recency in days, `None` for all time.
+    pub guess_recency_days: Option<u32>,
+    /// Skip storing a message identical to the author's previous one in the
+    /// same channel.
+    pub dedupe_consecutive: bool,
+    /// Words generated output must not contain, on top of the defaults.
+    pub banned_words: Vec<String>,
+    /// Leave messages with banned words out of chain training entirely.
+    pub filter_banned_training: bool,
+    /// Post generated messages on a timer.
+    pub autopost_enabled: bool,
+    /// Shortest wait between autoposts, in seconds.
+    pub autopost_min_secs: u64,
+    /// Longest wait between autoposts, in seconds.
+    pub autopost_max_secs: u64,
+    /// Channel autoposts go to, `None` to pick a popular one each time.
+    pub autopost_channel_id: Option<u64>,
+    /// Reply when the bot's name is said, not only when it's mentioned.
+    pub name_trigger: bool,
+    /// Other names that count as the bot's, lowercase.
+    pub name_aliases: Vec<String>,
+    /// Percent chance of replying to a message unprompted.
+    pub reply_chance: f64,
+    /// Prefixes of other bots' commands, messages starting with one aren't
+    /// stored.
+    pub command_prefixes: Vec<String>,
+}
+
+impl Default for GuildSettings {
+    fn default() -> Self {
+        GuildSettings {
+            stem_words: None,
+            guess_recency_days: None,
+            dedupe_consecutive: true,
+            banned_words: Vec::new(),
+            filter_banned_training: false,
+            autopost_enabled: true,
+            autopost_min_secs: DEFAULT_AUTOPOST_MIN_SECS,
+            autopost_max_secs: DEFAULT_AUTOPOST_MAX_SECS,
+            autopost_channel_id: None,
+            name_trigger: true,
+            name_aliases: Vec::new(),
+            reply_chance: 0.0,
+            command_prefixes: Vec::new(),
+        }
+    }
+}
+
+/// One `/collect` run, as recorded in `collection_runs`.
+#[derive(Debug, Clone)]
+pub struct CollectionRun {
+    pub channel_id: u64,
+    /// Unix timestamps, in seconds.
+    pub started_at: i64,
+    pub finished_at: Option<i64>,
+    pub messages_stored: i64,
+    pub messages_skipped: i64,
+    /// `running`, `completed` or `failed`.
+    pub status: String,
+    /// Oldest message the run got to.
+    pub oldest_message_id: Option<u64>,
+}
+
+/// What `purge_guild` removed.
+#[derive(Debug, Clone, Default)]
+pub struct GuildPurge {
+    /// Rows deleted across all tables.
+    pub rows: u64,
+    /// Channels that had messages or stats, for dropping their chains.
+    pub channel_ids: Vec<u64>,
+}
+
+/// What `purge_user` removed.
+#[derive(Debug, Clone, Default)]
+pub struct UserPurge {
+    pub messages: u64,
+    /// Channels the user had messages in, for dropping their chains.
+    pub channel_ids: Vec<u64>,
+}
+
+/// A user's all-time `/guess` record in a guild.
+#[derive(Debug, Clone)]
+pub struct GuessScore {
+    pub user_id: u64,
+    pub correct: i64,
+    /// Every guess, right or wrong.
+    pub attempts: i64,
+    /// Most rounds won in a row in one game.
+    pub best_streak: i64,
+    /// Unix timestamp, in seconds.
+    pub last_played: i64,
+}
+
+/// What's stored for a guild, for `/stats`.
+#[derive(Debug, Clone, Default)]
+pub struct GuildStats {
+    pub messages: i64,
+    pub authors: i64,
+    /// Distinct words across every author.
+    pub words: i64,
+    /// Unix timestamps, in seconds. `None` without any messages.
+    pub oldest_message_at: Option<i64>,
+    pub newest_message_at: Option<i64>,
+    /// Bytes of stored message text, a lower bound on the disk space used.
+    pub content_bytes: i64,
+}
+
+/// A member's stored activity in a guild, for `/userstats`.
+#[derive(Debug, Clone, Default)]
+pub struct UserMessageStats {
+    pub messages: i64,
+    /// Distinct words they used.
+    pub vocabulary: i64,
+    /// In characters, 0 without any messages.
+    pub average_length: f64,
+}
+
+/// Where `/collect` got to in a channel, across every run in it.
+#[derive(Debug, Clone)]
+pub struct CollectProgress {
+    pub channel_id: u64,
+    /// Oldest message reached, where the backfill continues from.
+    pub last_message_id: Option<u64>,
+    /// Newest message collected, where collecting newer messages starts.
+    pub newest_message_id: Option<u64>,
+    /// `running` until the backfill reaches the start of the channel, then
+    /// `completed`. `failed` when it stopped on an error.
+    pub status: String,
+    /// Unix timestamp, in seconds.
+    pub updated_at: i64,
+}
+
+/// Opt-in storage mode where every guild gets its own database file.
+#[derive(Debug, Clone)]
+pub struct GuildStorage {
+    /// Directory holding the `<guild_id>.db` files.
+    pub dir: PathBuf,
+    /// How many guild databases are kept open at once.
+    pub max_open: usize,
+}
+
+/// Open per-guild pools, least recently used first.
+struct GuildPools {
+    storage: GuildStorage,
+    max_connections: u32,
+    open: tokio::sync::Mutex<Vec<(u64, Pool)>>,
+    /// Guilds whose file was opened since startup, so a pool reopened after
+    /// being dropped doesn't fail a run that's still going.
+    seen: tokio::sync::Mutex<HashSet<u64>>,
+}
 
 pub struct Database {
+    /// The only database in single-file mode. In per-guild mode it still
+    /// holds the data that isn't tied to a guild, like runtime flags.
     pool: Pool,
+    guild_pools: Option<GuildPools>,
+    settings_cache: RwLock<HashMap<u64, GuildSettings>>,
+    /// Each guild's `(author_id, message count)` pairs and when they were read.
+    author_counts_cache: RwLock<HashMap<u64, (Instant, AuthorCounts)>>,
+    /// Known membership per `(guild_id, user_id)`, mirrors `guild_members`.
+    member_cache: RwLock<HashMap<(u64, u64), bool>>,
+    /// Mirrors `opted_out_users` as `(guild_id, user_id)`, checked per message.
+    opt_outs: RwLock<HashSet<(u64, u64)>>,
+    max_content_length: usize,
 }
 
 impl Database {
-    pub async fn new(database_url: &str) -> Result<Self, sqlx::Error> {
-        let pool = SqlitePool::connect(database_url).await?;
+    pub async fn new(
+        database_url: &str,
+        max_content_length: usize,
+        guild_storage: Option<GuildStorage>,
+        max_connections: u32,
+    ) -> Result<Self, sqlx::Error> {
+        let options = SqliteConnectOptions::from_str(database_url)?;
+        let pool = Self::connect(options, max_connections).await?;
+        Self::setup_tables(&pool).await?;
+        Self::fail_interrupted_runs(&pool).await?;
+
+        if let Some(storage) = &guild_storage {
+            tokio::fs::create_dir_all(&storage.dir).await?;
+        }
+
+        let opt_outs: Vec<(i64, i64)> =
+            sqlx::query_as("SELECT guild_id, user_id FROM opted_out_users")
+                .fetch_all(&pool)
+                .await?;
+
+        Ok(Database {
+            pool,
+            guild_pools: guild_storage.map(|storage| GuildPools {
+                storage,
+                max_connections,
+                open: tokio::sync::Mutex::new(Vec::new()),
+                seen: tokio::sync::Mutex::new(HashSet::new()),
+            }),
+            settings_cache: RwLock::new(HashMap::new()),
+            author_counts_cache: RwLock::new(HashMap::new()),
+            member_cache: RwLock::new(HashMap::new()),
+            opt_outs: RwLock::new(
+                opt_outs
+                    .into_iter()
+                    .map(|(guild_id, user_id)| (guild_id as u64, user_id as u64))
+                    .collect(),
+            ),
+            max_content_length,
+        })
+    }
+
+    /// Folds the write-ahead logs back into the database files and closes
+    /// every pool, so nothing is left half written when the bot exits.
+    pub async fn close(&self) {
+        let mut pools = vec![self.pool.clone()];
+        if let Some(guild_pools) = &self.guild_pools {
+            pools.extend(
+                guild_pools
+                    .open
+                    .lock()
+                    .await
+                    .drain(..)
+                    .map(|(_, pool)| pool),
+            );
+        }
+
+        for pool in pools {
+            if let Err(e) = sqlx::query("PRAGMA wal_checkpoint(TRUNCATE)")
+                .execute(&pool)
+                .await
+            {
+                warn!(error = %e, "Failed to checkpoint database");
+            }
+            pool.close().await;
+        }
+    }
+
+    /// The pool holding `guild_id`'s data.
+    ///
+    /// In per-guild mode the guild's file is opened (and created) on first
+    /// use, and the least recently used pool is dropped once too many are
+    /// open. Queries still running on a dropped pool finish normally.
+    async fn guild_pool(&self, guild_id: u64) -> Result<Pool, sqlx::Error> {
+        let Some(guild_pools) = &self.guild_pools else {
+            return Ok(self.pool.clone());
+        };
+
+        let mut open = guild_pools.open.lock().await;
+
+        if let Some(index) = open.iter().position(|(id, _)| *id == guild_id) {
+            let entry = open.remove(index);
+            let pool = entry.1.clone();
+            open.push(entry);
+            return Ok(pool);
+        }
+
+        let options = SqliteConnectOptions::new()
+            .filename(guild_pools.storage.dir.join(format!("{}.db", guild_id)));
+        let pool = Self::connect(options, guild_pools.max_connections).await?;
         Self::setup_tables(&pool).await?;
-        Ok(Database { pool })
+        if guild_pools.seen.lock().await.insert(guild_id) {
+            Self::fail_interrupted_runs(&pool).await?;
+        }
+
+        if open.len() >= guild_pools.storage.max_open.max(1) {
+            open.remove(0);
+        }
+        open.push((guild_id, pool.clone()));
+
+        Ok(pool)
+    }
+
+    /// Opens a pool, creating the file if needed. WAL lets the message
+    /// handler, autoposting and `/collect` read while another one writes.
+    async fn connect(
+        options: SqliteConnectOptions,
+        max_connections: u32,
+    ) -> Result<Pool, sqlx::Error> {
+        let options = options
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal)
+            .busy_timeout(BUSY_TIMEOUT)
+            .foreign_keys(true);
+
+        SqlitePoolOptions::new()
+            .max_connections(max_connections.max(1))
+            .connect_with(options)
+            .await
+    }
+
+    /// Marks `/collect` runs left `running` by a previous process as failed,
+    /// since nothing is going to finish them.
+    async fn fail_interrupted_runs(pool: &Pool) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query(
+            r#"
+            UPDATE collection_runs
+            SET status = 'failed', finished_at = CAST(strftime('%s', 'now') AS INTEGER)
+            WHERE status = 'running'
+            "#,
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Adds a column to an existing table, doing nothing if it's already there.
+    /// Returns whether it was added.
+    async fn add_column_if_missing(
+        conn: &mut SqliteConnection,
+        table: &str,
+        column: &str,
+        definition: &str,
+    ) -> Result<bool, sqlx::Error> {
+        let exists: Option<(String,)> =
+            sqlx::query_as("SELECT name FROM pragma_table_info(?) WHERE name = ?")
+                .bind(table)
+                .bind(column)
+                .fetch_optional(&mut *conn)
+                .await?;
+
+        if exists.is_some() {
+            return Ok(false);
+        }
+
+        sqlx::query(&format!(
+            "ALTER TABLE {} ADD COLUMN {} {}",
+            table, column, definition
+        ))
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(true)
     }
 
     async fn setup_tables(pool: &Pool) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS schema_version (
+                version INTEGER PRIMARY KEY,
+                applied_at INTEGER NOT NULL
+            )
+            "#,
+        )
+        .execute(pool)
+        .await?;
+
+        for (index, migration) in MIGRATIONS.iter().enumerate() {
+            let version = index as i64 + 1;
+            let mut tx = pool.begin().await?;
+
+            let (current,): (i64,) =
+                sqlx::query_as("SELECT COALESCE(MAX(version), 0) FROM schema_version")
+                    .fetch_one(&mut *tx)
+                    .await?;
+            if current >= version {
+                continue;
+            }
+
+            migration(&mut tx).await?;
+
+            sqlx::query("INSERT INTO schema_version (version, applied_at) VALUES (?, CAST(strftime('%s', 'now') AS INTEGER))")
+                .bind(version)
+                .execute(&mut *tx)
+                .await?;
+            tx.commit().await?;
+
+            info!(version, "Migrated database schema");
+        }
+
+        // Search works without it, just slower
+        if let Err(e) = Self::setup_search_index(pool).await {
+            warn!(error = %e, "Full-text search unavailable, falling back to LIKE");
+        }
+
+        Ok(())
+    }
+
+    /// The schema from before versioning. Every step is idempotent, so
+    /// databases created back then converge on it too.
+    async fn migrate_initial_schema(conn: &mut SqliteConnection) -> Result<(), sqlx::Error> {
         // Create messages table
         sqlx::query(
             r#"
@@ -26,7 +622,7 @@ impl Database {
             )
             "#,
         )
-        .execute(pool)
+        .execute(&mut *conn)
         .await?;
 
         sqlx::query(
@@ -40,7 +636,7 @@ impl Database {
             )
             "#,
         )
-        .execute(pool)
+        .execute(&mut *conn)
         .await?;
 
         sqlx::query(
@@ -53,291 +649,3672 @@ impl Database {
             )
             "#,
         )
-        .execute(pool)
+        .execute(&mut *conn)
+        .await?;
+
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS runtime_flags (
+                name TEXT PRIMARY KEY,
+                enabled INTEGER NOT NULL DEFAULT 0
+            )
+            "#,
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS guild_settings (
+                guild_id INTEGER PRIMARY KEY,
+                stem_words TEXT
+            )
+            "#,
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS collection_runs (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                guild_id INTEGER NOT NULL,
+                channel_id INTEGER NOT NULL,
+                started_at INTEGER NOT NULL,
+                finished_at INTEGER,
+                messages_stored INTEGER NOT NULL DEFAULT 0,
+                messages_skipped INTEGER NOT NULL DEFAULT 0,
+                status TEXT NOT NULL,
+                oldest_message_id INTEGER
+            )
+            "#,
+        )
+        .execute(&mut *conn)
         .await?;
 
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS banned_words (
+                guild_id INTEGER NOT NULL,
+                word TEXT NOT NULL,
+                PRIMARY KEY (guild_id, word)
+            )
+            "#,
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS guild_members (
+                guild_id INTEGER NOT NULL,
+                user_id INTEGER NOT NULL,
+                present INTEGER NOT NULL,
+                updated_at INTEGER NOT NULL,
+                PRIMARY KEY (guild_id, user_id)
+            )
+            "#,
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        Self::add_column_if_missing(conn, "guild_settings", "guess_recency_days", "INTEGER")
+            .await?;
+
+        Self::add_column_if_missing(
+            conn,
+            "guild_settings",
+            "dedupe_consecutive",
+            "INTEGER NOT NULL DEFAULT 1",
+        )
+        .await?;
+
+        Self::add_column_if_missing(
+            conn,
+            "guild_settings",
+            "filter_banned_training",
+            "INTEGER NOT NULL DEFAULT 0",
+        )
+        .await?;
+
+        // Stemmed form of `word`, NULL when the guild doesn't stem
+        Self::add_column_if_missing(conn, "word_counts", "stem", "TEXT").await?;
+
+        // Set when `content` was cut to the storage limit
+        Self::add_column_if_missing(conn, "messages", "truncated", "INTEGER NOT NULL DEFAULT 0")
+            .await?;
+
+        // The message this one replied to, if any
+        Self::add_column_if_missing(conn, "messages", "replied_to_message_id", "INTEGER").await?;
+
         // Create indexes for performance
 
         sqlx::query("CREATE INDEX IF NOT EXISTS idx_channel_stats_ranking ON channel_stats (guild_id, count DESC)")
-            .execute(pool)
+            .execute(&mut *conn)
             .await?;
 
         sqlx::query("CREATE INDEX IF NOT EXISTS idx_word_counts_ranking ON word_counts (guild_id, count DESC)")
-            .execute(pool)
+            .execute(&mut *conn)
             .await?;
 
+        sqlx::query(
+            "CREATE INDEX IF NOT EXISTS idx_word_counts_guild_word ON word_counts (guild_id, word)",
+        )
+        .execute(&mut *conn)
+        .await?;
+
         sqlx::query("CREATE INDEX IF NOT EXISTS idx_messages_guild_channel ON messages (guild_id, channel_id)")
-            .execute(pool)
+            .execute(&mut *conn)
             .await?;
 
         sqlx::query("CREATE INDEX IF NOT EXISTS idx_messages_guild_author ON messages (guild_id, author_id)")
-            .execute(pool)
+            .execute(&mut *conn)
+            .await?;
+
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_collection_runs_guild ON collection_runs (guild_id, id DESC)")
+            .execute(&mut *conn)
             .await?;
 
         sqlx::query("CREATE INDEX IF NOT EXISTS idx_messages_guild ON messages (guild_id)")
-            .execute(pool)
+            .execute(&mut *conn)
+            .await?;
+
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_messages_guild_reply ON messages (guild_id, replied_to_message_id)")
+            .execute(&mut *conn)
             .await?;
 
         Ok(())
     }
 
-    pub async fn insert_message(
-        &self,
-        message_id: u64,
-        author_id: u64,
-        channel_id: u64,
-        guild_id: u64,
-        content: &str,
-    ) -> Result<(), sqlx::Error> {
+    /// Adds `messages.created_at`.
+    async fn migrate_message_timestamps(conn: &mut SqliteConnection) -> Result<(), sqlx::Error> {
+        // Unix seconds, from the snowflake. Rows stored before the column
+        // existed get theirs decoded from `message_id` once.
+        if Self::add_column_if_missing(conn, "messages", "created_at", "INTEGER").await? {
+            sqlx::query(
+                "UPDATE messages SET created_at = ((message_id >> 22) + ?) / 1000 WHERE created_at IS NULL",
+            )
+            .bind(DISCORD_EPOCH_MS as i64)
+            .execute(&mut *conn)
+            .await?;
+        }
+
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_messages_guild_created ON messages (guild_id, created_at)")
+            .execute(&mut *conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Creates the FTS5 index over message content, kept in sync by triggers,
+    /// and fills it from existing messages the first time.
+    /// Users who asked not to be recorded. They're kept in the main
+    /// database, next to the runtime flags, even in per-guild mode.
+    async fn migrate_opted_out_users(conn: &mut SqliteConnection) -> Result<(), sqlx::Error> {
         sqlx::query(
-            "INSERT INTO messages (message_id, author_id, channel_id, guild_id, content) VALUES (?, ?, ?, ?, ?)"
+            r#"
+            CREATE TABLE IF NOT EXISTS opted_out_users (
+                guild_id INTEGER NOT NULL,
+                user_id INTEGER NOT NULL,
+                PRIMARY KEY (guild_id, user_id)
+            )
+            "#,
         )
-        .bind(message_id as i64)
-        .bind(author_id as i64)
-        .bind(channel_id as i64)
-        .bind(guild_id as i64)
-        .bind(content)
-        .execute(&self.pool)
+        .execute(&mut *conn)
         .await?;
 
+        Ok(())
+    }
+
+    /// Per-channel cursors so `/collect` can pick up where it stopped.
+    async fn migrate_collect_progress(conn: &mut SqliteConnection) -> Result<(), sqlx::Error> {
         sqlx::query(
             r#"
-            INSERT INTO channel_stats (guild_id, channel_id, count)
-            VALUES (?, ?, 1)
-            ON CONFLICT(guild_id, channel_id) 
-            DO UPDATE SET count = count + 1
+            CREATE TABLE IF NOT EXISTS collect_progress (
+                guild_id INTEGER NOT NULL,
+                channel_id INTEGER NOT NULL,
+                last_message_id INTEGER,
+                newest_message_id INTEGER,
+                status TEXT NOT NULL,
+                updated_at INTEGER NOT NULL,
+                PRIMARY KEY (guild_id, channel_id)
+            )
             "#,
         )
-        .bind(guild_id as i64)
-        .bind(channel_id as i64)
-        .execute(&self.pool)
+        .execute(&mut *conn)
         .await?;
 
-        let prefix_list = [
-            "$", "&", "!", ".", "m.", ">", "<", "[", "]", "@", "#", "%", "^", "*", ",",
-        ];
+        Ok(())
+    }
 
-        let mut local_counts: HashMap<String, i32> = HashMap::new();
+    async fn migrate_guess_scores(conn: &mut SqliteConnection) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS guess_scores (
+                guild_id INTEGER NOT NULL,
+                user_id INTEGER NOT NULL,
+                correct INTEGER NOT NULL DEFAULT 0,
+                attempts INTEGER NOT NULL DEFAULT 0,
+                best_streak INTEGER NOT NULL DEFAULT 0,
+                last_played INTEGER NOT NULL,
+                PRIMARY KEY (guild_id, user_id)
+            )
+            "#,
+        )
+        .execute(&mut *conn)
+        .await?;
 
-        for word in content.split_whitespace() {
-            let word_lower = word.to_lowercase();
+        Ok(())
+    }
 
-            if prefix_list.iter().any(|&p| p == word_lower) {
+    async fn migrate_autopost_settings(conn: &mut SqliteConnection) -> Result<(), sqlx::Error> {
+        Self::add_column_if_missing(
+            conn,
+            "guild_settings",
+            "autopost_enabled",
+            "INTEGER NOT NULL DEFAULT 1",
+        )
+        .await?;
+
+        Self::add_column_if_missing(
+            conn,
+            "guild_settings",
+            "autopost_min_secs",
+            &format!("INTEGER NOT NULL DEFAULT {}", DEFAULT_AUTOPOST_MIN_SECS),
+        )
+        .await?;
+
+        Self::add_column_if_missing(
+            conn,
+            "guild_settings",
+            "autopost_max_secs",
+            &format!("INTEGER NOT NULL DEFAULT {}", DEFAULT_AUTOPOST_MAX_SECS),
+        )
+        .await?;
+
+        Self::add_column_if_missing(conn, "guild_settings", "autopost_channel_id", "INTEGER")
+            .await?;
+
+        Ok(())
+    }
+
+    async fn migrate_name_trigger(conn: &mut SqliteConnection) -> Result<(), sqlx::Error> {
+        Self::add_column_if_missing(
+            conn,
+            "guild_settings",
+            "name_trigger",
+            "INTEGER NOT NULL DEFAULT 1",
+        )
+        .await?;
+
+        // Comma separated
+        Self::add_column_if_missing(conn, "guild_settings", "name_aliases", "TEXT").await?;
+
+        Ok(())
+    }
+
+    async fn migrate_reply_chance(conn: &mut SqliteConnection) -> Result<(), sqlx::Error> {
+        // In percent
+        Self::add_column_if_missing(
+            conn,
+            "guild_settings",
+            "reply_chance",
+            "REAL NOT NULL DEFAULT 0",
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn migrate_command_prefixes(conn: &mut SqliteConnection) -> Result<(), sqlx::Error> {
+        // Space separated, commands can start with a comma
+        Self::add_column_if_missing(conn, "guild_settings", "command_prefixes", "TEXT").await?;
+
+        Ok(())
+    }
+
+    async fn migrate_rebuild_progress(conn: &mut SqliteConnection) -> Result<(), sqlx::Error> {
+        // Where a running (or interrupted) rebuild of the aggregates got to
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS rebuild_progress (
+                guild_id INTEGER PRIMARY KEY,
+                last_message_id INTEGER NOT NULL
+            )
+            "#,
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn setup_search_index(pool: &Pool) -> Result<(), sqlx::Error> {
+        if Self::has_search_index(pool).await? {
+            return Ok(());
+        }
+
+        // Fails when SQLite was built without FTS5
+        sqlx::query(
+            "CREATE VIRTUAL TABLE messages_fts USING fts5(content, content = 'messages', content_rowid = 'message_id')",
+        )
+        .execute(pool)
+        .await?;
+
+        sqlx::query(
+            r#"
+            CREATE TRIGGER IF NOT EXISTS messages_fts_insert AFTER INSERT ON messages BEGIN
+                INSERT INTO messages_fts (rowid, content) VALUES (new.message_id, new.content);
+            END
+            "#,
+        )
+        .execute(pool)
+        .await?;
+
+        sqlx::query(
+            r#"
+            CREATE TRIGGER IF NOT EXISTS messages_fts_delete AFTER DELETE ON messages BEGIN
+                INSERT INTO messages_fts (messages_fts, rowid, content) VALUES ('delete', old.message_id, old.content);
+            END
+            "#,
+        )
+        .execute(pool)
+        .await?;
+
+        sqlx::query(
+            r#"
+            CREATE TRIGGER IF NOT EXISTS messages_fts_update AFTER UPDATE OF content ON messages BEGIN
+                INSERT INTO messages_fts (messages_fts, rowid, content) VALUES ('delete', old.message_id, old.content);
+                INSERT INTO messages_fts (rowid, content) VALUES (new.message_id, new.content);
+            END
+            "#,
+        )
+        .execute(pool)
+        .await?;
+
+        sqlx::query("INSERT INTO messages_fts (messages_fts) VALUES ('rebuild')")
+            .execute(pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn has_search_index(pool: &Pool) -> Result<bool, sqlx::Error> {
+        let exists: Option<(String,)> = sqlx::query_as(
+            "SELECT name FROM sqlite_master WHERE type = 'table' AND name = 'messages_fts'",
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(exists.is_some())
+    }
+
+    pub async fn insert_message(
+        &self,
+        message_id: u64,
+        author_id: u64,
+        channel_id: u64,
+        guild_id: u64,
+        content: &str,
+        replied_to_message_id: Option<u64>,
+    ) -> Result<InsertOutcome, sqlx::Error> {
+        let pool = self.guild_pool(guild_id).await?;
+        let stemmer = self.get_guild_settings(guild_id).await?.stem_words;
+
+        let record = MessageRecord {
+            message_id,
+            author_id,
+            channel_id,
+            content,
+            replied_to_message_id,
+        };
+
+        // All or nothing, so the counts never disagree with the messages
+        let mut tx = pool.begin().await?;
+        let outcome = self
+            .store_message(&mut tx, guild_id, &record, stemmer)
+            .await?;
+        tx.commit().await?;
+
+        Ok(outcome)
+    }
+
+    /// Stores a page of messages in one transaction, and returns what
+    /// happened to each one, `None` for a message that failed. A failed
+    /// message is skipped without affecting the rest.
+    pub async fn insert_messages_batch(
+        &self,
+        guild_id: u64,
+        records: &[MessageRecord<'_>],
+    ) -> Result<Vec<Option<InsertOutcome>>, sqlx::Error> {
+        let pool = self.guild_pool(guild_id).await?;
+        let stemmer = self.get_guild_settings(guild_id).await?.stem_words;
+
+        let mut tx = pool.begin().await?;
+        let mut stored = Vec::with_capacity(records.len());
+
+        for record in records {
+            // A savepoint each, so a failed message leaves no partial counts
+            let mut savepoint = Connection::begin(&mut *tx).await?;
+
+            match self
+                .store_message(&mut savepoint, guild_id, record, stemmer)
+                .await
+            {
+                Ok(outcome) => {
+                    savepoint.commit().await?;
+                    stored.push(Some(outcome));
+                }
+                Err(_) => {
+                    savepoint.rollback().await?;
+                    stored.push(None);
+                }
+            }
+        }
+
+        tx.commit().await?;
+        Ok(stored)
+    }
+
+    /// Inserts the message and updates `channel_stats` and `word_counts`
+    /// with it, on a connection the caller wraps in a transaction. A message
+    /// that's already stored is left alone, so its words aren't counted
+    /// twice.
+    async fn store_message(
+        &self,
+        conn: &mut SqliteConnection,
+        guild_id: u64,
+        record: &MessageRecord<'_>,
+        stemmer: Option<Stemmer>,
+    ) -> Result<InsertOutcome, sqlx::Error> {
+        let (content, truncated) = self.truncate_content(record.content);
+
+        let inserted = sqlx::query(
+            "INSERT OR IGNORE INTO messages (message_id, author_id, channel_id, guild_id, content, truncated, replied_to_message_id, created_at) VALUES (?, ?, ?, ?, ?, ?, ?, ?)"
+        )
+        .bind(record.message_id as i64)
+        .bind(record.author_id as i64)
+        .bind(record.channel_id as i64)
+        .bind(guild_id as i64)
+        .bind(content)
+        .bind(truncated)
+        .bind(record.replied_to_message_id.map(|id| id as i64))
+        .bind(MessageId::new(record.message_id).created_at().unix_timestamp())
+        .execute(&mut *conn)
+        .await?
+        .rows_affected();
+
+        if inserted == 0 {
+            return Ok(InsertOutcome::Duplicate);
+        }
+
+        // A running rebuild counts it once it gets there
+        if !Self::is_counted(conn, guild_id, record.message_id).await? {
+            return Ok(InsertOutcome::Inserted);
+        }
+
+        sqlx::query(
+            r#"
+            INSERT INTO channel_stats (guild_id, channel_id, count)
+            VALUES (?, ?, 1)
+            ON CONFLICT(guild_id, channel_id) 
+            DO UPDATE SET count = count + 1
+            "#,
+        )
+        .bind(guild_id as i64)
+        .bind(record.channel_id as i64)
+        .execute(&mut *conn)
+        .await?;
+
+        Self::add_word_counts(conn, guild_id, record.author_id, content, stemmer).await?;
+
+        Ok(InsertOutcome::Inserted)
+    }
+
+    /// Whether `message_id` is already in the guild's aggregates, i.e. no
+    /// rebuild is running or it has scanned past the message. Only reliable
+    /// once the transaction has written, so a rebuild can't move meanwhile.
+    async fn is_counted(
+        conn: &mut SqliteConnection,
+        guild_id: u64,
+        message_id: u64,
+    ) -> Result<bool, sqlx::Error> {
+        let cursor = Self::rebuild_cursor(conn, guild_id).await?;
+
+        Ok(cursor.is_none_or(|cursor| message_id as i64 <= cursor))
+    }
+
+    /// The last message a running rebuild has counted, `None` without one.
+    async fn rebuild_cursor(
+        conn: &mut SqliteConnection,
+        guild_id: u64,
+    ) -> Result<Option<i64>, sqlx::Error> {
+        let cursor: Option<(i64,)> =
+            sqlx::query_as("SELECT last_message_id FROM rebuild_progress WHERE guild_id = ?")
+                .bind(guild_id as i64)
+                .fetch_optional(&mut *conn)
+                .await?;
+
+        Ok(cursor.map(|(cursor,)| cursor))
+    }
+
+    /// Cuts content longer than `max_content_length` on a char boundary, and
+    /// says whether it did.
+    fn truncate_content<'a>(&self, content: &'a str) -> (&'a str, bool) {
+        match content.char_indices().nth(self.max_content_length) {
+            Some((end, _)) => (&content[..end], true),
+            None => (content, false),
+        }
+    }
+
+    /// Adds the words of `content` to the author's `word_counts`.
+    async fn add_word_counts(
+        conn: &mut SqliteConnection,
+        guild_id: u64,
+        author_id: u64,
+        content: &str,
+        stemmer: Option<Stemmer>,
+    ) -> Result<(), sqlx::Error> {
+        let words: Vec<(String, i32)> = tally_words(content).into_iter().collect();
+        for chunk in words.chunks(INSERT_CHUNK_SIZE) {
+            let mut query_builder: QueryBuilder<Sqlite> = QueryBuilder::new(
+                "INSERT INTO word_counts (guild_id, author_id, word, stem, count) ",
+            );
+
+            query_builder.push_values(chunk, |mut row, (word, count)| {
+                row.push_bind(guild_id as i64)
+                    .push_bind(author_id as i64)
+                    .push_bind(word.as_str())
+                    .push_bind(stemmer.map(|stemmer| stemmer.stem(word)))
+                    .push_bind(*count);
+            });
+
+            query_builder.push(
+                " ON CONFLICT(guild_id, author_id, word) DO UPDATE SET count = count + excluded.count, stem = excluded.stem",
+            );
+
+            query_builder.build().execute(&mut *conn).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Takes the words of `content` back out of the author's `word_counts`,
+    /// dropping words that reach zero.
+    async fn remove_word_counts(
+        conn: &mut SqliteConnection,
+        guild_id: u64,
+        author_id: u64,
+        content: &str,
+    ) -> Result<(), sqlx::Error> {
+        for (word, count) in tally_words(content) {
+            sqlx::query(
+                "UPDATE word_counts SET count = count - ? WHERE guild_id = ? AND author_id = ? AND word = ?",
+            )
+            .bind(count)
+            .bind(guild_id as i64)
+            .bind(author_id as i64)
+            .bind(word)
+            .execute(&mut *conn)
+            .await?;
+        }
+
+        sqlx::query("DELETE FROM word_counts WHERE guild_id = ? AND author_id = ? AND count <= 0")
+            .bind(guild_id as i64)
+            .bind(author_id as i64)
+            .execute(&mut *conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Replaces an edited message's content and moves its word counts over
+    /// to the new words. Returns `false` for messages that were never stored.
+    pub async fn update_message_content(
+        &self,
+        guild_id: u64,
+        message_id: u64,
+        new_content: &str,
+    ) -> Result<bool, sqlx::Error> {
+        let pool = self.guild_pool(guild_id).await?;
+        let stemmer = self.get_guild_settings(guild_id).await?.stem_words;
+
+        let mut tx = pool.begin().await?;
+
+        let stored: Option<(i64, String)> = sqlx::query_as(
+            "SELECT author_id, content FROM messages WHERE guild_id = ? AND message_id = ?",
+        )
+        .bind(guild_id as i64)
+        .bind(message_id as i64)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some((author_id, old_content)) = stored else {
+            return Ok(false);
+        };
+
+        let (content, truncated) = self.truncate_content(new_content);
+        if content == old_content {
+            return Ok(true);
+        }
+
+        sqlx::query(
+            "UPDATE messages SET content = ?, truncated = ? WHERE guild_id = ? AND message_id = ?",
+        )
+        .bind(content)
+        .bind(truncated)
+        .bind(guild_id as i64)
+        .bind(message_id as i64)
+        .execute(&mut *tx)
+        .await?;
+
+        if Self::is_counted(&mut tx, guild_id, message_id).await? {
+            let author_id = author_id as u64;
+            Self::remove_word_counts(&mut tx, guild_id, author_id, &old_content).await?;
+            Self::add_word_counts(&mut tx, guild_id, author_id, content, stemmer).await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(true)
+    }
+
+    pub async fn get_messages_for_markov(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+        prefixes: &[&str],
+        limit: usize,
+    ) -> Result<Vec<String>, sqlx::Error> {
+        let pool = self.guild_pool(guild_id).await?;
+
+        let mut query =
+            QueryBuilder::<Sqlite>::new("SELECT content FROM messages WHERE guild_id = ");
+        query
+            .push_bind(guild_id as i64)
+            .push(" AND channel_id = ")
+            .push_bind(channel_id as i64)
+            .push(" AND LENGTH(content) > 10 AND truncated = 0");
+
+        for prefix in prefixes {
+            query
+                .push(" AND content NOT LIKE ")
+                .push_bind(*prefix)
+                .push(" || '%'");
+        }
+
+        self.push_opt_out_exclusion(&mut query, guild_id);
+
+        query
+            .push(" ORDER BY RANDOM() LIMIT ")
+            .push_bind(limit as i64);
+
+        let rows: Vec<(String,)> = query.build_query_as().fetch_all(&pool).await?;
+
+        Ok(rows.into_iter().map(|(content,)| content).collect())
+    }
+
+    /// Up to `limit` random messages from every channel of the guild, for
+    /// channels too small for their own chain. Same filters as
+    /// `get_messages_for_markov`.
+    pub async fn get_messages_for_markov_guild(
+        &self,
+        guild_id: u64,
+        prefixes: &[&str],
+        limit: usize,
+    ) -> Result<Vec<String>, sqlx::Error> {
+        let pool = self.guild_pool(guild_id).await?;
+
+        let mut query =
+            QueryBuilder::<Sqlite>::new("SELECT content FROM messages WHERE guild_id = ");
+        query
+            .push_bind(guild_id as i64)
+            .push(" AND LENGTH(content) > 10 AND truncated = 0");
+
+        for prefix in prefixes {
+            query
+                .push(" AND content NOT LIKE ")
+                .push_bind(*prefix)
+                .push(" || '%'");
+        }
+
+        self.push_opt_out_exclusion(&mut query, guild_id);
+
+        query
+            .push(" ORDER BY RANDOM() LIMIT ")
+            .push_bind(limit as i64);
+
+        let rows: Vec<(String,)> = query.build_query_as().fetch_all(&pool).await?;
+
+        Ok(rows.into_iter().map(|(content,)| content).collect())
+    }
+
+    /// Up to `limit` of the author's most recent messages for chain
+    /// training, with the same filters as `get_messages_for_markov`.
+    pub async fn get_messages_for_markov_by_author(
+        &self,
+        guild_id: u64,
+        author_id: u64,
+        prefixes: &[&str],
+        limit: usize,
+    ) -> Result<Vec<String>, sqlx::Error> {
+        let pool = self.guild_pool(guild_id).await?;
+
+        let mut query =
+            QueryBuilder::<Sqlite>::new("SELECT content FROM messages WHERE guild_id = ");
+        query
+            .push_bind(guild_id as i64)
+            .push(" AND author_id = ")
+            .push_bind(author_id as i64)
+            .push(" AND LENGTH(content) > 10 AND truncated = 0");
+
+        for prefix in prefixes {
+            query
+                .push(" AND content NOT LIKE ")
+                .push_bind(*prefix)
+                .push(" || '%'");
+        }
+
+        self.push_opt_out_exclusion(&mut query, guild_id);
+
+        query
+            .push(" ORDER BY message_id DESC LIMIT ")
+            .push_bind(limit as i64);
+
+        let rows: Vec<(String,)> = query.build_query_as().fetch_all(&pool).await?;
+
+        Ok(rows.into_iter().map(|(content,)| content).collect())
+    }
+
+    /// How many messages were sent in the guild since `since`, in Unix
+    /// seconds.
+    pub async fn get_message_count_since(
+        &self,
+        guild_id: u64,
+        since: i64,
+    ) -> Result<u64, sqlx::Error> {
+        let pool = self.guild_pool(guild_id).await?;
+
+        let (count,): (i64,) =
+            sqlx::query_as("SELECT COUNT(*) FROM messages WHERE guild_id = ? AND created_at >= ?")
+                .bind(guild_id as i64)
+                .bind(since)
+                .fetch_one(&pool)
+                .await?;
+
+        Ok(count as u64)
+    }
+
+    /// Counts the messages `get_messages_for_markov` could pick from, or
+    /// `get_messages_for_markov_guild` without a channel.
+    pub async fn count_markov_eligible_messages(
+        &self,
+        guild_id: u64,
+        channel_id: Option<u64>,
+        prefixes: &[&str],
+    ) -> Result<u64, sqlx::Error> {
+        let pool = self.guild_pool(guild_id).await?;
+
+        let mut query =
+            QueryBuilder::<Sqlite>::new("SELECT COUNT(*) FROM messages WHERE guild_id = ");
+        query.push_bind(guild_id as i64);
+
+        if let Some(channel_id) = channel_id {
+            query
+                .push(" AND channel_id = ")
+                .push_bind(channel_id as i64);
+        }
+
+        query.push(" AND LENGTH(content) > 10 AND truncated = 0");
+
+        for prefix in prefixes {
+            query
+                .push(" AND content NOT LIKE ")
+                .push_bind(*prefix)
+                .push(" || '%'");
+        }
+
+        self.push_opt_out_exclusion(&mut query, guild_id);
+
+        let (count,): (i64,) = query.build_query_as().fetch_one(&pool).await?;
+
+        Ok(count as u64)
+    }
+
+    /// Returns `(parent_content, reply_content)` pairs for stored replies
+    /// whose parent message is stored too, newest first.
+    pub async fn get_reply_pairs(
+        &self,
+        guild_id: u64,
+        limit: i64,
+    ) -> Result<Vec<(String, String)>, sqlx::Error> {
+        let pool = self.guild_pool(guild_id).await?;
+
+        let rows = sqlx::query_as::<_, (String, String)>(
+            r#"
+            SELECT parent.content, reply.content FROM messages reply
+            JOIN messages parent ON parent.message_id = reply.replied_to_message_id
+            WHERE reply.guild_id = ? AND reply.replied_to_message_id IS NOT NULL
+            ORDER BY reply.message_id DESC
+            LIMIT ?
+            "#,
+        )
+        .bind(guild_id as i64)
+        .bind(limit)
+        .fetch_all(&pool)
+        .await?;
+
+        Ok(rows)
+    }
+
+    /// Returns how often each of `words` was used across the guild. Words
+    /// that were never used are left out.
+    pub async fn get_word_totals(
+        &self,
+        guild_id: u64,
+        words: &[String],
+    ) -> Result<HashMap<String, i64>, sqlx::Error> {
+        let pool = self.guild_pool(guild_id).await?;
+
+        if words.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let mut query_builder: QueryBuilder<Sqlite> =
+            QueryBuilder::new("SELECT word, SUM(count) FROM word_counts WHERE guild_id = ");
+        query_builder.push_bind(guild_id as i64);
+        query_builder.push(" AND word IN (");
+
+        let mut separated = query_builder.separated(", ");
+        for word in words {
+            separated.push_bind(word.as_str());
+        }
+
+        query_builder.push(") GROUP BY word");
+
+        let rows = query_builder
+            .build_query_as::<(String, i64)>()
+            .fetch_all(&pool)
+            .await?;
+
+        Ok(rows.into_iter().collect())
+    }
+
+    /// Returns up to `limit` `(channel_id, message_count)` pairs, busiest first.
+    pub async fn get_top_channels(
+        &self,
+        guild_id: u64,
+        limit: i64,
+    ) -> Result<Vec<(u64, i64)>, sqlx::Error> {
+        let pool = self.guild_pool(guild_id).await?;
+
+        let rows = sqlx::query_as::<_, (i64, i64)>(
+            "SELECT channel_id, count FROM channel_stats WHERE guild_id = ? ORDER BY count DESC LIMIT ?",
+        )
+        .bind(guild_id as i64)
+        .bind(limit)
+        .fetch_all(&pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(channel_id, count)| (channel_id as u64, count))
+            .collect())
+    }
+
+    /// Every author who used `word` (or its stem, with stemming on) as
+    /// `(author_id, uses, author_total)`, where `author_total` counts all
+    /// of the author's words. Most uses first.
+    pub async fn get_word_breakdown(
+        &self,
+        guild_id: u64,
+        word: &str,
+    ) -> Result<Vec<(u64, i64, i64)>, sqlx::Error> {
+        let pool = self.guild_pool(guild_id).await?;
+        let stemmer = self.get_guild_settings(guild_id).await?.stem_words;
+
+        let word = word.to_lowercase();
+        let (condition, word) = match stemmer {
+            Some(stemmer) => ("COALESCE(stem, word) = ?", stemmer.stem(&word)),
+            None => ("word = ?", word),
+        };
+
+        let rows = sqlx::query_as::<_, (i64, i64, i64)>(&format!(
+            r#"
+            SELECT author_id, SUM(count) AS uses,
+                (SELECT SUM(count) FROM word_counts totals
+                 WHERE totals.guild_id = word_counts.guild_id AND totals.author_id = word_counts.author_id)
+            FROM word_counts
+            WHERE guild_id = ? AND {}
+            GROUP BY author_id
+            ORDER BY uses DESC
+            "#,
+            condition
+        ))
+        .bind(guild_id as i64)
+        .bind(word)
+        .fetch_all(&pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(author_id, uses, total)| (author_id as u64, uses, total))
+            .collect())
+    }
+
+    /// `(word, author_id, uses)` rows matching `filter`, `offset` rows in.
+    pub async fn get_leaderboard_data(
+        &self,
+        guild_id: u64,
+        filter: &LeaderboardFilter,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<(String, u64, i64)>, sqlx::Error> {
+        let pool = self.guild_pool(guild_id).await?;
+
+        let min_users = filter.min_users;
+        let stemmer = self.get_guild_settings(guild_id).await?.stem_words;
+
+        // With stemming on, inflections are summed under their stem and shown
+        // as their most used surface form (SQLite picks the bare `word` column
+        // from the row that holds the MAX)
+        let mut sql = match stemmer {
+            Some(_) => String::from(
+                "SELECT word, author_id, SUM(count) AS uses, MAX(count) FROM word_counts WHERE guild_id = ? AND LENGTH(word) >= ?"
+            ),
+            None => String::from(
+                "SELECT word, author_id, count AS uses FROM word_counts WHERE guild_id = ? AND LENGTH(word) >= ?"
+            ),
+        };
+
+        if filter.target_user_id.is_some() {
+            sql.push_str(" AND author_id = ?");
+        }
+        if filter.target_word.is_some() {
+            match stemmer {
+                Some(_) => sql.push_str(" AND COALESCE(stem, word) = ?"),
+                None => sql.push_str(" AND word = ?"),
+            }
+        }
+
+        if !filter.excludes.is_empty() {
+            sql.push_str(" AND word NOT IN (");
+            for (i, _) in filter.excludes.iter().enumerate() {
+                if i > 0 {
+                    sql.push_str(", ");
+                }
+                sql.push('?');
+            }
+            sql.push(')');
+        }
+
+        // Drop words that fewer than `min_users` distinct people have used
+        if min_users > 1 {
+            match stemmer {
+                Some(_) => sql.push_str(
+                    " AND COALESCE(stem, word) IN (SELECT COALESCE(stem, word) FROM word_counts WHERE guild_id = ? GROUP BY COALESCE(stem, word) HAVING COUNT(DISTINCT author_id) >= ?)",
+                ),
+                None => sql.push_str(
+                    " AND word IN (SELECT word FROM word_counts WHERE guild_id = ? GROUP BY word HAVING COUNT(DISTINCT author_id) >= ?)",
+                ),
+            }
+        }
+
+        if stemmer.is_some() {
+            sql.push_str(" GROUP BY author_id, COALESCE(stem, word)");
+        }
+
+        sql.push_str(&format!(
+            " ORDER BY {} LIMIT ? OFFSET ?",
+            filter.sort.order_by()
+        ));
+
+        let target_word = filter.target_word.as_deref().map(|word| match stemmer {
+            Some(stemmer) => stemmer.stem(&word.to_lowercase()),
+            None => word.to_string(),
+        });
+
+        let mut query = sqlx::query_as::<_, (String, i64, i64)>(&sql)
+            .bind(guild_id as i64)
+            .bind(filter.min_length);
+
+        if let Some(uid) = filter.target_user_id {
+            query = query.bind(uid as i64);
+        }
+        if let Some(word) = target_word {
+            query = query.bind(word);
+        }
+        for word in &filter.excludes {
+            query = query.bind(word);
+        }
+        if min_users > 1 {
+            query = query.bind(guild_id as i64).bind(min_users);
+        }
+
+        query = query.bind(limit).bind(offset);
+
+        let rows = query.fetch_all(&pool).await?;
+
+        Ok(rows.into_iter().map(|(w, u, c)| (w, u as u64, c)).collect())
+    }
+
+    /// Every author's total uses of words at least `min_length` long, or of
+    /// `target_word` only, as `(author_id, uses)`. Most uses first.
+    pub async fn get_user_totals(
+        &self,
+        guild_id: u64,
+        target_word: Option<&str>,
+        min_length: i64,
+        excludes: &[String],
+        limit: i64,
+    ) -> Result<Vec<(u64, i64)>, sqlx::Error> {
+        let pool = self.guild_pool(guild_id).await?;
+        let stemmer = self.get_guild_settings(guild_id).await?.stem_words;
+
+        let mut query = QueryBuilder::<Sqlite>::new(
+            "SELECT author_id, SUM(count) AS uses FROM word_counts WHERE guild_id = ",
+        );
+        query
+            .push_bind(guild_id as i64)
+            .push(" AND LENGTH(word) >= ")
+            .push_bind(min_length);
+
+        if let Some(word) = target_word {
+            let word = word.to_lowercase();
+            match stemmer {
+                Some(stemmer) => query
+                    .push(" AND COALESCE(stem, word) = ")
+                    .push_bind(stemmer.stem(&word)),
+                None => query.push(" AND word = ").push_bind(word),
+            };
+        }
+
+        if !excludes.is_empty() {
+            query.push(" AND word NOT IN (");
+            let mut separated = query.separated(", ");
+            for word in excludes {
+                separated.push_bind(word.as_str());
+            }
+            separated.push_unseparated(")");
+        }
+
+        query
+            .push(" GROUP BY author_id ORDER BY uses DESC LIMIT ")
+            .push_bind(limit);
+
+        let rows: Vec<(i64, i64)> = query.build_query_as().fetch_all(&pool).await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(author_id, uses)| (author_id as u64, uses))
+            .collect())
+    }
+
+    /// Picks a random message matching `filter`, every match being equally
+    /// likely. Message IDs are spread unevenly, so picking a random point in
+    /// the ID range would favour messages after long quiet stretches.
+    pub async fn get_random_message(
+        &self,
+        guild_id: u64,
+        filter: &RandomMessageFilter,
+    ) -> Result<Option<StoredMessage>, sqlx::Error> {
+        let pool = self.guild_pool(guild_id).await?;
+
+        let mut query_builder: QueryBuilder<Sqlite> = QueryBuilder::new(
+            "SELECT message_id, content, author_id, channel_id, truncated FROM messages",
+        );
+        let author_lengths = self.author_lengths(guild_id, filter).await?;
+        self.push_random_message_conditions(
+            &mut query_builder,
+            guild_id,
+            filter,
+            author_lengths.as_deref(),
+        );
+
+        query_builder.push(" ORDER BY RANDOM() LIMIT 1");
+
+        let row = query_builder.build().fetch_optional(&pool).await?;
+
+        match row {
+            Some(row) => Ok(Some(StoredMessage {
+                message_id: row.get::<i64, _>("message_id") as u64,
+                author_id: row.get::<i64, _>("author_id") as u64,
+                channel_id: row.get::<i64, _>("channel_id") as u64,
+                content: row.get::<String, _>("content"),
+                truncated: row.get::<bool, _>("truncated"),
+            })),
+            None => Ok(None),
+        }
+    }
+
+    /// Each author's `max_words` most used words as `(author_id, word, count)`,
+    /// for authors with at least `min_messages` messages.
+    pub async fn get_author_vocabularies(
+        &self,
+        guild_id: u64,
+        min_messages: i64,
+        max_words: i64,
+    ) -> Result<Vec<(u64, String, i64)>, sqlx::Error> {
+        let pool = self.guild_pool(guild_id).await?;
+
+        let rows = sqlx::query_as::<_, (i64, String, i64)>(
+            r#"
+            SELECT author_id, word, count FROM (
+                SELECT author_id, word, count,
+                    ROW_NUMBER() OVER (PARTITION BY author_id ORDER BY count DESC) AS position
+                FROM word_counts
+                WHERE guild_id = ? AND author_id IN (
+                    SELECT author_id FROM messages
+                    WHERE guild_id = ?
+                    GROUP BY author_id
+                    HAVING COUNT(*) >= ?
+                )
+            )
+            WHERE position <= ?
+            "#,
+        )
+        .bind(guild_id as i64)
+        .bind(guild_id as i64)
+        .bind(min_messages)
+        .bind(max_words)
+        .fetch_all(&pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(author_id, word, count)| (author_id as u64, word, count))
+            .collect())
+    }
+
+    /// Returns the author's `(message_count, rank)`, rank 1 being whoever
+    /// sent the most messages, or `None` if nothing of theirs is stored.
+    pub async fn get_author_rank(
+        &self,
+        guild_id: u64,
+        author_id: u64,
+    ) -> Result<Option<(i64, i64)>, sqlx::Error> {
+        let pool = self.guild_pool(guild_id).await?;
+
+        let (message_count,): (i64,) =
+            sqlx::query_as("SELECT COUNT(*) FROM messages WHERE guild_id = ? AND author_id = ?")
+                .bind(guild_id as i64)
+                .bind(author_id as i64)
+                .fetch_one(&pool)
+                .await?;
+
+        if message_count == 0 {
+            return Ok(None);
+        }
+
+        let (ahead,): (i64,) = sqlx::query_as(
+            r#"
+            SELECT COUNT(*) FROM (
+                SELECT author_id FROM messages
+                WHERE guild_id = ?
+                GROUP BY author_id
+                HAVING COUNT(*) > ?
+            )
+            "#,
+        )
+        .bind(guild_id as i64)
+        .bind(message_count)
+        .fetch_one(&pool)
+        .await?;
+
+        Ok(Some((message_count, ahead + 1)))
+    }
+
+    /// Counts `user_id`'s stored messages and words in one round-trip.
+    pub async fn get_user_message_stats(
+        &self,
+        guild_id: u64,
+        user_id: u64,
+    ) -> Result<UserMessageStats, sqlx::Error> {
+        let pool = self.guild_pool(guild_id).await?;
+
+        let (messages, average_length, vocabulary): (i64, Option<f64>, i64) = sqlx::query_as(
+            r#"
+            SELECT
+                COUNT(*),
+                AVG(LENGTH(content)),
+                (SELECT COUNT(DISTINCT word) FROM word_counts WHERE guild_id = ? AND author_id = ?)
+            FROM messages
+            WHERE guild_id = ? AND author_id = ?
+            "#,
+        )
+        .bind(guild_id as i64)
+        .bind(user_id as i64)
+        .bind(guild_id as i64)
+        .bind(user_id as i64)
+        .fetch_one(&pool)
+        .await?;
+
+        Ok(UserMessageStats {
+            messages,
+            vocabulary,
+            average_length: average_length.unwrap_or(0.0),
+        })
+    }
+
+    /// `user_id`'s `limit` most used words as `(word, uses)`, counted like
+    /// `/leaderboard user:@them` but without `STOPWORDS`.
+    pub async fn get_user_top_words(
+        &self,
+        guild_id: u64,
+        user_id: u64,
+        limit: i64,
+    ) -> Result<Vec<(String, i64)>, sqlx::Error> {
+        let filter = LeaderboardFilter {
+            target_user_id: Some(user_id),
+            min_length: 3,
+            excludes: STOPWORDS.iter().map(|word| word.to_string()).collect(),
+            min_users: 1,
+            ..Default::default()
+        };
+
+        let rows = self
+            .get_leaderboard_data(guild_id, &filter, limit, 0)
+            .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(word, _, uses)| (word, uses))
+            .collect())
+    }
+
+    /// The channel `user_id` has the most stored messages in, as
+    /// `(channel_id, message_count)`.
+    pub async fn get_user_top_channel(
+        &self,
+        guild_id: u64,
+        user_id: u64,
+    ) -> Result<Option<(u64, i64)>, sqlx::Error> {
+        let pool = self.guild_pool(guild_id).await?;
+
+        let row = sqlx::query_as::<_, (i64, i64)>(
+            r#"
+            SELECT channel_id, COUNT(*) AS messages
+            FROM messages
+            WHERE guild_id = ? AND author_id = ?
+            GROUP BY channel_id
+            ORDER BY messages DESC
+            LIMIT 1
+            "#,
+        )
+        .bind(guild_id as i64)
+        .bind(user_id as i64)
+        .fetch_optional(&pool)
+        .await?;
+
+        Ok(row.map(|(channel_id, messages)| (channel_id as u64, messages)))
+    }
+
+    /// Returns up to `limit` `(author_id, message_count)` pairs, most messages
+    /// first, and the guild's total message count. Only messages with an id
+    /// (and so a timestamp) at or after `since` count, when it's given.
+    pub async fn get_top_posters(
+        &self,
+        guild_id: u64,
+        limit: i64,
+        since: Option<u64>,
+    ) -> Result<(Vec<(u64, i64)>, i64), sqlx::Error> {
+        let pool = self.guild_pool(guild_id).await?;
+
+        let rows = sqlx::query_as::<_, (i64, i64, i64)>(TOP_POSTERS_QUERY)
+            .bind(guild_id as i64)
+            .bind(since.unwrap_or(0) as i64)
+            .bind(limit)
+            .fetch_all(&pool)
+            .await?;
+
+        let total = rows.first().map_or(0, |(_, _, total)| *total);
+
+        Ok((
+            rows.into_iter()
+                .map(|(author_id, messages, _)| (author_id as u64, messages))
+                .collect(),
+            total,
+        ))
+    }
+
+    /// Returns `(author_id, average_length, message_count, longest_length)`
+    /// for authors with at least `min_messages` messages, wordiest first.
+    pub async fn get_verbosity_leaderboard(
+        &self,
+        guild_id: u64,
+        min_messages: i64,
+        limit: i64,
+    ) -> Result<Vec<(u64, f64, i64, i64)>, sqlx::Error> {
+        let pool = self.guild_pool(guild_id).await?;
+
+        let rows = sqlx::query_as::<_, (i64, f64, i64, i64)>(
+            r#"
+            SELECT author_id, AVG(LENGTH(content)) AS average_length, COUNT(*), MAX(LENGTH(content))
+            FROM messages
+            WHERE guild_id = ?
+            GROUP BY author_id
+            HAVING COUNT(*) >= ?
+            ORDER BY average_length DESC
+            LIMIT ?
+            "#,
+        )
+        .bind(guild_id as i64)
+        .bind(min_messages)
+        .bind(limit)
+        .fetch_all(&pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(author_id, average, count, longest)| (author_id as u64, average, count, longest))
+            .collect())
+    }
+
+    /// Compares word counts of messages from `current_start_id` on against
+    /// those from `previous_start_id` up to it, returning
+    /// `(word, current_count, previous_count)` by growth, fastest first.
+    ///
+    /// Words need at least `min_length` characters and `min_count` uses in
+    /// the current window.
+    pub async fn get_trending_words(
+        &self,
+        guild_id: u64,
+        previous_start_id: u64,
+        current_start_id: u64,
+        min_length: usize,
+        min_count: i64,
+    ) -> Result<Vec<(String, i64, i64)>, sqlx::Error> {
+        let pool = self.guild_pool(guild_id).await?;
+
+        // Words only exist after tallying, so both windows are counted here
+        let rows = sqlx::query_as::<_, (i64, String)>(
+            "SELECT message_id, content FROM messages WHERE guild_id = ? AND message_id >= ?",
+        )
+        .bind(guild_id as i64)
+        .bind(previous_start_id as i64)
+        .fetch_all(&pool)
+        .await?;
+
+        let mut counts: HashMap<String, (i64, i64)> = HashMap::new();
+        for (message_id, content) in rows {
+            let current = message_id as u64 >= current_start_id;
+
+            for (word, count) in tally_words(&content) {
+                if word.chars().count() < min_length || STOPWORDS.contains(&word.as_str()) {
+                    continue;
+                }
+
+                let entry = counts.entry(word).or_default();
+                match current {
+                    true => entry.0 += count as i64,
+                    false => entry.1 += count as i64,
+                }
+            }
+        }
+
+        let mut trending: Vec<(String, i64, i64)> = counts
+            .into_iter()
+            .filter(|(_, (current, _))| *current >= min_count)
+            .map(|(word, (current, previous))| (word, current, previous))
+            .collect();
+
+        // Smoothed so brand new words don't divide by zero
+        let growth = |current: i64, previous: i64| (current + 1) as f64 / (previous + 1) as f64;
+        trending.sort_by(|a, b| {
+            growth(b.1, b.2)
+                .total_cmp(&growth(a.1, a.2))
+                .then(b.1.cmp(&a.1))
+        });
+
+        Ok(trending)
+    }
+
+    /// Finds messages containing every word of `query`, best matches first,
+    /// as `(message_id, channel_id, author_id, snippet)` with matches in bold.
+    ///
+    /// Uses the full-text index when there is one, otherwise a much slower
+    /// `LIKE` scan ordered by recency that returns whole messages.
+    pub async fn search_messages(
+        &self,
+        guild_id: u64,
+        query: &str,
+        limit: i64,
+    ) -> Result<Vec<(u64, u64, u64, String)>, sqlx::Error> {
+        let pool = self.guild_pool(guild_id).await?;
+
+        let words: Vec<&str> = query.split_whitespace().collect();
+        if words.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut query_builder: QueryBuilder<Sqlite> = if Self::has_search_index(&pool).await? {
+            // Quote every word so user input can't use FTS5 query syntax
+            let match_query = words
+                .iter()
+                .map(|word| format!("\"{}\"", word.replace('"', "\"\"")))
+                .collect::<Vec<_>>()
+                .join(" ");
+
+            let mut query_builder = QueryBuilder::new(
+                "SELECT messages.message_id, messages.channel_id, messages.author_id, snippet(messages_fts, 0, '**', '**', '…', 16) \
+                FROM messages_fts JOIN messages ON messages.message_id = messages_fts.rowid \
+                WHERE messages_fts MATCH ",
+            );
+            query_builder
+                .push_bind(match_query)
+                .push(" AND messages.guild_id = ")
+                .push_bind(guild_id as i64);
+            self.push_opt_out_exclusion(&mut query_builder, guild_id);
+            query_builder.push(" ORDER BY rank");
+            query_builder
+        } else {
+            let mut query_builder = QueryBuilder::new(
+                "SELECT message_id, channel_id, author_id, content FROM messages WHERE guild_id = ",
+            );
+            query_builder.push_bind(guild_id as i64);
+            for word in words {
+                let escaped = word
+                    .replace('\\', "\\\\")
+                    .replace('%', "\\%")
+                    .replace('_', "\\_");
+                query_builder
+                    .push(" AND content LIKE ")
+                    .push_bind(format!("%{}%", escaped))
+                    .push(" ESCAPE '\\'");
+            }
+            self.push_opt_out_exclusion(&mut query_builder, guild_id);
+            query_builder.push(" ORDER BY message_id DESC");
+            query_builder
+        };
+
+        let rows: Vec<(i64, i64, i64, String)> = query_builder
+            .push(" LIMIT ")
+            .push_bind(limit)
+            .build_query_as()
+            .fetch_all(&pool)
+            .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(message_id, channel_id, author_id, snippet)| {
+                (
+                    message_id as u64,
+                    channel_id as u64,
+                    author_id as u64,
+                    snippet,
+                )
+            })
+            .collect())
+    }
+
+    /// Counts the messages `get_random_message` could pick from.
+    pub async fn count_random_message_candidates(
+        &self,
+        guild_id: u64,
+        filter: &RandomMessageFilter,
+    ) -> Result<i64, sqlx::Error> {
+        let pool = self.guild_pool(guild_id).await?;
+
+        let mut query_builder: QueryBuilder<Sqlite> =
+            QueryBuilder::new("SELECT COUNT(*) FROM messages");
+        let author_lengths = self.author_lengths(guild_id, filter).await?;
+        self.push_random_message_conditions(
+            &mut query_builder,
+            guild_id,
+            filter,
+            author_lengths.as_deref(),
+        );
+
+        let (count,): (i64,) = query_builder.build_query_as().fetch_one(&pool).await?;
+
+        Ok(count)
+    }
+
+    /// How many messages each author has stored, read at most once per
+    /// `AUTHOR_COUNTS_TTL`.
+    async fn author_message_counts(&self, guild_id: u64) -> Result<AuthorCounts, sqlx::Error> {
+        if let Some((read_at, counts)) = self.author_counts_cache.read().unwrap().get(&guild_id) {
+            if read_at.elapsed() < AUTHOR_COUNTS_TTL {
+                return Ok(counts.clone());
+            }
+        }
+
+        let pool = self.guild_pool(guild_id).await?;
+
+        let rows: Vec<(i64, i64)> = sqlx::query_as(
+            "SELECT author_id, COUNT(*) FROM messages WHERE guild_id = ? GROUP BY author_id",
+        )
+        .bind(guild_id as i64)
+        .fetch_all(&pool)
+        .await?;
+
+        let counts = Arc::new(
+            rows.into_iter()
+                .map(|(author_id, count)| (author_id as u64, count))
+                .collect::<Vec<_>>(),
+        );
+        self.author_counts_cache
+            .write()
+            .unwrap()
+            .insert(guild_id, (Instant::now(), counts.clone()));
+
+        Ok(counts)
+    }
+
+    /// Authors grouped by their `min_length_for`, as `(min_length, author_ids)`.
+    /// `None` unless the filter scales length by author.
+    async fn author_lengths(
+        &self,
+        guild_id: u64,
+        filter: &RandomMessageFilter,
+    ) -> Result<Option<Vec<(u64, Vec<u64>)>>, sqlx::Error> {
+        if !filter.scale_length_by_author {
+            return Ok(None);
+        }
+
+        let counts = self.author_message_counts(guild_id).await?;
+
+        let mut lengths: Vec<(u64, Vec<u64>)> = Vec::new();
+        for (author_id, count) in counts.iter() {
+            let Some(min_length) = min_length_for(*count) else {
                 continue;
+            };
+            match lengths.iter_mut().find(|(length, _)| *length == min_length) {
+                Some((_, authors)) => authors.push(*author_id),
+                None => lengths.push((min_length, vec![*author_id])),
+            }
+        }
+
+        Ok(Some(lengths))
+    }
+
+    fn push_random_message_conditions(
+        &self,
+        query_builder: &mut QueryBuilder<Sqlite>,
+        guild_id: u64,
+        filter: &RandomMessageFilter,
+        author_lengths: Option<&[(u64, Vec<u64>)]>,
+    ) {
+        let prefix_list = [
+            "$", "&", "!", ".", "m.", ">", "<", "[", "]", "@", "#", "^", "*", ",", "https", "http",
+        ];
+
+        query_builder
+            .push(" WHERE guild_id = ")
+            .push_bind(guild_id as i64)
+            .push(" AND LENGTH(content) >= ")
+            .push_bind(filter.min_length as i64);
+
+        // Authors below every bucket aren't in any list and are left out
+        if let Some(author_lengths) = author_lengths {
+            query_builder.push(" AND (0");
+            for (min_length, author_ids) in author_lengths {
+                query_builder
+                    .push(" OR (author_id IN (SELECT value FROM json_each(")
+                    .push_bind(json_id_list(author_ids.iter().copied()))
+                    .push(")) AND LENGTH(content) >= ")
+                    .push_bind(*min_length as i64)
+                    .push(")");
+            }
+            query_builder.push(")");
+        }
+
+        for prefix in prefix_list {
+            query_builder
+                .push(" AND content NOT LIKE ")
+                .push_bind(prefix)
+                .push(" || '%'");
+        }
+
+        if let Some(min_message_id) = filter.min_message_id {
+            query_builder
+                .push(" AND message_id >= ")
+                .push_bind(min_message_id as i64);
+        }
+
+        if let Some(channel_id) = filter.channel_id {
+            query_builder
+                .push(" AND channel_id = ")
+                .push_bind(channel_id as i64);
+        }
+
+        if let Some(author_id) = filter.author_id {
+            query_builder
+                .push(" AND author_id = ")
+                .push_bind(author_id as i64);
+        }
+
+        if let Some(contains) = &filter.contains {
+            let escaped = contains
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_");
+            query_builder
+                .push(" AND content LIKE ")
+                .push_bind(format!("%{}%", escaped))
+                .push(" ESCAPE '\\'");
+        }
+
+        // One JSON bind per list, however long it gets
+        if !filter.excluded_ids.is_empty() {
+            query_builder
+                .push(" AND message_id NOT IN (SELECT value FROM json_each(")
+                .push_bind(json_id_list(filter.excluded_ids.iter().copied()))
+                .push("))");
+        }
+
+        if !filter.excluded_author_ids.is_empty() {
+            query_builder
+                .push(" AND author_id NOT IN (SELECT value FROM json_each(")
+                .push_bind(json_id_list(filter.excluded_author_ids.iter().copied()))
+                .push("))");
+        }
+
+        self.push_opt_out_exclusion(query_builder, guild_id);
+    }
+
+    /// Leaves out messages from users who opted out in the guild or
+    /// everywhere, so they stop showing up before anything is purged.
+    fn push_opt_out_exclusion(&self, query_builder: &mut QueryBuilder<Sqlite>, guild_id: u64) {
+        let user_ids = self.opted_out_user_ids(guild_id);
+
+        if !user_ids.is_empty() {
+            query_builder
+                .push(" AND author_id NOT IN (SELECT value FROM json_each(")
+                .push_bind(json_id_list(user_ids))
+                .push("))");
+        }
+    }
+
+    /// Records the start of a `/collect` run and returns its id.
+    pub async fn start_collection_run(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+    ) -> Result<i64, sqlx::Error> {
+        let pool = self.guild_pool(guild_id).await?;
+
+        let (id,): (i64,) = sqlx::query_as(
+            r#"
+            INSERT INTO collection_runs (guild_id, channel_id, started_at, status)
+            VALUES (?, ?, CAST(strftime('%s', 'now') AS INTEGER), 'running')
+            RETURNING id
+            "#,
+        )
+        .bind(guild_id as i64)
+        .bind(channel_id as i64)
+        .fetch_one(&pool)
+        .await?;
+
+        Ok(id)
+    }
+
+    /// Saves a run's progress. Passing a `finished_status` also marks it as
+    /// finished.
+    pub async fn update_collection_run(
+        &self,
+        guild_id: u64,
+        run_id: i64,
+        messages_stored: u64,
+        messages_skipped: u64,
+        oldest_message_id: Option<u64>,
+        finished_status: Option<&str>,
+    ) -> Result<(), sqlx::Error> {
+        let pool = self.guild_pool(guild_id).await?;
+
+        sqlx::query(
+            r#"
+            UPDATE collection_runs SET
+                messages_stored = ?,
+                messages_skipped = ?,
+                oldest_message_id = ?,
+                status = COALESCE(?, status),
+                finished_at = CASE WHEN ? IS NULL THEN NULL ELSE CAST(strftime('%s', 'now') AS INTEGER) END
+            WHERE id = ?
+            "#,
+        )
+        .bind(messages_stored as i64)
+        .bind(messages_skipped as i64)
+        .bind(oldest_message_id.map(|id| id as i64))
+        .bind(finished_status)
+        .bind(finished_status)
+        .bind(run_id)
+        .execute(&pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// The guild's most recent `/collect` runs, newest first.
+    pub async fn get_collection_runs(
+        &self,
+        guild_id: u64,
+        limit: i64,
+    ) -> Result<Vec<CollectionRun>, sqlx::Error> {
+        let pool = self.guild_pool(guild_id).await?;
+
+        let rows = sqlx::query(
+            r#"
+            SELECT channel_id, started_at, finished_at, messages_stored, messages_skipped, status, oldest_message_id
+            FROM collection_runs
+            WHERE guild_id = ?
+            ORDER BY id DESC
+            LIMIT ?
+            "#,
+        )
+        .bind(guild_id as i64)
+        .bind(limit)
+        .fetch_all(&pool)
+        .await?;
+
+        Ok(rows
+            .iter()
+            .map(|row| CollectionRun {
+                channel_id: row.get::<i64, _>("channel_id") as u64,
+                started_at: row.get("started_at"),
+                finished_at: row.get("finished_at"),
+                messages_stored: row.get("messages_stored"),
+                messages_skipped: row.get("messages_skipped"),
+                status: row.get("status"),
+                oldest_message_id: row
+                    .get::<Option<i64>, _>("oldest_message_id")
+                    .map(|id| id as u64),
+            })
+            .collect())
+    }
+
+    /// Saves where `/collect` got to in a channel. `None` leaves a cursor
+    /// or the status as it was, and the newest message only ever moves
+    /// forward.
+    pub async fn save_collect_progress(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+        last_message_id: Option<u64>,
+        newest_message_id: Option<u64>,
+        status: Option<&str>,
+    ) -> Result<(), sqlx::Error> {
+        let pool = self.guild_pool(guild_id).await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO collect_progress (guild_id, channel_id, last_message_id, newest_message_id, status, updated_at)
+            VALUES (?, ?, ?, ?, COALESCE(?, 'running'), CAST(strftime('%s', 'now') AS INTEGER))
+            ON CONFLICT(guild_id, channel_id) DO UPDATE SET
+                last_message_id = COALESCE(excluded.last_message_id, last_message_id),
+                newest_message_id = MAX(COALESCE(newest_message_id, 0), COALESCE(excluded.newest_message_id, 0)),
+                status = COALESCE(?, status),
+                updated_at = excluded.updated_at
+            "#,
+        )
+        .bind(guild_id as i64)
+        .bind(channel_id as i64)
+        .bind(last_message_id.map(|id| id as i64))
+        .bind(newest_message_id.map(|id| id as i64))
+        .bind(status)
+        .bind(status)
+        .execute(&pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Where `/collect` got to in one channel, `None` if it never ran there.
+    pub async fn get_collect_progress(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+    ) -> Result<Option<CollectProgress>, sqlx::Error> {
+        let pool = self.guild_pool(guild_id).await?;
+
+        let row = sqlx::query(
+            "SELECT channel_id, last_message_id, newest_message_id, status, updated_at FROM collect_progress WHERE guild_id = ? AND channel_id = ?",
+        )
+        .bind(guild_id as i64)
+        .bind(channel_id as i64)
+        .fetch_optional(&pool)
+        .await?;
+
+        Ok(row.as_ref().map(Self::collect_progress_from_row))
+    }
+
+    /// Where `/collect` got to in every channel of the guild, most recently
+    /// updated first.
+    pub async fn get_guild_collect_progress(
+        &self,
+        guild_id: u64,
+    ) -> Result<Vec<CollectProgress>, sqlx::Error> {
+        let pool = self.guild_pool(guild_id).await?;
+
+        let rows = sqlx::query(
+            "SELECT channel_id, last_message_id, newest_message_id, status, updated_at FROM collect_progress WHERE guild_id = ? ORDER BY updated_at DESC",
+        )
+        .bind(guild_id as i64)
+        .fetch_all(&pool)
+        .await?;
+
+        Ok(rows.iter().map(Self::collect_progress_from_row).collect())
+    }
+
+    fn collect_progress_from_row(row: &sqlx::sqlite::SqliteRow) -> CollectProgress {
+        // Zero stands in for "not yet" in the MAX above
+        let id = |column: &str| {
+            row.get::<Option<i64>, _>(column)
+                .filter(|id| *id > 0)
+                .map(|id| id as u64)
+        };
+
+        CollectProgress {
+            channel_id: row.get::<i64, _>("channel_id") as u64,
+            last_message_id: id("last_message_id"),
+            newest_message_id: id("newest_message_id"),
+            status: row.get("status"),
+            updated_at: row.get("updated_at"),
+        }
+    }
+
+    /// Counts a `/guess` guess, right or wrong.
+    pub async fn record_guess_result(
+        &self,
+        guild_id: u64,
+        user_id: u64,
+        correct: bool,
+    ) -> Result<(), sqlx::Error> {
+        let pool = self.guild_pool(guild_id).await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO guess_scores (guild_id, user_id, correct, attempts, last_played)
+            VALUES (?, ?, ?, 1, CAST(strftime('%s', 'now') AS INTEGER))
+            ON CONFLICT(guild_id, user_id) DO UPDATE SET
+                correct = correct + excluded.correct,
+                attempts = attempts + 1,
+                last_played = excluded.last_played
+            "#,
+        )
+        .bind(guild_id as i64)
+        .bind(user_id as i64)
+        .bind(correct as i64)
+        .execute(&pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Keeps `streak` as the user's best streak if it beats the one stored.
+    pub async fn record_guess_streak(
+        &self,
+        guild_id: u64,
+        user_id: u64,
+        streak: u32,
+    ) -> Result<(), sqlx::Error> {
+        let pool = self.guild_pool(guild_id).await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO guess_scores (guild_id, user_id, best_streak, last_played)
+            VALUES (?, ?, ?, CAST(strftime('%s', 'now') AS INTEGER))
+            ON CONFLICT(guild_id, user_id) DO UPDATE SET
+                best_streak = MAX(best_streak, excluded.best_streak)
+            "#,
+        )
+        .bind(guild_id as i64)
+        .bind(user_id as i64)
+        .bind(streak as i64)
+        .execute(&pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// The guild's best `/guess` players, most correct guesses first.
+    pub async fn get_guess_leaderboard(
+        &self,
+        guild_id: u64,
+        limit: u32,
+    ) -> Result<Vec<GuessScore>, sqlx::Error> {
+        let pool = self.guild_pool(guild_id).await?;
+
+        let rows = sqlx::query(
+            r#"
+            SELECT user_id, correct, attempts, best_streak, last_played
+            FROM guess_scores
+            WHERE guild_id = ? AND correct > 0
+            ORDER BY correct DESC, attempts ASC, best_streak DESC
+            LIMIT ?
+            "#,
+        )
+        .bind(guild_id as i64)
+        .bind(limit as i64)
+        .fetch_all(&pool)
+        .await?;
+
+        Ok(rows
+            .iter()
+            .map(|row| GuessScore {
+                user_id: row.get::<i64, _>("user_id") as u64,
+                correct: row.get("correct"),
+                attempts: row.get("attempts"),
+                best_streak: row.get("best_streak"),
+                last_played: row.get("last_played"),
+            })
+            .collect())
+    }
+
+    pub async fn get_runtime_flag(&self, name: &str) -> Result<bool, sqlx::Error> {
+        let row = sqlx::query("SELECT enabled FROM runtime_flags WHERE name = ?")
+            .bind(name)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        match row {
+            Some(row) => Ok(row.get::<i64, _>("enabled") != 0),
+            None => Ok(false),
+        }
+    }
+
+    pub async fn set_runtime_flag(&self, name: &str, enabled: bool) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO runtime_flags (name, enabled)
+            VALUES (?, ?)
+            ON CONFLICT(name)
+            DO UPDATE SET enabled = excluded.enabled
+            "#,
+        )
+        .bind(name)
+        .bind(enabled as i64)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Deletes everything stored for a guild: messages, derived stats,
+    /// settings, banned words, members and collection history.
+    pub async fn purge_guild(&self, guild_id: u64) -> Result<GuildPurge, sqlx::Error> {
+        const TABLES: [&str; 10] = [
+            "messages",
+            "word_counts",
+            "channel_stats",
+            "rebuild_progress",
+            "guild_settings",
+            "banned_words",
+            "guild_members",
+            "collection_runs",
+            "collect_progress",
+            "guess_scores",
+        ];
+
+        let pool = self.guild_pool(guild_id).await?;
+        let mut tx = pool.begin().await?;
+
+        let channel_ids: Vec<(i64,)> = sqlx::query_as(
+            "SELECT channel_id FROM channel_stats WHERE guild_id = ? UNION SELECT DISTINCT channel_id FROM messages WHERE guild_id = ?",
+        )
+        .bind(guild_id as i64)
+        .bind(guild_id as i64)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        let mut rows = 0;
+        for table in TABLES {
+            rows += sqlx::query(&format!("DELETE FROM {} WHERE guild_id = ?", table))
+                .bind(guild_id as i64)
+                .execute(&mut *tx)
+                .await?
+                .rows_affected();
+        }
+
+        tx.commit().await?;
+
+        self.settings_cache.write().unwrap().remove(&guild_id);
+
+        Ok(GuildPurge {
+            rows,
+            channel_ids: channel_ids.into_iter().map(|(id,)| id as u64).collect(),
+        })
+    }
+
+    /// Deletes a user's messages, word counts and guess scores in a guild, and
+    /// takes their messages back out of `channel_stats`.
+    pub async fn purge_user(&self, guild_id: u64, user_id: u64) -> Result<UserPurge, sqlx::Error> {
+        let pool = self.guild_pool(guild_id).await?;
+        // Immediate, so a running rebuild can't move on while we count
+        let mut tx = pool.begin_with("BEGIN IMMEDIATE").await?;
+        let cursor = Self::rebuild_cursor(&mut tx, guild_id).await?;
+
+        // Messages a running rebuild hasn't reached aren't counted yet
+        let channels: Vec<(i64, i64)> = sqlx::query_as(
+            "SELECT channel_id, COUNT(*) FROM messages WHERE guild_id = ? AND author_id = ? AND message_id <= ? GROUP BY channel_id",
+        )
+        .bind(guild_id as i64)
+        .bind(user_id as i64)
+        .bind(cursor.unwrap_or(i64::MAX))
+        .fetch_all(&mut *tx)
+        .await?;
+
+        let messages = sqlx::query("DELETE FROM messages WHERE guild_id = ? AND author_id = ?")
+            .bind(guild_id as i64)
+            .bind(user_id as i64)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+
+        for (channel_id, count) in &channels {
+            sqlx::query(
+                "UPDATE channel_stats SET count = MAX(count - ?, 0) WHERE guild_id = ? AND channel_id = ?",
+            )
+            .bind(count)
+            .bind(guild_id as i64)
+            .bind(channel_id)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        sqlx::query("DELETE FROM word_counts WHERE guild_id = ? AND author_id = ?")
+            .bind(guild_id as i64)
+            .bind(user_id as i64)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query("DELETE FROM guess_scores WHERE guild_id = ? AND user_id = ?")
+            .bind(guild_id as i64)
+            .bind(user_id as i64)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(UserPurge {
+            messages,
+            channel_ids: channels
+                .into_iter()
+                .map(|(channel_id, _)| channel_id as u64)
+                .collect(),
+        })
+    }
+
+    /// Counts what's stored for a guild in one round-trip.
+    pub async fn get_guild_stats(&self, guild_id: u64) -> Result<GuildStats, sqlx::Error> {
+        let pool = self.guild_pool(guild_id).await?;
+
+        let (messages, authors, oldest_message_at, newest_message_at, content_bytes, words): (
+            i64,
+            i64,
+            Option<i64>,
+            Option<i64>,
+            i64,
+            i64,
+        ) = sqlx::query_as(
+            r#"
+            SELECT
+                COUNT(*),
+                COUNT(DISTINCT author_id),
+                MIN(created_at),
+                MAX(created_at),
+                COALESCE(SUM(LENGTH(CAST(content AS BLOB))), 0),
+                (SELECT COUNT(DISTINCT word) FROM word_counts WHERE guild_id = ?)
+            FROM messages
+            WHERE guild_id = ?
+            "#,
+        )
+        .bind(guild_id as i64)
+        .bind(guild_id as i64)
+        .fetch_one(&pool)
+        .await?;
+
+        Ok(GuildStats {
+            messages,
+            authors,
+            words,
+            oldest_message_at,
+            newest_message_at,
+            content_bytes,
+        })
+    }
+
+    /// Returns the number of `(word_counts, channel_stats)` rows for a guild.
+    pub async fn count_derived_rows(&self, guild_id: u64) -> Result<(i64, i64), sqlx::Error> {
+        let pool = self.guild_pool(guild_id).await?;
+
+        let counts: (i64, i64) = sqlx::query_as(
+            r#"
+            SELECT
+                (SELECT COUNT(*) FROM word_counts WHERE guild_id = ?),
+                (SELECT COUNT(*) FROM channel_stats WHERE guild_id = ?)
+            "#,
+        )
+        .bind(guild_id as i64)
+        .bind(guild_id as i64)
+        .fetch_one(&pool)
+        .await?;
+
+        Ok(counts)
+    }
+
+    /// Recomputes `word_counts` and `channel_stats` for a guild from `messages`.
+    ///
+    /// The aggregates are cleared and messages are counted back in chunks, each
+    /// in its own short transaction, so stores and edits keep going meanwhile.
+    /// Those skip messages the rebuild hasn't reached yet, see `is_counted`.
+    /// The cursor lives in `rebuild_progress`, so a rebuild that was
+    /// interrupted picks up where it stopped. `on_progress` is called with the
+    /// number of messages scanned so far.
+    pub async fn rebuild_derived_tables(
+        &self,
+        guild_id: u64,
+        mut on_progress: impl FnMut(u64),
+    ) -> Result<(), sqlx::Error> {
+        let pool = self.guild_pool(guild_id).await?;
+        let stemmer = self.get_guild_settings(guild_id).await?.stem_words;
+
+        let mut tx = pool.begin_with("BEGIN IMMEDIATE").await?;
+        if Self::rebuild_cursor(&mut tx, guild_id).await?.is_none() {
+            sqlx::query("DELETE FROM word_counts WHERE guild_id = ?")
+                .bind(guild_id as i64)
+                .execute(&mut *tx)
+                .await?;
+
+            sqlx::query("DELETE FROM channel_stats WHERE guild_id = ?")
+                .bind(guild_id as i64)
+                .execute(&mut *tx)
+                .await?;
+
+            sqlx::query("INSERT INTO rebuild_progress (guild_id, last_message_id) VALUES (?, 0)")
+                .bind(guild_id as i64)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+
+        let mut scanned = 0;
+
+        while let Some(counted) = Self::rebuild_chunk(&pool, guild_id, stemmer).await? {
+            scanned += counted;
+            on_progress(scanned);
+        }
+
+        Ok(())
+    }
+
+    /// Counts the next chunk of a running rebuild and moves its cursor past
+    /// it, or ends the rebuild when nothing is left. Returns how many
+    /// messages were counted, `None` once done.
+    async fn rebuild_chunk(
+        pool: &Pool,
+        guild_id: u64,
+        stemmer: Option<Stemmer>,
+    ) -> Result<Option<u64>, sqlx::Error> {
+        let mut tx = pool.begin_with("BEGIN IMMEDIATE").await?;
+
+        let Some(cursor) = Self::rebuild_cursor(&mut tx, guild_id).await? else {
+            return Ok(None);
+        };
+
+        let rows: Vec<(i64, i64, i64, String)> = sqlx::query_as(
+            "SELECT message_id, author_id, channel_id, content FROM messages WHERE guild_id = ? AND message_id > ? ORDER BY message_id LIMIT ?",
+        )
+        .bind(guild_id as i64)
+        .bind(cursor)
+        .bind(REBUILD_CHUNK_SIZE)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        let Some((last_message_id, _, _, _)) = rows.last() else {
+            sqlx::query("DELETE FROM rebuild_progress WHERE guild_id = ?")
+                .bind(guild_id as i64)
+                .execute(&mut *tx)
+                .await?;
+            tx.commit().await?;
+
+            return Ok(None);
+        };
+        let last_message_id = *last_message_id;
+
+        let mut words: HashMap<(i64, String), i64> = HashMap::new();
+        let mut channels: HashMap<i64, i64> = HashMap::new();
+        for (_, author_id, channel_id, content) in &rows {
+            *channels.entry(*channel_id).or_insert(0) += 1;
+            for (word, count) in tally_words(content) {
+                *words.entry((*author_id, word)).or_insert(0) += count as i64;
             }
-            *local_counts.entry(word_lower).or_insert(0) += 1;
         }
 
-        for (word, count) in local_counts {
-            sqlx::query(
-                r#"
-                INSERT INTO word_counts (guild_id, author_id, word, count)
-                VALUES (?, ?, ?, ?)
-                ON CONFLICT(guild_id, author_id, word) 
-                DO UPDATE SET count = count + excluded.count
-                "#,
-            )
+        let channels: Vec<_> = channels.into_iter().collect();
+        for chunk in channels.chunks(INSERT_CHUNK_SIZE) {
+            let mut query_builder: QueryBuilder<Sqlite> =
+                QueryBuilder::new("INSERT INTO channel_stats (guild_id, channel_id, count) ");
+
+            query_builder.push_values(chunk, |mut row, (channel_id, count)| {
+                row.push_bind(guild_id as i64)
+                    .push_bind(*channel_id)
+                    .push_bind(*count);
+            });
+
+            query_builder.push(
+                " ON CONFLICT(guild_id, channel_id) DO UPDATE SET count = count + excluded.count",
+            );
+
+            query_builder.build().execute(&mut *tx).await?;
+        }
+
+        let words: Vec<_> = words.into_iter().collect();
+        for chunk in words.chunks(INSERT_CHUNK_SIZE) {
+            let mut query_builder: QueryBuilder<Sqlite> = QueryBuilder::new(
+                "INSERT INTO word_counts (guild_id, author_id, word, stem, count) ",
+            );
+
+            query_builder.push_values(chunk, |mut row, ((author_id, word), count)| {
+                row.push_bind(guild_id as i64)
+                    .push_bind(*author_id)
+                    .push_bind(word.as_str())
+                    .push_bind(stemmer.map(|stemmer| stemmer.stem(word)))
+                    .push_bind(*count);
+            });
+
+            query_builder.push(
+                " ON CONFLICT(guild_id, author_id, word) DO UPDATE SET count = count + excluded.count, stem = excluded.stem",
+            );
+
+            query_builder.build().execute(&mut *tx).await?;
+        }
+
+        sqlx::query("UPDATE rebuild_progress SET last_message_id = ? WHERE guild_id = ?")
+            .bind(last_message_id)
+            .bind(guild_id as i64)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(Some(rows.len() as u64))
+    }
+
+    pub async fn get_guild_settings(&self, guild_id: u64) -> Result<GuildSettings, sqlx::Error> {
+        if let Some(settings) = self.settings_cache.read().unwrap().get(&guild_id) {
+            return Ok(settings.clone());
+        }
+
+        let pool = self.guild_pool(guild_id).await?;
+
+        let row = sqlx::query(
+            r#"
+            SELECT stem_words, guess_recency_days, dedupe_consecutive, filter_banned_training,
+                autopost_enabled, autopost_min_secs, autopost_max_secs, autopost_channel_id,
+                name_trigger, name_aliases, reply_chance, command_prefixes
+            FROM guild_settings
+            WHERE guild_id = ?
+            "#,
+        )
+        .bind(guild_id as i64)
+        .fetch_optional(&pool)
+        .await?;
+
+        let banned_words: Vec<(String,)> =
+            sqlx::query_as("SELECT word FROM banned_words WHERE guild_id = ? ORDER BY word")
+                .bind(guild_id as i64)
+                .fetch_all(&pool)
+                .await?;
+        let banned_words = banned_words.into_iter().map(|(word,)| word).collect();
+
+        let settings = match row {
+            Some(row) => GuildSettings {
+                stem_words: row
+                    .get::<Option<String>, _>("stem_words")
+                    .and_then(|code| Stemmer::from_code(&code)),
+                guess_recency_days: row
+                    .get::<Option<i64>, _>("guess_recency_days")
+                    .map(|days| days as u32),
+                dedupe_consecutive: row.get::<bool, _>("dedupe_consecutive"),
+                banned_words,
+                filter_banned_training: row.get::<bool, _>("filter_banned_training"),
+                autopost_enabled: row.get::<bool, _>("autopost_enabled"),
+                autopost_min_secs: row.get::<i64, _>("autopost_min_secs") as u64,
+                autopost_max_secs: row.get::<i64, _>("autopost_max_secs") as u64,
+                autopost_channel_id: row
+                    .get::<Option<i64>, _>("autopost_channel_id")
+                    .map(|channel_id| channel_id as u64),
+                name_trigger: row.get::<bool, _>("name_trigger"),
+                name_aliases: row
+                    .get::<Option<String>, _>("name_aliases")
+                    .map(|aliases| {
+                        aliases
+                            .split(',')
+                            .filter(|alias| !alias.is_empty())
+                            .map(str::to_string)
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+                reply_chance: row.get::<f64, _>("reply_chance"),
+                command_prefixes: row
+                    .get::<Option<String>, _>("command_prefixes")
+                    .map(|prefixes| prefixes.split_whitespace().map(str::to_string).collect())
+                    .unwrap_or_default(),
+            },
+            None => GuildSettings {
+                banned_words,
+                ..GuildSettings::default()
+            },
+        };
+
+        self.settings_cache
+            .write()
+            .unwrap()
+            .insert(guild_id, settings.clone());
+
+        Ok(settings)
+    }
+
+    /// Writes every column of the guild's `guild_settings` row. Banned words
+    /// live in their own table and are left alone.
+    pub async fn upsert_guild_settings(
+        &self,
+        guild_id: u64,
+        settings: &GuildSettings,
+    ) -> Result<(), sqlx::Error> {
+        let pool = self.guild_pool(guild_id).await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO guild_settings (
+                guild_id, stem_words, guess_recency_days, dedupe_consecutive,
+                filter_banned_training, autopost_enabled, autopost_min_secs,
+                autopost_max_secs, autopost_channel_id, name_trigger, name_aliases,
+                reply_chance, command_prefixes
+            )
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT(guild_id)
+            DO UPDATE SET
+                stem_words = excluded.stem_words,
+                guess_recency_days = excluded.guess_recency_days,
+                dedupe_consecutive = excluded.dedupe_consecutive,
+                filter_banned_training = excluded.filter_banned_training,
+                autopost_enabled = excluded.autopost_enabled,
+                autopost_min_secs = excluded.autopost_min_secs,
+                autopost_max_secs = excluded.autopost_max_secs,
+                autopost_channel_id = excluded.autopost_channel_id,
+                name_trigger = excluded.name_trigger,
+                name_aliases = excluded.name_aliases,
+                reply_chance = excluded.reply_chance,
+                command_prefixes = excluded.command_prefixes
+            "#,
+        )
+        .bind(guild_id as i64)
+        .bind(settings.stem_words.map(|stemmer| stemmer.code()))
+        .bind(settings.guess_recency_days.map(|days| days as i64))
+        .bind(settings.dedupe_consecutive)
+        .bind(settings.filter_banned_training)
+        .bind(settings.autopost_enabled)
+        .bind(settings.autopost_min_secs as i64)
+        .bind(settings.autopost_max_secs as i64)
+        .bind(
+            settings
+                .autopost_channel_id
+                .map(|channel_id| channel_id as i64),
+        )
+        .bind(settings.name_trigger)
+        .bind(Some(settings.name_aliases.join(",")).filter(|aliases| !aliases.is_empty()))
+        .bind(settings.reply_chance)
+        .bind(Some(settings.command_prefixes.join(" ")).filter(|prefixes| !prefixes.is_empty()))
+        .execute(&pool)
+        .await?;
+
+        self.settings_cache.write().unwrap().remove(&guild_id);
+
+        Ok(())
+    }
+
+    pub async fn set_stem_words(
+        &self,
+        guild_id: u64,
+        stemmer: Option<Stemmer>,
+    ) -> Result<(), sqlx::Error> {
+        let pool = self.guild_pool(guild_id).await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO guild_settings (guild_id, stem_words)
+            VALUES (?, ?)
+            ON CONFLICT(guild_id)
+            DO UPDATE SET stem_words = excluded.stem_words
+            "#,
+        )
+        .bind(guild_id as i64)
+        .bind(stemmer.map(|stemmer| stemmer.code()))
+        .execute(&pool)
+        .await?;
+
+        self.settings_cache.write().unwrap().remove(&guild_id);
+
+        Ok(())
+    }
+
+    pub async fn set_guess_recency_days(
+        &self,
+        guild_id: u64,
+        days: Option<u32>,
+    ) -> Result<(), sqlx::Error> {
+        let pool = self.guild_pool(guild_id).await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO guild_settings (guild_id, guess_recency_days)
+            VALUES (?, ?)
+            ON CONFLICT(guild_id)
+            DO UPDATE SET guess_recency_days = excluded.guess_recency_days
+            "#,
+        )
+        .bind(guild_id as i64)
+        .bind(days.map(|days| days as i64))
+        .execute(&pool)
+        .await?;
+
+        self.settings_cache.write().unwrap().remove(&guild_id);
+
+        Ok(())
+    }
+
+    pub async fn set_dedupe_consecutive(
+        &self,
+        guild_id: u64,
+        enabled: bool,
+    ) -> Result<(), sqlx::Error> {
+        let pool = self.guild_pool(guild_id).await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO guild_settings (guild_id, dedupe_consecutive)
+            VALUES (?, ?)
+            ON CONFLICT(guild_id)
+            DO UPDATE SET dedupe_consecutive = excluded.dedupe_consecutive
+            "#,
+        )
+        .bind(guild_id as i64)
+        .bind(enabled)
+        .execute(&pool)
+        .await?;
+
+        self.settings_cache.write().unwrap().remove(&guild_id);
+
+        Ok(())
+    }
+
+    pub async fn set_filter_banned_training(
+        &self,
+        guild_id: u64,
+        enabled: bool,
+    ) -> Result<(), sqlx::Error> {
+        let pool = self.guild_pool(guild_id).await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO guild_settings (guild_id, filter_banned_training)
+            VALUES (?, ?)
+            ON CONFLICT(guild_id)
+            DO UPDATE SET filter_banned_training = excluded.filter_banned_training
+            "#,
+        )
+        .bind(guild_id as i64)
+        .bind(enabled)
+        .execute(&pool)
+        .await?;
+
+        self.settings_cache.write().unwrap().remove(&guild_id);
+
+        Ok(())
+    }
+
+    /// Adds `word` to the guild's banned words, returning `false` if it was
+    /// already there.
+    pub async fn add_banned_word(&self, guild_id: u64, word: &str) -> Result<bool, sqlx::Error> {
+        let pool = self.guild_pool(guild_id).await?;
+
+        let result =
+            sqlx::query("INSERT OR IGNORE INTO banned_words (guild_id, word) VALUES (?, ?)")
+                .bind(guild_id as i64)
+                .bind(word)
+                .execute(&pool)
+                .await?;
+
+        self.settings_cache.write().unwrap().remove(&guild_id);
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Removes `word` from the guild's banned words, returning `false` if it
+    /// wasn't there.
+    pub async fn remove_banned_word(&self, guild_id: u64, word: &str) -> Result<bool, sqlx::Error> {
+        let pool = self.guild_pool(guild_id).await?;
+
+        let result = sqlx::query("DELETE FROM banned_words WHERE guild_id = ? AND word = ?")
+            .bind(guild_id as i64)
+            .bind(word)
+            .execute(&pool)
+            .await?;
+
+        self.settings_cache.write().unwrap().remove(&guild_id);
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Records whether `user_id` is currently in the guild.
+    pub async fn set_member(
+        &self,
+        guild_id: u64,
+        user_id: u64,
+        present: bool,
+    ) -> Result<(), sqlx::Error> {
+        let pool = self.guild_pool(guild_id).await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO guild_members (guild_id, user_id, present, updated_at)
+            VALUES (?, ?, ?, CAST(strftime('%s', 'now') AS INTEGER))
+            ON CONFLICT(guild_id, user_id)
+            DO UPDATE SET present = excluded.present, updated_at = excluded.updated_at
+            "#,
+        )
+        .bind(guild_id as i64)
+        .bind(user_id as i64)
+        .bind(present)
+        .execute(&pool)
+        .await?;
+
+        self.member_cache
+            .write()
+            .unwrap()
+            .insert((guild_id, user_id), present);
+
+        Ok(())
+    }
+
+    /// Marks someone seen posting in the guild as present, skipping the
+    /// write when that's already known.
+    pub async fn observe_member(&self, guild_id: u64, user_id: u64) -> Result<(), sqlx::Error> {
+        let known = self
+            .member_cache
+            .read()
+            .unwrap()
+            .get(&(guild_id, user_id))
+            .copied();
+
+        match known {
+            Some(true) => Ok(()),
+            _ => self.set_member(guild_id, user_id, true).await,
+        }
+    }
+
+    /// Whether `user_id` is still in the guild.
+    ///
+    /// Users the bot knows nothing about count as present, which is all of
+    /// them when the `GUILD_MEMBERS` intent is off.
+    pub async fn is_member(&self, guild_id: u64, user_id: u64) -> Result<bool, sqlx::Error> {
+        if let Some(present) = self.member_cache.read().unwrap().get(&(guild_id, user_id)) {
+            return Ok(*present);
+        }
+
+        let pool = self.guild_pool(guild_id).await?;
+
+        let present: Option<(bool,)> =
+            sqlx::query_as("SELECT present FROM guild_members WHERE guild_id = ? AND user_id = ?")
+                .bind(guild_id as i64)
+                .bind(user_id as i64)
+                .fetch_optional(&pool)
+                .await?;
+
+        let present = present.is_none_or(|(present,)| present);
+
+        self.member_cache
+            .write()
+            .unwrap()
+            .insert((guild_id, user_id), present);
+
+        Ok(present)
+    }
+
+    /// Whether `user_id` opted out of being recorded in the guild, or
+    /// everywhere.
+    pub fn is_opted_out(&self, guild_id: u64, user_id: u64) -> bool {
+        let opt_outs = self.opt_outs.read().unwrap();
+        opt_outs.contains(&(guild_id, user_id)) || opt_outs.contains(&(GLOBAL_OPT_OUT, user_id))
+    }
+
+    /// Everyone who opted out of being recorded in the guild, or everywhere.
+    pub fn opted_out_user_ids(&self, guild_id: u64) -> Vec<u64> {
+        self.opt_outs
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(opt_out_guild_id, _)| {
+                *opt_out_guild_id == guild_id || *opt_out_guild_id == GLOBAL_OPT_OUT
+            })
+            .map(|(_, user_id)| *user_id)
+            .collect()
+    }
+
+    /// Opts the user out of (or back into) being recorded in `guild_id`,
+    /// or everywhere when it's `None`.
+    pub async fn set_opted_out(
+        &self,
+        guild_id: Option<u64>,
+        user_id: u64,
+        opted_out: bool,
+    ) -> Result<(), sqlx::Error> {
+        let guild_id = guild_id.unwrap_or(GLOBAL_OPT_OUT);
+
+        let query = if opted_out {
+            "INSERT OR IGNORE INTO opted_out_users (guild_id, user_id) VALUES (?, ?)"
+        } else {
+            "DELETE FROM opted_out_users WHERE guild_id = ? AND user_id = ?"
+        };
+
+        sqlx::query(query)
             .bind(guild_id as i64)
-            .bind(author_id as i64)
-            .bind(word)
-            .bind(count)
+            .bind(user_id as i64)
             .execute(&self.pool)
             .await?;
+
+        let mut opt_outs = self.opt_outs.write().unwrap();
+        if opted_out {
+            opt_outs.insert((guild_id, user_id));
+        } else {
+            opt_outs.remove(&(guild_id, user_id));
         }
 
         Ok(())
     }
+}
 
-    pub async fn get_messages_for_markov(
-        &self,
-        guild_id: u64,
-        channel_id: u64,
-        prefixes: &[&str],
-        limit: usize,
-    ) -> Result<Vec<String>, sqlx::Error> {
-        let prefix_conditions = prefixes
-            .iter()
-            .map(|_| "content NOT LIKE ? || '%'")
-            .collect::<Vec<_>>()
-            .join(" AND ");
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serenity::all::Timestamp;
+
+    const GUILD_ID: u64 = 1;
+
+    /// A fresh in-memory database. One connection, since every connection
+    /// to `sqlite::memory:` gets a database of its own.
+    async fn memory_db() -> Database {
+        Database::new("sqlite::memory:", 2000, None, 1)
+            .await
+            .unwrap()
+    }
 
-        let bounds: Option<(i64, i64)> = sqlx::query_as(
-            "SELECT MIN(message_id), MAX(message_id) FROM messages WHERE guild_id = ? AND channel_id = ?"
+    async fn word_counts(db: &Database) -> Vec<(i64, String, i64)> {
+        sqlx::query_as(
+            "SELECT author_id, word, count FROM word_counts WHERE guild_id = ? ORDER BY author_id, word",
         )
-        .bind(guild_id as i64)
-        .bind(channel_id as i64)
-        .fetch_optional(&self.pool)
-        .await?;
+        .bind(GUILD_ID as i64)
+        .fetch_all(&db.pool)
+        .await
+        .unwrap()
+    }
 
-        let (min_id, max_id) = match bounds {
-            Some((min, max)) if min > 0 && max > 0 => (min, max),
-            _ => return Ok(Vec::new()),
-        };
+    async fn channel_stats(db: &Database) -> Vec<(i64, i64)> {
+        sqlx::query_as(
+            "SELECT channel_id, count FROM channel_stats WHERE guild_id = ? ORDER BY channel_id",
+        )
+        .bind(GUILD_ID as i64)
+        .fetch_all(&db.pool)
+        .await
+        .unwrap()
+    }
+
+    async fn schema(pool: &Pool) -> Vec<(String, String, Option<String>)> {
+        sqlx::query_as("SELECT type, name, sql FROM sqlite_master ORDER BY type, name")
+            .fetch_all(pool)
+            .await
+            .unwrap()
+    }
+
+    async fn versions(pool: &Pool) -> Vec<(i64,)> {
+        sqlx::query_as("SELECT version FROM schema_version ORDER BY version")
+            .fetch_all(pool)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn migrations_run_twice_give_the_same_schema() {
+        let options = SqliteConnectOptions::from_str("sqlite::memory:").unwrap();
+        let pool = Database::connect(options, 1).await.unwrap();
 
-        let query = format!(
-            "SELECT content FROM messages 
-             WHERE guild_id = ? 
-             AND channel_id = ? 
-             AND message_id >= (ABS(RANDOM()) % (? - ?) + ?) 
-             AND LENGTH(content) > 10 
-             AND {} 
-             LIMIT ?",
-            prefix_conditions
+        Database::setup_tables(&pool).await.unwrap();
+        let first_schema = schema(&pool).await;
+        let first_versions = versions(&pool).await;
+
+        Database::setup_tables(&pool).await.unwrap();
+
+        assert_eq!(schema(&pool).await, first_schema);
+        assert_eq!(versions(&pool).await, first_versions);
+        assert_eq!(
+            first_versions.last(),
+            Some(&(MIGRATIONS.len() as i64,)),
+            "every migration should be recorded"
         );
+    }
 
-        let mut query_builder = sqlx::query(&query)
-            .bind(guild_id as i64)
-            .bind(channel_id as i64)
-            .bind(max_id)
-            .bind(min_id)
-            .bind(min_id);
+    #[tokio::test]
+    async fn every_migration_can_run_again() {
+        let options = SqliteConnectOptions::from_str("sqlite::memory:").unwrap();
+        let pool = Database::connect(options, 1).await.unwrap();
+        Database::setup_tables(&pool).await.unwrap();
+        let migrated = schema(&pool).await;
 
-        for prefix in prefixes {
-            query_builder = query_builder.bind(*prefix);
+        // Databases from before versioning may already have any of it
+        for migration in MIGRATIONS {
+            let mut tx = pool.begin().await.unwrap();
+            migration(&mut tx).await.unwrap();
+            tx.commit().await.unwrap();
         }
 
-        let rows = query_builder
-            .bind(limit as i64)
-            .bind(guild_id as i64)
-            .bind(channel_id as i64)
-            .bind(limit as i64)
-            .fetch_all(&self.pool)
-            .await?;
+        assert_eq!(schema(&pool).await, migrated);
+    }
+
+    #[tokio::test]
+    async fn rebuild_restores_corrupted_aggregates() {
+        let db = memory_db().await;
+        let messages = [
+            (10, 1, 100, "hello there general kenobi"),
+            (11, 2, 100, "hello hello world"),
+            (12, 1, 200, "another channel entirely"),
+        ];
+        for (message_id, author_id, channel_id, content) in messages {
+            db.insert_message(message_id, author_id, channel_id, GUILD_ID, content, None)
+                .await
+                .unwrap();
+        }
+
+        let expected_words = word_counts(&db).await;
+        let expected_channels = channel_stats(&db).await;
+
+        sqlx::query("UPDATE word_counts SET count = count + 7 WHERE word = 'hello'")
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        sqlx::query("DELETE FROM word_counts WHERE word = 'world'")
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        sqlx::query(
+            "INSERT INTO word_counts (guild_id, author_id, word, count) VALUES (?, 3, 'ghost', 4)",
+        )
+        .bind(GUILD_ID as i64)
+        .execute(&db.pool)
+        .await
+        .unwrap();
+        sqlx::query("UPDATE channel_stats SET count = 99 WHERE channel_id = 100")
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        sqlx::query("DELETE FROM channel_stats WHERE channel_id = 200")
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        assert_ne!(word_counts(&db).await, expected_words);
 
-        let messages: Vec<String> = rows
+        let mut progress = Vec::new();
+        db.rebuild_derived_tables(GUILD_ID, |scanned| progress.push(scanned))
+            .await
+            .unwrap();
+
+        assert_eq!(word_counts(&db).await, expected_words);
+        assert_eq!(channel_stats(&db).await, expected_channels);
+        assert_eq!(progress.last(), Some(&3));
+    }
+
+    #[tokio::test]
+    async fn rebuild_keeps_messages_stored_while_it_runs() {
+        let db = memory_db().await;
+        let chunk = REBUILD_CHUNK_SIZE as u64;
+
+        // Even ids, so backfilled messages can land between them
+        let contents: Vec<String> = (0..3 * chunk)
+            .map(|index| format!("word{} shared text", index % 37))
+            .collect();
+        let records: Vec<MessageRecord> = contents
             .iter()
-            .map(|row| row.get::<String, _>("content"))
+            .enumerate()
+            .map(|(index, content)| MessageRecord {
+                message_id: 2 * (index as u64 + 1),
+                author_id: index as u64 % 5,
+                channel_id: 100 + index as u64 % 3,
+                content,
+                replied_to_message_id: None,
+            })
             .collect();
+        db.insert_messages_batch(GUILD_ID, &records).await.unwrap();
+
+        let stored = std::sync::atomic::AtomicU64::new(0);
+        let mut interleaved = false;
+        let rebuild = db.rebuild_derived_tables(GUILD_ID, |_| {
+            let stored = stored.load(std::sync::atomic::Ordering::SeqCst);
+            interleaved |= stored > 0 && stored < 40;
+        });
+        let writes = async {
+            for index in 0..40u64 {
+                // Backfilled history and brand new messages, plus edits on both sides
+                let message_id = match index % 2 {
+                    0 => 2 * (index * chunk / 20) + 1,
+                    _ => 10 * chunk + index,
+                };
+                db.insert_message(message_id, 9, 300, GUILD_ID, "fresh words here", None)
+                    .await
+                    .unwrap();
+                db.update_message_content(GUILD_ID, 2 * (index * chunk / 20 + 1), "edited text")
+                    .await
+                    .unwrap();
+                stored.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                tokio::task::yield_now().await;
+            }
+        };
+        let (result, ()) = tokio::join!(rebuild, writes);
+        result.unwrap();
+        assert!(interleaved, "no writes landed while the rebuild ran");
 
-        Ok(messages)
+        let words = word_counts(&db).await;
+        let channels = channel_stats(&db).await;
+        let (messages,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM messages")
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(messages as u64, 3 * chunk + 40);
+        assert!(channels.contains(&(300, 40)));
+
+        // A rebuild with nothing else going on agrees
+        db.rebuild_derived_tables(GUILD_ID, |_| ()).await.unwrap();
+        assert_eq!(word_counts(&db).await, words);
+        assert_eq!(channel_stats(&db).await, channels);
     }
 
-    pub async fn get_most_popular_channel(&self, guild_id: u64) -> Result<u64, sqlx::Error> {
-        let row = sqlx::query(
-            "SELECT channel_id FROM channel_stats WHERE guild_id = ? ORDER BY count DESC LIMIT 1",
+    #[tokio::test]
+    async fn interrupted_rebuild_resumes() {
+        let db = memory_db().await;
+        for message_id in 1..=10u64 {
+            db.insert_message(message_id, 1, 100, GUILD_ID, "hello world", None)
+                .await
+                .unwrap();
+        }
+        let words = word_counts(&db).await;
+
+        // As if a rebuild stopped after the first four messages
+        sqlx::query("DELETE FROM word_counts")
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        sqlx::query("UPDATE channel_stats SET count = 4")
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        sqlx::query(
+            "INSERT INTO word_counts (guild_id, author_id, word, count) VALUES (?, 1, 'hello', 4), (?, 1, 'world', 4)",
         )
-        .bind(guild_id as i64)
-        .fetch_optional(&self.pool)
-        .await?;
+        .bind(GUILD_ID as i64)
+        .bind(GUILD_ID as i64)
+        .execute(&db.pool)
+        .await
+        .unwrap();
+        sqlx::query("INSERT INTO rebuild_progress (guild_id, last_message_id) VALUES (?, 4)")
+            .bind(GUILD_ID as i64)
+            .execute(&db.pool)
+            .await
+            .unwrap();
 
-        match row {
-            Some(row) => Ok(row.get::<i64, _>("channel_id") as u64),
-            None => Ok(0),
+        let mut progress = Vec::new();
+        db.rebuild_derived_tables(GUILD_ID, |scanned| progress.push(scanned))
+            .await
+            .unwrap();
+
+        assert_eq!(progress, [6]);
+        assert_eq!(word_counts(&db).await, words);
+        assert_eq!(channel_stats(&db).await, vec![(100, 10)]);
+    }
+
+    #[tokio::test]
+    async fn markov_messages_skip_prefixes_and_short_messages() {
+        let db = memory_db().await;
+
+        // 200 each of commands, short messages and usable ones
+        for index in 0..600u64 {
+            let content = match index % 3 {
+                0 => format!("!command number {}", index),
+                1 => format!("hi {}", index),
+                _ => format!("a proper sentence number {}", index),
+            };
+            db.insert_message(index + 1, 1, 100, GUILD_ID, &content, None)
+                .await
+                .unwrap();
         }
+
+        let all = db
+            .get_messages_for_markov(GUILD_ID, 100, &["!", "$"], 1000)
+            .await
+            .unwrap();
+        assert_eq!(all.len(), 200);
+        assert!(all
+            .iter()
+            .all(|content| content.starts_with("a proper sentence")));
+
+        let limited = db
+            .get_messages_for_markov(GUILD_ID, 100, &["!", "$"], 50)
+            .await
+            .unwrap();
+        assert_eq!(limited.len(), 50);
+
+        let unfiltered = db
+            .get_messages_for_markov(GUILD_ID, 100, &[], 1000)
+            .await
+            .unwrap();
+        assert_eq!(unfiltered.len(), 400);
+        assert!(unfiltered
+            .iter()
+            .all(|content| content.chars().count() > 10));
     }
 
-    pub async fn get_leaderboard_data(
-        &self,
-        guild_id: u64,
-        target_user_id: Option<u64>,
-        target_word: Option<&str>,
-        min_length: i64,
-        excludes: Option<Vec<String>>,
-        limit: i64,
-    ) -> Result<Vec<(String, u64, i64)>, sqlx::Error> {
-        let mut sql = String::from(
-            "SELECT word, author_id, count FROM word_counts WHERE guild_id = ? AND LENGTH(word) >= ?"
+    #[tokio::test]
+    async fn storing_a_message_twice_counts_it_once() {
+        let db = memory_db().await;
+
+        let first = db
+            .insert_message(10, 1, 100, GUILD_ID, "counting words twice", None)
+            .await
+            .unwrap();
+        let words = word_counts(&db).await;
+        let channels = channel_stats(&db).await;
+
+        let second = db
+            .insert_message(10, 1, 100, GUILD_ID, "counting words twice", None)
+            .await
+            .unwrap();
+
+        assert_eq!(first, InsertOutcome::Inserted);
+        assert_eq!(second, InsertOutcome::Duplicate);
+        assert_eq!(word_counts(&db).await, words);
+        assert_eq!(channel_stats(&db).await, vec![(100, 1)]);
+        assert_eq!(channel_stats(&db).await, channels);
+
+        // A page from `/collect` overlapping what's already stored
+        let records = [10, 11].map(|message_id| MessageRecord {
+            message_id,
+            author_id: 1,
+            channel_id: 100,
+            content: "counting words twice",
+            replied_to_message_id: None,
+        });
+        let outcomes = db.insert_messages_batch(GUILD_ID, &records).await.unwrap();
+
+        assert_eq!(
+            outcomes,
+            vec![
+                Some(InsertOutcome::Duplicate),
+                Some(InsertOutcome::Inserted)
+            ]
         );
+        assert_eq!(channel_stats(&db).await, vec![(100, 2)]);
+        assert!(word_counts(&db)
+            .await
+            .iter()
+            .all(|(_, _, count)| *count == 2));
+    }
 
-        if target_user_id.is_some() {
-            sql.push_str(" AND author_id = ?");
+    #[tokio::test]
+    async fn edits_replace_content_and_word_counts() {
+        let db = memory_db().await;
+        db.insert_message(10, 1, 100, GUILD_ID, "apple banana apple", None)
+            .await
+            .unwrap();
+        db.insert_message(11, 1, 100, GUILD_ID, "banana split", None)
+            .await
+            .unwrap();
+
+        let updated = db
+            .update_message_content(GUILD_ID, 10, "cherry banana")
+            .await
+            .unwrap();
+        assert!(updated);
+
+        let (content,): (String,) =
+            sqlx::query_as("SELECT content FROM messages WHERE message_id = 10")
+                .fetch_one(&db.pool)
+                .await
+                .unwrap();
+        assert_eq!(content, "cherry banana");
+
+        // "apple" is gone entirely, not left behind at zero
+        assert_eq!(
+            word_counts(&db).await,
+            vec![
+                (1, "banana".to_string(), 2),
+                (1, "cherry".to_string(), 1),
+                (1, "split".to_string(), 1),
+            ]
+        );
+
+        let missing = db
+            .update_message_content(GUILD_ID, 99, "never stored")
+            .await
+            .unwrap();
+        assert!(!missing);
+    }
+
+    #[tokio::test]
+    async fn random_messages_ignore_id_gaps() {
+        let db = memory_db().await;
+
+        // Half the messages packed together, half after long quiet stretches
+        let packed = 1..=50u64;
+        let spread = (1..=50u64).map(|index| index * 1_000_000_000);
+        for message_id in packed.chain(spread) {
+            db.insert_message(message_id, 1, 100, GUILD_ID, "some message text", None)
+                .await
+                .unwrap();
         }
-        if target_word.is_some() {
-            sql.push_str(" AND word = ?");
+
+        let filter = RandomMessageFilter::default();
+        let picks = 1000;
+        let mut packed_picks = 0;
+        for _ in 0..picks {
+            let message = db
+                .get_random_message(GUILD_ID, &filter)
+                .await
+                .unwrap()
+                .unwrap();
+            if message.message_id <= 50 {
+                packed_picks += 1;
+            }
         }
 
-        if let Some(ref ex) = excludes {
-            if !ex.is_empty() {
-                sql.push_str(" AND word NOT IN (");
-                for (i, _) in ex.iter().enumerate() {
-                    if i > 0 {
-                        sql.push_str(", ");
-                    }
-                    sql.push_str("?");
-                }
-                sql.push(')');
+        // Roughly half either way, 400 is about six standard deviations out
+        assert!(
+            (400..=600).contains(&packed_picks),
+            "{} of {} picks were packed messages",
+            packed_picks,
+            picks
+        );
+    }
+
+    /// Message ids `search_messages` finds for each query, sorted.
+    async fn search_ids(db: &Database, queries: &[&str]) -> Vec<Vec<u64>> {
+        let mut results = Vec::new();
+        for query in queries {
+            let mut ids: Vec<u64> = db
+                .search_messages(GUILD_ID, query, 100)
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|(message_id, _, _, _)| message_id)
+                .collect();
+            ids.sort();
+            results.push(ids);
+        }
+        results
+    }
+
+    #[tokio::test]
+    async fn search_backends_find_the_same_messages() {
+        let corpus = [
+            "the quick brown fox jumps",
+            "a lazy dog sleeps all day",
+            "The Quick thinking dog",
+            "foxes and dogs are friends",
+            "brown bread for the fox",
+            "nothing to see here",
+        ];
+        let queries = ["fox", "quick", "dog", "brown fox", "QUICK dog", "missing"];
+
+        let indexed = memory_db().await;
+        let scanned = memory_db().await;
+        for db in [&indexed, &scanned] {
+            for (index, content) in corpus.iter().enumerate() {
+                db.insert_message(index as u64 + 1, 1, 100, GUILD_ID, content, None)
+                    .await
+                    .unwrap();
             }
         }
 
-        let mut query = sqlx::query_as::<_, (String, i64, i64)>(&sql)
-            .bind(guild_id as i64)
-            .bind(min_length);
+        // Without the index, searches fall back to LIKE
+        for statement in [
+            "DROP TRIGGER messages_fts_insert",
+            "DROP TRIGGER messages_fts_delete",
+            "DROP TRIGGER messages_fts_update",
+            "DROP TABLE messages_fts",
+        ] {
+            sqlx::query(statement).execute(&scanned.pool).await.unwrap();
+        }
+        assert!(Database::has_search_index(&indexed.pool).await.unwrap());
+        assert!(!Database::has_search_index(&scanned.pool).await.unwrap());
 
-        if let Some(uid) = target_user_id {
-            query = query.bind(uid as i64);
+        let expected = search_ids(&indexed, &queries).await;
+        assert_eq!(expected[0], vec![1, 5]);
+        assert_eq!(expected[3], vec![1, 5]);
+        assert_eq!(expected[4], vec![3]);
+        assert!(expected[5].is_empty());
+
+        // LIKE also matches inside words, "foxes" and "dogs" among them
+        let mut scanned_ids = search_ids(&scanned, &queries).await;
+        for ids in &mut scanned_ids {
+            ids.retain(|id| *id != 4);
         }
-        if let Some(word) = target_word {
-            query = query.bind(word);
+        assert_eq!(scanned_ids, expected);
+    }
+
+    /// A message id sent `seconds_ago` seconds before now.
+    fn snowflake_seconds_ago(seconds_ago: i64) -> u64 {
+        let sent_ms = (Timestamp::now().unix_timestamp() - seconds_ago) as u64 * 1000;
+        (sent_ms - DISCORD_EPOCH_MS) << 22
+    }
+
+    #[tokio::test]
+    async fn message_count_since_uses_snowflake_timestamps() {
+        let db = memory_db().await;
+        let day = 24 * 60 * 60;
+
+        for (index, days_ago) in [1, 2, 10, 40].into_iter().enumerate() {
+            let message_id = snowflake_seconds_ago(days_ago * day) + index as u64;
+            db.insert_message(message_id, 1, 100, GUILD_ID, "timestamped", None)
+                .await
+                .unwrap();
+        }
+
+        let now = Timestamp::now().unix_timestamp();
+        let count_since = |days: i64| db.get_message_count_since(GUILD_ID, now - days * day);
+
+        assert_eq!(count_since(7).await.unwrap(), 2);
+        assert_eq!(count_since(30).await.unwrap(), 3);
+        assert_eq!(count_since(365).await.unwrap(), 4);
+        assert_eq!(
+            db.get_message_count_since(GUILD_ID + 1, 0).await.unwrap(),
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn restart_fails_runs_left_running() {
+        let path = std::env::temp_dir().join(format!("yorjik-runs-{}.db", std::process::id()));
+        let url = format!("sqlite://{}", path.display());
+
+        let db = Database::new(&url, 2000, None, 1).await.unwrap();
+        let interrupted = db.start_collection_run(GUILD_ID, 10).await.unwrap();
+        let finished = db.start_collection_run(GUILD_ID, 20).await.unwrap();
+        db.update_collection_run(GUILD_ID, finished, 5, 0, Some(1234), Some("completed"))
+            .await
+            .unwrap();
+        db.update_collection_run(GUILD_ID, interrupted, 3, 1, Some(5678), None)
+            .await
+            .unwrap();
+        db.close().await;
+
+        let db = Database::new(&url, 2000, None, 1).await.unwrap();
+        let runs = db.get_collection_runs(GUILD_ID, 10).await.unwrap();
+        db.close().await;
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
         }
-        if let Some(ex) = excludes {
-            for word in ex {
-                query = query.bind(word);
+
+        let statuses: Vec<_> = runs
+            .iter()
+            .map(|run| {
+                (
+                    run.channel_id,
+                    run.status.as_str(),
+                    run.finished_at.is_some(),
+                )
+            })
+            .collect();
+        assert_eq!(statuses, [(20, "completed", true), (10, "failed", true)]);
+        assert_eq!(runs[1].messages_stored, 3);
+        assert_eq!(runs[1].oldest_message_id, Some(5678));
+    }
+
+    #[test]
+    fn min_length_follows_the_buckets() {
+        assert_eq!(min_length_for(0), None);
+        assert_eq!(min_length_for(99), None);
+        assert_eq!(min_length_for(100), Some(90));
+        assert_eq!(min_length_for(999), Some(90));
+        assert_eq!(min_length_for(1000), Some(60));
+        assert_eq!(min_length_for(4999), Some(60));
+        assert_eq!(min_length_for(5000), Some(30));
+        assert_eq!(min_length_for(i64::MAX), Some(30));
+    }
+
+    #[tokio::test]
+    async fn random_messages_scale_length_by_author() {
+        let db = memory_db().await;
+
+        let short = "x".repeat(40);
+        let medium = "x".repeat(70);
+        let long = "x".repeat(95);
+        // Author 1 is in the 60 bucket, author 2 in the 90 one, author 3 in none
+        let mut contents: Vec<(u64, &str)> = Vec::new();
+        contents.extend(std::iter::repeat_n((1, short.as_str()), 999));
+        contents.push((1, medium.as_str()));
+        contents.extend(std::iter::repeat_n((2, medium.as_str()), 50));
+        contents.extend(std::iter::repeat_n((2, long.as_str()), 50));
+        contents.extend(std::iter::repeat_n((3, long.as_str()), 50));
+
+        let records: Vec<MessageRecord> = contents
+            .iter()
+            .enumerate()
+            .map(|(index, (author_id, content))| MessageRecord {
+                message_id: index as u64 + 1,
+                author_id: *author_id,
+                channel_id: 100,
+                content,
+                replied_to_message_id: None,
+            })
+            .collect();
+        db.insert_messages_batch(GUILD_ID, &records).await.unwrap();
+
+        let filter = RandomMessageFilter {
+            scale_length_by_author: true,
+            ..Default::default()
+        };
+        let count = db
+            .count_random_message_candidates(GUILD_ID, &filter)
+            .await
+            .unwrap();
+        assert_eq!(count, 51);
+
+        for _ in 0..50 {
+            let message = db
+                .get_random_message(GUILD_ID, &filter)
+                .await
+                .unwrap()
+                .unwrap();
+            match message.author_id {
+                1 => assert_eq!(message.content, medium),
+                2 => assert_eq!(message.content, long),
+                author_id => panic!("picked a message from author {}", author_id),
             }
         }
+    }
 
-        query = query.bind(limit);
+    #[tokio::test]
+    async fn trending_words_rank_the_second_week_spike_first() {
+        let db = memory_db().await;
+        let day = 24 * 60 * 60;
 
-        let rows = query.fetch_all(&self.pool).await?;
+        let mut messages: Vec<(i64, &str)> = Vec::new();
+        // Outside both windows, never counted
+        messages.extend(std::iter::repeat_n((20 * day, "pizza pizza pizza"), 5));
+        // Previous week
+        messages.push((10 * day, "pizza tonight"));
+        messages.extend(std::iter::repeat_n((9 * day, "steady chatter"), 5));
+        // Current week
+        messages.extend(std::iter::repeat_n((2 * day, "pizza with the"), 10));
+        messages.extend(std::iter::repeat_n((day, "steady chatter ok"), 5));
+        messages.push((day, "rare"));
 
-        Ok(rows.into_iter().map(|(w, u, c)| (w, u as u64, c)).collect())
+        for (index, (seconds_ago, content)) in messages.into_iter().enumerate() {
+            let message_id = snowflake_seconds_ago(seconds_ago) + index as u64;
+            db.insert_message(message_id, 1, 100, GUILD_ID, content, None)
+                .await
+                .unwrap();
+        }
+
+        let trending = db
+            .get_trending_words(
+                GUILD_ID,
+                snowflake_seconds_ago(14 * day),
+                snowflake_seconds_ago(7 * day),
+                3,
+                3,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(trending[0], ("pizza".to_string(), 10, 1));
+        assert!(trending.contains(&("steady".to_string(), 5, 5)));
+        assert!(trending.contains(&("chatter".to_string(), 5, 5)));
+        // Stopwords, short words and rare words are left out
+        for word in ["the", "ok", "rare", "tonight"] {
+            assert!(trending
+                .iter()
+                .all(|(trending_word, _, _)| trending_word != word));
+        }
     }
 
-    pub async fn get_random_message(
-        &self,
-        guild_id: u64,
-        min_letters_amount: u64,
-    ) -> Result<Option<(String, u64)>, sqlx::Error> {
-        let prefix_list: Vec<&str> = vec![
-            "$", "&", "!", ".", "m.", ">", "<", "[", "]", "@", "#", "^", "*", ",", "https", "http",
-        ];
+    #[tokio::test]
+    async fn top_posters_read_only_the_author_index() {
+        let db = memory_db().await;
+
+        // Author `n % 100` wrote message `n`, so lower authors have one more
+        sqlx::query(
+            r#"
+            WITH RECURSIVE ids(n) AS (SELECT 1 UNION ALL SELECT n + 1 FROM ids WHERE n < 300050)
+            INSERT INTO messages (message_id, author_id, channel_id, guild_id, content)
+            SELECT n, n % 100, n % 7, ?, 'synthetic message' FROM ids
+            "#,
+        )
+        .bind(GUILD_ID as i64)
+        .execute(&db.pool)
+        .await
+        .unwrap();
+
+        let plan: Vec<String> = sqlx::query(&format!("EXPLAIN QUERY PLAN {}", TOP_POSTERS_QUERY))
+            .bind(GUILD_ID as i64)
+            .bind(0)
+            .bind(10)
+            .fetch_all(&db.pool)
+            .await
+            .unwrap()
+            .iter()
+            .map(|row| row.get::<String, _>("detail"))
+            .collect();
+        assert!(
+            plan.iter()
+                .any(|detail| detail.contains("USING COVERING INDEX idx_messages_guild_author")),
+            "{:?}",
+            plan
+        );
 
-        let prefix_conditions = prefix_list
+        let (top, total) = db.get_top_posters(GUILD_ID, 3, None).await.unwrap();
+        // Authors 1 to 50 tie for the top
+        assert_eq!(top.len(), 3);
+        assert!(top
             .iter()
-            .map(|_| "content NOT LIKE ? || '%'")
-            .collect::<Vec<_>>()
-            .join(" AND ");
+            .all(|(author_id, messages)| (1..=50).contains(author_id) && *messages == 3001));
+        assert_eq!(total, 300050);
 
-        let bounds: Option<(i64, i64)> = sqlx::query_as(
-            "SELECT MIN(message_id), MAX(message_id) FROM messages WHERE guild_id = ?",
-        )
-        .bind(guild_id as i64)
-        .fetch_optional(&self.pool)
-        .await?;
+        let (top, total) = db.get_top_posters(GUILD_ID, 1, Some(300001)).await.unwrap();
+        assert_eq!(top.len(), 1);
+        assert_eq!(top[0].1, 1);
+        assert_eq!(total, 50);
+    }
+
+    #[tokio::test]
+    async fn guild_stats_count_one_guild() {
+        let db = memory_db().await;
+
+        let empty = db.get_guild_stats(GUILD_ID).await.unwrap();
+        assert_eq!((empty.messages, empty.authors, empty.words), (0, 0, 0));
+        assert_eq!(empty.content_bytes, 0);
+        assert_eq!(empty.oldest_message_at, None);
+        assert_eq!(empty.newest_message_at, None);
+
+        let day = 24 * 60 * 60;
+        let oldest = snowflake_seconds_ago(3 * day);
+        let newest = snowflake_seconds_ago(day);
+        db.insert_message(oldest, 1, 100, GUILD_ID, "hello world", None)
+            .await
+            .unwrap();
+        db.insert_message(oldest + 1, 2, 100, GUILD_ID, "hello güzel", None)
+            .await
+            .unwrap();
+        db.insert_message(newest, 1, 200, GUILD_ID, "world again", None)
+            .await
+            .unwrap();
+        // Another guild's messages aren't counted
+        db.insert_message(newest + 1, 3, 300, GUILD_ID + 1, "elsewhere entirely", None)
+            .await
+            .unwrap();
+
+        let stats = db.get_guild_stats(GUILD_ID).await.unwrap();
+        assert_eq!(stats.messages, 3);
+        assert_eq!(stats.authors, 2);
+        // hello, world, güzel, again
+        assert_eq!(stats.words, 4);
+        // `ü` is two bytes
+        assert_eq!(stats.content_bytes, 11 + 12 + 11);
+        assert_eq!(
+            stats.oldest_message_at,
+            Some(MessageId::new(oldest).created_at().unix_timestamp())
+        );
+        assert_eq!(
+            stats.newest_message_at,
+            Some(MessageId::new(newest).created_at().unix_timestamp())
+        );
+    }
+
+    #[tokio::test]
+    async fn exclusions_past_the_bind_limit() {
+        let db = memory_db().await;
+
+        // Real snowflakes, well past what a double holds exactly
+        let first_id = snowflake_seconds_ago(60);
+        for index in 0..10 {
+            db.insert_message(first_id + index, index, 100, GUILD_ID, "some message", None)
+                .await
+                .unwrap();
+        }
 
-        let (min_id, max_id) = match bounds {
-            Some((min, max)) if min > 0 && max > 0 => (min, max),
-            _ => return Ok(None),
+        // More ids than SQLite allows binds in one statement
+        let filter = RandomMessageFilter {
+            excluded_ids: (first_id..first_id + 40_000)
+                .filter(|id| *id != first_id + 3)
+                .collect(),
+            ..Default::default()
         };
+        assert_eq!(
+            db.count_random_message_candidates(GUILD_ID, &filter)
+                .await
+                .unwrap(),
+            1
+        );
+        let message = db
+            .get_random_message(GUILD_ID, &filter)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(message.message_id, first_id + 3);
+
+        let filter = RandomMessageFilter {
+            excluded_author_ids: (0..40_000).filter(|id| *id != 7).collect(),
+            ..Default::default()
+        };
+        let message = db
+            .get_random_message(GUILD_ID, &filter)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(message.author_id, 7);
 
-        let query = format!(
-            "SELECT content, author_id FROM messages 
-             WHERE guild_id = ? 
-             AND message_id >= (ABS(RANDOM()) % (? - ?) + ?) 
-             AND LENGTH(content) >= ? 
-             AND {} 
-             LIMIT 1",
-            prefix_conditions
+        // Opted out authors are left out too
+        db.set_opted_out(Some(GUILD_ID), 7, true).await.unwrap();
+        assert!(db
+            .get_random_message(GUILD_ID, &filter)
+            .await
+            .unwrap()
+            .is_none());
+        assert_eq!(
+            db.count_random_message_candidates(GUILD_ID, &RandomMessageFilter::default())
+                .await
+                .unwrap(),
+            9
         );
+    }
 
-        let mut query_builder = sqlx::query(&query)
-            .bind(guild_id as i64)
-            .bind(max_id)
-            .bind(min_id)
-            .bind(min_id)
-            .bind(min_letters_amount as i64);
+    #[tokio::test]
+    async fn reply_pairs_join_replies_to_stored_parents() {
+        let db = memory_db().await;
+        db.insert_message(10, 1, 100, GUILD_ID, "what's for dinner", None)
+            .await
+            .unwrap();
+        db.insert_message(11, 2, 100, GUILD_ID, "pasta again", Some(10))
+            .await
+            .unwrap();
+        db.insert_message(12, 1, 100, GUILD_ID, "not pasta again", Some(11))
+            .await
+            .unwrap();
+        // The parent was never stored
+        db.insert_message(13, 3, 100, GUILD_ID, "replying to nothing", Some(5))
+            .await
+            .unwrap();
+        db.insert_message(14, 3, 100, GUILD_ID, "not a reply at all", None)
+            .await
+            .unwrap();
+
+        let stored: Vec<Option<i64>> =
+            sqlx::query_scalar("SELECT replied_to_message_id FROM messages ORDER BY message_id")
+                .fetch_all(&db.pool)
+                .await
+                .unwrap();
+        assert_eq!(stored, vec![None, Some(10), Some(11), Some(5), None]);
+
+        let pairs = db.get_reply_pairs(GUILD_ID, 10).await.unwrap();
+        assert_eq!(
+            pairs,
+            vec![
+                ("pasta again".to_string(), "not pasta again".to_string()),
+                ("what's for dinner".to_string(), "pasta again".to_string()),
+            ]
+        );
+
+        let newest = db.get_reply_pairs(GUILD_ID, 1).await.unwrap();
+        assert_eq!(newest, pairs[..1]);
+    }
+
+    async fn leaderboard_words(db: &Database, min_users: i64) -> Vec<String> {
+        let filter = LeaderboardFilter {
+            min_length: 1,
+            min_users,
+            ..Default::default()
+        };
+        let mut words: Vec<String> = db
+            .get_leaderboard_data(GUILD_ID, &filter, 100, 0)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|(word, _, _)| word)
+            .collect();
+        words.sort();
+        words.dedup();
+        words
+    }
+
+    async fn store_shared_and_private_words(db: &Database) {
+        // "pizza" is everyone's, "zebra" only the first author's however
+        // often they say it, and "running"/"runs" share a stem
+        let messages = [
+            (1, "pizza zebra zebra zebra running"),
+            (2, "pizza runs"),
+            (3, "pizza"),
+        ];
+        for (index, (author_id, content)) in messages.into_iter().enumerate() {
+            db.insert_message(10 + index as u64, author_id, 100, GUILD_ID, content, None)
+                .await
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn leaderboard_min_users_drops_private_words() {
+        let db = memory_db().await;
+        store_shared_and_private_words(&db).await;
+
+        assert_eq!(
+            leaderboard_words(&db, 1).await,
+            ["pizza", "running", "runs", "zebra"]
+        );
+        assert_eq!(leaderboard_words(&db, 2).await, ["pizza"]);
+        assert_eq!(leaderboard_words(&db, 3).await, ["pizza"]);
+        assert!(leaderboard_words(&db, 4).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn leaderboard_min_users_counts_authors_per_stem() {
+        let db = memory_db().await;
+        db.set_stem_words(GUILD_ID, Some(Stemmer::English))
+            .await
+            .unwrap();
+        store_shared_and_private_words(&db).await;
+
+        assert_eq!(
+            leaderboard_words(&db, 1).await,
+            ["pizza", "running", "runs", "zebra"]
+        );
+        // Two people used some form of "run"
+        assert_eq!(
+            leaderboard_words(&db, 2).await,
+            ["pizza", "running", "runs"]
+        );
+        assert_eq!(leaderboard_words(&db, 3).await, ["pizza"]);
+    }
 
-        for prefix in &prefix_list {
-            query_builder = query_builder.bind(*prefix);
+    #[tokio::test]
+    async fn leaderboard_rows_follow_the_sort() {
+        let db = memory_db().await;
+        let messages = [
+            (1, "common common common common common alpha alpha alpha"),
+            (2, "middle middle middle rare"),
+        ];
+        for (index, (author_id, content)) in messages.into_iter().enumerate() {
+            db.insert_message(10 + index as u64, author_id, 100, GUILD_ID, content, None)
+                .await
+                .unwrap();
         }
 
-        let row = query_builder.fetch_optional(&self.pool).await?;
+        let rows = |sort, limit, offset| {
+            let db = &db;
+            async move {
+                let filter = LeaderboardFilter {
+                    min_length: 1,
+                    min_users: 1,
+                    sort,
+                    ..Default::default()
+                };
+                db.get_leaderboard_data(GUILD_ID, &filter, limit, offset)
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|(word, _, uses)| (word, uses))
+                    .collect::<Vec<_>>()
+            }
+        };
+        let expected = |rows: &[(&str, i64)]| {
+            rows.iter()
+                .map(|(word, uses)| (word.to_string(), *uses))
+                .collect::<Vec<_>>()
+        };
 
-        match row {
-            Some(row) => Ok(Some((
-                row.get::<String, _>("content"),
-                row.get::<i64, _>("author_id") as u64,
-            ))),
-            None => Ok(None),
+        // Ties go alphabetically
+        assert_eq!(
+            rows(LeaderboardSort::MostUsed, 10, 0).await,
+            expected(&[("common", 5), ("alpha", 3), ("middle", 3), ("rare", 1)])
+        );
+        assert_eq!(
+            rows(LeaderboardSort::LeastUsed, 10, 0).await,
+            expected(&[("rare", 1), ("alpha", 3), ("middle", 3), ("common", 5)])
+        );
+        assert_eq!(
+            rows(LeaderboardSort::Alphabetical, 10, 0).await,
+            expected(&[("alpha", 3), ("common", 5), ("middle", 3), ("rare", 1)])
+        );
+
+        // Pages pick up where the last one stopped
+        assert_eq!(
+            rows(LeaderboardSort::MostUsed, 1, 0).await,
+            expected(&[("common", 5)])
+        );
+        assert_eq!(
+            rows(LeaderboardSort::MostUsed, 2, 1).await,
+            expected(&[("alpha", 3), ("middle", 3)])
+        );
+        assert!(rows(LeaderboardSort::MostUsed, 10, 4).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn eligible_counts_match_the_training_messages() {
+        let db = Database::new("sqlite::memory:", 40, None, 1).await.unwrap();
+        let prefixes = ["!", "https"];
+        let messages = [
+            (100, 1, "long enough to learn from"),
+            (100, 1, "another one that counts"),
+            (100, 1, "too short"),
+            (100, 1, "!play some song please"),
+            (100, 1, "https://example.com/page"),
+            (
+                100,
+                1,
+                "cut off because it goes on for far too long to keep",
+            ),
+            (100, 2, "someone who opted out later"),
+            (200, 1, "a different channel entirely"),
+        ];
+        for (index, (channel_id, author_id, content)) in messages.into_iter().enumerate() {
+            db.insert_message(
+                10 + index as u64,
+                author_id,
+                channel_id,
+                GUILD_ID,
+                content,
+                None,
+            )
+            .await
+            .unwrap();
         }
+        db.set_opted_out(Some(GUILD_ID), 2, true).await.unwrap();
+
+        let channel = db
+            .get_messages_for_markov(GUILD_ID, 100, &prefixes, 100)
+            .await
+            .unwrap();
+        let guild = db
+            .get_messages_for_markov_guild(GUILD_ID, &prefixes, 100)
+            .await
+            .unwrap();
+        assert_eq!(channel.len(), 2);
+        assert_eq!(guild.len(), 3);
+
+        assert_eq!(
+            db.count_markov_eligible_messages(GUILD_ID, Some(100), &prefixes)
+                .await
+                .unwrap(),
+            channel.len() as u64
+        );
+        assert_eq!(
+            db.count_markov_eligible_messages(GUILD_ID, None, &prefixes)
+                .await
+                .unwrap(),
+            guild.len() as u64
+        );
+        assert_eq!(
+            db.count_markov_eligible_messages(GUILD_ID, Some(300), &prefixes)
+                .await
+                .unwrap(),
+            0
+        );
     }
 }
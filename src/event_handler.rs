@@ -1,124 +1,125 @@
+use std::collections::HashMap;
 use std::env;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Instant;
 
-use tokio::time::Duration;
-
-use rand::rngs::StdRng;
 use rand::Rng;
-use rand::SeedableRng;
+use tokio::time::Duration;
+use tracing::{error, info, info_span, warn, Instrument};
 
-use serenity::all::CreateCommand;
-use serenity::builder::GetMessages;
+use serenity::all::{
+    CommandInteraction as SlashCommand, CreateCommand, CreateInteractionResponse,
+    CreateInteractionResponseMessage, EditInteractionResponse, Guild, GuildId, Member,
+    MessageUpdateEvent, UnavailableGuild, User,
+};
 use serenity::model::{application::Interaction, channel::Message, gateway::Ready};
 use serenity::prelude::*;
 use serenity::{
-    all::{Command as CommandInteraction, CreateMessage},
+    all::{Command as CommandInteraction, CreateAllowedMentions, CreateMessage},
     async_trait,
 };
 
-use crate::commands::Command;
-use crate::database::Database;
-use crate::utils::helpers::{generate_markov_message, get_most_popular_channel};
+use crate::commands::{CommandFn, Component};
+use crate::database::{Database, GuildSettings, InsertOutcome};
+use crate::tasks;
+use crate::tasks::heartbeat::record_stored_messages;
+use crate::utils::chain_cache::ChainKey;
+use crate::utils::helpers::{
+    chime_in_reply, continue_reply_chain, feed_markov_chain, has_ignored_prefix,
+    is_repeated_message, is_storable, logging_paused, posting_paused, purge_guild_data,
+    record_reply_chain, replied_to_message_id, says_name, take_chime_in, take_name_trigger,
+};
+use crate::utils::responder::{continue_conversation, reply_to};
+use crate::TaskSupervisorGlobal;
 
 pub struct Handler {
-    pub commands: Vec<Command>,
+    pub commands: HashMap<String, CommandFn>,
+    pub components: Vec<Component>,
     pub registered: Vec<CreateCommand>,
     pub database: Arc<Database>,
+    /// Where trained chains are saved.
+    pub chain_dir: PathBuf,
+    /// When each chain loaded on startup was trained.
+    pub saved_chains: HashMap<ChainKey, Instant>,
 }
 
 #[async_trait]
 impl EventHandler for Handler {
     async fn ready(&self, ctx: Context, bot: Ready) {
-        println!("Bot has started as {}", bot.user.name);
+        info!(user = %bot.user.name, "Bot has started");
 
-        match CommandInteraction::set_global_commands(&ctx.http, self.registered.clone()).await {
-            Err(e) => {
-                eprintln!("There was an error while registering commands: {}", e);
-            }
-            Ok(_) => {}
+        if let Err(e) =
+            CommandInteraction::set_global_commands(&ctx.http, self.registered.clone()).await
+        {
+            error!(error = %e, "Failed to register commands");
         }
 
+        let Some(supervisor) = ctx.data.read().await.get::<TaskSupervisorGlobal>().cloned() else {
+            return;
+        };
+
+        // Train the busiest channels' chains before anyone asks for them
+        let warmup_chains = env::var("WARMUP_CHAINS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(tasks::warmup::DEFAULT_WARMUP_CHAINS);
+        supervisor
+            .run_once(
+                "warmup",
+                Box::pin(tasks::warmup::run(
+                    ctx.clone(),
+                    self.database.clone(),
+                    warmup_chains,
+                )),
+            )
+            .await;
+
         // Random message generator on loop
-        let mut rng = StdRng::from_entropy();
-        let database_clone = self.database.clone();
-        tokio::spawn(async move {
-            loop {
-                // Fetch vector of guilds the bot is in.
-                let guild_ids = ctx.cache.guilds();
-
-                // Loop over the guild ids
-                for guild_id in guild_ids {
-                    // Get the channel id of the most popular channel
-                    let popular_channel_id =
-                        get_most_popular_channel(guild_id, database_clone.clone()).await;
-                    let all_channels = ctx.http.get_channels(guild_id).await.unwrap();
-
-                    if let Some(channel_id) = all_channels
-                        .iter()
-                        .find(|channel| channel.id.get() == popular_channel_id)
-                        .map(|channel| channel.id)
-                    {
-                        // Fetch the channel
-                        let channel = ctx.http.get_channel(channel_id).await.unwrap();
-
-                        match channel.guild() {
-                            Some(channel) => {
-                                let messages = channel
-                                    .messages(&ctx.http, GetMessages::new().limit(100))
-                                    .await
-                                    .unwrap();
-
-                                let mut messages_have_bot = false;
-                                for message in messages {
-                                    if message.author.id.get() == ctx.cache.current_user().id.get()
-                                    {
-                                        messages_have_bot = true;
-                                    }
-                                }
-
-                                // Only send a message if builder is not None
-                                if let Some(markov_message) = generate_markov_message(
-                                    &ctx,
-                                    guild_id,
-                                    channel.id,
-                                    None,
-                                    database_clone.clone(),
-                                )
-                                .await
-                                {
-                                    if !messages_have_bot {
-                                        channel
-                                            .send_message(
-                                                &ctx.http,
-                                                CreateMessage::new().content(markov_message),
-                                            )
-                                            .await
-                                            .unwrap();
-                                    }
-                                }
-                            }
-                            None => {}
-                        }
-                    }
-                }
+        let autopost_ctx = ctx.clone();
+        let database = self.database.clone();
+        supervisor
+            .ensure_running(
+                "autopost",
+                Arc::new(move || {
+                    Box::pin(tasks::autopost::run(autopost_ctx.clone(), database.clone()))
+                }),
+            )
+            .await;
 
-                // Wait a random second from 300 to 900
-                let range = rng.gen_range(300..900);
-                tokio::time::sleep(Duration::from_secs(range)).await;
-            }
-        });
+        let chain_store_ctx = ctx.clone();
+        let chain_dir = self.chain_dir.clone();
+        let saved_chains = self.saved_chains.clone();
+        supervisor
+            .ensure_running(
+                "chain-store",
+                Arc::new(move || {
+                    Box::pin(tasks::chain_store::run(
+                        chain_store_ctx.clone(),
+                        chain_dir.clone(),
+                        saved_chains.clone(),
+                    ))
+                }),
+            )
+            .await;
 
         if let Ok(url) = env::var("UPTIME_KUMA_URL") {
-            tokio::spawn(async move {
-                loop {
-                    match reqwest::get(&url).await {
-                        Ok(_) => (),
-                        Err(e) => eprintln!("Failed to ping Kuma: {}", e),
-                    }
+            let interval = env::var("UPTIME_KUMA_INTERVAL")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .map_or(
+                    tasks::heartbeat::DEFAULT_HEARTBEAT_INTERVAL,
+                    Duration::from_secs,
+                );
 
-                    tokio::time::sleep(Duration::from_secs(60)).await;
-                }
-            });
+            supervisor
+                .ensure_running(
+                    "uptime-kuma",
+                    Arc::new(move || {
+                        Box::pin(tasks::heartbeat::run(ctx.clone(), url.clone(), interval))
+                    }),
+                )
+                .await;
         }
     }
 
@@ -133,48 +134,152 @@ impl EventHandler for Handler {
             _ => return,
         };
 
-        // write message into database
+        // Always remember the message, even when it isn't stored
+        let repeated = is_repeated_message(&ctx, &msg).await;
+        let settings = match self.database.get_guild_settings(guild_id.get()).await {
+            Ok(settings) => settings,
+            Err(e) => {
+                error!(guild_id = guild_id.get(), error = %e, "Failed to fetch guild settings");
+                GuildSettings::default()
+            }
+        };
+
+        let skip_repeat = repeated && settings.dedupe_consecutive;
+        if skip_repeat {
+            info!(
+                guild_id = guild_id.get(),
+                channel_id = msg.channel_id.get(),
+                user_id = msg.author.id.get(),
+                "Skipped repeated message"
+            );
+        }
+
         if let Err(e) = self
             .database
-            .insert_message(
-                msg.id.get(),
-                msg.author.id.get(),
-                msg.channel_id.get(),
-                guild_id.get(),
-                &msg.content,
-            )
+            .observe_member(guild_id.get(), msg.author.id.get())
             .await
         {
-            eprintln!("Failed to insert message into database: {}", e);
+            error!(guild_id = guild_id.get(), error = %e, "Failed to record guild member");
         }
 
-        if let Some(referenced_message) = &msg.referenced_message {
-            if referenced_message.author.id == ctx.cache.current_user().id
-                && !referenced_message.embeds.is_empty()
+        let opted_out = self
+            .database
+            .is_opted_out(guild_id.get(), msg.author.id.get());
+
+        // write message into database, unless the owner froze collection
+        if !skip_repeat
+            && !opted_out
+            && is_storable(&msg, &settings.command_prefixes)
+            && !logging_paused(&ctx).await
+        {
+            match self
+                .database
+                .insert_message(
+                    msg.id.get(),
+                    msg.author.id.get(),
+                    msg.channel_id.get(),
+                    guild_id.get(),
+                    &msg.content,
+                    replied_to_message_id(&msg),
+                )
+                .await
             {
+                Ok(InsertOutcome::Inserted) => {
+                    record_stored_messages(&ctx, 1).await;
+                    feed_markov_chain(&ctx, guild_id, msg.channel_id, &msg.content, &self.database)
+                        .await;
+                }
+                Ok(InsertOutcome::Duplicate) => (),
+                Err(e) => error!(
+                    guild_id = guild_id.get(),
+                    channel_id = msg.channel_id.get(),
+                    error = %e,
+                    "Failed to store message"
+                ),
+            }
+        }
+
+        let bot_id = ctx.cache.current_user().id;
+
+        // Replies to the bot's own text messages count as talking to it
+        let replied_to_bot = match &msg.referenced_message {
+            Some(referenced_message) if referenced_message.author.id == bot_id => {
+                if !referenced_message.embeds.is_empty() {
+                    return;
+                }
+                Some(referenced_message.id)
+            }
+            _ => None,
+        };
+
+        if posting_paused(&ctx).await {
+            return;
+        }
+
+        if let Some(parent_id) = replied_to_bot {
+            let Some(root) = continue_reply_chain(&ctx, parent_id.get()).await else {
                 return;
+            };
+
+            let typing = ctx.http.start_typing(msg.channel_id);
+
+            let outcome = continue_conversation(
+                &ctx,
+                &msg.content,
+                guild_id,
+                msg.channel_id,
+                self.database.clone(),
+            )
+            .await;
+
+            let builder = CreateMessage::new()
+                .content(outcome.into_content())
+                .reference_message(&msg)
+                .allowed_mentions(CreateAllowedMentions::new().replied_user(true));
+
+            match msg.channel_id.send_message(&ctx.http, builder).await {
+                Ok(sent) => record_reply_chain(&ctx, root, sent.id.get()).await,
+                Err(e) => error!(
+                    guild_id = guild_id.get(),
+                    channel_id = msg.channel_id.get(),
+                    error = %e,
+                    "Failed to continue the conversation"
+                ),
             }
+
+            typing.stop();
+            return;
         }
 
-        if msg.mentions_me(&ctx.http).await.unwrap_or(false) {
+        let bot_name = ctx.cache.current_user().name.clone();
+        let names_bot = settings.name_trigger
+            && says_name(
+                &msg.content,
+                std::iter::once(bot_name.as_str())
+                    .chain(settings.name_aliases.iter().map(String::as_str)),
+            );
+
+        // Mentions always get a reply, saying the name is rate limited
+        let triggered = msg.mentions_me(&ctx.http).await.unwrap_or(false)
+            || (names_bot && take_name_trigger(&ctx, msg.channel_id).await);
+
+        if triggered {
             let typing = ctx.http.start_typing(msg.channel_id);
 
-            let builder = match generate_markov_message(
+            // Answer like people answered similar messages, if we've seen any
+            let outcome = reply_to(
                 &ctx,
+                &msg.content,
                 guild_id,
                 msg.channel_id,
-                None,
                 self.database.clone(),
             )
-            .await
-            {
-                Some(markov_message) => CreateMessage::new()
-                    .content(markov_message)
-                    .reference_message(&msg),
-                None => CreateMessage::new()
-                    .content("Please wait until this channel has over 500 messages.")
-                    .reference_message(&msg),
-            };
+            .await;
+
+            let builder = CreateMessage::new()
+                .content(outcome.into_content())
+                .reference_message(&msg)
+                .allowed_mentions(CreateAllowedMentions::new().replied_user(true));
 
             msg.channel_id
                 .send_message(&ctx.http, builder)
@@ -182,24 +287,202 @@ impl EventHandler for Handler {
                 .unwrap();
 
             typing.stop();
+            return;
+        }
+
+        // Now and then join the conversation unprompted
+        let chime_in = settings.reply_chance > 0.0
+            && !opted_out
+            && !has_ignored_prefix(&msg.content)
+            && rand::thread_rng().gen_bool((settings.reply_chance / 100.0).min(1.0));
+
+        if chime_in && take_chime_in(&ctx, msg.channel_id).await {
+            let Some(reply) = chime_in_reply(
+                &ctx,
+                guild_id,
+                msg.channel_id,
+                &msg.content,
+                self.database.clone(),
+            )
+            .await
+            else {
+                return;
+            };
+
+            let builder = CreateMessage::new()
+                .content(reply)
+                .reference_message(&msg)
+                .allowed_mentions(CreateAllowedMentions::new());
+
+            if let Err(e) = msg.channel_id.send_message(&ctx.http, builder).await {
+                error!(
+                    guild_id = guild_id.get(),
+                    channel_id = msg.channel_id.get(),
+                    error = %e,
+                    "Failed to reply unprompted"
+                );
+            }
+        }
+    }
+
+    // Outages mark the guild unavailable, only a removal should cost its data
+    async fn guild_delete(&self, ctx: Context, incomplete: UnavailableGuild, _full: Option<Guild>) {
+        if incomplete.unavailable {
+            return;
+        }
+
+        match purge_guild_data(&ctx, &self.database, incomplete.id).await {
+            Ok(rows) => info!(
+                guild_id = incomplete.id.get(),
+                rows, "Removed from guild, purged its data"
+            ),
+            Err(e) => error!(guild_id = incomplete.id.get(), error = %e, "Failed to purge guild"),
+        }
+    }
+
+    // Keep stored content in line with what the author left it as
+    async fn message_update(
+        &self,
+        _ctx: Context,
+        _old_if_available: Option<Message>,
+        _new: Option<Message>,
+        event: MessageUpdateEvent,
+    ) {
+        let (Some(guild_id), Some(content)) = (event.guild_id, event.content) else {
+            return;
+        };
+
+        match self
+            .database
+            .update_message_content(guild_id.get(), event.id.get(), &content)
+            .await
+        {
+            Ok(_) => (),
+            Err(e) => {
+                error!(guild_id = guild_id.get(), message_id = event.id.get(), error = %e, "Failed to update edited message")
+            }
+        }
+    }
+
+    // Membership events only arrive with the GUILD_MEMBERS intent
+    async fn guild_member_addition(&self, _ctx: Context, new_member: Member) {
+        if let Err(e) = self
+            .database
+            .set_member(new_member.guild_id.get(), new_member.user.id.get(), true)
+            .await
+        {
+            error!(guild_id = new_member.guild_id.get(), error = %e, "Failed to record guild member");
+        }
+    }
+
+    async fn guild_member_removal(
+        &self,
+        _ctx: Context,
+        guild_id: GuildId,
+        user: User,
+        _member_data_if_available: Option<Member>,
+    ) {
+        if let Err(e) = self
+            .database
+            .set_member(guild_id.get(), user.id.get(), false)
+            .await
+        {
+            error!(guild_id = guild_id.get(), error = %e, "Failed to record guild member");
         }
     }
 
     async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
-        if let Interaction::Command(interaction) = interaction {
-            for command in &self.commands {
-                if interaction.data.name.as_str() == command.name {
-                    // Execute command
-                    if let Err(reason) =
-                        (command.exec)(&ctx, &interaction, self.database.clone()).await
-                    {
-                        println!(
-                            "There was an error while handling command {}: {:#?}",
-                            command.name, reason
-                        )
+        match interaction {
+            Interaction::Command(interaction) => {
+                // Everything logged while the command runs carries this
+                let span = info_span!(
+                    "command",
+                    name = %interaction.data.name,
+                    guild_id = interaction.guild_id.map(|guild_id| guild_id.get()),
+                    channel_id = interaction.channel_id.get(),
+                    user_id = interaction.user.id.get(),
+                );
+
+                self.run_command(&ctx, &interaction).instrument(span).await;
+            }
+            Interaction::Component(interaction) => {
+                let Some((prefix, _)) = interaction.data.custom_id.split_once(':') else {
+                    return;
+                };
+
+                let span = info_span!(
+                    "component",
+                    custom_id = %interaction.data.custom_id,
+                    guild_id = interaction.guild_id.map(|guild_id| guild_id.get()),
+                    channel_id = interaction.channel_id.get(),
+                    user_id = interaction.user.id.get(),
+                );
+
+                for component in &self.components {
+                    if prefix == component.prefix {
+                        if let Err(reason) =
+                            (component.exec)(&ctx, &interaction, self.database.clone())
+                                .instrument(span.clone())
+                                .await
+                        {
+                            span.in_scope(|| error!(error = ?reason, "Component failed"));
+                        }
                     }
                 }
             }
+            _ => (),
         }
     }
 }
+
+impl Handler {
+    async fn run_command(&self, ctx: &Context, interaction: &SlashCommand) {
+        // Discord can still show commands that were just removed
+        let Some(exec) = self.commands.get(interaction.data.name.as_str()) else {
+            warn!("Received unknown command");
+            reply_failed(ctx, interaction, "Unknown command, try again shortly.").await;
+            return;
+        };
+
+        let started = Instant::now();
+        let result = exec(ctx, interaction, self.database.clone()).await;
+        let elapsed_ms = started.elapsed().as_millis() as u64;
+
+        match result {
+            Ok(()) => info!(elapsed_ms, "Handled command"),
+            Err(reason) => {
+                error!(elapsed_ms, error = ?reason, "Command failed");
+                reply_failed(
+                    ctx,
+                    interaction,
+                    "Something went wrong while running this command.",
+                )
+                .await;
+            }
+        }
+    }
+}
+
+/// Tells the user their command didn't go through. Commands that already
+/// responded, or deferred, get that response edited instead.
+async fn reply_failed(ctx: &Context, interaction: &SlashCommand, content: &str) {
+    let response = CreateInteractionResponse::Message(
+        CreateInteractionResponseMessage::new()
+            .content(content)
+            .ephemeral(true),
+    );
+    if interaction
+        .create_response(&ctx.http, response)
+        .await
+        .is_ok()
+    {
+        return;
+    }
+
+    if let Err(e) = interaction
+        .edit_response(&ctx.http, EditInteractionResponse::new().content(content))
+        .await
+    {
+        warn!(error = %e, "Failed to tell the user the command failed");
+    }
+}
@@ -1,18 +1,119 @@
 use dotenvy::dotenv;
+use serenity::all::{ShardManager, UserId};
 use serenity::prelude::*;
 use std::collections::HashMap;
 use std::env;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use tokio::sync::RwLock;
+use tokio::time::{sleep, Duration, Instant};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+use tracing_subscriber::EnvFilter;
 
 mod commands;
 mod database;
 mod event_handler;
+mod tasks;
 mod utils;
 
 pub struct MarkovChainGlobal;
 impl TypeMapKey for MarkovChainGlobal {
-    type Value = Arc<RwLock<HashMap<u64, utils::markov_chain::Chain>>>;
+    type Value = Arc<RwLock<utils::chain_cache::ChainCache<utils::chain_cache::ChainKey>>>;
+}
+
+/// Chains trained on a single user's messages, keyed by `(guild_id, user_id)`.
+pub struct AuthorChainsGlobal;
+impl TypeMapKey for AuthorChainsGlobal {
+    type Value = Arc<RwLock<utils::chain_cache::ChainCache<(u64, u64)>>>;
+}
+
+/// Where trained chains are saved, see `tasks::chain_store`.
+pub struct ChainDirGlobal;
+impl TypeMapKey for ChainDirGlobal {
+    type Value = std::path::PathBuf;
+}
+
+/// `(guild_id, channel_id)` of every channel `/collect` is working on.
+pub struct ActiveCollectionsGlobal;
+impl TypeMapKey for ActiveCollectionsGlobal {
+    type Value = Arc<std::sync::Mutex<std::collections::HashSet<(u64, u64)>>>;
+}
+
+pub struct StyleModelsGlobal;
+impl TypeMapKey for StyleModelsGlobal {
+    type Value = Arc<RwLock<HashMap<u64, (std::time::Instant, Arc<utils::style::StyleModel>)>>>;
+}
+
+/// Runtime kill switches the owner can flip with `/admin`.
+#[derive(Default)]
+pub struct RuntimeFlags {
+    pub posting_paused: AtomicBool,
+    pub logging_paused: AtomicBool,
+}
+
+pub struct RuntimeFlagsGlobal;
+impl TypeMapKey for RuntimeFlagsGlobal {
+    type Value = Arc<RuntimeFlags>;
+}
+
+pub struct RecentMessagesGlobal;
+impl TypeMapKey for RecentMessagesGlobal {
+    type Value = Arc<std::sync::Mutex<utils::dedupe::RecentMessages>>;
+}
+
+/// When each channel last got a reply to the bot's name being said.
+pub struct NameTriggerCooldownsGlobal;
+impl TypeMapKey for NameTriggerCooldownsGlobal {
+    type Value = Arc<std::sync::Mutex<HashMap<u64, std::time::Instant>>>;
+}
+
+/// When each channel last got an unprompted reply.
+pub struct ChimeInCooldownsGlobal;
+impl TypeMapKey for ChimeInCooldownsGlobal {
+    type Value = Arc<std::sync::Mutex<HashMap<u64, std::time::Instant>>>;
+}
+
+/// Threads of replies to the bot, see `utils::reply_chains`.
+pub struct ReplyChainsGlobal;
+impl TypeMapKey for ReplyChainsGlobal {
+    type Value = Arc<std::sync::Mutex<utils::reply_chains::ReplyChains>>;
+}
+
+pub struct TaskSupervisorGlobal;
+impl TypeMapKey for TaskSupervisorGlobal {
+    type Value = Arc<tasks::TaskSupervisor>;
+}
+
+pub struct HeartbeatStatsGlobal;
+impl TypeMapKey for HeartbeatStatsGlobal {
+    type Value = Arc<tasks::heartbeat::HeartbeatStats>;
+}
+
+/// When the bot was started, for `/ping`'s uptime.
+pub struct StartedAtGlobal;
+impl TypeMapKey for StartedAtGlobal {
+    type Value = std::time::Instant;
+}
+
+pub struct ShardManagerGlobal;
+impl TypeMapKey for ShardManagerGlobal {
+    type Value = Arc<ShardManager>;
+}
+
+/// Cancelled once the bot starts shutting down, long-running work stops or
+/// saves its place when it is.
+pub struct ShutdownGlobal;
+impl TypeMapKey for ShutdownGlobal {
+    type Value = CancellationToken;
+}
+
+/// How long background tasks and collections get to wrap up on shutdown.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub struct BotOwner;
+impl TypeMapKey for BotOwner {
+    type Value = Option<UserId>;
 }
 
 #[tokio::main]
@@ -20,35 +121,233 @@ async fn main() {
     // load env variables
     dotenv().ok();
 
+    // RUST_LOG picks what gets logged, our own info and everyone's warnings
+    // by default
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| EnvFilter::new("warn,yorjik=info")),
+        )
+        .init();
+
+    let max_content_length = env::var("MAX_CONTENT_LENGTH")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(database::DEFAULT_MAX_CONTENT_LENGTH);
+
+    // one database file per guild instead of a shared data.db, opt-in
+    let guild_storage = env::var("PER_GUILD_DATABASES")
+        .is_ok_and(|value| value == "true")
+        .then(|| database::GuildStorage {
+            dir: env::var("GUILD_DATABASE_DIR")
+                .unwrap_or_else(|_| "data".to_string())
+                .into(),
+            max_open: env::var("MAX_OPEN_GUILD_DATABASES")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(database::DEFAULT_MAX_OPEN_GUILD_DATABASES),
+        });
+
+    let database_url =
+        env::var("DATABASE_URL").unwrap_or_else(|_| database::DEFAULT_DATABASE_URL.to_string());
+    let max_connections = env::var("DATABASE_MAX_CONNECTIONS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(database::DEFAULT_MAX_CONNECTIONS);
+
     // initialize database
     let database = Arc::new(
-        database::Database::new("sqlite:data.db")
-            .await
-            .expect("Failed to initialize database"),
+        database::Database::new(
+            &database_url,
+            max_content_length,
+            guild_storage,
+            max_connections,
+        )
+        .await
+        .expect("Failed to initialize database"),
     );
 
     let discord_token =
         env::var("DISCORD_TOKEN").expect("Expected DISCORD_TOKEN to be defined in environment.");
 
-    let intents = GatewayIntents::GUILD_MESSAGES | GatewayIntents::MESSAGE_CONTENT;
-    let commands = commands::commands_vecs();
+    let mut intents = GatewayIntents::GUILD_MESSAGES | GatewayIntents::MESSAGE_CONTENT;
+
+    // privileged, so it has to be enabled in the developer portal first
+    if env::var("GUILD_MEMBERS_INTENT").is_ok_and(|value| value == "true") {
+        intents |= GatewayIntents::GUILD_MEMBERS;
+    }
+    let commands = commands::commands_map();
+    let components = commands::components_vecs();
     let registered = commands::register_vecs();
 
-    let markov_cache = Arc::new(RwLock::new(HashMap::new()));
+    // pick up the chains trained before the last restart
+    let chain_dir: std::path::PathBuf = env::var("CHAIN_DIR")
+        .unwrap_or_else(|_| tasks::chain_store::DEFAULT_CHAIN_DIR.to_string())
+        .into();
+    let chain_max_age = env::var("CHAIN_MAX_AGE_HOURS")
+        .ok()
+        .and_then(|value| value.parse::<u64>().ok())
+        .map_or(tasks::chain_store::DEFAULT_CHAIN_MAX_AGE, |hours| {
+            std::time::Duration::from_secs(hours * 60 * 60)
+        });
+    let saved_chains = tasks::chain_store::load_chains(&chain_dir, chain_max_age);
+    let saved_chains_at = saved_chains
+        .iter()
+        .map(|(key, cached)| (*key, cached.trained_at))
+        .collect();
+
+    // bound the chains kept in memory, least recently used go first
+    let cache_capacity = env::var("MARKOV_CACHE_CAPACITY")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(utils::chain_cache::DEFAULT_CACHE_CAPACITY);
+    let cache_size_budget = env::var("MARKOV_CACHE_SIZE_BUDGET")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(utils::chain_cache::DEFAULT_CACHE_SIZE_BUDGET);
+
+    let mut markov_cache = utils::chain_cache::ChainCache::new(cache_capacity, cache_size_budget);
+    for (key, cached) in saved_chains {
+        markov_cache.insert(key, cached);
+    }
+    let markov_cache = Arc::new(RwLock::new(markov_cache));
+    let author_cache = Arc::new(RwLock::new(utils::chain_cache::ChainCache::new(
+        cache_capacity,
+        cache_size_budget,
+    )));
+
+    // restore the kill switches so a restart doesn't silently re-enable things
+    let runtime_flags = Arc::new(RuntimeFlags::default());
+    for (name, flag) in [
+        ("posting_paused", &runtime_flags.posting_paused),
+        ("logging_paused", &runtime_flags.logging_paused),
+    ] {
+        match database.get_runtime_flag(name).await {
+            Ok(value) => flag.store(value, Ordering::Relaxed),
+            Err(e) => error!(flag = name, error = %e, "Failed to load runtime flag"),
+        }
+    }
+
+    let shutdown = CancellationToken::new();
+    let supervisor = Arc::new(tasks::TaskSupervisor::new(shutdown.clone()));
+    let active_collections: <ActiveCollectionsGlobal as TypeMapKey>::Value = Arc::default();
 
     // build the Discord client, and pass in our event handler
     let mut client = Client::builder(discord_token, intents)
         .event_handler(event_handler::Handler {
             commands,
+            components,
             registered,
             database: database.clone(),
+            chain_dir: chain_dir.clone(),
+            saved_chains: saved_chains_at,
         })
         .type_map_insert::<MarkovChainGlobal>(markov_cache)
+        .type_map_insert::<AuthorChainsGlobal>(author_cache)
+        .type_map_insert::<ChainDirGlobal>(chain_dir)
+        .type_map_insert::<ActiveCollectionsGlobal>(active_collections.clone())
+        .type_map_insert::<StyleModelsGlobal>(Arc::default())
+        .type_map_insert::<RuntimeFlagsGlobal>(runtime_flags)
+        .type_map_insert::<RecentMessagesGlobal>(Arc::default())
+        .type_map_insert::<NameTriggerCooldownsGlobal>(Arc::default())
+        .type_map_insert::<ChimeInCooldownsGlobal>(Arc::default())
+        .type_map_insert::<ReplyChainsGlobal>(Arc::default())
+        .type_map_insert::<TaskSupervisorGlobal>(supervisor.clone())
+        .type_map_insert::<ShutdownGlobal>(shutdown.clone())
+        .type_map_insert::<StartedAtGlobal>(std::time::Instant::now())
+        .type_map_insert::<HeartbeatStatsGlobal>(Arc::default())
         .await
         .expect("Error creating client.");
 
-    // run the client
-    if let Err(reason) = client.start().await {
-        println!("Error starting client: {:?}", reason);
+    // resolve the bot owner for owner-only commands
+    let owner = match client.http.get_current_application_info().await {
+        Ok(info) => match info.team {
+            Some(team) => Some(team.owner_user_id),
+            None => info.owner.map(|owner| owner.id),
+        },
+        Err(e) => {
+            error!(error = %e, "Failed to fetch application info");
+            None
+        }
+    };
+    {
+        let mut data = client.data.write().await;
+        data.insert::<BotOwner>(owner);
+        data.insert::<ShardManagerGlobal>(client.shard_manager.clone());
+    }
+
+    // Discord's recommended shard count unless SHARD_COUNT says otherwise
+    let shard_count = env::var("SHARD_COUNT")
+        .ok()
+        .and_then(|value| value.parse::<u32>().ok())
+        .filter(|count| *count > 0);
+
+    // run the client until it stops or we get Ctrl-C or SIGTERM
+    let shard_manager = client.shard_manager.clone();
+    let start = async {
+        match shard_count {
+            Some(count) => client.start_shards(count).await,
+            None => client.start_autosharded().await,
+        }
+    };
+    tokio::select! {
+        result = start => {
+            if let Err(reason) = result {
+                error!(error = ?reason, "Error starting client");
+            }
+        }
+        _ = wait_for_signal() => {
+            info!("Shutting down");
+            shutdown.cancel();
+            shard_manager.shutdown_all().await;
+        }
+    }
+
+    // The client can also stop on its own, wind down the same way
+    shutdown.cancel();
+    supervisor.drain(SHUTDOWN_TIMEOUT).await;
+    wait_for_collections(&active_collections, SHUTDOWN_TIMEOUT).await;
+    database.close().await;
+
+    info!("Shutdown complete");
+}
+
+async fn wait_for_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => (),
+                    _ = terminate.recv() => (),
+                }
+                return;
+            }
+            Err(e) => warn!(error = %e, "Failed to listen for SIGTERM"),
+        }
+    }
+
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        error!(error = %e, "Failed to listen for Ctrl-C");
+        std::future::pending::<()>().await;
+    }
+}
+
+/// Waits for running `/collect`s to save their place, they stop at the next
+/// page once shutdown starts.
+async fn wait_for_collections(
+    active: &<ActiveCollectionsGlobal as TypeMapKey>::Value,
+    timeout: Duration,
+) {
+    let deadline = Instant::now() + timeout;
+
+    while !active.lock().unwrap().is_empty() {
+        if Instant::now() >= deadline {
+            warn!("Collections still running at shutdown, their last page may be collected again");
+            return;
+        }
+        sleep(Duration::from_millis(100)).await;
     }
 }
@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use rand::rngs::StdRng;
+use rand::Rng;
+use rand::SeedableRng;
+use serenity::all::{CreateAllowedMentions, CreateMessage, GuildId};
+use serenity::builder::GetMessages;
+use serenity::prelude::*;
+use tokio::time::{Duration, Instant};
+use tracing::{error, info};
+
+use crate::database::Database;
+use crate::utils::helpers::{
+    generate_markov_message, pick_autopost_channel, posting_paused, GenerationOptions,
+    MarkovOutcome, MESSAGE_CHAR_LIMIT,
+};
+
+use super::shutdown_token;
+
+/// How often guilds are checked for a post that's due.
+const TICK: Duration = Duration::from_secs(30);
+
+/// What happened in one guild during a tick.
+enum GuildOutcome {
+    /// A post was due and its channel was checked, whether or not anything
+    /// ended up being sent.
+    Processed,
+    /// Autoposting is off, nothing is due yet, or there's nowhere to post.
+    Skipped,
+}
+
+/// Per-guild state carried between ticks.
+struct Scheduler {
+    rng: StdRng,
+    /// Last channel posted to per guild, so posts move around
+    last_channels: HashMap<GuildId, u64>,
+    /// When each guild gets its next post, guilds without one are due now
+    next_posts: HashMap<GuildId, Instant>,
+}
+
+/// Posts a generated message in every guild that has autoposting on, waiting
+/// however long each guild configured between posts (5 to 15 minutes unless
+/// changed with `/config autopost`).
+pub async fn run(ctx: Context, database: Arc<Database>) {
+    let shutdown = shutdown_token(&ctx).await;
+    let mut scheduler = Scheduler {
+        rng: StdRng::from_entropy(),
+        last_channels: HashMap::new(),
+        next_posts: HashMap::new(),
+    };
+
+    loop {
+        let (mut processed, mut skipped, mut errored) = (0, 0, 0);
+
+        for guild_id in ctx.cache.guilds() {
+            // The owner may pause posting at any moment
+            if shutdown.is_cancelled() || posting_paused(&ctx).await {
+                break;
+            }
+
+            // A failing guild is logged and left for its next turn
+            match scheduler.run_guild(&ctx, &database, guild_id).await {
+                Ok(GuildOutcome::Processed) => processed += 1,
+                Ok(GuildOutcome::Skipped) => skipped += 1,
+                Err(e) => {
+                    error!(guild_id = guild_id.get(), error = %e, "Autopost failed");
+                    errored += 1;
+                }
+            }
+        }
+
+        info!(processed, skipped, errored, "Autopost tick");
+
+        tokio::select! {
+            _ = tokio::time::sleep(TICK) => (),
+            _ = shutdown.cancelled() => return,
+        }
+    }
+}
+
+impl Scheduler {
+    /// Posts in `guild_id` if it's due. Errors name the call that failed.
+    async fn run_guild(
+        &mut self,
+        ctx: &Context,
+        database: &Arc<Database>,
+        guild_id: GuildId,
+    ) -> Result<GuildOutcome, String> {
+        let settings = database
+            .get_guild_settings(guild_id.get())
+            .await
+            .map_err(|e| format!("get_guild_settings: {}", e))?;
+
+        if !settings.autopost_enabled {
+            self.next_posts.remove(&guild_id);
+            return Ok(GuildOutcome::Skipped);
+        }
+
+        // A shorter interval set since the last post applies right away
+        let now = Instant::now();
+        let latest = now + Duration::from_secs(settings.autopost_max_secs);
+        if let Some(next_post) = self.next_posts.get_mut(&guild_id) {
+            *next_post = (*next_post).min(latest);
+            if *next_post > now {
+                return Ok(GuildOutcome::Skipped);
+            }
+        }
+
+        let wait = self.rng.gen_range(
+            settings.autopost_min_secs..=settings.autopost_max_secs.max(settings.autopost_min_secs),
+        );
+        self.next_posts
+            .insert(guild_id, now + Duration::from_secs(wait));
+
+        let target_channel_id = match settings.autopost_channel_id {
+            Some(channel_id) => channel_id,
+            None => {
+                let Some(channel_id) = pick_autopost_channel(
+                    guild_id,
+                    database.clone(),
+                    self.last_channels.get(&guild_id).copied(),
+                )
+                .await
+                else {
+                    return Ok(GuildOutcome::Skipped);
+                };
+                channel_id
+            }
+        };
+        self.last_channels.insert(guild_id, target_channel_id);
+
+        let Some(channel) = ctx
+            .http
+            .get_channels(guild_id)
+            .await
+            .map_err(|e| format!("get_channels: {}", e))?
+            .into_iter()
+            .find(|channel| channel.id.get() == target_channel_id)
+        else {
+            return Ok(GuildOutcome::Skipped);
+        };
+
+        let bot_id = ctx.cache.current_user().id;
+        let member = guild_id
+            .member(ctx, bot_id)
+            .await
+            .map_err(|e| format!("member: {}", e))?;
+
+        // Without a cached guild the calls below fail and get logged instead
+        let permissions = ctx
+            .cache
+            .guild(guild_id)
+            .map(|guild| guild.user_permissions_in(&channel, &member));
+        if permissions.is_some_and(|permissions| {
+            !(permissions.view_channel()
+                && permissions.read_message_history()
+                && permissions.send_messages())
+        }) {
+            return Ok(GuildOutcome::Skipped);
+        }
+
+        let messages = channel
+            .messages(&ctx.http, GetMessages::new().limit(100))
+            .await
+            .map_err(|e| format!("messages in channel {}: {}", channel.id, e))?;
+
+        // Stay quiet until someone else has talked since the last post
+        if messages.iter().any(|message| message.author.id == bot_id) {
+            return Ok(GuildOutcome::Processed);
+        }
+
+        // Only post when something was generated, failures stay quiet
+        if let MarkovOutcome::Generated(markov_message) = generate_markov_message(
+            ctx,
+            guild_id,
+            channel.id,
+            database.clone(),
+            MESSAGE_CHAR_LIMIT,
+            GenerationOptions::default(),
+        )
+        .await
+        {
+            channel
+                .send_message(
+                    &ctx.http,
+                    CreateMessage::new()
+                        .content(markov_message)
+                        .allowed_mentions(CreateAllowedMentions::new()),
+                )
+                .await
+                .map_err(|e| format!("send_message in channel {}: {}", channel.id, e))?;
+        }
+
+        Ok(GuildOutcome::Processed)
+    }
+}
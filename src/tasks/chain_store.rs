@@ -0,0 +1,177 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime};
+
+use serenity::prelude::*;
+use tokio::time::{sleep, Duration};
+use tracing::{error, info, warn};
+
+use crate::utils::chain_cache::ChainKey;
+use crate::utils::markov_chain::{CachedChain, Chain};
+use crate::MarkovChainGlobal;
+
+use super::shutdown_token;
+
+/// Default directory trained chains are saved to.
+pub const DEFAULT_CHAIN_DIR: &str = "chains";
+
+/// Default age after which a saved chain is no longer loaded.
+pub const DEFAULT_CHAIN_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Time between checks for newly trained chains.
+const SAVE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Prefix of saved guild chain file names, channel chains have none.
+const GUILD_FILE_PREFIX: &str = "guild-";
+
+fn chain_path(dir: &Path, key: ChainKey) -> PathBuf {
+    match key {
+        ChainKey::Channel(channel_id) => dir.join(format!("{}.chain", channel_id)),
+        ChainKey::Guild(guild_id) => dir.join(format!("{}{}.chain", GUILD_FILE_PREFIX, guild_id)),
+    }
+}
+
+/// The key a saved chain's file name stands for.
+fn chain_key(path: &Path) -> Option<ChainKey> {
+    if path.extension()? != "chain" {
+        return None;
+    }
+
+    let stem = path.file_stem()?.to_str()?;
+    match stem.strip_prefix(GUILD_FILE_PREFIX) {
+        Some(guild_id) => guild_id.parse().ok().map(ChainKey::Guild),
+        None => stem.parse().ok().map(ChainKey::Channel),
+    }
+}
+
+/// Loads every saved chain in `dir` younger than `max_age`, dated
+/// to when its file was written.
+///
+/// Stale, corrupt and unreadable files are skipped, a missing directory
+/// just means there's nothing to load.
+pub fn load_chains(dir: &Path, max_age: Duration) -> HashMap<ChainKey, CachedChain> {
+    let mut chains = HashMap::new();
+
+    let Ok(entries) = fs::read_dir(dir) else {
+        return chains;
+    };
+
+    for path in entries.flatten().map(|entry| entry.path()) {
+        let Some(key) = chain_key(&path) else {
+            continue;
+        };
+
+        let age = fs::metadata(&path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok());
+        let Some(age) = age.filter(|age| *age <= max_age) else {
+            continue;
+        };
+
+        match Chain::load_from(&path) {
+            Ok(chain) => {
+                let trained_at = Instant::now().checked_sub(age).unwrap_or_else(Instant::now);
+                chains.insert(key, CachedChain { chain, trained_at });
+            }
+            Err(e) => warn!(path = %path.display(), error = %e, "Skipping saved chain"),
+        }
+    }
+
+    info!(chains = chains.len(), dir = %dir.display(), "Loaded saved chains");
+    chains
+}
+
+/// Deletes the saved chains for `keys`, so they aren't loaded again.
+pub fn remove_chains(dir: &Path, keys: &[ChainKey]) {
+    for key in keys {
+        let path = chain_path(dir, *key);
+        match fs::remove_file(&path) {
+            Ok(()) => (),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+            Err(e) => warn!(path = %path.display(), error = %e, "Failed to delete saved chain"),
+        }
+    }
+}
+
+/// Saves newly trained chains to `dir`, so they survive a restart.
+///
+/// A chain is only written again once it's been retrained. `saved` holds
+/// when each saved chain was trained. Whatever is still unsaved is written
+/// one last time on shutdown.
+pub async fn run(ctx: Context, dir: PathBuf, mut saved: HashMap<ChainKey, Instant>) {
+    if let Err(e) = tokio::fs::create_dir_all(&dir).await {
+        error!(dir = %dir.display(), error = %e, "Failed to create chain directory");
+    }
+
+    let shutdown = shutdown_token(&ctx).await;
+
+    loop {
+        tokio::select! {
+            _ = sleep(SAVE_INTERVAL) => save_unsaved(&ctx, &dir, &mut saved).await,
+            _ = shutdown.cancelled() => {
+                save_unsaved(&ctx, &dir, &mut saved).await;
+                info!("Saved chains for shutdown");
+                return;
+            }
+        }
+    }
+}
+
+async fn save_unsaved(ctx: &Context, dir: &Path, saved: &mut HashMap<ChainKey, Instant>) {
+    let cache = ctx.data.read().await.get::<MarkovChainGlobal>().cloned();
+    let Some(cache) = cache else {
+        return;
+    };
+
+    // Copy them out so training isn't blocked while files are written
+    let unsaved: Vec<(ChainKey, Chain, Instant)> = cache
+        .read()
+        .await
+        .iter()
+        .filter(|(key, cached)| saved.get(key) != Some(&cached.trained_at))
+        .map(|(key, cached)| (*key, cached.chain.clone(), cached.trained_at))
+        .collect();
+
+    for (key, chain, trained_at) in unsaved {
+        let path = chain_path(dir, key);
+
+        match tokio::task::spawn_blocking(move || chain.save_to(&path)).await {
+            Ok(Ok(())) => {
+                saved.insert(key, trained_at);
+            }
+            Ok(Err(e)) => error!(key = ?key, error = %e, "Failed to save chain"),
+            Err(e) => error!(key = ?key, error = %e, "Failed to save chain"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn legacy_saves_are_skipped_and_current_ones_loaded() {
+        let dir = std::env::temp_dir().join(format!("chain-store-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        fs::write(
+            chain_path(&dir, ChainKey::Channel(1)),
+            "markov-chain v1 1\nthe\tcat dog\n",
+        )
+        .unwrap();
+        let mut chain = Chain::new(1);
+        chain.train(vec!["the cat sat down".to_string()]);
+        chain
+            .save_to(&chain_path(&dir, ChainKey::Guild(2)))
+            .unwrap();
+
+        let chains = load_chains(&dir, DEFAULT_CHAIN_MAX_AGE);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(chains.len(), 1);
+        assert!(chains.contains_key(&ChainKey::Guild(2)));
+        assert_eq!(chains[&ChainKey::Guild(2)].chain.transitions(), 3);
+    }
+}
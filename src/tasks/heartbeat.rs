@@ -0,0 +1,203 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use rand::Rng;
+use reqwest::Url;
+use serenity::all::{ConnectionStage, ShardManager};
+use serenity::prelude::*;
+use tokio::time::{sleep, Duration, Instant};
+use tracing::{error, info, warn};
+
+use crate::{HeartbeatStatsGlobal, ShardManagerGlobal};
+
+/// Default time between heartbeats.
+pub const DEFAULT_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How long the gateway may be disconnected before the bot reports down.
+const DOWN_THRESHOLD: Duration = Duration::from_secs(120);
+
+/// Longest wait between heartbeats while the push endpoint keeps failing.
+const MAX_FAILURE_BACKOFF: Duration = Duration::from_secs(15 * 60);
+
+/// Counters reported with every heartbeat.
+#[derive(Default)]
+pub struct HeartbeatStats {
+    /// Messages stored since the last heartbeat.
+    pub messages_stored: AtomicU64,
+}
+
+/// Adds `count` to the messages reported with the next heartbeat.
+pub async fn record_stored_messages(ctx: &Context, count: u64) {
+    let data_read = ctx.data.read().await;
+    if let Some(stats) = data_read.get::<HeartbeatStatsGlobal>() {
+        stats.messages_stored.fetch_add(count, Ordering::Relaxed);
+    }
+}
+
+/// Pushes the bot's status to an Uptime Kuma push monitor at `url` every
+/// `interval`.
+pub async fn run(ctx: Context, url: String, interval: Duration) {
+    let url = match Url::parse(&url) {
+        Ok(url) => url,
+        Err(e) => {
+            error!(url, error = %e, "Invalid UPTIME_KUMA_URL");
+            return;
+        }
+    };
+
+    let shutdown = super::shutdown_token(&ctx).await;
+    let mut disconnected_since: Option<Instant> = None;
+    let mut failures: u32 = 0;
+
+    loop {
+        let (connected, latency) = gateway_state(&ctx).await;
+
+        disconnected_since = match (connected, disconnected_since) {
+            (true, _) => None,
+            (false, Some(since)) => Some(since),
+            (false, None) => Some(Instant::now()),
+        };
+
+        let up = is_up(disconnected_since.map(|since| since.elapsed()));
+        let guilds = ctx.cache.guilds().len();
+        let messages_stored = match ctx.data.read().await.get::<HeartbeatStatsGlobal>() {
+            Some(stats) => stats.messages_stored.swap(0, Ordering::Relaxed),
+            None => 0,
+        };
+
+        let message = match up {
+            true => format!("{} guilds, {} messages stored", guilds, messages_stored),
+            false => "Gateway disconnected".to_string(),
+        };
+
+        let push_url = heartbeat_url(&url, up, &message, latency);
+
+        match reqwest::get(push_url)
+            .await
+            .and_then(|res| res.error_for_status())
+        {
+            Ok(_) => {
+                if failures > 0 {
+                    info!(failures, "Kuma reachable again");
+                }
+                failures = 0;
+            }
+            Err(e) => {
+                failures += 1;
+                warn!(attempt = failures, error = %e, "Failed to ping Kuma");
+            }
+        }
+
+        tokio::select! {
+            _ = sleep(next_delay(interval, failures)) => (),
+            _ = shutdown.cancelled() => return,
+        }
+    }
+}
+
+/// Whether this process has a working gateway connection, and the highest
+/// latency among its shards.
+async fn gateway_state(ctx: &Context) -> (bool, Option<Duration>) {
+    let Some(shard_manager) = ctx.data.read().await.get::<ShardManagerGlobal>().cloned() else {
+        return (true, None);
+    };
+
+    shard_state(&shard_manager).await
+}
+
+async fn shard_state(shard_manager: &Arc<ShardManager>) -> (bool, Option<Duration>) {
+    let runners = shard_manager.runners.lock().await;
+
+    let connected = !runners.is_empty()
+        && runners
+            .values()
+            .all(|runner| runner.stage == ConnectionStage::Connected);
+    let latency = runners.values().filter_map(|runner| runner.latency).max();
+
+    (connected, latency)
+}
+
+/// The bot is down once the gateway has been disconnected for longer than
+/// `DOWN_THRESHOLD`, short reconnects don't count.
+fn is_up(disconnected_for: Option<Duration>) -> bool {
+    disconnected_for.is_none_or(|elapsed| elapsed < DOWN_THRESHOLD)
+}
+
+/// `url` with Kuma's push parameters set, replacing any already on it.
+fn heartbeat_url(url: &Url, up: bool, message: &str, latency: Option<Duration>) -> Url {
+    let mut push_url = url.clone();
+
+    let kept: Vec<(String, String)> = url
+        .query_pairs()
+        .filter(|(key, _)| !matches!(key.as_ref(), "status" | "msg" | "ping"))
+        .map(|(key, value)| (key.into_owned(), value.into_owned()))
+        .collect();
+
+    {
+        let mut query = push_url.query_pairs_mut();
+        query.clear();
+        query.extend_pairs(kept);
+        query.append_pair("status", if up { "up" } else { "down" });
+        query.append_pair("msg", message);
+        query.append_pair(
+            "ping",
+            &latency.map_or(String::new(), |latency| latency.as_millis().to_string()),
+        );
+    }
+
+    push_url
+}
+
+/// Doubles the interval for every consecutive failure, up to a cap, with
+/// some jitter so retries don't line up.
+fn next_delay(interval: Duration, failures: u32) -> Duration {
+    if failures == 0 {
+        return interval;
+    }
+
+    let backoff = interval
+        .saturating_mul(2u32.saturating_pow(failures.min(16)))
+        .min(MAX_FAILURE_BACKOFF);
+    let jitter = rand::thread_rng().gen_range(0..=backoff.as_millis() as u64 / 4);
+
+    backoff + Duration::from_millis(jitter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_disconnects_still_count_as_up() {
+        assert!(is_up(None));
+        assert!(is_up(Some(Duration::ZERO)));
+        assert!(is_up(Some(DOWN_THRESHOLD - Duration::from_millis(1))));
+        assert!(!is_up(Some(DOWN_THRESHOLD)));
+        assert!(!is_up(Some(DOWN_THRESHOLD * 10)));
+    }
+
+    #[test]
+    fn push_parameters_are_set() {
+        let url = Url::parse("https://kuma.example/api/push/abc").unwrap();
+
+        assert_eq!(
+            heartbeat_url(&url, true, "3 guilds", Some(Duration::from_millis(42))).as_str(),
+            "https://kuma.example/api/push/abc?status=up&msg=3+guilds&ping=42"
+        );
+        assert_eq!(
+            heartbeat_url(&url, false, "Gateway disconnected", None).as_str(),
+            "https://kuma.example/api/push/abc?status=down&msg=Gateway+disconnected&ping="
+        );
+    }
+
+    #[test]
+    fn push_parameters_replace_ones_on_the_url() {
+        let url =
+            Url::parse("https://kuma.example/api/push/abc?status=up&msg=OK&ping=&token=x").unwrap();
+
+        assert_eq!(
+            heartbeat_url(&url, false, "a&b=c", None).as_str(),
+            "https://kuma.example/api/push/abc?token=x&status=down&msg=a%26b%3Dc&ping="
+        );
+    }
+}
@@ -0,0 +1,240 @@
+pub mod autopost;
+pub mod chain_store;
+pub mod heartbeat;
+pub mod warmup;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use serenity::futures::future::BoxFuture;
+use serenity::prelude::*;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, timeout, Duration, Instant};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+/// Shortest wait before restarting a task that stopped.
+const MIN_RESTART_DELAY: Duration = Duration::from_secs(5);
+
+/// Longest wait between restarts of a task that keeps stopping.
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(300);
+
+/// A task that ran at least this long is considered healthy again.
+const HEALTHY_RUN_TIME: Duration = Duration::from_secs(600);
+
+/// Builds a fresh run of a background task.
+pub type TaskFactory = Arc<dyn Fn() -> BoxFuture<'static, ()> + Send + Sync>;
+
+/// Keeps long-running background tasks alive, exactly once each.
+///
+/// `ready` fires again on every gateway reconnect, so starting tasks has to
+/// be idempotent. Tasks are expected to run forever; one that returns or
+/// panics is restarted with a growing delay, until `shutdown` is cancelled.
+pub struct TaskSupervisor {
+    tasks: Mutex<HashMap<&'static str, JoinHandle<()>>>,
+    shutdown: CancellationToken,
+}
+
+impl TaskSupervisor {
+    pub fn new(shutdown: CancellationToken) -> Self {
+        Self {
+            tasks: Mutex::default(),
+            shutdown,
+        }
+    }
+
+    /// Starts the task called `name` unless it's already running.
+    pub async fn ensure_running(&self, name: &'static str, factory: TaskFactory) {
+        let mut tasks = self.tasks.lock().await;
+
+        if tasks.get(name).is_some_and(|handle| !handle.is_finished()) {
+            return;
+        }
+
+        info!(task = name, "Starting background task");
+        tasks.insert(
+            name,
+            tokio::spawn(supervise(name, factory, self.shutdown.clone())),
+        );
+    }
+
+    /// Starts the one-off task called `name`, unless it was started before.
+    /// It isn't restarted once it returns.
+    pub async fn run_once(&self, name: &'static str, task: BoxFuture<'static, ()>) {
+        let mut tasks = self.tasks.lock().await;
+
+        if tasks.contains_key(name) {
+            return;
+        }
+
+        info!(task = name, "Starting one-off task");
+        tasks.insert(name, tokio::spawn(task));
+    }
+
+    /// Waits up to `limit` for every task to finish after shutdown was
+    /// cancelled, aborting the ones that don't.
+    pub async fn drain(&self, limit: Duration) {
+        let deadline = Instant::now() + limit;
+        let tasks = std::mem::take(&mut *self.tasks.lock().await);
+
+        for (name, mut handle) in tasks {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if timeout(left, &mut handle).await.is_err() {
+                warn!(task = name, "Background task didn't stop in time, aborting");
+                handle.abort();
+            }
+        }
+    }
+}
+
+/// The token cancelled when the bot shuts down.
+pub async fn shutdown_token(ctx: &Context) -> CancellationToken {
+    ctx.data
+        .read()
+        .await
+        .get::<crate::ShutdownGlobal>()
+        .cloned()
+        .unwrap_or_default()
+}
+
+async fn supervise(name: &'static str, factory: TaskFactory, shutdown: CancellationToken) {
+    let mut restart_delay = MIN_RESTART_DELAY;
+
+    loop {
+        let started = Instant::now();
+
+        // A separate task, so a panic ends up here instead of killing us
+        match tokio::spawn(factory()).await {
+            Ok(()) if shutdown.is_cancelled() => return,
+            Ok(()) => warn!(task = name, "Background task stopped unexpectedly"),
+            Err(e) => error!(task = name, error = %e, "Background task panicked"),
+        }
+
+        if shutdown.is_cancelled() {
+            return;
+        }
+
+        if started.elapsed() >= HEALTHY_RUN_TIME {
+            restart_delay = MIN_RESTART_DELAY;
+        }
+
+        warn!(
+            task = name,
+            delay_secs = restart_delay.as_secs(),
+            "Restarting background task"
+        );
+        tokio::select! {
+            _ = sleep(restart_delay) => (),
+            _ = shutdown.cancelled() => return,
+        }
+        restart_delay = (restart_delay * 2).min(MAX_RESTART_DELAY);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+
+    /// A factory that records when each run starts and then does `run`.
+    fn recording_factory(
+        starts: Arc<std::sync::Mutex<Vec<Instant>>>,
+        run: fn(usize) -> BoxFuture<'static, ()>,
+    ) -> TaskFactory {
+        Arc::new(move || {
+            let mut starts = starts.lock().unwrap();
+            starts.push(Instant::now());
+            run(starts.len())
+        })
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn ensure_running_starts_a_task_once() {
+        let supervisor = TaskSupervisor::new(CancellationToken::new());
+        let runs = Arc::new(AtomicU32::new(0));
+
+        for _ in 0..3 {
+            let runs = runs.clone();
+            supervisor
+                .ensure_running(
+                    "forever",
+                    Arc::new(move || {
+                        runs.fetch_add(1, Ordering::SeqCst);
+                        Box::pin(std::future::pending())
+                    }),
+                )
+                .await;
+            sleep(Duration::from_secs(1)).await;
+        }
+
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stopped_tasks_restart_with_a_growing_delay() {
+        let shutdown = CancellationToken::new();
+        let supervisor = TaskSupervisor::new(shutdown.clone());
+        let starts = Arc::new(std::sync::Mutex::new(Vec::new()));
+
+        // Every other run panics instead of returning
+        let factory = recording_factory(starts.clone(), |run| {
+            Box::pin(async move {
+                if run % 2 == 0 {
+                    panic!("run {} failed", run);
+                }
+            })
+        });
+        supervisor.ensure_running("flaky", factory).await;
+
+        sleep(Duration::from_secs(36)).await;
+        shutdown.cancel();
+        supervisor.drain(Duration::from_secs(1)).await;
+
+        let starts = starts.lock().unwrap();
+        let delays: Vec<u64> = starts
+            .windows(2)
+            .map(|pair| (pair[1] - pair[0]).as_secs())
+            .collect();
+        assert_eq!(delays, [5, 10, 20]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn shutdown_stops_restarts() {
+        let shutdown = CancellationToken::new();
+        let supervisor = TaskSupervisor::new(shutdown.clone());
+        let starts = Arc::new(std::sync::Mutex::new(Vec::new()));
+
+        let factory = recording_factory(starts.clone(), |_| Box::pin(async {}));
+        supervisor.ensure_running("short", factory).await;
+
+        sleep(Duration::from_secs(1)).await;
+        shutdown.cancel();
+        supervisor.drain(Duration::from_secs(1)).await;
+        sleep(MAX_RESTART_DELAY).await;
+
+        assert_eq!(starts.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn one_off_tasks_run_once() {
+        let supervisor = TaskSupervisor::new(CancellationToken::new());
+        let runs = Arc::new(AtomicU32::new(0));
+
+        for _ in 0..2 {
+            let runs = runs.clone();
+            supervisor
+                .run_once(
+                    "once",
+                    Box::pin(async move {
+                        runs.fetch_add(1, Ordering::SeqCst);
+                    }),
+                )
+                .await;
+            sleep(Duration::from_secs(1)).await;
+        }
+
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,77 @@
+use std::sync::Arc;
+use std::time::Instant;
+
+use serenity::all::{ChannelId, GuildId};
+use serenity::prelude::*;
+use tokio::time::{sleep, Duration};
+use tracing::{info, warn};
+
+use crate::database::Database;
+use crate::utils::helpers::{with_markov_chain, MarkovOutcome};
+
+/// Default number of chains trained on startup, across all guilds.
+pub const DEFAULT_WARMUP_CHAINS: usize = 10;
+
+/// Pause between chains, so SQLite isn't hit by every fetch at once.
+const WARMUP_DELAY: Duration = Duration::from_secs(2);
+
+/// Trains the chains of the `total` busiest channels across all guilds, so
+/// the first mentions after a restart don't wait on a cold chain.
+///
+/// A channel that fails is logged and skipped.
+pub async fn run(ctx: Context, database: Arc<Database>, total: usize) {
+    let started = Instant::now();
+    let shutdown = super::shutdown_token(&ctx).await;
+
+    let mut channels: Vec<(GuildId, u64, i64)> = Vec::new();
+    for guild_id in ctx.cache.guilds() {
+        match database
+            .get_top_channels(guild_id.get(), total as i64)
+            .await
+        {
+            Ok(top_channels) => channels.extend(
+                top_channels
+                    .into_iter()
+                    .map(|(channel_id, count)| (guild_id, channel_id, count)),
+            ),
+            Err(e) => warn!(guild_id = guild_id.get(), error = %e, "Failed to get top channels"),
+        }
+    }
+
+    channels.sort_by_key(|(_, _, count)| std::cmp::Reverse(*count));
+    channels.truncate(total);
+
+    let mut warmed = 0;
+    for (index, (guild_id, channel_id, _)) in channels.into_iter().enumerate() {
+        if index > 0 {
+            tokio::select! {
+                _ = sleep(WARMUP_DELAY) => (),
+                _ = shutdown.cancelled() => break,
+            }
+        }
+
+        match with_markov_chain(
+            &ctx,
+            guild_id,
+            ChannelId::new(channel_id),
+            database.clone(),
+            false,
+            |_| (),
+        )
+        .await
+        {
+            MarkovOutcome::Generated(()) => warmed += 1,
+            MarkovOutcome::NotEnoughMessages { .. } => (),
+            _ => warn!(
+                guild_id = guild_id.get(),
+                channel_id, "Failed to warm chain"
+            ),
+        }
+    }
+
+    info!(
+        warmed,
+        elapsed_secs = started.elapsed().as_secs(),
+        "Warmed chains"
+    );
+}
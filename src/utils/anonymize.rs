@@ -0,0 +1,189 @@
+// Hides who wrote a message, for guess rounds. Names are matched as whole
+// words after folding case and diacritics, so "Yoru" masks "**yörü**" but
+// leaves "yorumlar" alone.
+
+/// Replaces a masked name.
+const NAME_PLACEHOLDER: &str = "[name]";
+
+/// Replaces a masked mention of someone else.
+const MENTION_PLACEHOLDER: &str = "[someone]";
+
+/// Names shorter than this are too likely to be ordinary words.
+const MIN_NAME_LENGTH: usize = 3;
+
+/// Masks `names` (the author's username, display name, nick...) in `content`
+/// and strips mentions of `author_id`. With `mask_mentions`, mentions of
+/// anyone else become `[someone]`.
+pub fn anonymize(content: &str, author_id: u64, names: &[&str], mask_mentions: bool) -> String {
+    let content = replace_mentions(content, author_id, mask_mentions);
+
+    let mut names: Vec<Vec<char>> = names
+        .iter()
+        .map(|name| name.chars().map(fold_char).collect::<Vec<_>>())
+        .filter(|name| name.len() >= MIN_NAME_LENGTH)
+        .collect();
+
+    // Longest first, so a nick containing the username is masked whole
+    names.sort_by_key(|name| std::cmp::Reverse(name.len()));
+    names.dedup();
+
+    let chars: Vec<char> = content.chars().collect();
+    let folded: Vec<char> = chars.iter().copied().map(fold_char).collect();
+    let mut masked = vec![false; chars.len()];
+
+    for name in &names {
+        let mut start = 0;
+
+        while start + name.len() <= folded.len() {
+            let end = start + name.len();
+
+            if folded[start..end] == name[..]
+                && !masked[start..end].contains(&true)
+                && is_boundary(&chars, start.checked_sub(1))
+                && is_boundary(&chars, Some(end))
+            {
+                masked[start..end].fill(true);
+                start = end;
+            } else {
+                start += 1;
+            }
+        }
+    }
+
+    let mut anonymized = String::with_capacity(content.len());
+    for (index, c) in chars.iter().enumerate() {
+        match masked[index] {
+            true if index == 0 || !masked[index - 1] => anonymized.push_str(NAME_PLACEHOLDER),
+            true => (),
+            false => anonymized.push(*c),
+        }
+    }
+
+    anonymized
+}
+
+/// Strips `<@author_id>` mentions and, with `mask_others`, swaps the rest for
+/// `[someone]`.
+fn replace_mentions(content: &str, author_id: u64, mask_others: bool) -> String {
+    let mut replaced = String::with_capacity(content.len());
+    let mut rest = content;
+
+    while let Some(start) = rest.find("<@") {
+        replaced.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let inner = rest[2..].strip_prefix('!').unwrap_or(&rest[2..]);
+        let digits = inner.len() - inner.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+
+        let user_id = inner[..digits].parse::<u64>().ok();
+        let Some(user_id) = user_id.filter(|_| inner[digits..].starts_with('>')) else {
+            // Not a user mention, e.g. a role
+            replaced.push_str("<@");
+            rest = &rest[2..];
+            continue;
+        };
+
+        let mention_length = rest.len() - inner.len() + digits + 1;
+        if user_id == author_id {
+            // Leave no double space behind
+            if replaced.ends_with(' ') && rest[mention_length..].starts_with(' ') {
+                replaced.pop();
+            }
+        } else if mask_others {
+            replaced.push_str(MENTION_PLACEHOLDER);
+        } else {
+            replaced.push_str(&rest[..mention_length]);
+        }
+
+        rest = &rest[mention_length..];
+    }
+
+    replaced.push_str(rest);
+    replaced.trim().to_string()
+}
+
+/// Whether the char at `index` (if any) can't continue a word.
+fn is_boundary(chars: &[char], index: Option<usize>) -> bool {
+    index
+        .and_then(|index| chars.get(index))
+        .is_none_or(|c| !c.is_alphanumeric())
+}
+
+/// Lowercases `c` and drops its diacritics, keeping one char for one char so
+/// positions line up with the original.
+fn fold_char(c: char) -> char {
+    let lower = c.to_lowercase().next().unwrap_or(c);
+
+    match lower {
+        'á' | 'à' | 'â' | 'ä' | 'ã' | 'å' => 'a',
+        'é' | 'è' | 'ê' | 'ë' => 'e',
+        'í' | 'ì' | 'î' | 'ï' | 'ı' => 'i',
+        'ó' | 'ò' | 'ô' | 'ö' | 'õ' => 'o',
+        'ú' | 'ù' | 'û' | 'ü' => 'u',
+        'ç' => 'c',
+        'ğ' => 'g',
+        'ñ' => 'n',
+        'ş' => 's',
+        c => c,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AUTHOR: u64 = 1;
+
+    #[test]
+    fn names_inside_words_are_left_alone() {
+        assert_eq!(
+            anonymize("yorumlar çok güzel", AUTHOR, &["Yoru"], false),
+            "yorumlar çok güzel"
+        );
+        assert_eq!(
+            anonymize("ayoru and yoru", AUTHOR, &["yoru"], false),
+            "ayoru and [name]"
+        );
+    }
+
+    #[test]
+    fn names_in_markdown_are_masked() {
+        assert_eq!(
+            anonymize("**yörü** said hi", AUTHOR, &["Yoru"], false),
+            "**[name]** said hi"
+        );
+        assert_eq!(
+            anonymize("_YORU_, `yoru`", AUTHOR, &["yörü"], false),
+            "_[name]_, `[name]`"
+        );
+    }
+
+    #[test]
+    fn longest_name_is_masked_whole() {
+        assert_eq!(
+            anonymize("yoru_kun and yoru", AUTHOR, &["yoru", "yoru_kun"], false),
+            "[name] and [name]"
+        );
+    }
+
+    #[test]
+    fn short_names_are_ignored() {
+        assert_eq!(anonymize("yo dude", AUTHOR, &["yo"], false), "yo dude");
+    }
+
+    #[test]
+    fn self_mentions_leave_no_extra_spaces() {
+        assert_eq!(anonymize("hey <@1> look", AUTHOR, &[], false), "hey look");
+        assert_eq!(anonymize("<@1> hi", AUTHOR, &[], false), "hi");
+        assert_eq!(anonymize("hi <@!1>", AUTHOR, &[], false), "hi");
+        assert_eq!(anonymize("hi<@1>there", AUTHOR, &[], false), "hithere");
+    }
+
+    #[test]
+    fn other_mentions_are_masked_on_request() {
+        assert_eq!(anonymize("ask <@2>", AUTHOR, &[], false), "ask <@2>");
+        assert_eq!(anonymize("ask <@2>", AUTHOR, &[], true), "ask [someone]");
+        // Roles and broken mentions stay as they are
+        assert_eq!(anonymize("<@&3> <@x>", AUTHOR, &[], true), "<@&3> <@x>");
+    }
+}
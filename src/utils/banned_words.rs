@@ -0,0 +1,246 @@
+// Keeps slurs out of generated output. Matching works on whole words after
+// normalizing case, diacritics, leetspeak and stretched letters, so
+// "N1ggggér" is caught while "Scunthorpe" is left alone. Stretching only
+// ever adds letters, so "Niger" doesn't match "nigger". An entry ending in
+// `*` bans every word starting with it instead.
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::sync::OnceLock;
+
+use tracing::error;
+
+/// Always banned, on top of each server's own list.
+const DEFAULT_BANNED_WORDS: [&str; 9] = [
+    "nigger", "nigga", "faggot", "fag", "retard", "tranny", "kike", "chink", "spic",
+];
+
+pub struct BannedWords {
+    /// Keyed by `Runs::letters`, so a word is only compared to entries
+    /// spelled with the same letters.
+    words: HashMap<String, Vec<Runs>>,
+    /// Entries that ended in `*`.
+    prefixes: Vec<Runs>,
+}
+
+impl BannedWords {
+    /// The default list plus the words in `BANNED_WORDS_FILE` and `custom`
+    /// words.
+    pub fn new(custom: &[String]) -> Self {
+        let mut banned = BannedWords {
+            words: HashMap::new(),
+            prefixes: Vec::new(),
+        };
+
+        let entries = DEFAULT_BANNED_WORDS
+            .iter()
+            .copied()
+            .chain(file_words().iter().map(String::as_str))
+            .chain(custom.iter().map(String::as_str));
+
+        for entry in entries {
+            match entry.strip_suffix('*') {
+                Some(prefix) => banned.prefixes.push(normalize_word(prefix)),
+                None => {
+                    let word = normalize_word(entry);
+                    banned.words.entry(word.letters()).or_default().push(word);
+                }
+            }
+        }
+
+        banned.words.remove("");
+        banned.prefixes.retain(|prefix| !prefix.0.is_empty());
+        banned
+    }
+
+    /// Whether any word of `text` is banned.
+    pub fn matches(&self, text: &str) -> bool {
+        text.split_whitespace().map(normalize_word).any(|word| {
+            let whole = self.words.get(&word.letters()).is_some_and(|entries| {
+                entries
+                    .iter()
+                    .any(|entry| entry.0.len() == word.0.len() && word.starts_with(entry))
+            });
+
+            whole || self.prefixes.iter().any(|prefix| word.starts_with(prefix))
+        })
+    }
+}
+
+/// A normalized word as runs of the same letter, "nigger" being `n i g×2 e r`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Runs(Vec<(char, usize)>);
+
+impl Runs {
+    /// The word with every run collapsed to one letter.
+    fn letters(&self) -> String {
+        self.0.iter().map(|(c, _)| c).collect()
+    }
+
+    /// Whether the word starts with `prefix`'s letters, each repeated at
+    /// least as often.
+    fn starts_with(&self, prefix: &Runs) -> bool {
+        prefix.0.len() <= self.0.len()
+            && self
+                .0
+                .iter()
+                .zip(&prefix.0)
+                .all(|((c, count), (prefix_c, prefix_count))| {
+                    c == prefix_c && count >= prefix_count
+                })
+    }
+}
+
+/// Entries of the file at `BANNED_WORDS_FILE`, one per line, read once.
+/// Blank lines and lines starting with `#` are skipped.
+fn file_words() -> &'static [String] {
+    static FILE_WORDS: OnceLock<Vec<String>> = OnceLock::new();
+
+    FILE_WORDS.get_or_init(|| {
+        let Ok(path) = env::var("BANNED_WORDS_FILE") else {
+            return Vec::new();
+        };
+
+        match fs::read_to_string(&path) {
+            Ok(contents) => contents
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(str::to_string)
+                .collect(),
+            Err(e) => {
+                error!(path, error = %e, "Failed to read banned words");
+                Vec::new()
+            }
+        }
+    })
+}
+
+/// Lowercases `word`, folds diacritics and leetspeak to plain letters, drops
+/// punctuation and groups repeated letters.
+///
+/// Punctuation around the word goes first, so a trailing "!" isn't read
+/// as an "i". `@` and `$` can still start a word, as in "$pic".
+fn normalize_word(word: &str) -> Runs {
+    let word = word
+        .trim_end_matches(|c: char| !c.is_alphanumeric())
+        .trim_start_matches(|c: char| !c.is_alphanumeric() && c != '@' && c != '$');
+
+    let mut runs: Vec<(char, usize)> = Vec::with_capacity(word.len());
+
+    for c in word.chars().flat_map(char::to_lowercase) {
+        let Some(c) = fold_char(c) else {
+            continue;
+        };
+
+        match runs.last_mut() {
+            Some((last, count)) if *last == c => *count += 1,
+            _ => runs.push((c, 1)),
+        }
+    }
+
+    Runs(runs)
+}
+
+fn fold_char(c: char) -> Option<char> {
+    let folded = match c {
+        'á' | 'à' | 'â' | 'ä' | 'ã' | 'å' | '4' | '@' => 'a',
+        'é' | 'è' | 'ê' | 'ë' | '3' => 'e',
+        'í' | 'ì' | 'î' | 'ï' | 'ı' | '1' | '!' => 'i',
+        'ó' | 'ò' | 'ô' | 'ö' | 'õ' | '0' => 'o',
+        'ú' | 'ù' | 'û' | 'ü' => 'u',
+        'ç' => 'c',
+        'ğ' => 'g',
+        'ñ' => 'n',
+        'ş' | '$' | '5' => 's',
+        '7' => 't',
+        c if c.is_alphanumeric() => c,
+        _ => return None,
+    };
+
+    Some(folded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn defaults() -> BannedWords {
+        BannedWords::new(&[])
+    }
+
+    #[test]
+    fn leetspeak_is_caught() {
+        let banned = defaults();
+
+        for text in ["n1gg3r", "r3t@rd", "f4gg0t", "$pic", "n!gger", "tr@nny"] {
+            assert!(banned.matches(text), "{} got through", text);
+        }
+    }
+
+    #[test]
+    fn diacritics_and_case_are_caught() {
+        let banned = defaults();
+
+        for text in ["Nìggér", "RETÄRD", "Fággót", "çhink"] {
+            assert!(banned.matches(text), "{} got through", text);
+        }
+    }
+
+    #[test]
+    fn stretched_letters_are_caught() {
+        let banned = defaults();
+
+        for text in ["niiiggggerrr", "N1ggggér", "reeetaaard", "faaaag"] {
+            assert!(banned.matches(text), "{} got through", text);
+        }
+    }
+
+    #[test]
+    fn surrounding_punctuation_is_ignored() {
+        let banned = defaults();
+
+        for text in [
+            "retard!",
+            "retard?!",
+            "(retard)",
+            "\"retard\",",
+            "...retard",
+            "**retard**",
+        ] {
+            assert!(banned.matches(text), "{} got through", text);
+        }
+        assert!(banned.matches("you are such a retard!"));
+    }
+
+    #[test]
+    fn innocent_words_are_left_alone() {
+        let banned = defaults();
+
+        for text in [
+            "Scunthorpe",
+            "Niger",
+            "Nigeria",
+            "snigger",
+            "spice",
+            "retardant",
+            "fagus",
+            "Spicy food in Scunthorpe!",
+        ] {
+            assert!(!banned.matches(text), "{} was banned", text);
+        }
+    }
+
+    #[test]
+    fn custom_words_and_prefixes() {
+        let banned = BannedWords::new(&["zorp".to_string(), "blorg*".to_string()]);
+
+        assert!(banned.matches("ZORP!"));
+        assert!(!banned.matches("zorps"));
+        assert!(banned.matches("blorgification"));
+        assert!(banned.matches("bl0rrrg"));
+        assert!(!banned.matches("ablorg"));
+        assert!(!banned.matches("blor"));
+    }
+}
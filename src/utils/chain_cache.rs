@@ -0,0 +1,252 @@
+// Trained chains kept in memory, bounded both by how many there are and by
+// roughly how much memory they take. When either limit is hit the chain used
+// least recently goes first.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::utils::markov_chain::CachedChain;
+
+/// Default number of chains kept at once.
+pub const DEFAULT_CACHE_CAPACITY: usize = 64;
+
+/// Default memory budget for all cached chains together, in bytes.
+pub const DEFAULT_CACHE_SIZE_BUDGET: usize = 256 * 1024 * 1024;
+
+/// What a cached chain was trained on. Guild chains stand in for channels
+/// that don't have enough messages for their own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChainKey {
+    Channel(u64),
+    Guild(u64),
+}
+
+struct Entry {
+    cached: CachedChain,
+    /// `approx_size` when the chain was inserted.
+    size: usize,
+    /// Value of the cache's clock when the chain was last used.
+    last_used: AtomicU64,
+}
+
+pub struct ChainCache<K> {
+    entries: HashMap<K, Entry>,
+    capacity: usize,
+    size_budget: usize,
+    /// Ticks on every use, so reads can update recency under a read lock.
+    clock: AtomicU64,
+    /// Held while a chain is trained, so concurrent callers wait for it
+    /// instead of training it again.
+    training: Mutex<HashMap<K, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+impl<K: Hash + Eq + Copy> ChainCache<K> {
+    pub fn new(capacity: usize, size_budget: usize) -> Self {
+        ChainCache {
+            entries: HashMap::new(),
+            capacity: capacity.max(1),
+            size_budget,
+            clock: AtomicU64::new(0),
+            training: Mutex::default(),
+        }
+    }
+
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// The chain cached under `key`, marking it as recently used.
+    pub fn get(&self, key: &K) -> Option<&CachedChain> {
+        let entry = self.entries.get(key)?;
+        entry.last_used.store(self.tick(), Ordering::Relaxed);
+        Some(&entry.cached)
+    }
+
+    /// Like `get`, for chains that learn new messages in place. Growth after
+    /// insertion isn't counted against the budget.
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut CachedChain> {
+        let now = self.tick();
+        let entry = self.entries.get_mut(key)?;
+        *entry.last_used.get_mut() = now;
+        Some(&mut entry.cached)
+    }
+
+    /// The lock to hold while training the chain for `key`.
+    pub fn training_lock(&self, key: K) -> Arc<tokio::sync::Mutex<()>> {
+        self.training
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .entry(key)
+            .or_default()
+            .clone()
+    }
+
+    /// Caches `cached` under `key`, then evicts the least recently used
+    /// chains until the cache is back within its capacity and budget. The
+    /// chain just inserted is never evicted, even when it's over budget on
+    /// its own.
+    pub fn insert(&mut self, key: K, cached: CachedChain) {
+        // Anyone still waiting holds their own handle to the lock
+        self.training
+            .get_mut()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .remove(&key);
+
+        let size = cached.chain.approx_size();
+        let last_used = AtomicU64::new(self.tick());
+
+        self.entries.insert(
+            key,
+            Entry {
+                cached,
+                size,
+                last_used,
+            },
+        );
+
+        let mut total_size: usize = self.entries.values().map(|entry| entry.size).sum();
+        while self.entries.len() > 1
+            && (self.entries.len() > self.capacity || total_size > self.size_budget)
+        {
+            let Some(oldest) = self
+                .entries
+                .iter()
+                .filter(|(k, _)| **k != key)
+                .min_by_key(|(_, entry)| entry.last_used.load(Ordering::Relaxed))
+                .map(|(k, _)| *k)
+            else {
+                break;
+            };
+
+            if let Some(evicted) = self.entries.remove(&oldest) {
+                total_size -= evicted.size;
+            }
+        }
+    }
+
+    /// Drops every chain whose key fails `keep`, and returns how many went.
+    pub fn retain(&mut self, mut keep: impl FnMut(&K) -> bool) -> usize {
+        let before = self.entries.len();
+        self.entries.retain(|key, _| keep(key));
+        self.training
+            .get_mut()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .retain(|key, _| keep(key));
+        before - self.entries.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &CachedChain)> {
+        self.entries.iter().map(|(key, entry)| (key, &entry.cached))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::utils::markov_chain::Chain;
+
+    fn cached(words: usize) -> CachedChain {
+        let mut chain = Chain::new(1);
+        let sentence: Vec<String> = (0..words).map(|i| format!("word{i}")).collect();
+        chain.train(vec![sentence.join(" ")]);
+        CachedChain {
+            chain,
+            trained_at: Instant::now(),
+        }
+    }
+
+    fn total_size(cache: &ChainCache<u64>) -> usize {
+        cache.entries.values().map(|entry| entry.size).sum()
+    }
+
+    #[test]
+    fn stays_within_capacity() {
+        let mut cache = ChainCache::new(3, usize::MAX);
+        for key in 0..10 {
+            cache.insert(key, cached(5));
+            assert!(cache.entries.len() <= 3);
+            assert!(cache.get(&key).is_some());
+        }
+    }
+
+    #[test]
+    fn stays_within_size_budget() {
+        let size = cached(20).chain.approx_size();
+        let mut cache = ChainCache::new(100, size * 3);
+        for key in 0..10 {
+            cache.insert(key, cached(20));
+            assert!(total_size(&cache) <= size * 3);
+            assert!(cache.get(&key).is_some());
+        }
+        assert_eq!(cache.entries.len(), 3);
+    }
+
+    #[test]
+    fn least_recently_used_goes_first() {
+        let mut cache = ChainCache::new(2, usize::MAX);
+        cache.insert(1, cached(5));
+        cache.insert(2, cached(5));
+        cache.get(&1);
+        cache.insert(3, cached(5));
+
+        assert!(cache.get(&1).is_some());
+        assert!(cache.get(&2).is_none());
+        assert!(cache.get(&3).is_some());
+    }
+
+    #[test]
+    fn oversized_insert_survives_alone() {
+        let mut cache = ChainCache::new(10, 1);
+        cache.insert(1, cached(5));
+        cache.insert(2, cached(50));
+
+        assert_eq!(cache.entries.len(), 1);
+        assert!(cache.get(&2).is_some());
+    }
+
+    #[test]
+    fn training_lock_is_shared_per_key() {
+        let mut cache = ChainCache::new(10, usize::MAX);
+        let first = cache.training_lock(1);
+        assert!(Arc::ptr_eq(&first, &cache.training_lock(1)));
+        assert!(!Arc::ptr_eq(&first, &cache.training_lock(2)));
+
+        // Training finished, so the next one gets a fresh lock
+        cache.insert(1, cached(5));
+        assert!(!Arc::ptr_eq(&first, &cache.training_lock(1)));
+    }
+
+    #[test]
+    fn retain_drops_chains_and_their_training_locks() {
+        let mut cache = ChainCache::new(10, usize::MAX);
+        for key in [
+            ChainKey::Channel(1),
+            ChainKey::Channel(2),
+            ChainKey::Guild(1),
+        ] {
+            cache.insert(key, cached(5));
+        }
+        let waiting = cache.training_lock(ChainKey::Channel(3));
+
+        // Everything learned from guild 1, which owns channels 1 and 3
+        let forgotten = cache.retain(|key| {
+            !matches!(
+                key,
+                ChainKey::Guild(1) | ChainKey::Channel(1) | ChainKey::Channel(3)
+            )
+        });
+
+        assert_eq!(forgotten, 2);
+        assert!(cache.get(&ChainKey::Channel(1)).is_none());
+        assert!(cache.get(&ChainKey::Guild(1)).is_none());
+        assert!(cache.get(&ChainKey::Channel(2)).is_some());
+        assert!(!Arc::ptr_eq(
+            &waiting,
+            &cache.training_lock(ChainKey::Channel(3))
+        ));
+    }
+}
@@ -0,0 +1,165 @@
+/// Removes fenced code blocks, inline code spans and `>` quoted lines.
+///
+/// Used before counting words and training chains so pasted code and quoted
+/// replies don't leak into either. The stored message content is left alone.
+pub fn strip_code_and_quotes(content: &str) -> String {
+    let without_fences = strip_delimited(content, "```");
+
+    let mut lines = Vec::new();
+    for line in without_fences.lines() {
+        let trimmed = line.trim_start();
+
+        // `>>>` quotes everything that follows it
+        if trimmed.starts_with(">>>") {
+            break;
+        }
+
+        if trimmed.starts_with('>') {
+            continue;
+        }
+
+        lines.push(strip_delimited(&strip_delimited(line, "``"), "`"));
+    }
+
+    lines.join("\n")
+}
+
+/// Drops every `delimiter ... delimiter` span, including the delimiters.
+///
+/// An unterminated span is not rendered as code by Discord either, so only
+/// the stray delimiter is dropped and the text after it is kept.
+fn strip_delimited(content: &str, delimiter: &str) -> String {
+    let mut result = String::with_capacity(content.len());
+    let mut rest = content;
+
+    while let Some(start) = rest.find(delimiter) {
+        result.push_str(&rest[..start]);
+        let after_open = &rest[start + delimiter.len()..];
+
+        match after_open.find(delimiter) {
+            Some(end) => rest = &after_open[end + delimiter.len()..],
+            None => {
+                rest = after_open;
+                break;
+            }
+        }
+    }
+
+    result.push_str(rest);
+    result
+}
+
+/// Shortens `text` to at most `limit` characters, cutting at a word boundary
+/// and ending with an ellipsis when anything was removed.
+pub fn truncate_at_word_boundary(text: &str, limit: usize) -> String {
+    if text.chars().count() <= limit {
+        return text.to_string();
+    }
+
+    // Leave room for the ellipsis
+    let budget = limit.saturating_sub(1);
+    let end = text
+        .char_indices()
+        .nth(budget)
+        .map_or(text.len(), |(i, _)| i);
+    let cut = &text[..end];
+
+    // Back up to the last whitespace, unless the text is one giant word
+    let cut = match cut.rfind(char::is_whitespace) {
+        Some(i) if i > 0 => &cut[..i],
+        _ => cut,
+    };
+
+    format!("{}…", cut.trim_end())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fenced_blocks_are_removed() {
+        assert_eq!(
+            strip_code_and_quotes("before\n```rust\nfn main() {}\n```\nafter"),
+            "before\n\nafter"
+        );
+        assert_eq!(strip_code_and_quotes("a ```x``` b ```y``` c"), "a  b  c");
+    }
+
+    #[test]
+    fn unterminated_fences_keep_the_text() {
+        assert_eq!(strip_code_and_quotes("look ```at this"), "look at this");
+        assert_eq!(strip_code_and_quotes("a `b"), "a b");
+    }
+
+    #[test]
+    fn inline_code_is_removed() {
+        assert_eq!(strip_code_and_quotes("run `cargo test` now"), "run  now");
+        assert_eq!(strip_code_and_quotes("a ``code with ` inside`` b"), "a  b");
+        assert_eq!(strip_code_and_quotes("`one` and `two`"), " and ");
+    }
+
+    #[test]
+    fn quoted_lines_are_removed() {
+        assert_eq!(
+            strip_code_and_quotes("> quoted\nmine\n  > indented quote\nalso mine"),
+            "mine\nalso mine"
+        );
+        // `>>>` quotes the rest of the message
+        assert_eq!(
+            strip_code_and_quotes("mine\n>>> quoted\nstill quoted"),
+            "mine"
+        );
+        // Only at the start of a line
+        assert_eq!(strip_code_and_quotes("a > b"), "a > b");
+    }
+
+    #[test]
+    fn mixed_content_keeps_only_prose() {
+        let content = "> what does this do\nit prints `x`:\n```\nprint(x)\n```\nthat's all";
+        assert_eq!(strip_code_and_quotes(content), "it prints :\n\nthat's all");
+    }
+
+    #[test]
+    fn text_at_the_limit_is_left_alone() {
+        let text = "word ".repeat(400);
+        let text = text.trim_end();
+        assert_eq!(text.chars().count(), 1999);
+
+        let exact = format!("{}x", text);
+        assert_eq!(exact.chars().count(), 2000);
+        assert_eq!(truncate_at_word_boundary(&exact, 2000), exact);
+    }
+
+    #[test]
+    fn one_over_the_limit_cuts_at_a_word() {
+        let text = format!("{} finale", "word ".repeat(399).trim_end());
+        assert_eq!(text.chars().count(), 2001);
+
+        let truncated = truncate_at_word_boundary(&text, 2000);
+        assert!(truncated.chars().count() <= 2000);
+        assert!(truncated.ends_with("word…"));
+        assert_eq!(truncated, format!("{}…", "word ".repeat(399).trim_end()));
+    }
+
+    #[test]
+    fn one_giant_word_is_cut_mid_word() {
+        let text = "a".repeat(2001);
+
+        let truncated = truncate_at_word_boundary(&text, 2000);
+        assert_eq!(truncated, format!("{}…", "a".repeat(1999)));
+        assert_eq!(truncated.chars().count(), 2000);
+    }
+
+    #[test]
+    fn multi_byte_text_is_cut_on_a_character() {
+        // The cut lands right after a two byte `ş` and inside a run of them
+        let text = "ş".repeat(2001);
+        let truncated = truncate_at_word_boundary(&text, 2000);
+        assert_eq!(truncated, format!("{}…", "ş".repeat(1999)));
+
+        let text = format!("{} 🎉🎉🎉", "ğ".repeat(1997));
+        let truncated = truncate_at_word_boundary(&text, 2000);
+        assert_eq!(truncated, format!("{}…", "ğ".repeat(1997)));
+    }
+}
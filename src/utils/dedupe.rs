@@ -0,0 +1,97 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+/// How many `(channel, author)` pairs are remembered before the least
+/// recently seen half is forgotten.
+const MAX_TRACKED_AUTHORS: usize = 10_000;
+
+/// Remembers the last message each author sent in each channel, to catch
+/// people sending the same thing over and over.
+#[derive(Default)]
+pub struct RecentMessages {
+    /// `(channel_id, author_id)` -> (content hash, when it was last seen)
+    last_seen: HashMap<(u64, u64), (u64, u64)>,
+    tick: u64,
+}
+
+impl RecentMessages {
+    /// Records `content` as the author's latest message in the channel and
+    /// returns whether it repeats the previous one. Differences in
+    /// whitespace don't count.
+    pub fn is_repeat(&mut self, channel_id: u64, author_id: u64, content: &str) -> bool {
+        self.tick += 1;
+
+        let hash = content_hash(content);
+        let previous = self
+            .last_seen
+            .insert((channel_id, author_id), (hash, self.tick));
+
+        if self.last_seen.len() > MAX_TRACKED_AUTHORS {
+            self.forget_oldest();
+        }
+
+        previous.is_some_and(|(previous_hash, _)| previous_hash == hash)
+    }
+
+    fn forget_oldest(&mut self) {
+        let mut ticks: Vec<u64> = self.last_seen.values().map(|(_, tick)| *tick).collect();
+        ticks.sort_unstable();
+        let cutoff = ticks[ticks.len() / 2];
+
+        self.last_seen.retain(|_, (_, tick)| *tick > cutoff);
+    }
+}
+
+fn content_hash(content: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for word in content.split_whitespace() {
+        word.hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_author_and_channel_repeat() {
+        let mut recent = RecentMessages::default();
+        assert!(!recent.is_repeat(1, 10, "hello there"));
+        assert!(recent.is_repeat(1, 10, "hello there"));
+        assert!(!recent.is_repeat(1, 10, "something else"));
+        assert!(!recent.is_repeat(1, 10, "hello there"));
+    }
+
+    #[test]
+    fn other_channels_and_authors_are_tracked_separately() {
+        let mut recent = RecentMessages::default();
+        assert!(!recent.is_repeat(1, 10, "hello"));
+        assert!(!recent.is_repeat(2, 10, "hello"));
+        assert!(!recent.is_repeat(1, 20, "hello"));
+
+        // Neither broke the original author's streak
+        assert!(recent.is_repeat(1, 10, "hello"));
+    }
+
+    #[test]
+    fn whitespace_differences_still_repeat() {
+        let mut recent = RecentMessages::default();
+        assert!(!recent.is_repeat(1, 10, "hello there"));
+        assert!(recent.is_repeat(1, 10, "  hello\n\tthere "));
+        assert!(!recent.is_repeat(1, 10, "hellothere"));
+    }
+
+    #[test]
+    fn oldest_authors_are_forgotten() {
+        let mut recent = RecentMessages::default();
+        for author_id in 0..=MAX_TRACKED_AUTHORS as u64 {
+            recent.is_repeat(1, author_id, "hi");
+        }
+
+        assert!(recent.last_seen.len() <= MAX_TRACKED_AUTHORS);
+        assert!(!recent.is_repeat(1, 0, "hi"));
+        assert!(recent.is_repeat(1, MAX_TRACKED_AUTHORS as u64, "hi"));
+    }
+}
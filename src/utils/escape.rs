@@ -0,0 +1,152 @@
+// Helpers for safely interpolating user content into Discord markdown.
+
+use serenity::all::{Cache, UserId};
+
+const ZERO_WIDTH_SPACE: char = '\u{200B}';
+
+/// Makes `content` safe to put inside a ```` ``` ```` code block by breaking
+/// up backtick runs so the fence can't be closed early.
+pub fn escape_codeblock(content: &str) -> String {
+    content.replace('`', &format!("`{}", ZERO_WIDTH_SPACE))
+}
+
+/// Makes `content` safe to put inside single-backtick inline code, where
+/// backslash escapes don't work, by swapping backticks for a lookalike.
+pub fn escape_inline_code(content: &str) -> String {
+    content.replace('`', "ˋ")
+}
+
+/// Backslash-escapes markdown syntax so `content` renders literally.
+pub fn escape_markdown(content: &str) -> String {
+    let mut escaped = String::with_capacity(content.len());
+
+    for c in content.chars() {
+        if matches!(
+            c,
+            '\\' | '*' | '_' | '~' | '`' | '|' | '>' | '#' | '-' | '[' | ']' | '(' | ')'
+        ) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+/// Defuses pings in generated text: `@everyone` and `@here` are broken up,
+/// user mentions become the user's plain `@name` and role mentions a plain
+/// `@role`.
+///
+/// Senders should still pass empty allowed mentions, this keeps the text
+/// itself harmless wherever it ends up, e.g. after "Post publicly".
+pub fn sanitize_output(text: &str, cache: &Cache) -> String {
+    let mut sanitized = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find("<@") {
+        sanitized.push_str(&rest[..start]);
+        rest = &rest[start + 2..];
+
+        let is_role = rest.starts_with('&');
+        let inner = rest
+            .strip_prefix('&')
+            .or_else(|| rest.strip_prefix('!'))
+            .unwrap_or(rest);
+        let digits = inner.len() - inner.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+
+        let id = inner[..digits].parse::<u64>().ok().filter(|id| *id != 0);
+        let Some(id) = id.filter(|_| inner[digits..].starts_with('>')) else {
+            sanitized.push_str("<@");
+            continue;
+        };
+
+        if is_role {
+            sanitized.push_str("@role");
+        } else {
+            match cache.user(UserId::new(id)) {
+                Some(user) => {
+                    sanitized.push('@');
+                    sanitized.push_str(user.display_name());
+                }
+                None => sanitized.push_str("@someone"),
+            }
+        }
+
+        rest = &inner[digits + 1..];
+    }
+    sanitized.push_str(rest);
+
+    sanitized
+        .replace("@everyone", &format!("@{}everyone", ZERO_WIDTH_SPACE))
+        .replace("@here", &format!("@{}here", ZERO_WIDTH_SPACE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ZWSP: &str = "\u{200B}";
+
+    #[test]
+    fn backticks_cant_close_code() {
+        assert_eq!(
+            escape_codeblock("```rust\nfn main() {}\n```"),
+            format!("`{0}`{0}`{0}rust\nfn main() {{}}\n`{0}`{0}`{0}", ZWSP)
+        );
+        assert!(!escape_codeblock("a ``` b").contains("```"));
+        assert_eq!(escape_inline_code("`tick` name"), "ˋtickˋ name");
+    }
+
+    #[test]
+    fn markdown_renders_literally() {
+        assert_eq!(escape_markdown("**bold** _it_"), r"\*\*bold\*\* \_it\_");
+        assert_eq!(escape_markdown("`code` > quote"), r"\`code\` \> quote");
+        assert_eq!(escape_markdown("[link](url)"), r"\[link\]\(url\)");
+        assert_eq!(escape_markdown(r"back\slash"), r"back\\slash");
+        assert_eq!(escape_markdown("plain ünïcode"), "plain ünïcode");
+    }
+
+    #[test]
+    fn names_are_escaped_like_content() {
+        // Display names can hold the same markdown as messages
+        assert_eq!(escape_markdown("*~yörü~*"), r"\*\~yörü\~\*");
+        assert_eq!(escape_markdown("__@everyone__"), r"\_\_@everyone\_\_");
+        assert_eq!(
+            sanitize_output("@everyone", &Cache::new()),
+            format!("@{}everyone", ZWSP)
+        );
+    }
+
+    #[test]
+    fn mass_pings_are_broken_up() {
+        let cache = Cache::new();
+
+        assert_eq!(
+            sanitize_output("hey @everyone and @here", &cache),
+            format!("hey @{0}everyone and @{0}here", ZWSP)
+        );
+    }
+
+    #[test]
+    fn mentions_become_plain_text() {
+        let cache = Cache::new();
+
+        assert_eq!(sanitize_output("hi <@123>!", &cache), "hi @someone!");
+        assert_eq!(sanitize_output("hi <@!123>", &cache), "hi @someone");
+        assert_eq!(sanitize_output("<@&456> team", &cache), "@role team");
+        assert_eq!(
+            sanitize_output("<@1><@&2><@!3>", &cache),
+            "@someone@role@someone"
+        );
+    }
+
+    #[test]
+    fn broken_mentions_are_left_as_text() {
+        let cache = Cache::new();
+
+        assert_eq!(sanitize_output("<@abc>", &cache), "<@abc>");
+        assert_eq!(sanitize_output("<@0>", &cache), "<@0>");
+        assert_eq!(sanitize_output("<@123", &cache), "<@123");
+        assert_eq!(sanitize_output("a <@ b", &cache), "a <@ b");
+    }
+}
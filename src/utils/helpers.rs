@@ -1,81 +1,1591 @@
-use rand::rngs::StdRng;
+use rand::distributions::{Distribution, WeightedIndex};
 use rand::Rng;
-use rand::SeedableRng;
+use std::collections::HashMap;
+use std::env;
+use std::future::Future;
+use std::hash::Hash;
+use std::ops::RangeInclusive;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use serenity::all::{ChannelId, Context, GuildId};
+use serenity::all::{Cache, ChannelId, Context, GuildId, Message, MessageType, Timestamp, UserId};
+use tokio::sync::RwLock;
+use tracing::{error, info, warn};
 
 use crate::database::Database;
+use crate::tasks;
+use crate::utils::banned_words::BannedWords;
+use crate::utils::chain_cache::{ChainCache, ChainKey};
+use crate::utils::content::truncate_at_word_boundary;
+use crate::utils::escape::sanitize_output;
 use crate::utils::markov_chain;
-use crate::MarkovChainGlobal;
+use crate::{
+    AuthorChainsGlobal, BotOwner, ChainDirGlobal, ChimeInCooldownsGlobal, MarkovChainGlobal,
+    NameTriggerCooldownsGlobal, RecentMessagesGlobal, ReplyChainsGlobal, RuntimeFlagsGlobal,
+    StyleModelsGlobal,
+};
 
 const DATABASE_MESSAGE_FETCH_LIMIT: usize = 5000;
 
+/// Milliseconds between the Unix epoch and the first Discord snowflake.
+pub const DISCORD_EPOCH_MS: u64 = 1_420_070_400_000;
+
+/// Discord's limit for plain message content, in characters.
+pub const MESSAGE_CHAR_LIMIT: usize = 2000;
+
+/// Discord's limit for an embed description, in characters.
+pub const EMBED_DESCRIPTION_CHAR_LIMIT: usize = 4096;
+
+/// Default memory budget for a single trained chain, in bytes.
+const DEFAULT_CHAIN_SIZE_BUDGET: usize = 8 * 1024 * 1024;
+
+/// Cached chains stop learning from new messages past this many
+/// transitions, so a busy channel can't grow one without bound.
+const MAX_INCREMENTAL_TRANSITIONS: usize = 2_000_000;
+
+/// Default time a trained chain is used before it's retrained.
+const DEFAULT_CHAIN_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Highest transition count pruning will go up to when shrinking a chain.
+const MAX_PRUNE_COUNT: usize = 5;
+
+/// How many times generation is retried when the output has a banned word.
+const MAX_GENERATION_ATTEMPTS: usize = 5;
+
+/// How many times a generation is re-rolled while it copies a trained
+/// message word for word.
+const MAX_NOVELTY_ATTEMPTS: usize = 5;
+
+/// How many times an identical message is trained on.
+const MAX_DUPLICATE_SENTENCES: usize = 3;
+
+/// How many eligible messages a channel needs before a chain is trained.
+pub const MIN_MARKOV_MESSAGES: u64 = 500;
+
+/// How many training messages a chain needs before it uses two words of
+/// context instead of one.
+const MIN_ORDER_TWO_MESSAGES: usize = 2000;
+
+/// How many eligible messages a user needs before they can be impersonated.
+pub const MIN_AUTHOR_MARKOV_MESSAGES: u64 = 200;
+
+/// How many of the busiest channels are checked for autonomous posts.
+const AUTOPOST_CHANNEL_LOOKUP: i64 = 10;
+
+/// How many eligible channels autonomous posts rotate between.
+const AUTOPOST_CANDIDATES: usize = 3;
+
+/// Shortest time between replies to the bot's name in a channel.
+const NAME_TRIGGER_COOLDOWN: Duration = Duration::from_secs(30);
+
+/// Shortest time between unprompted replies in a channel.
+const CHIME_IN_COOLDOWN: Duration = Duration::from_secs(10 * 60);
+
+/// Shortest message content worth storing, in characters.
+const MIN_STORED_LENGTH: usize = 2;
+
+/// Shortest word of a message an unprompted reply may start from.
+const CHIME_IN_SEED_LENGTH: usize = 4;
+
+/// Messages starting with one of these are bot commands or links, and are
+/// left out of chain training.
+const MARKOV_IGNORED_PREFIXES: [&str; 16] = [
+    "$", "&", "!", ".", "m.", ">", "<", "[", "]", "@", "#", "^", "*", ",", "https", "http",
+];
+
+/// The result of running something against a channel's chain.
+pub enum MarkovOutcome<T> {
+    Generated(T),
+    /// The channel has `have` eligible messages but a chain needs `need`.
+    NotEnoughMessages {
+        have: u64,
+        need: u64,
+    },
+    /// Every attempt contained a banned word.
+    Filtered,
+    /// Something went wrong, the details are logged.
+    Error,
+}
+
+impl<T> MarkovOutcome<T> {
+    pub fn generated(self) -> Option<T> {
+        match self {
+            MarkovOutcome::Generated(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+impl MarkovOutcome<String> {
+    /// The generated message, or an explanation of why there isn't one.
+    pub fn into_content(self) -> String {
+        match self {
+            MarkovOutcome::Generated(message) => message,
+            MarkovOutcome::NotEnoughMessages { have, need } => format!(
+                "This server has **{}** messages I can learn from, **{}** more are needed. \
+                Run `/collect` to import older messages.",
+                have,
+                need.saturating_sub(have)
+            ),
+            MarkovOutcome::Filtered => {
+                "I couldn't come up with anything appropriate, try again.".to_string()
+            }
+            MarkovOutcome::Error => {
+                "Something went wrong while generating a message, please try again later."
+                    .to_string()
+            }
+        }
+    }
+}
+
+/// Fewest words generated output aims for when no minimum is asked for.
+pub const DEFAULT_MIN_WORDS: usize = 3;
+
+/// Most words generated output may have when no maximum is asked for.
+const DEFAULT_MAX_WORDS: usize = 16;
+
+/// Highest `min_words` or `max_words` that can be asked for.
+pub const MAX_WORDS_LIMIT: usize = 50;
+
+/// How `generate_markov_message` generates.
+#[derive(Debug, Clone, Copy)]
+pub struct GenerationOptions<'a> {
+    /// A word or phrase the output starts with.
+    pub seed: Option<&'a str>,
+    /// Retrain the chain even when the cached one is still fresh.
+    pub refresh: bool,
+    /// Below 1.0 favors common transitions, above 1.0 evens them out.
+    pub temperature: f32,
+    /// Fewest words the output should have, `DEFAULT_MIN_WORDS` if unset.
+    pub min_words: Option<usize>,
+    /// Most words the output may have.
+    pub max_words: Option<usize>,
+}
+
+impl GenerationOptions<'_> {
+    /// The bounds for one generation. The upper one is picked at random
+    /// between the minimum and the maximum, so lengths vary.
+    pub fn word_range(&self) -> RangeInclusive<usize> {
+        let max_words = self
+            .max_words
+            .unwrap_or(DEFAULT_MAX_WORDS.max(self.min_words.unwrap_or(0)));
+        let min_words = self.min_words.unwrap_or(DEFAULT_MIN_WORDS).min(max_words);
+
+        min_words..=rand::thread_rng().gen_range(min_words..=max_words)
+    }
+}
+
+impl Default for GenerationOptions<'_> {
+    fn default() -> Self {
+        GenerationOptions {
+            seed: None,
+            refresh: false,
+            temperature: markov_chain::DEFAULT_TEMPERATURE,
+            min_words: None,
+            max_words: None,
+        }
+    }
+}
+
 pub async fn generate_markov_message(
     ctx: &Context,
     guild_id: GuildId,
     channel_id: ChannelId,
-    custom_word: Option<&str>,
     database: Arc<Database>,
+    char_limit: usize,
+    options: GenerationOptions<'_>,
+) -> MarkovOutcome<String> {
+    let banned_words = match database.get_guild_settings(guild_id.get()).await {
+        Ok(settings) => BannedWords::new(&settings.banned_words),
+        Err(e) => {
+            error!(guild_id = guild_id.get(), error = %e, "Failed to fetch guild settings");
+            return MarkovOutcome::Error;
+        }
+    };
+
+    let outcome = with_markov_chain(
+        ctx,
+        guild_id,
+        channel_id,
+        database,
+        options.refresh,
+        |chain| generate_clean(chain, &options, &banned_words, &ctx.cache, char_limit),
+    )
+    .await;
+
+    into_filtered(outcome)
+}
+
+/// Like `generate_markov_message`, but from `user_id`'s own chain.
+pub async fn generate_author_message(
+    ctx: &Context,
+    guild_id: GuildId,
+    user_id: UserId,
+    database: Arc<Database>,
+    char_limit: usize,
+) -> MarkovOutcome<String> {
+    let banned_words = match database.get_guild_settings(guild_id.get()).await {
+        Ok(settings) => BannedWords::new(&settings.banned_words),
+        Err(e) => {
+            error!(guild_id = guild_id.get(), error = %e, "Failed to fetch guild settings");
+            return MarkovOutcome::Error;
+        }
+    };
+
+    let outcome = with_author_chain(ctx, guild_id, user_id, database, |chain| {
+        generate_clean(
+            chain,
+            &GenerationOptions::default(),
+            &banned_words,
+            &ctx.cache,
+            char_limit,
+        )
+    })
+    .await;
+
+    into_filtered(outcome)
+}
+
+/// Generates up to `MAX_GENERATION_ATTEMPTS` times until the output has no
+/// banned words and fits in `char_limit` characters once its mentions are
+/// defused.
+///
+/// When every clean attempt is too long, the first one is cut at a word
+/// boundary instead.
+fn generate_clean(
+    chain: &markov_chain::Chain,
+    options: &GenerationOptions,
+    banned_words: &BannedWords,
+    cache: &Cache,
+    char_limit: usize,
 ) -> Option<String> {
-    {
-        let data_read = ctx.data.read().await;
-        if let Some(cache_lock) = data_read.get::<MarkovChainGlobal>() {
-            let cache = cache_lock.read().await;
-            if let Some(chain) = cache.get(&channel_id.get()) {
-                let mut rng = rand::thread_rng();
-                let max_words = rng.gen_range(1..15);
-                return Some(chain.generate(max_words, custom_word));
+    let words = options.word_range();
+    let mut too_long = None;
+
+    for _ in 0..MAX_GENERATION_ATTEMPTS {
+        let message = sanitize_output(
+            &chain.generate_novel(
+                words.clone(),
+                options.seed,
+                options.temperature,
+                MAX_NOVELTY_ATTEMPTS,
+                &mut rand::thread_rng(),
+            ),
+            cache,
+        );
+
+        if message.chars().count() <= char_limit {
+            if !banned_words.matches(&message) {
+                return Some(message);
             }
+            continue;
+        }
+
+        let message = truncate_at_word_boundary(&message, char_limit);
+        if too_long.is_none() && !banned_words.matches(&message) {
+            too_long = Some(message);
+        }
+    }
+
+    too_long
+}
+
+/// Turns a generation that gave up on banned words into `Filtered`.
+fn into_filtered(outcome: MarkovOutcome<Option<String>>) -> MarkovOutcome<String> {
+    match outcome {
+        MarkovOutcome::Generated(Some(message)) => MarkovOutcome::Generated(message),
+        MarkovOutcome::Generated(None) | MarkovOutcome::Filtered => MarkovOutcome::Filtered,
+        MarkovOutcome::NotEnoughMessages { have, need } => {
+            MarkovOutcome::NotEnoughMessages { have, need }
         }
+        MarkovOutcome::Error => MarkovOutcome::Error,
     }
+}
+
+/// Whether a cached chain can be used instead of training a new one. With
+/// `refresh` only a chain trained after `requested_at` will do, e.g. by a
+/// concurrent call this one waited for.
+fn is_usable(cached: &markov_chain::CachedChain, refresh: bool, requested_at: Instant) -> bool {
+    cached.trained_at >= requested_at || (!refresh && cached.trained_at.elapsed() < chain_ttl())
+}
+
+/// Runs `f` against the channel's chain, training and caching it first if
+/// needed. A chain older than `MARKOV_CHAIN_TTL_SECONDS` is retrained, and
+/// `refresh` retrains it regardless.
+///
+/// A channel with too few messages for its own chain uses one trained on
+/// the whole guild instead.
+pub async fn with_markov_chain<T>(
+    ctx: &Context,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    database: Arc<Database>,
+    refresh: bool,
+    f: impl FnOnce(&markov_chain::Chain) -> T,
+) -> MarkovOutcome<T> {
+    let cache_lock = ctx.data.read().await.get::<MarkovChainGlobal>().cloned();
+    with_channel_or_guild_chain(cache_lock, guild_id, channel_id, database, refresh, f).await
+}
+
+/// `with_markov_chain` against an explicit cache.
+async fn with_channel_or_guild_chain<T>(
+    cache_lock: Option<Arc<RwLock<ChainCache<ChainKey>>>>,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    database: Arc<Database>,
+    refresh: bool,
+    f: impl FnOnce(&markov_chain::Chain) -> T,
+) -> MarkovOutcome<T> {
+    let channel_key = ChainKey::Channel(channel_id.get());
+
+    let has_channel_chain = match &cache_lock {
+        Some(cache_lock) => cache_lock
+            .read()
+            .await
+            .get(&channel_key)
+            .is_some_and(|cached| is_usable(cached, refresh, Instant::now())),
+        None => false,
+    };
+
+    // Counting is cheap, and unlike the samples below it's exact
+    let key = match has_channel_chain {
+        true => channel_key,
+        false => match database
+            .count_markov_eligible_messages(
+                guild_id.get(),
+                Some(channel_id.get()),
+                &MARKOV_IGNORED_PREFIXES,
+            )
+            .await
+        {
+            Ok(count) if count >= MIN_MARKOV_MESSAGES => channel_key,
+            Ok(_) => ChainKey::Guild(guild_id.get()),
+            Err(e) => {
+                error!(guild_id = guild_id.get(), error = %e, "Failed to count messages for markov chain");
+                return MarkovOutcome::Error;
+            }
+        },
+    };
+
+    let train = async {
+        let (channel, label) = match key {
+            ChainKey::Channel(_) => (Some(channel_id.get()), format!("channel {}", channel_id)),
+            ChainKey::Guild(_) => (None, format!("guild {}", guild_id)),
+        };
+
+        if channel.is_none() {
+            let eligible = match database
+                .count_markov_eligible_messages(guild_id.get(), None, &MARKOV_IGNORED_PREFIXES)
+                .await
+            {
+                Ok(count) => count,
+                Err(e) => {
+                    error!(guild_id = guild_id.get(), error = %e, "Failed to count messages for markov chain");
+                    return MarkovOutcome::Error;
+                }
+            };
+
+            if eligible < MIN_MARKOV_MESSAGES {
+                return MarkovOutcome::NotEnoughMessages {
+                    have: eligible,
+                    need: MIN_MARKOV_MESSAGES,
+                };
+            }
+        }
+
+        let sentences = match channel {
+            Some(channel) => {
+                database
+                    .get_messages_for_markov(
+                        guild_id.get(),
+                        channel,
+                        &MARKOV_IGNORED_PREFIXES,
+                        DATABASE_MESSAGE_FETCH_LIMIT,
+                    )
+                    .await
+            }
+            None => {
+                database
+                    .get_messages_for_markov_guild(
+                        guild_id.get(),
+                        &MARKOV_IGNORED_PREFIXES,
+                        DATABASE_MESSAGE_FETCH_LIMIT,
+                    )
+                    .await
+            }
+        };
+
+        let sentences = match sentences {
+            Ok(sentences) => sentences,
+            Err(e) => {
+                error!(guild_id = guild_id.get(), error = %e, "Failed to fetch messages for markov chain");
+                return MarkovOutcome::Error;
+            }
+        };
+
+        train_chain(guild_id, sentences, database.clone(), label).await
+    };
+
+    with_cached_chain(cache_lock, key, refresh, train, f).await
+}
+
+/// Runs `f` against `user_id`'s chain, trained only on their messages and
+/// cached separately from the channel chains.
+pub async fn with_author_chain<T>(
+    ctx: &Context,
+    guild_id: GuildId,
+    user_id: UserId,
+    database: Arc<Database>,
+    f: impl FnOnce(&markov_chain::Chain) -> T,
+) -> MarkovOutcome<T> {
+    let cache_lock = ctx.data.read().await.get::<AuthorChainsGlobal>().cloned();
 
-    let prefixes = [
-        "$", "&", "!", ".", "m.", ">", "<", "[", "]", "@", "#", "^", "*", ",", "https", "http",
-    ];
+    let train = async {
+        let sentences = match database
+            .get_messages_for_markov_by_author(
+                guild_id.get(),
+                user_id.get(),
+                &MARKOV_IGNORED_PREFIXES,
+                DATABASE_MESSAGE_FETCH_LIMIT,
+            )
+            .await
+        {
+            Ok(sentences) => sentences,
+            Err(e) => {
+                error!(guild_id = guild_id.get(), error = %e, "Failed to fetch messages for markov chain");
+                return MarkovOutcome::Error;
+            }
+        };
+
+        // Fewer messages than this make one-word parrots, not impressions
+        if (sentences.len() as u64) < MIN_AUTHOR_MARKOV_MESSAGES {
+            return MarkovOutcome::NotEnoughMessages {
+                have: sentences.len() as u64,
+                need: MIN_AUTHOR_MARKOV_MESSAGES,
+            };
+        }
 
-    let sentences = match database
-        .get_messages_for_markov(
-            guild_id.get(),
-            channel_id.get(),
-            &prefixes,
-            DATABASE_MESSAGE_FETCH_LIMIT,
+        train_chain(
+            guild_id,
+            sentences,
+            database.clone(),
+            format!("user {}", user_id),
         )
         .await
+    };
+
+    with_cached_chain(cache_lock, (guild_id.get(), user_id.get()), false, train, f).await
+}
+
+/// Runs `f` against the chain cached under `key`, or trains one with
+/// `train` and caches it.
+///
+/// Only one call trains a given chain at a time, concurrent calls wait for
+/// it and use the result.
+async fn with_cached_chain<K: Hash + Eq + Copy, T>(
+    cache_lock: Option<Arc<RwLock<ChainCache<K>>>>,
+    key: K,
+    refresh: bool,
+    train: impl Future<Output = MarkovOutcome<markov_chain::Chain>>,
+    f: impl FnOnce(&markov_chain::Chain) -> T,
+) -> MarkovOutcome<T> {
+    let requested_at = Instant::now();
+
+    let training_lock = match &cache_lock {
+        Some(cache_lock) => {
+            let cache = cache_lock.read().await;
+            if let Some(cached) = cache.get(&key) {
+                if is_usable(cached, refresh, requested_at) {
+                    return MarkovOutcome::Generated(f(&cached.chain));
+                }
+            }
+            Some(cache.training_lock(key))
+        }
+        None => None,
+    };
+
+    let _training = match &training_lock {
+        Some(training_lock) => Some(training_lock.lock().await),
+        None => None,
+    };
+
+    // Someone else may have trained it while we waited
+    if let Some(cache_lock) = &cache_lock {
+        let cache = cache_lock.read().await;
+        if let Some(cached) = cache.get(&key) {
+            if is_usable(cached, refresh, requested_at) {
+                return MarkovOutcome::Generated(f(&cached.chain));
+            }
+        }
+    }
+
+    let markov_chain = match train.await {
+        MarkovOutcome::Generated(markov_chain) => markov_chain,
+        MarkovOutcome::NotEnoughMessages { have, need } => {
+            return MarkovOutcome::NotEnoughMessages { have, need }
+        }
+        MarkovOutcome::Filtered => return MarkovOutcome::Filtered,
+        MarkovOutcome::Error => return MarkovOutcome::Error,
+    };
+
+    let result = f(&markov_chain);
+
+    if let Some(cache_lock) = &cache_lock {
+        cache_lock.write().await.insert(
+            key,
+            markov_chain::CachedChain {
+                chain: markov_chain,
+                trained_at: Instant::now(),
+            },
+        );
+    }
+
+    MarkovOutcome::Generated(result)
+}
+
+/// Trains a chain on `sentences`, leaving out ones with banned words when the
+/// guild asks for it. `label` names the chain in logs.
+async fn train_chain(
+    guild_id: GuildId,
+    sentences: Vec<String>,
+    database: Arc<Database>,
+    label: String,
+) -> MarkovOutcome<markov_chain::Chain> {
+    let banned_words = match database.get_guild_settings(guild_id.get()).await {
+        Ok(settings) if settings.filter_banned_training => {
+            Some(BannedWords::new(&settings.banned_words))
+        }
+        Ok(_) => None,
+        Err(e) => {
+            error!(guild_id = guild_id.get(), error = %e, "Failed to fetch guild settings");
+            None
+        }
+    };
+
+    // Filtering and training are pure CPU work, keep them off the async
+    // workers so events don't stall while a cold chain is trained
+    match tokio::task::spawn_blocking(move || {
+        let sentences: Vec<String> = match banned_words {
+            Some(banned_words) => sentences
+                .into_iter()
+                .filter(|sentence| !banned_words.matches(sentence))
+                .collect(),
+            None => sentences,
+        };
+
+        // Two words of context read better, but need a corpus that repeats
+        // word pairs often enough to ever branch
+        let order = match sentences.len() >= MIN_ORDER_TWO_MESSAGES {
+            true => 2,
+            false => 1,
+        };
+
+        let mut markov_chain = markov_chain::Chain::new(order)
+            .keep_unicode_emoji(keep_emoji())
+            .with_polish(true)
+            .with_max_duplicates(MAX_DUPLICATE_SENTENCES);
+
+        let dropped = markov_chain.train(sentences);
+        if dropped > 0 {
+            info!(chain = %label, dropped, "Dropped duplicate sentences");
+        }
+
+        prune_to_budget(&mut markov_chain, &label);
+        markov_chain
+    })
+    .await
+    {
+        Ok(markov_chain) => MarkovOutcome::Generated(markov_chain),
+        Err(e) => {
+            error!(guild_id = guild_id.get(), error = %e, "Failed to train markov chain");
+            MarkovOutcome::Error
+        }
+    }
+}
+
+/// Whether `content` starts like a bot command or a link.
+pub fn has_ignored_prefix(content: &str) -> bool {
+    MARKOV_IGNORED_PREFIXES
+        .iter()
+        .any(|prefix| content.starts_with(prefix))
+}
+
+/// Feeds a new message into the channel's and the guild's cached chains,
+/// so they pick up new vocabulary without being retrained.
+pub async fn feed_markov_chain(
+    ctx: &Context,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    content: &str,
+    database: &Database,
+) {
+    // Same filters as `get_messages_for_markov`
+    if content.chars().count() <= 10 || has_ignored_prefix(content) {
+        return;
+    }
+
+    match database.get_guild_settings(guild_id.get()).await {
+        Ok(settings) if settings.filter_banned_training => {
+            if BannedWords::new(&settings.banned_words).matches(content) {
+                return;
+            }
+        }
+        Ok(_) => (),
+        Err(e) => {
+            error!(guild_id = guild_id.get(), error = %e, "Failed to fetch guild settings");
+            return;
+        }
+    }
+
+    let data_read = ctx.data.read().await;
+    if let Some(cache_lock) = data_read.get::<MarkovChainGlobal>() {
+        let mut cache = cache_lock.write().await;
+        for key in [
+            ChainKey::Channel(channel_id.get()),
+            ChainKey::Guild(guild_id.get()),
+        ] {
+            if let Some(cached) = cache.get_mut(&key) {
+                if cached.chain.transitions() < MAX_INCREMENTAL_TRANSITIONS {
+                    cached.chain.train_one(content);
+                }
+            }
+        }
+    }
+}
+
+/// How long a trained chain is used before it's retrained with newer
+/// messages, from `MARKOV_CHAIN_TTL_SECONDS`.
+pub fn chain_ttl() -> Duration {
+    env::var("MARKOV_CHAIN_TTL_SECONDS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .map_or(DEFAULT_CHAIN_TTL, Duration::from_secs)
+}
+
+/// Whether chains keep unicode emoji, from `MARKOV_KEEP_EMOJI`. Custom
+/// emoji markup is always dropped.
+fn keep_emoji() -> bool {
+    env::var("MARKOV_KEEP_EMOJI").map_or(true, |value| value != "false")
+}
+
+/// Prunes rare transitions until the chain fits `MARKOV_CHAIN_SIZE_BUDGET`
+/// bytes, or until pruning gets too aggressive to keep output varied.
+fn prune_to_budget(chain: &mut markov_chain::Chain, label: &str) {
+    let budget = env::var("MARKOV_CHAIN_SIZE_BUDGET")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_CHAIN_SIZE_BUDGET);
+
+    let original_size = chain.approx_size();
+    if original_size <= budget {
+        return;
+    }
+
+    for min_count in 2..=MAX_PRUNE_COUNT {
+        chain.prune(min_count);
+        if chain.approx_size() <= budget {
+            break;
+        }
+    }
+
+    info!(
+        chain = %label,
+        from_bytes = original_size,
+        to_bytes = chain.approx_size(),
+        "Pruned chain"
+    );
+}
+
+/// Picks the channel an autonomous post goes to, or `None` if no channel
+/// has enough messages for a chain.
+///
+/// The choice is random among the busiest eligible channels, weighted by
+/// message count, and avoids `last_channel_id` when there's an alternative.
+pub async fn pick_autopost_channel(
+    guild_id: GuildId,
+    database: Arc<Database>,
+    last_channel_id: Option<u64>,
+) -> Option<u64> {
+    let top_channels = match database
+        .get_top_channels(guild_id.get(), AUTOPOST_CHANNEL_LOOKUP)
+        .await
     {
-        Ok(sentences) => sentences,
+        Ok(channels) => channels,
         Err(e) => {
-            eprintln!("Failed to fetch messages for markov chain: {}", e);
+            error!(guild_id = guild_id.get(), error = %e, "Failed to get top channels");
             return None;
         }
     };
 
-    if sentences.len() < 500 {
+    let mut candidates = Vec::new();
+    for (channel_id, count) in top_channels {
+        if candidates.len() >= AUTOPOST_CANDIDATES {
+            break;
+        }
+
+        match database
+            .count_markov_eligible_messages(
+                guild_id.get(),
+                Some(channel_id),
+                &MARKOV_IGNORED_PREFIXES,
+            )
+            .await
+        {
+            Ok(eligible) if eligible >= MIN_MARKOV_MESSAGES => candidates.push((channel_id, count)),
+            Ok(_) => (),
+            Err(e) => warn!(
+                guild_id = guild_id.get(),
+                channel_id,
+                error = %e,
+                "Failed to count messages in channel"
+            ),
+        }
+    }
+
+    if candidates.len() > 1 {
+        candidates.retain(|(channel_id, _)| Some(*channel_id) != last_channel_id);
+    }
+
+    let weights = WeightedIndex::new(candidates.iter().map(|(_, count)| (*count).max(1))).ok()?;
+    let (channel_id, count) = candidates[weights.sample(&mut rand::thread_rng())];
+
+    info!(
+        guild_id = guild_id.get(),
+        channel_id,
+        messages = count,
+        candidates = candidates.len(),
+        "Picked autopost channel"
+    );
+
+    Some(channel_id)
+}
+
+pub async fn posting_paused(ctx: &Context) -> bool {
+    let data_read = ctx.data.read().await;
+    data_read
+        .get::<RuntimeFlagsGlobal>()
+        .is_some_and(|flags| flags.posting_paused.load(Ordering::Relaxed))
+}
+
+pub async fn logging_paused(ctx: &Context) -> bool {
+    let data_read = ctx.data.read().await;
+    data_read
+        .get::<RuntimeFlagsGlobal>()
+        .is_some_and(|flags| flags.logging_paused.load(Ordering::Relaxed))
+}
+
+/// Whether `msg` repeats its author's previous message in the channel.
+pub async fn is_repeated_message(ctx: &Context, msg: &Message) -> bool {
+    let data_read = ctx.data.read().await;
+    data_read
+        .get::<RecentMessagesGlobal>()
+        .is_some_and(|recent| {
+            recent.lock().unwrap().is_repeat(
+                msg.channel_id.get(),
+                msg.author.id.get(),
+                &msg.content,
+            )
+        })
+}
+
+/// Whether `content` says one of `names` as whole words, ignoring case.
+pub fn says_name<'a>(content: &str, names: impl IntoIterator<Item = &'a str>) -> bool {
+    let words = name_words(content);
+
+    names.into_iter().any(|name| {
+        let name = name_words(name);
+        !name.is_empty() && words.windows(name.len()).any(|window| window == name)
+    })
+}
+
+fn name_words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Starts the name trigger cooldown in `channel_id`, returning `false` if
+/// it's still running from the last reply.
+pub async fn take_name_trigger(ctx: &Context, channel_id: ChannelId) -> bool {
+    let data_read = ctx.data.read().await;
+    data_read
+        .get::<NameTriggerCooldownsGlobal>()
+        .is_none_or(|cooldowns| take_cooldown(cooldowns, channel_id, NAME_TRIGGER_COOLDOWN))
+}
+
+/// Starts the unprompted reply cooldown in `channel_id`, returning `false`
+/// if it's still running from the last one.
+pub async fn take_chime_in(ctx: &Context, channel_id: ChannelId) -> bool {
+    let data_read = ctx.data.read().await;
+    data_read
+        .get::<ChimeInCooldownsGlobal>()
+        .is_none_or(|cooldowns| take_cooldown(cooldowns, channel_id, CHIME_IN_COOLDOWN))
+}
+
+fn take_cooldown(
+    cooldowns: &std::sync::Mutex<HashMap<u64, Instant>>,
+    channel_id: ChannelId,
+    cooldown: Duration,
+) -> bool {
+    let mut cooldowns = cooldowns.lock().unwrap();
+    let now = Instant::now();
+    cooldowns.retain(|_, replied| now.duration_since(*replied) < cooldown);
+
+    match cooldowns.contains_key(&channel_id.get()) {
+        true => false,
+        false => {
+            cooldowns.insert(channel_id.get(), now);
+            true
+        }
+    }
+}
+
+/// Generates an unprompted reply to `content`, starting from one of its
+/// words when the chain knows any.
+pub async fn chime_in_reply(
+    ctx: &Context,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    content: &str,
+    database: Arc<Database>,
+) -> Option<String> {
+    let seed = {
+        let words: Vec<&str> = content
+            .split_whitespace()
+            .filter(|word| {
+                word.chars().count() >= CHIME_IN_SEED_LENGTH
+                    && word.chars().all(char::is_alphabetic)
+            })
+            .collect();
+        words
+            .get(rand::thread_rng().gen_range(0..words.len().max(1)))
+            .copied()
+    };
+
+    if let Some(seed) = seed {
+        let options = GenerationOptions {
+            seed: Some(seed),
+            ..GenerationOptions::default()
+        };
+        if let MarkovOutcome::Generated(reply) = generate_markov_message(
+            ctx,
+            guild_id,
+            channel_id,
+            database.clone(),
+            MESSAGE_CHAR_LIMIT,
+            options,
+        )
+        .await
+        {
+            return Some(reply);
+        }
+    }
+
+    generate_markov_message(
+        ctx,
+        guild_id,
+        channel_id,
+        database,
+        MESSAGE_CHAR_LIMIT,
+        GenerationOptions::default(),
+    )
+    .await
+    .generated()
+}
+
+/// Counts an answer to a reply to the bot's `message_id`, returning the root
+/// of its thread of replies, or `None` once the thread went on long enough.
+pub async fn continue_reply_chain(ctx: &Context, message_id: u64) -> Option<u64> {
+    let data_read = ctx.data.read().await;
+    match data_read.get::<ReplyChainsGlobal>() {
+        Some(chains) => chains.lock().unwrap().continue_from(message_id),
+        None => Some(message_id),
+    }
+}
+
+/// Remembers the bot's answer `message_id` as part of `root`'s thread.
+pub async fn record_reply_chain(ctx: &Context, root: u64, message_id: u64) {
+    let data_read = ctx.data.read().await;
+    if let Some(chains) = data_read.get::<ReplyChainsGlobal>() {
+        chains.lock().unwrap().record(root, message_id);
+    }
+}
+
+pub async fn is_bot_owner(ctx: &Context, user_id: UserId) -> bool {
+    let data_read = ctx.data.read().await;
+    matches!(data_read.get::<BotOwner>(), Some(Some(owner)) if *owner == user_id)
+}
+
+/// Whether `msg` has text worth storing. Bot and webhook posts, attachment
+/// and sticker only posts, join, boost and pin notices, and commands for
+/// other bots are dropped outright rather than counted anywhere.
+pub fn is_storable(msg: &Message, command_prefixes: &[String]) -> bool {
+    let content = msg.content.trim();
+
+    !msg.author.bot
+        && msg.webhook_id.is_none()
+        && matches!(msg.kind, MessageType::Regular | MessageType::InlineReply)
+        && content.chars().count() >= MIN_STORED_LENGTH
+        && !command_prefixes
+            .iter()
+            .any(|prefix| content.starts_with(prefix.as_str()))
+}
+
+/// The id of the message `msg` replied to, ignoring other reference kinds
+/// such as crossposts and pins.
+pub fn replied_to_message_id(msg: &Message) -> Option<u64> {
+    if msg.kind != MessageType::InlineReply {
         return None;
     }
 
-    let mut markov_chain = markov_chain::Chain::new();
-    markov_chain.train(sentences);
+    msg.message_reference
+        .as_ref()
+        .and_then(|reference| reference.message_id)
+        .map(|id| id.get())
+}
 
-    {
+/// The smallest snowflake id created `days` days ago, usable as a lower bound
+/// on message ids when filtering by age.
+pub fn snowflake_days_ago(days: u64) -> u64 {
+    let now_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64);
+    let cutoff_ms = now_ms.saturating_sub(days * 24 * 60 * 60 * 1000);
+
+    cutoff_ms.saturating_sub(DISCORD_EPOCH_MS) << 22
+}
+
+/// Formats a length of time like `1h 5m` or `42s`.
+pub fn format_duration(seconds: i64) -> String {
+    match seconds {
+        s if s >= 3600 => format!("{}h {}m", s / 3600, s % 3600 / 60),
+        s if s >= 60 => format!("{}m {}s", s / 60, s % 60),
+        s => format!("{}s", s.max(0)),
+    }
+}
+
+/// Reads a date as the smallest snowflake id created then. Takes a day like
+/// `2024-05-01`, an RFC 3339 timestamp, or a number of days ago like `30d`.
+pub fn parse_date_snowflake(input: &str) -> Option<u64> {
+    let input = input.trim();
+
+    if let Some(days) = input.strip_suffix('d') {
+        return days.parse().ok().map(snowflake_days_ago);
+    }
+
+    let timestamp = Timestamp::parse(input)
+        .or_else(|_| Timestamp::parse(&format!("{}T00:00:00Z", input)))
+        .ok()?;
+    let ms = u64::try_from(timestamp.unix_timestamp()).ok()? * 1000;
+
+    Some(ms.saturating_sub(DISCORD_EPOCH_MS) << 22)
+}
+
+/// Deletes a guild's stored data along with every chain and style model
+/// trained on it, saved ones included. Returns how many rows were deleted.
+pub async fn purge_guild_data(
+    ctx: &Context,
+    database: &Database,
+    guild_id: GuildId,
+) -> Result<u64, sqlx::Error> {
+    let purge = database.purge_guild(guild_id.get()).await?;
+    forget_chains(ctx, guild_id, &purge.channel_ids, None).await;
+
+    Ok(purge.rows)
+}
+
+/// Deletes a user's stored messages in a guild, and forgets the chains
+/// and style model that learned from them. Returns how many messages
+/// were deleted.
+pub async fn purge_user_data(
+    ctx: &Context,
+    database: &Database,
+    guild_id: GuildId,
+    user_id: UserId,
+) -> Result<u64, sqlx::Error> {
+    let purge = database.purge_user(guild_id.get(), user_id.get()).await?;
+    forget_chains(ctx, guild_id, &purge.channel_ids, Some(user_id)).await;
+
+    Ok(purge.messages)
+}
+
+/// Drops every chain trained on the guild's messages, in memory and on
+/// disk, so they're trained again under the guild's current settings.
+pub async fn forget_guild_chains(
+    ctx: &Context,
+    database: &Database,
+    guild_id: GuildId,
+) -> Result<(), sqlx::Error> {
+    // A channel only gets its own chain once it has messages counted
+    let channel_ids: Vec<u64> = database
+        .get_top_channels(guild_id.get(), i64::MAX)
+        .await?
+        .into_iter()
+        .map(|(channel_id, _)| channel_id)
+        .collect();
+    forget_chains(ctx, guild_id, &channel_ids, None).await;
+
+    Ok(())
+}
+
+/// Drops the chains of `channel_ids` and the guild's fallback chain, in
+/// memory and on disk, along with the guild's style model. Author chains
+/// go for `author`, or for everyone in the guild when it's `None`.
+async fn forget_chains(
+    ctx: &Context,
+    guild_id: GuildId,
+    channel_ids: &[u64],
+    author: Option<UserId>,
+) {
+    let mut keys: Vec<ChainKey> = channel_ids
+        .iter()
+        .map(|channel_id| ChainKey::Channel(*channel_id))
+        .collect();
+    keys.push(ChainKey::Guild(guild_id.get()));
+
+    let (markov_cache, author_cache, style_models, chain_dir) = {
         let data_read = ctx.data.read().await;
-        if let Some(cache_lock) = data_read.get::<MarkovChainGlobal>() {
-            let mut cache = cache_lock.write().await;
-            cache.insert(channel_id.get(), markov_chain.clone());
+        (
+            data_read.get::<MarkovChainGlobal>().cloned(),
+            data_read.get::<AuthorChainsGlobal>().cloned(),
+            data_read.get::<StyleModelsGlobal>().cloned(),
+            data_read.get::<ChainDirGlobal>().cloned(),
+        )
+    };
+
+    if let Some(cache) = markov_cache {
+        cache.write().await.retain(|key| !keys.contains(key));
+    }
+    if let Some(cache) = author_cache {
+        cache.write().await.retain(|(author_guild_id, author_id)| {
+            *author_guild_id != guild_id.get()
+                || author.is_some_and(|author| author.get() != *author_id)
+        });
+    }
+    if let Some(models) = style_models {
+        models.write().await.remove(&guild_id.get());
+    }
+    if let Some(dir) = chain_dir {
+        tasks::chain_store::remove_chains(&dir, &keys);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serenity::all::{MessageId, MessageReference, WebhookId};
+
+    use super::*;
+
+    #[test]
+    fn poisoned_chains_never_get_a_banned_word_out() {
+        let banned_words = BannedWords::new(&["zorp".to_string()]);
+        let cache = Cache::new();
+
+        // Every word is some spelling of the banned one
+        let mut chain = markov_chain::Chain::new(1);
+        chain.train(
+            (0..200)
+                .map(|index| match index % 3 {
+                    0 => "zorp z0rp ZORP!".to_string(),
+                    1 => "zooorp zorp zórp".to_string(),
+                    _ => "z0rp, zorrrp zorp".to_string(),
+                })
+                .collect(),
+        );
+
+        for _ in 0..50 {
+            let message = generate_clean(
+                &chain,
+                &GenerationOptions::default(),
+                &banned_words,
+                &cache,
+                MESSAGE_CHAR_LIMIT,
+            );
+            assert_eq!(message, None);
         }
+        assert!(matches!(
+            into_filtered(MarkovOutcome::Generated(None)),
+            MarkovOutcome::Filtered
+        ));
     }
 
-    let mut rng = StdRng::from_entropy();
-    let max_words = rng.gen_range(1..15);
-    Some(markov_chain.generate(max_words, custom_word))
-}
+    #[test]
+    fn mostly_poisoned_chains_only_let_clean_text_out() {
+        let banned_words = BannedWords::new(&["zorp".to_string()]);
+        let cache = Cache::new();
 
-pub async fn get_most_popular_channel(guild_id: GuildId, database: Arc<Database>) -> u64 {
-    match database.get_most_popular_channel(guild_id.get()).await {
-        Ok(channel_id) => channel_id,
-        Err(e) => {
-            eprintln!("Failed to get most popular channel: {}", e);
-            0
+        let mut chain = markov_chain::Chain::new(1);
+        chain.train(
+            (0..500)
+                .map(|index| match index % 10 {
+                    0 => format!("a perfectly clean line number {}", index),
+                    _ => format!("zorp says z0rp number {} zorp", index),
+                })
+                .collect(),
+        );
+
+        for _ in 0..200 {
+            if let Some(message) = generate_clean(
+                &chain,
+                &GenerationOptions::default(),
+                &banned_words,
+                &cache,
+                MESSAGE_CHAR_LIMIT,
+            ) {
+                assert!(!banned_words.matches(&message), "{}", message);
+            }
+        }
+    }
+
+    #[test]
+    fn word_range_defaults_and_clamps() {
+        let options = GenerationOptions::default();
+        for _ in 0..50 {
+            let range = options.word_range();
+            assert_eq!(*range.start(), DEFAULT_MIN_WORDS);
+            assert!((DEFAULT_MIN_WORDS..=DEFAULT_MAX_WORDS).contains(range.end()));
+        }
+
+        // A minimum past the maximum is brought down to it
+        let options = GenerationOptions {
+            min_words: Some(10),
+            max_words: Some(5),
+            ..Default::default()
+        };
+        assert_eq!(options.word_range(), 5..=5);
+
+        // A minimum alone raises the default maximum
+        let options = GenerationOptions {
+            min_words: Some(30),
+            ..Default::default()
+        };
+        assert_eq!(options.word_range(), 30..=30);
+    }
+
+    #[test]
+    fn generated_messages_stay_within_the_word_range() {
+        let banned_words = BannedWords::new(&[]);
+        let cache = Cache::new();
+
+        // A loop with no dead ends, so only the range stops generation
+        let mut chain = markov_chain::Chain::new(1);
+        chain.train(vec![
+            "one two three four five one".to_string(),
+            "three six two".to_string(),
+        ]);
+
+        for (min_words, max_words) in [(4, 7), (1, 1), (9, 9), (12, 3)] {
+            let options = GenerationOptions {
+                min_words: Some(min_words),
+                max_words: Some(max_words),
+                ..Default::default()
+            };
+
+            for _ in 0..50 {
+                let message =
+                    generate_clean(&chain, &options, &banned_words, &cache, MESSAGE_CHAR_LIMIT)
+                        .unwrap();
+                let words = message.split_whitespace().count();
+                assert!(
+                    (min_words.min(max_words)..=max_words).contains(&words),
+                    "{} words for {}..={}: {}",
+                    words,
+                    min_words,
+                    max_words,
+                    message
+                );
+            }
+        }
+    }
+
+    fn message(kind: MessageType, content: &str) -> Message {
+        let mut msg = Message::default();
+        msg.kind = kind;
+        msg.content = content.to_string();
+        msg
+    }
+
+    #[test]
+    fn storable_messages_are_text_from_people() {
+        let prefixes = ["!".to_string(), "pls ".to_string()];
+
+        assert!(is_storable(
+            &message(MessageType::Regular, "hello there"),
+            &prefixes
+        ));
+        assert!(is_storable(
+            &message(MessageType::InlineReply, "ok"),
+            &prefixes
+        ));
+        // The prefix has to start the message
+        assert!(is_storable(
+            &message(MessageType::Regular, "wow!"),
+            &prefixes
+        ));
+        assert!(is_storable(
+            &message(MessageType::Regular, "please"),
+            &prefixes
+        ));
+        assert!(is_storable(&message(MessageType::Regular, "hi"), &[]));
+    }
+
+    #[test]
+    fn bots_and_webhooks_arent_stored() {
+        let mut bot = message(MessageType::Regular, "beep boop");
+        bot.author.bot = true;
+        assert!(!is_storable(&bot, &[]));
+
+        let mut webhook = message(MessageType::Regular, "relayed message");
+        webhook.webhook_id = Some(WebhookId::new(1));
+        assert!(!is_storable(&webhook, &[]));
+    }
+
+    #[test]
+    fn empty_and_system_messages_arent_stored() {
+        // Attachment or sticker only, and whitespace around one character
+        for content in ["", "   \n ", " x "] {
+            assert!(!is_storable(&message(MessageType::Regular, content), &[]));
+        }
+
+        for kind in [
+            MessageType::MemberJoin,
+            MessageType::NitroBoost,
+            MessageType::PinsAdd,
+            MessageType::ThreadCreated,
+        ] {
+            assert!(!is_storable(&message(kind, "some text"), &[]));
+        }
+    }
+
+    #[test]
+    fn other_bots_commands_arent_stored() {
+        let prefixes = ["!".to_string(), "pls ".to_string()];
+
+        for content in ["!play song", "  !skip", "pls rob someone"] {
+            assert!(!is_storable(
+                &message(MessageType::Regular, content),
+                &prefixes
+            ));
+        }
+    }
+
+    #[test]
+    fn only_inline_replies_have_a_parent() {
+        let reference = MessageReference::from((ChannelId::new(100), MessageId::new(10)));
+
+        let mut reply = message(MessageType::InlineReply, "pasta again");
+        reply.message_reference = Some(reference.clone());
+        assert_eq!(replied_to_message_id(&reply), Some(10));
+
+        // Crossposts and pins carry a reference too
+        let mut pin = message(MessageType::PinsAdd, "");
+        pin.message_reference = Some(reference);
+        assert_eq!(replied_to_message_id(&pin), None);
+
+        let regular = message(MessageType::Regular, "pasta again");
+        assert_eq!(replied_to_message_id(&regular), None);
+    }
+
+    async fn chain_cache_with_messages(
+        per_channel: &[(u64, u64)],
+    ) -> (Arc<RwLock<ChainCache<ChainKey>>>, Arc<Database>) {
+        let database = Database::new("sqlite::memory:", 2000, None, 1)
+            .await
+            .unwrap();
+        let mut message_id = 0;
+        for &(channel_id, count) in per_channel {
+            for index in 0..count {
+                message_id += 1;
+                let content = format!("message number {index} in channel c{channel_id}");
+                database
+                    .insert_message(message_id, 1, channel_id, 1, &content, None)
+                    .await
+                    .unwrap();
+            }
+        }
+
+        let cache = ChainCache::new(10, usize::MAX);
+        (Arc::new(RwLock::new(cache)), Arc::new(database))
+    }
+
+    async fn trained_at(
+        cache_lock: &RwLock<ChainCache<ChainKey>>,
+        key: ChainKey,
+    ) -> Option<Instant> {
+        cache_lock
+            .read()
+            .await
+            .get(&key)
+            .map(|cached| cached.trained_at)
+    }
+
+    #[tokio::test]
+    async fn quiet_channels_fall_back_to_the_guild_chain() {
+        let (cache_lock, database) =
+            chain_cache_with_messages(&[(100, MIN_MARKOV_MESSAGES), (200, 5), (300, 5)]).await;
+        let guild_id = GuildId::new(1);
+
+        // A busy channel gets its own chain, and no guild chain is trained
+        let outcome = with_channel_or_guild_chain(
+            Some(cache_lock.clone()),
+            guild_id,
+            ChannelId::new(100),
+            database.clone(),
+            false,
+            |chain| chain.stats().vocabulary,
+        )
+        .await;
+        let MarkovOutcome::Generated(channel_vocabulary) = outcome else {
+            panic!("no chain for the busy channel");
+        };
+        assert!(trained_at(&cache_lock, ChainKey::Channel(100))
+            .await
+            .is_some());
+        assert!(trained_at(&cache_lock, ChainKey::Guild(1)).await.is_none());
+
+        // A quiet one trains the guild chain instead of its own
+        let outcome = with_channel_or_guild_chain(
+            Some(cache_lock.clone()),
+            guild_id,
+            ChannelId::new(200),
+            database.clone(),
+            false,
+            |chain| chain.stats().vocabulary,
+        )
+        .await;
+        // Trained on every channel, so it knows the other channels' ids too
+        let MarkovOutcome::Generated(guild_vocabulary) = outcome else {
+            panic!("no chain for the quiet channel");
+        };
+        assert_eq!(guild_vocabulary, channel_vocabulary + 2);
+        assert!(trained_at(&cache_lock, ChainKey::Channel(200))
+            .await
+            .is_none());
+        let guild_trained_at = trained_at(&cache_lock, ChainKey::Guild(1)).await;
+        assert!(guild_trained_at.is_some());
+
+        // Every other quiet channel reuses it
+        for channel_id in [200, 300] {
+            let outcome = with_channel_or_guild_chain(
+                Some(cache_lock.clone()),
+                guild_id,
+                ChannelId::new(channel_id),
+                database.clone(),
+                false,
+                |_| (),
+            )
+            .await;
+            assert!(matches!(outcome, MarkovOutcome::Generated(())));
+        }
+        assert_eq!(
+            trained_at(&cache_lock, ChainKey::Guild(1)).await,
+            guild_trained_at
+        );
+        assert!(trained_at(&cache_lock, ChainKey::Channel(300))
+            .await
+            .is_none());
+        assert_eq!(cache_lock.read().await.iter().count(), 2);
+    }
+
+    #[tokio::test]
+    async fn quiet_guilds_have_not_enough_messages() {
+        let (cache_lock, database) = chain_cache_with_messages(&[(100, 5), (200, 5)]).await;
+
+        let outcome = with_channel_or_guild_chain(
+            Some(cache_lock.clone()),
+            GuildId::new(1),
+            ChannelId::new(100),
+            database,
+            false,
+            |_| (),
+        )
+        .await;
+
+        assert!(matches!(
+            outcome,
+            MarkovOutcome::NotEnoughMessages {
+                have: 10,
+                need: MIN_MARKOV_MESSAGES
+            }
+        ));
+        assert_eq!(cache_lock.read().await.iter().count(), 0);
+    }
+
+    #[test]
+    fn every_outcome_explains_itself() {
+        assert_eq!(
+            MarkovOutcome::Generated("hello there".to_string()).into_content(),
+            "hello there"
+        );
+
+        let not_enough = MarkovOutcome::<String>::NotEnoughMessages {
+            have: 120,
+            need: 500,
+        }
+        .into_content();
+        assert!(not_enough.contains("**120**"));
+        assert!(not_enough.contains("**380** more"));
+        assert!(not_enough.contains("/collect"));
+
+        let filtered = MarkovOutcome::<String>::Filtered.into_content();
+        let error = MarkovOutcome::<String>::Error.into_content();
+        assert_ne!(filtered, error);
+        assert_ne!(filtered, not_enough);
+        assert_ne!(error, not_enough);
+    }
+
+    #[test]
+    fn only_generated_outcomes_have_a_value() {
+        assert_eq!(MarkovOutcome::Generated(3).generated(), Some(3));
+        assert_eq!(
+            MarkovOutcome::<i32>::NotEnoughMessages { have: 0, need: 1 }.generated(),
+            None
+        );
+        assert_eq!(MarkovOutcome::<i32>::Filtered.generated(), None);
+        assert_eq!(MarkovOutcome::<i32>::Error.generated(), None);
+    }
+
+    fn large_corpus() -> Vec<String> {
+        (0..20_000)
+            .map(|index| {
+                format!(
+                    "word{} went to see word{} about word{} again",
+                    index % 997,
+                    index % 1009,
+                    index % 1013
+                )
+            })
+            .collect()
+    }
+
+    // A single threaded runtime, like a one core host: training inline
+    // would keep every other task waiting until it's done
+    #[tokio::test(flavor = "current_thread")]
+    async fn other_tasks_run_while_a_chain_trains() {
+        let database = Arc::new(
+            Database::new("sqlite::memory:", 2000, None, 1)
+                .await
+                .unwrap(),
+        );
+        let corpus = large_corpus();
+
+        let ticks = Arc::new(std::sync::atomic::AtomicU64::new(0));
+        let ticker = tokio::spawn({
+            let ticks = ticks.clone();
+            async move {
+                loop {
+                    ticks.fetch_add(1, Ordering::Relaxed);
+                    tokio::time::sleep(Duration::from_millis(1)).await;
+                }
+            }
+        });
+        tokio::task::yield_now().await;
+
+        let started = Instant::now();
+        let before = ticks.load(Ordering::Relaxed);
+        let outcome = train_chain(GuildId::new(1), corpus, database, "test".to_string()).await;
+        let during = ticks.load(Ordering::Relaxed) - before;
+        let elapsed = started.elapsed();
+        ticker.abort();
+
+        assert!(matches!(outcome, MarkovOutcome::Generated(_)));
+        // Roughly one tick per few milliseconds of training
+        assert!(
+            during as u128 >= elapsed.as_millis() / 10,
+            "{during} ticks in {elapsed:?}"
+        );
+        assert!(during > 5, "{during} ticks in {elapsed:?}");
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn filtering_the_corpus_leaves_the_runtime_free() {
+        let database = Database::new("sqlite::memory:", 2000, None, 1)
+            .await
+            .unwrap();
+        database.set_filter_banned_training(1, true).await.unwrap();
+        // Nothing else in the corpus is a stretched spelling of it
+        database.add_banned_word(1, "word989").await.unwrap();
+        let database = Arc::new(database);
+        let corpus = large_corpus();
+
+        let MarkovOutcome::Generated(unfiltered) = train_chain(
+            GuildId::new(2),
+            corpus.clone(),
+            database.clone(),
+            "unfiltered".to_string(),
+        )
+        .await
+        else {
+            panic!("training failed");
+        };
+
+        let ticks = Arc::new(std::sync::atomic::AtomicU64::new(0));
+        let ticker = tokio::spawn({
+            let ticks = ticks.clone();
+            async move {
+                loop {
+                    ticks.fetch_add(1, Ordering::Relaxed);
+                    tokio::time::sleep(Duration::from_millis(1)).await;
+                }
+            }
+        });
+        tokio::task::yield_now().await;
+
+        let before = ticks.load(Ordering::Relaxed);
+        let outcome = train_chain(GuildId::new(1), corpus, database, "test".to_string()).await;
+        let during = ticks.load(Ordering::Relaxed) - before;
+        ticker.abort();
+
+        let MarkovOutcome::Generated(chain) = outcome else {
+            panic!("training failed");
+        };
+        assert_eq!(chain.stats().vocabulary, unfiltered.stats().vocabulary - 1);
+        assert!(during > 5, "{during} ticks");
+    }
+
+    #[tokio::test]
+    async fn concurrent_calls_train_a_chain_once() {
+        let cache_lock = Arc::new(RwLock::new(ChainCache::new(10, usize::MAX)));
+        let trainings = std::sync::atomic::AtomicUsize::new(0);
+        let release = tokio::sync::Notify::new();
+
+        let call = || {
+            with_cached_chain(
+                Some(cache_lock.clone()),
+                ChainKey::Channel(1),
+                false,
+                async {
+                    trainings.fetch_add(1, Ordering::SeqCst);
+                    release.notified().await;
+                    let mut chain = markov_chain::Chain::new(1);
+                    chain.train(vec!["the only thing it knows".to_string()]);
+                    MarkovOutcome::Generated(chain)
+                },
+                |chain| chain.stats().vocabulary,
+            )
+        };
+
+        let waiting = async {
+            // Everyone is queued up behind the first training by now
+            while trainings.load(Ordering::SeqCst) == 0 {
+                tokio::task::yield_now().await;
+            }
+            for _ in 0..10 {
+                tokio::task::yield_now().await;
+            }
+
+            // Only the training lock is held, not the cache's
+            assert_eq!(cache_lock.try_read().unwrap().iter().count(), 0);
+            release.notify_one();
+        };
+        let (first, second, third, ()) = tokio::join!(call(), call(), call(), waiting);
+
+        for outcome in [first, second, third] {
+            assert!(matches!(outcome, MarkovOutcome::Generated(5)));
         }
+        assert_eq!(trainings.load(Ordering::SeqCst), 1);
+        assert_eq!(cache_lock.read().await.iter().count(), 1);
     }
 }
@@ -1,69 +1,1119 @@
+use rand::distributions::{Distribution, WeightedIndex};
 use rand::prelude::IteratorRandom;
 use rand::seq::SliceRandom;
+use rand::Rng;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::mem;
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::time::Instant;
+
+use crate::utils::content::strip_code_and_quotes;
+use crate::utils::string_cmp::levenshtein_similarity;
+
+/// Temperature that picks transitions in proportion to how often they were
+/// seen.
+pub const DEFAULT_TEMPERATURE: f32 = 1.0;
+
+/// First line of a saved chain, followed by its order and settings. Files
+/// from older versions lack the sentence hashes and aren't loaded.
+const FILE_HEADER: &str = "markov-chain v2";
+
+/// A chain in the cache, with when it was trained so it can expire.
+pub struct CachedChain {
+    pub chain: Chain,
+    pub trained_at: Instant,
+}
+
+/// How big a chain is, for `/markovstats`.
+pub struct ChainStats {
+    pub order: usize,
+    /// Distinct words the chain knows.
+    pub vocabulary: usize,
+    pub states: usize,
+    pub transitions: usize,
+    /// Distinct sentences trained on.
+    pub sentences: usize,
+}
 
 #[derive(Debug, Clone)]
 pub struct Chain {
+    /// How many preceding words pick the next one.
+    order: usize,
+    /// The last `order` words, joined by a space, to the words seen after them.
     chains: HashMap<String, Vec<String>>,
+    /// Total number of transitions across all states.
+    transitions: usize,
+    /// Hashes of the sentences trained on, to spot output that's a copy.
+    sentences: HashSet<u64>,
+    /// Whether unicode emoji survive `clean_sentence`.
+    keep_unicode_emoji: bool,
+    /// Whether generated sentences go through `polish`.
+    polish: bool,
+    /// How many times an identical sentence is trained on, `None` for no
+    /// limit.
+    max_duplicates: Option<usize>,
 }
 
 impl Chain {
-    pub fn new() -> Self {
+    pub fn new(order: usize) -> Self {
         Chain {
+            order: order.max(1),
             chains: HashMap::new(),
+            transitions: 0,
+            sentences: HashSet::new(),
+            keep_unicode_emoji: true,
+            polish: false,
+            max_duplicates: None,
         }
     }
 
-    /// Trains the chain using a vector of strings
-    pub fn train(&mut self, sentences: Vec<String>) {
+    /// Caps how many times an identical sentence is trained on, so a pasted
+    /// copypasta can't drown out everything else. There's no cap by default.
+    pub fn with_max_duplicates(mut self, max_duplicates: usize) -> Self {
+        self.max_duplicates = Some(max_duplicates.max(1));
+        self
+    }
+
+    /// Sets whether generated sentences are capitalized and punctuated, by
+    /// default they come out as trained.
+    pub fn with_polish(mut self, polish: bool) -> Self {
+        self.polish = polish;
+        self
+    }
+
+    /// Sets whether unicode emoji are kept when training, they are by
+    /// default.
+    pub fn keep_unicode_emoji(mut self, keep: bool) -> Self {
+        self.keep_unicode_emoji = keep;
+        self
+    }
+
+    /// Trains the chain using a vector of strings, and returns how many
+    /// were dropped as copies past `with_max_duplicates`.
+    pub fn train(&mut self, sentences: Vec<String>) -> usize {
+        let mut seen: HashMap<u64, usize> = HashMap::new();
+        let mut dropped = 0;
+
         // Loop over the sentences
         for sentence in sentences {
-            // Split the sentence into its words
+            let Some(sentence) = clean_sentence(&sentence, self.keep_unicode_emoji) else {
+                continue;
+            };
             let words: Vec<&str> = sentence.split_whitespace().collect();
-            // Loop over the words with `windows`, so ["word1", "word2", "word3"]
-            // will return ["word1", "word2"], and ["word2", "word3"]
-            for window in words.windows(2) {
-                // Make sure window has two elements
-                if let [first, second] = window {
-                    self.chains
-                        .entry(first.to_string())
-                        .or_insert_with(Vec::new)
-                        .push(second.to_string());
+            let hash = sentence_hash(&words);
+
+            if let Some(max_duplicates) = self.max_duplicates {
+                let count = seen.entry(hash).or_insert(0);
+                if *count >= max_duplicates {
+                    dropped += 1;
+                    continue;
                 }
+                *count += 1;
             }
+
+            self.learn(&words, hash);
         }
+
+        dropped
     }
 
-    pub fn generate(&self, word_limit: usize, custom_word: Option<&str>) -> String {
-        // Initiate the random number generator
-        let mut rng = rand::thread_rng();
-        // Pick a random word from the chains
-        let mut sentence: Vec<&str> = match custom_word {
-            Some(word) => word.split_whitespace().collect(),
-            None => match self.chains.keys().choose(&mut rng) {
-                Some(word) => vec![word],
-                None => return String::new(),
-            },
+    /// Adds a single sentence to the chain, once `clean_sentence` has been
+    /// through it. A sentence with nothing left is skipped.
+    pub fn train_one(&mut self, sentence: &str) {
+        let Some(sentence) = clean_sentence(sentence, self.keep_unicode_emoji) else {
+            return;
         };
+        // Split the sentence into its words
+        let words: Vec<&str> = sentence.split_whitespace().collect();
+        self.learn(&words, sentence_hash(&words));
+    }
+
+    fn learn(&mut self, words: &[&str], hash: u64) {
+        self.sentences.insert(hash);
+        // Loop over the words with `windows`, so with order 1
+        // ["word1", "word2", "word3"] will return ["word1", "word2"], and
+        // ["word2", "word3"]
+        for window in words.windows(self.order + 1) {
+            let (state, next) = window.split_at(self.order);
+            self.chains
+                .entry(state.join(" "))
+                .or_default()
+                .push(next[0].to_string());
+            self.transitions += 1;
+        }
+    }
+
+    /// Total number of transitions the chain has learned.
+    pub fn transitions(&self) -> usize {
+        self.transitions
+    }
+
+    /// Drops transitions seen fewer than `min_count` times from each state,
+    /// then drops states no remaining transition leads to.
+    ///
+    /// A state whose transitions would all be dropped keeps its most common
+    /// one, so pruning never creates new dead ends. A `min_count` of 0 or 1
+    /// leaves the chain alone.
+    pub fn prune(&mut self, min_count: usize) {
+        // Every transition was seen at least once
+        if min_count <= 1 {
+            return;
+        }
+
+        for next_words in self.chains.values_mut() {
+            let mut counts: HashMap<&str, usize> = HashMap::new();
+            for word in next_words.iter() {
+                *counts.entry(word).or_insert(0) += 1;
+            }
+
+            let mut kept: Vec<String> = next_words
+                .iter()
+                .filter(|word| counts[word.as_str()] >= min_count)
+                .cloned()
+                .collect();
 
-        let mut current_word = &sentence[sentence.len() - 1].to_string();
-
-        // Loop over the word_limit
-        for _ in 0..word_limit {
-            let next_words = self.chains.get(current_word);
-            match next_words {
-                Some(words) if !words.is_empty() => {
-                    current_word = match words.choose(&mut rng) {
-                        Some(word) => word,
-                        None => break,
-                    };
+            if kept.is_empty() {
+                if let Some((word, _)) = counts.iter().max_by_key(|(_, count)| **count) {
+                    kept.push(word.to_string());
                 }
-                _ => break,
             }
-            sentence.push(current_word);
+
+            *next_words = kept;
+        }
+
+        // The state after a transition is the old state shifted by one word
+        let reachable: HashSet<String> = self
+            .chains
+            .iter()
+            .flat_map(|(state, next_words)| {
+                let kept = state.split(' ').skip(1).collect::<Vec<_>>().join(" ");
+                next_words.iter().map(move |next| match kept.is_empty() {
+                    true => next.clone(),
+                    false => format!("{} {}", kept, next),
+                })
+            })
+            .collect();
+        self.chains.retain(|state, _| reachable.contains(state));
+        self.transitions = self.chains.values().map(Vec::len).sum();
+    }
+
+    pub fn stats(&self) -> ChainStats {
+        let vocabulary: HashSet<&str> = self
+            .chains
+            .iter()
+            .flat_map(|(state, next_words)| {
+                state
+                    .split(' ')
+                    .chain(next_words.iter().map(String::as_str))
+            })
+            .collect();
+
+        ChainStats {
+            order: self.order,
+            vocabulary: vocabulary.len(),
+            states: self.chains.len(),
+            transitions: self.transitions,
+            sentences: self.sentences.len(),
+        }
+    }
+
+    /// Rough number of bytes held by the chain.
+    pub fn approx_size(&self) -> usize {
+        self.sentences.len() * mem::size_of::<u64>()
+            + self
+                .chains
+                .iter()
+                .map(|(word, next_words)| {
+                    mem::size_of::<String>()
+                        + word.len()
+                        + mem::size_of::<Vec<String>>()
+                        + next_words
+                            .iter()
+                            .map(|next| mem::size_of::<String>() + next.len())
+                            .sum::<usize>()
+                })
+                .sum::<usize>()
+    }
+
+    /// Whether the chain knows at least one word that can follow `word`.
+    pub fn has_successors(&self, word: &str) -> bool {
+        match self.order {
+            1 => self.chains.get(word).is_some_and(|words| !words.is_empty()),
+            _ => self.states_starting_with(word).next().is_some(),
+        }
+    }
+
+    /// States whose first word is `word`.
+    fn states_starting_with<'a>(&'a self, word: &'a str) -> impl Iterator<Item = &'a String> {
+        self.chains.keys().filter(move |state| {
+            state
+                .strip_prefix(word)
+                .is_some_and(|rest| rest.starts_with(' '))
+        })
+    }
+
+    /// Generates a sentence of up to `words.end()` words, stopping early
+    /// only when the chain has nowhere to go. `words.start()` is checked by
+    /// `generate_novel`.
+    ///
+    /// `custom_word` may be a whole phrase. The output starts with it and
+    /// continues from its last word, or from the closest word the chain
+    /// knows when it doesn't know that one.
+    ///
+    /// A `temperature` below 1.0 favors the most common transitions, above
+    /// 1.0 evens them out.
+    pub fn generate(
+        &self,
+        words: RangeInclusive<usize>,
+        custom_word: Option<&str>,
+        temperature: f32,
+        rng: &mut impl Rng,
+    ) -> String {
+        self.finish(self.generate_raw(words, custom_word, temperature, rng))
+    }
+
+    /// `generate` before polishing, so it can be compared with what the
+    /// chain was trained on.
+    fn generate_raw(
+        &self,
+        words: RangeInclusive<usize>,
+        custom_word: Option<&str>,
+        temperature: f32,
+        rng: &mut impl Rng,
+    ) -> String {
+        let mut sentence: Vec<&str> = custom_word
+            .map(|phrase| phrase.split_whitespace().collect())
+            .unwrap_or_default();
+
+        // The words the next one is picked by, usually the end of `sentence`
+        let mut state: Vec<&str> = match sentence.last() {
+            Some(_)
+                if sentence.len() >= self.order
+                    && self
+                        .chains
+                        .contains_key(&sentence[sentence.len() - self.order..].join(" ")) =>
+            {
+                sentence[sentence.len() - self.order..].to_vec()
+            }
+            Some(last) => match self.seed_state(last, rng) {
+                Some(state) => {
+                    // The rest of a state begun by the phrase's last word
+                    // is part of the output
+                    let state: Vec<&str> = state.split(' ').collect();
+                    sentence.extend(&state[1..]);
+                    state
+                }
+                None => return sentence.join(" "),
+            },
+            // Pick a random state from the chains
+            None => match self.chains.keys().choose(rng) {
+                Some(state) => {
+                    sentence = state.split(' ').collect();
+                    sentence.clone()
+                }
+                None => return String::new(),
+            },
+        };
+
+        // Keep walking until the sentence is long enough or the chain ends
+        while sentence.len() < *words.end() {
+            let next_word = match self.chains.get(&state.join(" ")) {
+                Some(words) => match pick_next(words, temperature, rng) {
+                    Some(word) => word,
+                    None => break,
+                },
+                None => break,
+            };
+
+            sentence.push(next_word);
+            state.remove(0);
+            state.push(next_word);
         }
 
         sentence.join(" ")
     }
+
+    /// Like `generate`, but re-rolls up to `max_attempts` times while the
+    /// output is a word-for-word copy of a trained sentence or shorter than
+    /// `words.start()`. The last attempt is returned even if it's neither.
+    pub fn generate_novel(
+        &self,
+        words: RangeInclusive<usize>,
+        custom_word: Option<&str>,
+        temperature: f32,
+        max_attempts: usize,
+        rng: &mut impl Rng,
+    ) -> String {
+        let mut sentence = String::new();
+
+        for _ in 0..max_attempts.max(1) {
+            sentence = self.generate_raw(words.clone(), custom_word, temperature, rng);
+
+            let sentence_words: Vec<&str> = sentence.split_whitespace().collect();
+            if sentence_words.len() >= *words.start()
+                && !self.sentences.contains(&sentence_hash(&sentence_words))
+            {
+                break;
+            }
+        }
+
+        self.finish(sentence)
+    }
+
+    fn finish(&self, sentence: String) -> String {
+        match self.polish {
+            true => polish(&sentence),
+            false => sentence,
+        }
+    }
+
+    /// A state beginning with `word`, or with the known word closest to it.
+    fn seed_state<'a>(&'a self, word: &'a str, rng: &mut impl Rng) -> Option<&'a String> {
+        if let Some(state) = self.states_starting_with(word).choose(rng) {
+            return Some(state);
+        }
+        if let Some((state, _)) = self.chains.get_key_value(word) {
+            return Some(state);
+        }
+
+        let closest = self
+            .chains
+            .keys()
+            .filter_map(|state| state.split(' ').next())
+            .max_by(|a, b| {
+                levenshtein_similarity(word, a).total_cmp(&levenshtein_similarity(word, b))
+            })?;
+
+        match self.order {
+            1 => self.chains.get_key_value(closest).map(|(state, _)| state),
+            _ => self.states_starting_with(closest).choose(rng),
+        }
+    }
+    /// Writes the chain to `path`: a header with its order and settings, the
+    /// trained sentences' hashes on one line, then one state per line
+    /// followed by a tab and the words seen after it. Words never contain
+    /// whitespace, so no escaping is needed.
+    ///
+    /// The file is written next to `path` first and moved into place, so a
+    /// crash mid-write never leaves a half-written chain behind.
+    pub fn save_to(&self, path: &Path) -> io::Result<()> {
+        let temp_path = path.with_extension("tmp");
+        let mut writer = BufWriter::new(fs::File::create(&temp_path)?);
+
+        writeln!(
+            writer,
+            "{} {} {} {} {}",
+            FILE_HEADER,
+            self.order,
+            self.polish as u8,
+            self.keep_unicode_emoji as u8,
+            self.max_duplicates.unwrap_or(0)
+        )?;
+        let hashes: Vec<String> = self
+            .sentences
+            .iter()
+            .map(|hash| format!("{:x}", hash))
+            .collect();
+        writeln!(writer, "{}", hashes.join(" "))?;
+        for (state, next_words) in &self.chains {
+            writeln!(writer, "{}\t{}", state, next_words.join(" "))?;
+        }
+
+        writer.into_inner()?.sync_all()?;
+        fs::rename(temp_path, path)
+    }
+
+    /// Reads a chain written by `save_to`.
+    pub fn load_from(path: &Path) -> io::Result<Self> {
+        let invalid = |reason: &str| io::Error::new(io::ErrorKind::InvalidData, reason.to_string());
+
+        let mut lines = BufReader::new(fs::File::open(path)?).lines();
+
+        let header = lines.next().ok_or_else(|| invalid("empty file"))??;
+        let fields: Vec<usize> = header
+            .strip_prefix(FILE_HEADER)
+            .ok_or_else(|| invalid("bad header"))?
+            .split_whitespace()
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .map_err(|_| invalid("bad header"))?;
+        let [order, polish, keep_unicode_emoji, max_duplicates] = fields[..] else {
+            return Err(invalid("bad header"));
+        };
+        if order < 1 {
+            return Err(invalid("bad header"));
+        }
+
+        let hashes = lines.next().ok_or_else(|| invalid("missing sentences"))??;
+        let sentences = hashes
+            .split_whitespace()
+            .map(|hash| u64::from_str_radix(hash, 16))
+            .collect::<Result<_, _>>()
+            .map_err(|_| invalid("bad sentence hash"))?;
+
+        let mut chains = HashMap::new();
+        for line in lines {
+            let line = line?;
+            let (state, next_words) = line.split_once('\t').ok_or_else(|| invalid("bad line"))?;
+
+            if state.split(' ').count() != order {
+                return Err(invalid("state doesn't match the order"));
+            }
+
+            chains.insert(
+                state.to_string(),
+                next_words.split(' ').map(str::to_string).collect(),
+            );
+        }
+
+        let transitions = chains.values().map(Vec::len).sum();
+
+        Ok(Chain {
+            order,
+            chains,
+            transitions,
+            sentences,
+            keep_unicode_emoji: keep_unicode_emoji != 0,
+            polish: polish != 0,
+            max_duplicates: (max_duplicates > 0).then_some(max_duplicates),
+        })
+    }
+}
+
+/// Prepares a message for training: drops code and quotes, links, custom
+/// emoji and mention markup, and unicode emoji unless `keep_unicode_emoji`.
+///
+/// Returns `None` when no words are left, e.g. for a message that's only a
+/// link.
+pub fn clean_sentence(sentence: &str, keep_unicode_emoji: bool) -> Option<String> {
+    // Code and quotes would have the chain generating half-JSON
+    let sentence = strip_code_and_quotes(sentence);
+    let sentence = strip_markup(&sentence);
+
+    let words: Vec<String> = sentence
+        .split_whitespace()
+        .filter(|word| !is_link(word))
+        .map(|word| match keep_unicode_emoji {
+            true => word.to_string(),
+            false => word.chars().filter(|c| !is_unicode_emoji(*c)).collect(),
+        })
+        .filter(|word| !word.is_empty())
+        .collect();
+
+    match words.is_empty() {
+        true => None,
+        false => Some(words.join(" ")),
+    }
+}
+
+/// Makes a generated sentence read like one: single spaces, no leading
+/// punctuation or trailing commas, a capital first letter and a closing
+/// `.`, `!` or `?`.
+///
+/// No period is added after an emoji, quote or bracket, where it would look
+/// out of place.
+fn polish(sentence: &str) -> String {
+    let words: Vec<&str> = sentence
+        .split_whitespace()
+        .skip_while(|word| word.chars().all(|c| c.is_ascii_punctuation()))
+        .collect();
+
+    let sentence = words.join(" ");
+    let sentence = sentence.trim_end_matches([',', ';', ':', '-', ' ']);
+
+    let mut chars = sentence.chars();
+    let Some(first) = chars.next() else {
+        return String::new();
+    };
+
+    let mut polished: String = first.to_uppercase().chain(chars).collect();
+    if polished.ends_with(char::is_alphanumeric) {
+        polished.push('.');
+    }
+
+    polished
+}
+
+/// Drops `<:name:id>`, `<a:name:id>`, `<@id>`, `<@!id>`, `<@&id>` and
+/// `<#id>` anywhere in `text`, even glued to a word. Other `<...>` is kept.
+fn strip_markup(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('<') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        match rest.find('>').filter(|end| is_markup(&rest[1..*end])) {
+            // Keep the words on either side apart
+            Some(end) => {
+                result.push(' ');
+                rest = &rest[end + 1..];
+            }
+            None => {
+                result.push('<');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    result.push_str(rest);
+    result
+}
+
+/// Whether `inner`, the part between `<` and `>`, is emoji or mention markup.
+fn is_markup(inner: &str) -> bool {
+    let is_id = |id: &str| !id.is_empty() && id.chars().all(|c| c.is_ascii_digit());
+
+    if let Some(emoji) = inner.strip_prefix("a:").or_else(|| inner.strip_prefix(':')) {
+        return emoji
+            .split_once(':')
+            .is_some_and(|(name, id)| !name.is_empty() && is_id(id));
+    }
+
+    ["@!", "@&", "@", "#"]
+        .iter()
+        .find_map(|prefix| inner.strip_prefix(prefix))
+        .is_some_and(is_id)
+}
+
+/// Whether `word` is a link, bare or wrapped in `<>` to hide its embed.
+fn is_link(word: &str) -> bool {
+    let word = word.trim_start_matches(['<', '(']).to_lowercase();
+    ["http://", "https://", "www."]
+        .iter()
+        .any(|prefix| word.starts_with(prefix))
+}
+
+/// Rough check for pictographic emoji and the joiners and selectors that
+/// glue them together.
+fn is_unicode_emoji(c: char) -> bool {
+    matches!(
+        c as u32,
+        0x1F000..=0x1FAFF | 0x2600..=0x27BF | 0x2B00..=0x2BFF | 0xFE0F | 0x200D | 0x20E3
+    )
+}
+
+/// FNV-1a over the words, stable across builds since the hashes are saved.
+fn sentence_hash(words: &[&str]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+
+    let mut hash = OFFSET_BASIS;
+    for (index, word) in words.iter().enumerate() {
+        // A byte no UTF-8 text contains keeps "a b" and "ab" apart
+        let separator: &[u8] = match index {
+            0 => &[],
+            _ => &[0xff],
+        };
+        for byte in separator.iter().chain(word.as_bytes()) {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(PRIME);
+        }
+    }
+    hash
+}
+
+/// Picks one of `words`, each seen as many times as it's listed, with counts
+/// raised to `1 / temperature`.
+fn pick_next<'a>(words: &'a [String], temperature: f32, rng: &mut impl Rng) -> Option<&'a String> {
+    if temperature == DEFAULT_TEMPERATURE {
+        return words.choose(rng);
+    }
+
+    // In order of first appearance, so a seeded rng picks the same word
+    let mut counts: Vec<(&String, usize)> = Vec::new();
+    let mut positions: HashMap<&String, usize> = HashMap::new();
+    for word in words {
+        match positions.get(word) {
+            Some(&position) => counts[position].1 += 1,
+            None => {
+                positions.insert(word, counts.len());
+                counts.push((word, 1));
+            }
+        }
+    }
+
+    let exponent = 1.0 / temperature.max(f32::EPSILON) as f64;
+    let weights = WeightedIndex::new(
+        counts
+            .iter()
+            .map(|(_, count)| (*count as f64).powf(exponent)),
+    )
+    .ok()?;
+
+    Some(counts[weights.sample(rng)].0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn trained_chain() -> Chain {
+        let mut chain = Chain::new(1);
+        chain.train(vec![
+            "the cat sat on the mat".to_string(),
+            "the dog sat on the rug".to_string(),
+            "a cat ran to the dog".to_string(),
+            "the mat was red".to_string(),
+        ]);
+        chain
+    }
+
+    #[test]
+    fn generate_novel_is_deterministic_for_a_seed() {
+        let chain = trained_chain();
+
+        for seed in 0..20 {
+            let first =
+                chain.generate_novel(3..=12, None, 1.0, 5, &mut StdRng::seed_from_u64(seed));
+            let second =
+                chain.generate_novel(3..=12, None, 1.0, 5, &mut StdRng::seed_from_u64(seed));
+            assert_eq!(first, second);
+        }
+    }
+
+    #[test]
+    fn generate_novel_avoids_trained_sentences() {
+        let chain = trained_chain();
+        let trained = [
+            "the cat sat on the mat",
+            "the dog sat on the rug",
+            "a cat ran to the dog",
+            "the mat was red",
+        ];
+
+        let mut rng = StdRng::seed_from_u64(7);
+        for _ in 0..200 {
+            let sentence = chain.generate_novel(4..=12, None, 1.0, 50, &mut rng);
+            assert!(!trained.contains(&sentence.as_str()), "{}", sentence);
+        }
+    }
+
+    #[test]
+    fn generate_starts_with_the_seed_phrase() {
+        let chain = trained_chain();
+        let mut rng = StdRng::seed_from_u64(1);
+
+        let sentence = chain.generate(1..=6, Some("a cat"), 1.0, &mut rng);
+        assert!(sentence.starts_with("a cat"), "{}", sentence);
+    }
+
+    #[test]
+    fn saved_chain_keeps_sentences_and_settings() {
+        let mut chain = Chain::new(2)
+            .keep_unicode_emoji(false)
+            .with_polish(true)
+            .with_max_duplicates(3);
+        chain.train(vec![
+            "one two three four".to_string(),
+            "two three five six".to_string(),
+        ]);
+
+        let dir = std::env::temp_dir().join(format!("markov-chain-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("chain.chain");
+        chain.save_to(&path).unwrap();
+        let loaded = Chain::load_from(&path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(loaded.order, 2);
+        assert_eq!(loaded.chains, chain.chains);
+        assert_eq!(loaded.transitions, chain.transitions);
+        assert_eq!(loaded.sentences, chain.sentences);
+        assert!(!loaded.keep_unicode_emoji);
+        assert!(loaded.polish);
+        assert_eq!(loaded.max_duplicates, Some(3));
+    }
+
+    #[test]
+    fn old_chain_files_are_rejected() {
+        let dir = std::env::temp_dir().join(format!("markov-chain-v1-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("old.chain");
+        fs::write(&path, "markov-chain v1 1\nthe\tcat dog\n").unwrap();
+
+        let loaded = Chain::load_from(&path);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(loaded.is_err());
+    }
+
+    #[test]
+    fn sentence_hash_keeps_word_boundaries() {
+        assert_ne!(sentence_hash(&["a", "b"]), sentence_hash(&["ab"]));
+        assert_eq!(sentence_hash(&["a", "b"]), sentence_hash(&["a", "b"]));
+    }
+
+    /// How often each word is picked from `words` in 2000 seeded draws.
+    fn pick_counts(words: &[String], temperature: f32) -> HashMap<String, usize> {
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut picks = HashMap::new();
+        for _ in 0..2000 {
+            let word = pick_next(words, temperature, &mut rng).unwrap();
+            *picks.entry(word.clone()).or_insert(0) += 1;
+        }
+        picks
+    }
+
+    #[test]
+    fn temperature_shapes_the_picks() {
+        // "a" was seen four times as often as "b"
+        let words: Vec<String> = ["a", "b", "a", "a", "a", "a", "b", "a", "a", "a"]
+            .iter()
+            .map(|word| word.to_string())
+            .collect();
+
+        // Cold sticks to the most common word
+        assert_eq!(pick_counts(&words, 0.1).get("b"), None);
+
+        // Neutral follows the counts, about one in five
+        let b = pick_counts(&words, DEFAULT_TEMPERATURE)["b"];
+        assert!((300..=500).contains(&b), "{}", b);
+
+        // Hot evens them out
+        let b = pick_counts(&words, 100.0)["b"];
+        assert!((900..=1100).contains(&b), "{}", b);
+    }
+
+    #[test]
+    fn picks_are_repeatable_for_a_seed() {
+        let words: Vec<String> = (0..50).map(|index| format!("w{}", index % 7)).collect();
+
+        for temperature in [0.5, 2.0] {
+            let picks = |seed| {
+                let mut rng = StdRng::seed_from_u64(seed);
+                (0..20)
+                    .map(|_| pick_next(&words, temperature, &mut rng).unwrap().clone())
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(picks(9), picks(9));
+        }
+    }
+
+    #[test]
+    fn order_two_only_follows_trained_trigrams() {
+        let corpus = [
+            "the cat sat on the mat today",
+            "the dog sat on the rug again",
+            "a cat ran to the dog quickly",
+            "the mat was red and the rug was blue",
+            "on the mat the cat slept",
+        ];
+        let mut chain = Chain::new(2);
+        chain.train(corpus.iter().map(|line| line.to_string()).collect());
+
+        let trigrams: HashSet<Vec<&str>> = corpus
+            .iter()
+            .flat_map(|line| {
+                let words: Vec<&str> = line.split(' ').collect();
+                words.windows(3).map(<[&str]>::to_vec).collect::<Vec<_>>()
+            })
+            .collect();
+
+        let mut rng = StdRng::seed_from_u64(11);
+        for _ in 0..300 {
+            let sentence = chain.generate(2..=20, None, DEFAULT_TEMPERATURE, &mut rng);
+            let words: Vec<&str> = sentence.split(' ').collect();
+            for window in words.windows(3) {
+                assert!(trigrams.contains(window), "{:?} in {}", window, sentence);
+            }
+        }
+    }
+
+    #[test]
+    fn saved_chains_of_either_order_load_back() {
+        let dir = std::env::temp_dir().join(format!("markov-chain-orders-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        for order in [1, 2] {
+            let mut chain = Chain::new(order);
+            chain.train(vec![
+                "one two three four".to_string(),
+                "two three five six".to_string(),
+                "three five one two".to_string(),
+            ]);
+
+            let path = dir.join(format!("order-{}.chain", order));
+            chain.save_to(&path).unwrap();
+            let loaded = Chain::load_from(&path).unwrap();
+
+            assert_eq!(loaded.order, order);
+            assert_eq!(loaded.chains, chain.chains);
+            assert_eq!(loaded.transitions, chain.transitions);
+            assert_eq!(loaded.sentences, chain.sentences);
+            assert!(loaded.keep_unicode_emoji);
+            assert!(!loaded.polish);
+            assert_eq!(loaded.max_duplicates, None);
+
+            // The loaded chain still spots copies of what it was trained on
+            let mut rng = StdRng::seed_from_u64(order as u64);
+            let sentence = loaded.generate_novel(3..=4, None, DEFAULT_TEMPERATURE, 50, &mut rng);
+            assert_ne!(sentence, "one two three four");
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn legacy_saves_are_invalid_data() {
+        let dir = std::env::temp_dir().join(format!("markov-chain-legacy-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        // What v1 wrote: the order, then states straight away
+        let legacy = [
+            "markov-chain v1 1\nthe\tcat dog\ncat\tsat\n",
+            "markov-chain v1 2\nthe cat\tsat\ncat sat\ton\n",
+        ];
+        for (index, contents) in legacy.iter().enumerate() {
+            let path = dir.join(format!("legacy-{}.chain", index));
+            fs::write(&path, contents).unwrap();
+
+            let error = Chain::load_from(&path).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn training_one_at_a_time_matches_batch_training() {
+        let corpus = [
+            "the cat sat on the mat",
+            "check <https://example.com> the mat",
+            "the dog sat on the rug",
+            "https://only.a/link",
+            "a cat ran to the dog",
+        ];
+
+        for order in [1, 2] {
+            let mut batch = Chain::new(order);
+            batch.train(corpus.iter().map(|line| line.to_string()).collect());
+
+            // Trained on part of it, then fed the rest as messages arrive
+            let mut incremental = Chain::new(order);
+            incremental.train(corpus[..2].iter().map(|line| line.to_string()).collect());
+            for line in &corpus[2..] {
+                incremental.train_one(line);
+            }
+
+            assert_eq!(incremental.chains, batch.chains);
+            assert_eq!(incremental.transitions, batch.transitions);
+            assert_eq!(incremental.sentences, batch.sentences);
+        }
+    }
+
+    /// A chain where a few transitions are common and many were seen once.
+    fn noisy_chain() -> Chain {
+        let mut sentences = Vec::new();
+        for index in 0..30 {
+            sentences.push("the cat sat on the mat".to_string());
+            sentences.push(format!("the cat saw bird{} on the rug", index));
+        }
+        sentences.push("a lone dog barked once".to_string());
+
+        let mut chain = Chain::new(1);
+        chain.train(sentences);
+        chain
+    }
+
+    /// The state a transition from `state` to `next` leads to.
+    fn next_state(state: &str, next: &str) -> String {
+        let mut words: Vec<&str> = state.split(' ').skip(1).collect();
+        words.push(next);
+        words.join(" ")
+    }
+
+    #[test]
+    fn pruning_shrinks_the_chain() {
+        let chain = noisy_chain();
+        let mut pruned = chain.clone();
+        pruned.prune(2);
+
+        assert!(pruned.transitions() < chain.transitions());
+        assert!(pruned.chains.len() < chain.chains.len());
+        assert!(pruned.approx_size() < chain.approx_size());
+        assert_eq!(
+            pruned.transitions(),
+            pruned.chains.values().map(Vec::len).sum::<usize>()
+        );
+    }
+
+    #[test]
+    fn pruning_leaves_no_new_dead_ends() {
+        let chain = noisy_chain();
+        let mut pruned = chain.clone();
+        pruned.prune(3);
+
+        for (state, next_words) in &pruned.chains {
+            assert!(!next_words.is_empty(), "{} has nowhere to go", state);
+            for next in next_words {
+                let target = next_state(state, next);
+                assert_eq!(
+                    pruned.chains.contains_key(&target),
+                    chain.chains.contains_key(&target),
+                    "{} -> {}",
+                    state,
+                    next
+                );
+            }
+        }
+
+        // And whatever it generates only uses transitions it kept
+        let mut rng = StdRng::seed_from_u64(5);
+        for _ in 0..100 {
+            let sentence = pruned.generate(2..=12, None, DEFAULT_TEMPERATURE, &mut rng);
+            let words: Vec<&str> = sentence.split(' ').collect();
+            for pair in words.windows(2) {
+                assert!(
+                    pruned.chains[pair[0]].iter().any(|next| next == pair[1]),
+                    "{:?} in {}",
+                    pair,
+                    sentence
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn pruning_below_two_changes_nothing() {
+        let chain = noisy_chain();
+
+        for min_count in [0, 1] {
+            let mut pruned = chain.clone();
+            pruned.prune(min_count);
+
+            assert_eq!(pruned.chains, chain.chains);
+            assert_eq!(pruned.transitions(), chain.transitions());
+        }
+    }
+
+    #[test]
+    fn link_only_messages_are_dropped() {
+        assert_eq!(clean_sentence("https://example.com/a?b=c", true), None);
+        assert_eq!(clean_sentence("<https://example.com>", true), None);
+        assert_eq!(
+            clean_sentence("www.example.com   HTTP://EXAMPLE.COM", true),
+            None
+        );
+    }
+
+    #[test]
+    fn links_are_cut_out_of_text() {
+        assert_eq!(
+            clean_sentence("look at https://example.com this", true).as_deref(),
+            Some("look at this")
+        );
+        assert_eq!(
+            clean_sentence("(https://example.com) and <www.example.com> too", true).as_deref(),
+            Some("and too")
+        );
+    }
+
+    #[test]
+    fn unicode_emoji_follow_the_setting() {
+        assert_eq!(
+            clean_sentence("nice one 🎉👍🏽", true).as_deref(),
+            Some("nice one 🎉👍🏽")
+        );
+        assert_eq!(
+            clean_sentence("nice one 🎉👍", false).as_deref(),
+            Some("nice one")
+        );
+        assert_eq!(clean_sentence("so❤️good", false).as_deref(), Some("sogood"));
+        assert_eq!(clean_sentence("🎉 👍", false), None);
+    }
+
+    #[test]
+    fn markup_is_dropped_either_way() {
+        for keep in [true, false] {
+            assert_eq!(
+                clean_sentence("gg <:pog:123> <a:dance:456>", keep).as_deref(),
+                Some("gg")
+            );
+            assert_eq!(
+                clean_sentence("hi<@123>there <#789> <@&5>", keep).as_deref(),
+                Some("hi there")
+            );
+            assert_eq!(clean_sentence("<:pog:123>", keep), None);
+            assert_eq!(clean_sentence("a <b> c", keep).as_deref(), Some("a <b> c"));
+        }
+    }
+
+    #[test]
+    fn polish_capitalizes_and_punctuates() {
+        assert_eq!(polish("hello there"), "Hello there.");
+        assert_eq!(polish("érdekes   dolog"), "Érdekes dolog.");
+        assert_eq!(polish("is it?"), "Is it?");
+        assert_eq!(polish("wow!"), "Wow!");
+        assert_eq!(polish("and then,"), "And then.");
+        assert_eq!(polish("so -"), "So.");
+    }
+
+    #[test]
+    fn polish_drops_leading_punctuation() {
+        assert_eq!(polish(", . well then"), "Well then.");
+        assert_eq!(polish("... ok"), "Ok.");
+        assert_eq!(polish("!!"), "");
+        assert_eq!(polish(""), "");
+    }
+
+    #[test]
+    fn polish_adds_no_period_after_emoji_or_brackets() {
+        assert_eq!(polish("nice 🎉"), "Nice 🎉");
+        assert_eq!(polish("he said \"hi\""), "He said \"hi\"");
+        assert_eq!(polish("(maybe)"), "(maybe)");
+    }
+
+    #[test]
+    fn chains_polish_only_when_asked() {
+        let corpus = vec!["hello there friend".to_string()];
+
+        let mut plain = Chain::new(1);
+        plain.train(corpus.clone());
+        let mut polished = Chain::new(1).with_polish(true);
+        polished.train(corpus);
+
+        let mut rng = StdRng::seed_from_u64(2);
+        assert_eq!(
+            plain.generate(1..=3, Some("hello"), DEFAULT_TEMPERATURE, &mut rng),
+            "hello there friend"
+        );
+        assert_eq!(
+            polished.generate(1..=3, Some("hello"), DEFAULT_TEMPERATURE, &mut rng),
+            "Hello there friend."
+        );
+    }
+
+    #[test]
+    fn duplicate_cap_keeps_a_repeated_line_from_dominating() {
+        // 90% of the corpus is one pasted line
+        let mut corpus: Vec<String> = vec!["i like pills".to_string(); 900];
+        corpus.extend((0..100).map(|index| format!("i like thing{}", index)));
+
+        let mut uncapped = Chain::new(1);
+        assert_eq!(uncapped.train(corpus.clone()), 0);
+        let mut capped = Chain::new(1).with_max_duplicates(3);
+        assert_eq!(capped.train(corpus), 897);
+
+        let pills_share = |chain: &Chain| {
+            let mut rng = StdRng::seed_from_u64(4);
+            let pills = (0..1000)
+                .filter(|_| {
+                    chain.generate(3..=3, Some("i like"), DEFAULT_TEMPERATURE, &mut rng)
+                        == "i like pills"
+                })
+                .count();
+            pills as f64 / 1000.0
+        };
+
+        assert!(pills_share(&uncapped) > 0.8);
+        assert!(pills_share(&capped) < 0.1);
+    }
 }
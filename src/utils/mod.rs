@@ -1,3 +1,13 @@
+pub mod anonymize;
+pub mod banned_words;
+pub mod chain_cache;
+pub mod content;
+pub mod dedupe;
+pub mod escape;
 pub mod helpers;
 pub mod markov_chain;
+pub mod reply_chains;
+pub mod responder;
+pub mod stemmer;
 pub mod string_cmp;
+pub mod style;
@@ -0,0 +1,114 @@
+use std::collections::{HashMap, HashSet};
+
+/// How many times the bot answers within one thread of replies.
+const MAX_CHAIN_DEPTH: u32 = 10;
+
+/// How many of the bot's replies are remembered before the older half is
+/// forgotten.
+const MAX_TRACKED_REPLIES: usize = 10_000;
+
+/// Follows threads of replies to the bot, so people replying back and forth
+/// with it get cut off eventually.
+#[derive(Default)]
+pub struct ReplyChains {
+    /// The bot's message id -> id of the bot message its thread started at
+    roots: HashMap<u64, u64>,
+    /// Root message id -> how many times the bot answered in the thread
+    depths: HashMap<u64, u32>,
+}
+
+impl ReplyChains {
+    /// Counts an answer to a reply to the bot's `message_id`. Returns the
+    /// thread's root, or `None` once the thread is long enough.
+    pub fn continue_from(&mut self, message_id: u64) -> Option<u64> {
+        let root = self.roots.get(&message_id).copied().unwrap_or(message_id);
+        let depth = self.depths.entry(root).or_insert(0);
+
+        if *depth >= MAX_CHAIN_DEPTH {
+            return None;
+        }
+
+        *depth += 1;
+        Some(root)
+    }
+
+    /// Remembers the bot's `message_id` as part of `root`'s thread.
+    pub fn record(&mut self, root: u64, message_id: u64) {
+        self.roots.insert(message_id, root);
+
+        if self.roots.len() > MAX_TRACKED_REPLIES {
+            self.forget_oldest();
+        }
+    }
+
+    // Snowflakes grow over time, so the smaller half is the older one
+    fn forget_oldest(&mut self) {
+        let mut ids: Vec<u64> = self.roots.keys().copied().collect();
+        ids.sort_unstable();
+        let cutoff = ids[ids.len() / 2];
+
+        self.roots.retain(|id, _| *id > cutoff);
+        let live: HashSet<u64> = self.roots.values().copied().collect();
+        self.depths.retain(|root, _| live.contains(root));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn answers_stop_at_the_depth_limit() {
+        let mut chains = ReplyChains::default();
+
+        // The bot's first answer is the root of the thread
+        let mut message_id = 100;
+        for depth in 0..MAX_CHAIN_DEPTH as u64 {
+            assert_eq!(chains.continue_from(message_id), Some(100));
+            let answer = 101 + depth;
+            chains.record(100, answer);
+            message_id = answer;
+        }
+
+        assert_eq!(chains.continue_from(message_id), None);
+        // Replying further up the thread doesn't start it over
+        assert_eq!(chains.continue_from(100), None);
+        assert_eq!(chains.continue_from(101), None);
+    }
+
+    #[test]
+    fn threads_are_counted_separately() {
+        let mut chains = ReplyChains::default();
+        for _ in 0..MAX_CHAIN_DEPTH {
+            chains.continue_from(1);
+        }
+
+        assert_eq!(chains.continue_from(1), None);
+        assert_eq!(chains.continue_from(2), Some(2));
+    }
+
+    #[test]
+    fn unknown_messages_start_their_own_thread() {
+        let mut chains = ReplyChains::default();
+        chains.record(1, 2);
+
+        assert_eq!(chains.continue_from(2), Some(1));
+        assert_eq!(chains.continue_from(3), Some(3));
+    }
+
+    #[test]
+    fn oldest_replies_are_forgotten() {
+        let mut chains = ReplyChains::default();
+        for message_id in 1..=MAX_TRACKED_REPLIES as u64 + 1 {
+            chains.record(1, message_id);
+        }
+        chains.record(0, MAX_TRACKED_REPLIES as u64 + 2);
+
+        assert!(chains.roots.len() <= MAX_TRACKED_REPLIES);
+        assert_eq!(chains.continue_from(1), Some(1));
+        assert_eq!(
+            chains.continue_from(MAX_TRACKED_REPLIES as u64 + 2),
+            Some(0)
+        );
+    }
+}
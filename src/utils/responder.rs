@@ -0,0 +1,216 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use rand::seq::SliceRandom;
+use serenity::all::{ChannelId, Context, GuildId};
+use tracing::error;
+
+use crate::database::{Database, STOPWORDS};
+use crate::utils::banned_words::BannedWords;
+use crate::utils::content::{strip_code_and_quotes, truncate_at_word_boundary};
+use crate::utils::escape::sanitize_output;
+use crate::utils::helpers::{
+    generate_markov_message, with_markov_chain, GenerationOptions, MarkovOutcome,
+    MESSAGE_CHAR_LIMIT,
+};
+use crate::utils::markov_chain::DEFAULT_TEMPERATURE;
+
+/// How many of the most recent reply pairs are considered as candidates.
+const CANDIDATE_PAIR_LIMIT: i64 = 2000;
+
+/// How many words of the incoming message are looked up.
+const MAX_PROMPT_WORDS: usize = 20;
+
+/// Minimum similarity score for a stored prompt to count as a match.
+const MIN_MATCH_SCORE: f64 = 0.5;
+
+/// How many of the best matching pairs are used for seeding.
+const TOP_MATCHES: usize = 5;
+
+/// Generates a reply to `content` seeded from what people replied to
+/// similar messages in the past.
+///
+/// Similarity is the number of shared words weighted by their rarity in the
+/// guild, so "the" counts for almost nothing while a rare name dominates.
+/// Returns `None` when no stored prompt is similar enough.
+pub async fn respond_to(
+    ctx: &Context,
+    content: &str,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    database: Arc<Database>,
+) -> Option<String> {
+    let prompt_words: Vec<String> = salient_words(content)
+        .into_iter()
+        .take(MAX_PROMPT_WORDS)
+        .collect();
+
+    if prompt_words.is_empty() {
+        return None;
+    }
+
+    let frequencies = match database
+        .get_word_totals(guild_id.get(), &prompt_words)
+        .await
+    {
+        Ok(frequencies) => frequencies,
+        Err(e) => {
+            error!(guild_id = guild_id.get(), error = %e, "Failed to fetch word totals");
+            return None;
+        }
+    };
+
+    let weights: HashMap<&str, f64> = frequencies
+        .iter()
+        .map(|(word, count)| (word.as_str(), 1.0 / (1.0 + (*count as f64).ln())))
+        .collect();
+
+    if weights.is_empty() {
+        return None;
+    }
+
+    let pairs = match database
+        .get_reply_pairs(guild_id.get(), CANDIDATE_PAIR_LIMIT)
+        .await
+    {
+        Ok(pairs) => pairs,
+        Err(e) => {
+            error!(guild_id = guild_id.get(), error = %e, "Failed to fetch reply pairs");
+            return None;
+        }
+    };
+
+    let mut matches: Vec<(f64, &str)> = pairs
+        .iter()
+        .filter_map(|(parent, reply)| {
+            let score: f64 = salient_words(parent)
+                .iter()
+                .filter_map(|word| weights.get(word.as_str()))
+                .sum();
+
+            (score >= MIN_MATCH_SCORE).then_some((score, reply.as_str()))
+        })
+        .collect();
+
+    if matches.is_empty() {
+        return None;
+    }
+
+    matches.sort_by(|a, b| b.0.total_cmp(&a.0));
+    matches.truncate(TOP_MATCHES);
+
+    let banned_words = match database.get_guild_settings(guild_id.get()).await {
+        Ok(settings) => BannedWords::new(&settings.banned_words),
+        Err(e) => {
+            error!(guild_id = guild_id.get(), error = %e, "Failed to fetch guild settings");
+            return None;
+        }
+    };
+
+    let words = GenerationOptions::default().word_range();
+
+    with_markov_chain(ctx, guild_id, channel_id, database, false, |chain| {
+        // Seed from the first word of each matching reply the chain can continue
+        let seeds: Vec<&str> = matches
+            .iter()
+            .filter_map(|(_, reply)| {
+                reply
+                    .split_whitespace()
+                    .find(|word| chain.has_successors(word))
+            })
+            .collect();
+
+        seeds.choose(&mut rand::thread_rng()).map(|seed| {
+            truncate_at_word_boundary(
+                &sanitize_output(
+                    &chain.generate(
+                        words,
+                        Some(seed),
+                        DEFAULT_TEMPERATURE,
+                        &mut rand::thread_rng(),
+                    ),
+                    &ctx.cache,
+                ),
+                MESSAGE_CHAR_LIMIT,
+            )
+        })
+    })
+    .await
+    .generated()
+    .flatten()
+    .filter(|reply| !banned_words.matches(reply))
+}
+
+/// Answers `content` like `respond_to`, falling back to a plain generation
+/// when nothing similar was seen before.
+pub async fn reply_to(
+    ctx: &Context,
+    content: &str,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    database: Arc<Database>,
+) -> MarkovOutcome<String> {
+    match respond_to(ctx, content, guild_id, channel_id, database.clone()).await {
+        Some(reply) => MarkovOutcome::Generated(reply),
+        None => {
+            generate_markov_message(
+                ctx,
+                guild_id,
+                channel_id,
+                database,
+                MESSAGE_CHAR_LIMIT,
+                GenerationOptions::default(),
+            )
+            .await
+        }
+    }
+}
+
+/// Answers a reply to one of the bot's messages, starting from the longest
+/// meaningful word of `content` when it has one.
+pub async fn continue_conversation(
+    ctx: &Context,
+    content: &str,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    database: Arc<Database>,
+) -> MarkovOutcome<String> {
+    let seed = salient_words(content)
+        .into_iter()
+        .filter(|word| word.chars().all(char::is_alphabetic))
+        .filter(|word| !STOPWORDS.contains(&word.as_str()))
+        .max_by_key(|word| word.chars().count());
+
+    let Some(seed) = seed else {
+        return reply_to(ctx, content, guild_id, channel_id, database).await;
+    };
+
+    let options = GenerationOptions {
+        seed: Some(&seed),
+        ..GenerationOptions::default()
+    };
+    match generate_markov_message(
+        ctx,
+        guild_id,
+        channel_id,
+        database.clone(),
+        MESSAGE_CHAR_LIMIT,
+        options,
+    )
+    .await
+    {
+        MarkovOutcome::Generated(reply) => MarkovOutcome::Generated(reply),
+        _ => reply_to(ctx, content, guild_id, channel_id, database).await,
+    }
+}
+
+/// Lowercase words of at least three characters, without code, quotes,
+/// mentions or links.
+fn salient_words(content: &str) -> HashSet<String> {
+    strip_code_and_quotes(content)
+        .split_whitespace()
+        .filter(|word| word.chars().count() >= 3)
+        .filter(|word| !word.starts_with('<') && !word.starts_with("http"))
+        .map(|word| word.to_lowercase())
+        .collect()
+}
@@ -0,0 +1,213 @@
+// Light-weight stemmers used to group word inflections on the leaderboard.
+// They are intentionally conservative: a missed inflection is better than
+// merging two unrelated words.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stemmer {
+    English,
+    Turkish,
+}
+
+impl Stemmer {
+    pub fn from_code(code: &str) -> Option<Self> {
+        match code {
+            "en" => Some(Stemmer::English),
+            "tr" => Some(Stemmer::Turkish),
+            _ => None,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Stemmer::English => "en",
+            Stemmer::Turkish => "tr",
+        }
+    }
+
+    pub fn stem(&self, word: &str) -> String {
+        // Leave mentions, links, numbers and the like untouched
+        if !word.chars().all(char::is_alphabetic) {
+            return word.to_string();
+        }
+
+        match self {
+            Stemmer::English => stem_english(word),
+            Stemmer::Turkish => stem_turkish(word),
+        }
+    }
+}
+
+fn is_vowel(c: char) -> bool {
+    matches!(c, 'a' | 'e' | 'i' | 'o' | 'u')
+}
+
+/// Strips plural, `-ing` and `-ed` endings, e.g. "plays", "playing" and
+/// "played" all become "play".
+fn stem_english(word: &str) -> String {
+    if word.chars().count() <= 3 {
+        return word.to_string();
+    }
+
+    if let Some(stem) = word.strip_suffix("ies") {
+        if stem.chars().count() >= 2 {
+            return format!("{}y", stem);
+        }
+    }
+
+    if let Some(stem) = word.strip_suffix("sses") {
+        return format!("{}ss", stem);
+    }
+
+    for suffix in ["ches", "shes", "xes", "zes"] {
+        if word.ends_with(suffix) {
+            return word[..word.len() - 2].to_string();
+        }
+    }
+
+    if word.ends_with('s')
+        && !word.ends_with("ss")
+        && !word.ends_with("us")
+        && !word.ends_with("is")
+    {
+        return word[..word.len() - 1].to_string();
+    }
+
+    for suffix in ["ing", "ed"] {
+        let Some(stem) = word.strip_suffix(suffix) else {
+            continue;
+        };
+
+        // "ring", "need" and friends have no real stem left, and "speed" or
+        // "agreed" never had an "-ed" ending to begin with
+        if stem.chars().count() < 3
+            || !stem.chars().any(is_vowel)
+            || (suffix == "ed" && stem.ends_with('e'))
+        {
+            return word.to_string();
+        }
+
+        let chars: Vec<char> = stem.chars().collect();
+        let last = chars[chars.len() - 1];
+        let before_last = chars[chars.len() - 2];
+
+        // "running" -> "run", but "falling" -> "fall"
+        if last == before_last && !is_vowel(last) && !matches!(last, 'l' | 's' | 'z') {
+            return stem[..stem.len() - last.len_utf8()].to_string();
+        }
+
+        // "making" -> "make": short consonant-vowel-consonant stems lost an "e"
+        if chars.len() == 3
+            && !is_vowel(chars[0])
+            && is_vowel(chars[1])
+            && !is_vowel(chars[2])
+            && !matches!(chars[2], 'w' | 'x' | 'y')
+        {
+            return format!("{}e", stem);
+        }
+
+        return stem.to_string();
+    }
+
+    word.to_string()
+}
+
+/// Strips one case suffix and then one plural suffix, e.g. "evlerden" ->
+/// "ev". Vowel harmony makes a full Turkish stemmer a project of its own,
+/// this only covers the most common endings.
+fn stem_turkish(word: &str) -> String {
+    const CASE_SUFFIXES: [&str; 16] = [
+        "ından", "inden", "undan", "ünden", "dan", "den", "tan", "ten", "nın", "nin", "nun", "nün",
+        "da", "de", "ta", "te",
+    ];
+    const PLURAL_SUFFIXES: [&str; 2] = ["lar", "ler"];
+
+    let mut stem = word;
+
+    for suffix in CASE_SUFFIXES {
+        if let Some(rest) = stem.strip_suffix(suffix) {
+            if rest.chars().count() >= 4 {
+                stem = rest;
+                break;
+            }
+        }
+    }
+
+    for suffix in PLURAL_SUFFIXES {
+        if let Some(rest) = stem.strip_suffix(suffix) {
+            if rest.chars().count() >= 2 {
+                stem = rest;
+                break;
+            }
+        }
+    }
+
+    stem.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn english_strips_common_endings() {
+        let cases = [
+            ("plays", "play"),
+            ("playing", "play"),
+            ("played", "play"),
+            ("running", "run"),
+            ("falling", "fall"),
+            ("making", "make"),
+            ("parties", "party"),
+            ("classes", "class"),
+            ("watches", "watch"),
+        ];
+
+        for (word, stem) in cases {
+            assert_eq!(Stemmer::English.stem(word), stem, "{}", word);
+        }
+    }
+
+    #[test]
+    fn english_leaves_words_without_a_real_stem() {
+        for word in ["ring", "need", "speed", "agreed", "bus", "this", "its"] {
+            assert_eq!(Stemmer::English.stem(word), word);
+        }
+    }
+
+    #[test]
+    fn english_handles_multi_byte_letters() {
+        assert_eq!(Stemmer::English.stem("aççing"), "aç");
+        assert_eq!(Stemmer::English.stem("boññed"), "boñ");
+        assert_eq!(Stemmer::English.stem("çöğs"), "çöğ");
+        assert_eq!(Stemmer::English.stem("ñíes"), "ñíe");
+    }
+
+    #[test]
+    fn non_words_are_untouched() {
+        for word in ["<@123>", "https://example.com", "1990s"] {
+            assert_eq!(Stemmer::English.stem(word), word);
+            assert_eq!(Stemmer::Turkish.stem(word), word);
+        }
+    }
+
+    #[test]
+    fn turkish_strips_case_then_plural() {
+        assert_eq!(Stemmer::Turkish.stem("evlerden"), "ev");
+        assert_eq!(Stemmer::Turkish.stem("kitaplar"), "kitap");
+        assert_eq!(Stemmer::Turkish.stem("gözlerinden"), "göz");
+        assert_eq!(Stemmer::Turkish.stem("ev"), "ev");
+    }
+
+    #[test]
+    fn stemming_is_off_by_default() {
+        assert_eq!(crate::database::GuildSettings::default().stem_words, None);
+    }
+
+    #[test]
+    fn codes_round_trip() {
+        for stemmer in [Stemmer::English, Stemmer::Turkish] {
+            assert_eq!(Stemmer::from_code(stemmer.code()), Some(stemmer));
+        }
+        assert_eq!(Stemmer::from_code("de"), None);
+    }
+}
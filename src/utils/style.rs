@@ -0,0 +1,226 @@
+// Guesses who wrote a piece of text from how everyone uses words. Each author
+// gets a naive Bayes model over their `word_counts`, with add-one smoothing so
+// a single unseen word doesn't rule anyone out.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use serenity::all::{Context, GuildId, UserId};
+
+use crate::database::{tally_words, Database};
+use crate::StyleModelsGlobal;
+
+/// Authors with fewer messages than this aren't considered.
+pub const MIN_STYLE_MESSAGES: i64 = 50;
+
+/// How many of each author's most used words go into their model.
+const MAX_WORDS_PER_AUTHOR: i64 = 2000;
+
+/// How long a trained model is reused before it's rebuilt.
+const STYLE_MODEL_TTL: Duration = Duration::from_secs(30 * 60);
+
+/// How many candidates `guess_author` returns.
+const TOP_CANDIDATES: usize = 5;
+
+struct AuthorStyle {
+    user_id: UserId,
+    /// Smoothed log probability of each word the author used.
+    word_log_probs: HashMap<String, f64>,
+    /// Log probability of a word the author never used.
+    unseen_log_prob: f64,
+}
+
+/// Every eligible author of a guild.
+pub struct StyleModel {
+    authors: Vec<AuthorStyle>,
+}
+
+impl StyleModel {
+    fn train(rows: Vec<(u64, String, i64)>) -> Self {
+        let vocabulary: HashSet<&str> = rows.iter().map(|(_, word, _)| word.as_str()).collect();
+        let vocabulary_size = vocabulary.len() as f64;
+
+        let mut counts: HashMap<u64, Vec<(&str, i64)>> = HashMap::new();
+        for (author_id, word, count) in &rows {
+            counts
+                .entry(*author_id)
+                .or_default()
+                .push((word.as_str(), *count));
+        }
+
+        let authors = counts
+            .into_iter()
+            .map(|(author_id, words)| {
+                let total = words.iter().map(|(_, count)| *count).sum::<i64>() as f64;
+                let denominator = total + vocabulary_size;
+
+                AuthorStyle {
+                    user_id: UserId::new(author_id),
+                    word_log_probs: words
+                        .into_iter()
+                        .map(|(word, count)| {
+                            (word.to_string(), ((count as f64 + 1.0) / denominator).ln())
+                        })
+                        .collect(),
+                    unseen_log_prob: (1.0 / denominator).ln(),
+                }
+            })
+            .collect();
+
+        StyleModel { authors }
+    }
+
+    /// Authors ranked by how likely they wrote `text`, with confidences that
+    /// add up to 1 across all authors.
+    fn classify(&self, text: &str) -> Vec<(UserId, f64)> {
+        let words = tally_words(text);
+        let word_count: i32 = words.values().sum();
+
+        if words.is_empty() || self.authors.is_empty() {
+            return Vec::new();
+        }
+
+        // Averaged per word, otherwise long texts push everything to 0% or 100%
+        let scores: Vec<(UserId, f64)> = self
+            .authors
+            .iter()
+            .map(|author| {
+                let log_likelihood: f64 = words
+                    .iter()
+                    .map(|(word, count)| {
+                        let log_prob = author
+                            .word_log_probs
+                            .get(word)
+                            .copied()
+                            .unwrap_or(author.unseen_log_prob);
+                        log_prob * *count as f64
+                    })
+                    .sum();
+
+                (author.user_id, log_likelihood / word_count as f64)
+            })
+            .collect();
+
+        let best = scores
+            .iter()
+            .map(|(_, score)| *score)
+            .fold(f64::NEG_INFINITY, f64::max);
+        let normalizer: f64 = scores.iter().map(|(_, score)| (score - best).exp()).sum();
+
+        let mut candidates: Vec<(UserId, f64)> = scores
+            .into_iter()
+            .map(|(user_id, score)| (user_id, (score - best).exp() / normalizer))
+            .collect();
+
+        candidates.sort_by(|a, b| b.1.total_cmp(&a.1));
+        candidates
+    }
+}
+
+/// The authors whose style is closest to `text`, most likely first, with a
+/// confidence score each. Empty when no author has enough messages.
+///
+/// The guild's model is trained on first use and kept for
+/// `STYLE_MODEL_TTL`.
+pub async fn guess_author(
+    ctx: &Context,
+    guild_id: GuildId,
+    text: &str,
+    database: Arc<Database>,
+) -> Result<Vec<(UserId, f64)>, sqlx::Error> {
+    let model = style_model(ctx, guild_id, database).await?;
+
+    Ok(model
+        .classify(text)
+        .into_iter()
+        .take(TOP_CANDIDATES)
+        .collect())
+}
+
+async fn style_model(
+    ctx: &Context,
+    guild_id: GuildId,
+    database: Arc<Database>,
+) -> Result<Arc<StyleModel>, sqlx::Error> {
+    let cache = ctx.data.read().await.get::<StyleModelsGlobal>().cloned();
+
+    if let Some(cache) = &cache {
+        if let Some((trained_at, model)) = cache.read().await.get(&guild_id.get()) {
+            if trained_at.elapsed() < STYLE_MODEL_TTL {
+                return Ok(model.clone());
+            }
+        }
+    }
+
+    let rows = database
+        .get_author_vocabularies(guild_id.get(), MIN_STYLE_MESSAGES, MAX_WORDS_PER_AUTHOR)
+        .await?;
+    let model = Arc::new(StyleModel::train(rows));
+
+    if let Some(cache) = &cache {
+        cache
+            .write()
+            .await
+            .insert(guild_id.get(), (Instant::now(), model.clone()));
+    }
+
+    Ok(model)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows(author_id: u64, words: &[(&str, i64)]) -> Vec<(u64, String, i64)> {
+        words
+            .iter()
+            .map(|(word, count)| (author_id, word.to_string(), *count))
+            .collect()
+    }
+
+    fn model() -> StyleModel {
+        let mut vocabularies = rows(1, &[("pizza", 40), ("cheese", 30), ("oven", 10)]);
+        vocabularies.extend(rows(2, &[("rust", 50), ("cargo", 25), ("borrow", 15)]));
+        vocabularies.extend(rows(3, &[("guitar", 35), ("chord", 20), ("pizza", 5)]));
+        StyleModel::train(vocabularies)
+    }
+
+    fn assert_sums_to_one(candidates: &[(UserId, f64)]) {
+        let total: f64 = candidates.iter().map(|(_, confidence)| confidence).sum();
+        assert!((total - 1.0).abs() < 1e-9, "confidences sum to {total}");
+    }
+
+    #[test]
+    fn picks_the_author_whose_words_match() {
+        let model = model();
+
+        for (text, author_id) in [
+            ("cheese pizza from the oven", 1),
+            ("cargo won't let me borrow that", 2),
+            ("learning a new chord on guitar", 3),
+        ] {
+            let candidates = model.classify(text);
+            assert_eq!(candidates.len(), 3);
+            assert_eq!(candidates[0].0, UserId::new(author_id), "{text}");
+            assert!(candidates.windows(2).all(|pair| pair[0].1 >= pair[1].1));
+            assert_sums_to_one(&candidates);
+        }
+    }
+
+    #[test]
+    fn unknown_words_leave_everyone_possible() {
+        let candidates = model().classify("completely unrelated sentence");
+
+        assert_eq!(candidates.len(), 3);
+        assert!(candidates.iter().all(|(_, confidence)| *confidence > 0.0));
+        assert_sums_to_one(&candidates);
+    }
+
+    #[test]
+    fn nothing_to_classify_gives_no_candidates() {
+        assert!(model().classify("").is_empty());
+        assert!(model().classify("   ").is_empty());
+        assert!(StyleModel::train(Vec::new()).classify("pizza").is_empty());
+    }
+}